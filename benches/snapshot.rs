@@ -0,0 +1,295 @@
+use bevy::prelude::Entity;
+use bevy_playground::{
+    BlockShape, EmoteKind, LeaderboardEntry, NetworkedEntities, PlayerInput, PlayerSummary,
+    ProjectileConfig, ProjectileKind, ServerMessages,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn networked_entities(count: u32) -> NetworkedEntities {
+    let mut networked_entities = NetworkedEntities::default();
+    for i in 0..count {
+        networked_entities.entities.push(Entity::from_raw(i));
+        networked_entities
+            .translations
+            .push([i as f32, 0.51, -(i as f32)]);
+    }
+    networked_entities
+}
+
+fn player_summaries(count: u64) -> Vec<PlayerSummary> {
+    (0..count)
+        .map(|i| PlayerSummary {
+            id: 1_700_000_000_000 + i,
+            name: format!("player{}", i),
+            score: i * 10,
+            is_bot: i % 2 == 1,
+        })
+        .collect()
+}
+
+fn server_messages() -> Vec<(&'static str, ServerMessages)> {
+    let entity = Entity::from_raw(42);
+    let id = 1_700_000_000_000;
+    vec![
+        (
+            "player_create",
+            ServerMessages::PlayerCreate {
+                entity,
+                id: 1_700_000_000_000,
+                translation: [1.0, 0.51, -3.0],
+            },
+        ),
+        (
+            "player_remove",
+            ServerMessages::PlayerRemove {
+                id: 1_700_000_000_000,
+            },
+        ),
+        (
+            "spawn_projectile",
+            ServerMessages::SpawnProjectile {
+                entity,
+                translation: [1.0, 1.0, -3.0],
+                velocity: [10.0, 0.0, 0.0],
+                radius: 0.1,
+                kind: ProjectileKind::Fireball,
+            },
+        ),
+        (
+            "despawn_projectile",
+            ServerMessages::DespawnProjectile { entity },
+        ),
+        (
+            "spawn_solana_block",
+            ServerMessages::SpawnSolanaBlock {
+                entity,
+                transform: (0.0, 20.0, 0.0),
+                slot: 250_000_000,
+                shape: BlockShape::Cube,
+            },
+        ),
+        (
+            "despawn_solana_block",
+            ServerMessages::DespawnSolanaBlock { entity },
+        ),
+        (
+            "player_killed",
+            ServerMessages::PlayerKilled {
+                id,
+                killer: Some(id + 1),
+            },
+        ),
+        (
+            "spawn_ball",
+            ServerMessages::SpawnBall {
+                entity,
+                translation: [1.0, 0.51, -3.0],
+            },
+        ),
+        (
+            "chat_message",
+            ServerMessages::ChatMessage {
+                sender: Some(id),
+                message: "gg, one more?".to_string(),
+            },
+        ),
+        (
+            "world_settings",
+            ServerMessages::WorldSettings {
+                physics_timestep: 1.0 / 60.0,
+                snapshot_interval: Some(0.1),
+                lockstep_tick: Some(120),
+                lockstep_positions: (0..8).map(|i| (id + i, [i as f32, 0.51, 0.0])).collect(),
+                projectile: ProjectileConfig::default(),
+            },
+        ),
+        (
+            "lockstep_tick",
+            ServerMessages::LockstepTick {
+                tick: 120,
+                inputs: (0..8).map(|i| (id + i, PlayerInput::default())).collect(),
+            },
+        ),
+        (
+            "lockstep_desync",
+            ServerMessages::LockstepDesync { tick: 120 },
+        ),
+        (
+            "announcement",
+            ServerMessages::Announcement {
+                text: "Slot 250000000 reached".to_string(),
+            },
+        ),
+        (
+            "player_invulnerable",
+            ServerMessages::PlayerInvulnerable {
+                id,
+                invulnerable: true,
+            },
+        ),
+        (
+            "player_teleport",
+            ServerMessages::PlayerTeleport {
+                id,
+                translation: [1.0, 0.51, -3.0],
+            },
+        ),
+        (
+            "player_spawn_protection",
+            ServerMessages::PlayerSpawnProtection {
+                id,
+                protected: true,
+            },
+        ),
+        (
+            "impact_decal",
+            ServerMessages::ImpactDecal {
+                position: [1.0, 0.0, -3.0],
+                normal: [0.0, 1.0, 0.0],
+            },
+        ),
+        ("heal_effect", ServerMessages::HealEffect { target_id: id }),
+        (
+            "capture_point_update",
+            ServerMessages::CapturePointUpdate {
+                id: 1,
+                center: [0.0, 0.0, 0.0],
+                radius: 5.0,
+                controlling_team: Some(0),
+                progress: 0.5,
+            },
+        ),
+        (
+            "objective_holder",
+            ServerMessages::ObjectiveHolder { id: Some(id) },
+        ),
+        (
+            "cast_performed",
+            ServerMessages::CastPerformed {
+                caster_id: id,
+                position: [1.0, 1.0, -3.0],
+            },
+        ),
+        (
+            "player_knocked_up",
+            ServerMessages::PlayerKnockedUp { id, velocity: 6.0 },
+        ),
+        (
+            "match_results",
+            ServerMessages::MatchResults {
+                standings: player_summaries(16),
+                winners: vec![id],
+            },
+        ),
+        (
+            "emote_played",
+            ServerMessages::EmotePlayed {
+                id,
+                kind: EmoteKind::Wave,
+            },
+        ),
+        (
+            "ping_placed",
+            ServerMessages::PingPlaced {
+                id,
+                position: [1.0, 0.0, -3.0],
+            },
+        ),
+        (
+            "live_solana_blocks",
+            ServerMessages::LiveSolanaBlocks {
+                entities: (0..100).map(Entity::from_raw).collect(),
+            },
+        ),
+        (
+            "leaderboard",
+            ServerMessages::Leaderboard {
+                entries: player_summaries(10)
+                    .into_iter()
+                    .map(|summary| LeaderboardEntry {
+                        name: summary.name,
+                        score: summary.score,
+                    })
+                    .collect(),
+            },
+        ),
+        (
+            "player_list",
+            ServerMessages::PlayerList {
+                players: player_summaries(16),
+            },
+        ),
+        (
+            "batch",
+            ServerMessages::Batch {
+                messages: vec![
+                    ServerMessages::DespawnProjectile { entity },
+                    ServerMessages::ImpactDecal {
+                        position: [1.0, 0.0, -3.0],
+                        normal: [0.0, 1.0, 0.0],
+                    },
+                    ServerMessages::PlayerKilled { id, killer: None },
+                ],
+            },
+        ),
+        (
+            "chunk",
+            ServerMessages::Chunk {
+                id: 1,
+                index: 0,
+                count: 4,
+                bytes: vec![0; 1024],
+            },
+        ),
+    ]
+}
+
+fn bench_networked_entities(c: &mut Criterion) {
+    let mut group = c.benchmark_group("networked_entities");
+    for count in [10, 100, 1000] {
+        let snapshot = networked_entities(count);
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        println!(
+            "NetworkedEntities with {} entities: {} bytes",
+            count,
+            bytes.len()
+        );
+
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("serialize", count),
+            &snapshot,
+            |b, snapshot| b.iter(|| bincode::serialize(black_box(snapshot)).unwrap()),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("deserialize", count),
+            &bytes,
+            |b, bytes| {
+                b.iter(|| bincode::deserialize::<NetworkedEntities>(black_box(bytes)).unwrap())
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_server_messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("server_messages");
+    for (name, message) in server_messages() {
+        let bytes = bincode::serialize(&message).unwrap();
+        println!("ServerMessages::{}: {} bytes", name, bytes.len());
+
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("serialize", name),
+            &message,
+            |b, message| b.iter(|| bincode::serialize(black_box(message)).unwrap()),
+        );
+        group.bench_with_input(BenchmarkId::new("deserialize", name), &bytes, |b, bytes| {
+            b.iter(|| bincode::deserialize::<ServerMessages>(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_networked_entities, bench_server_messages);
+criterion_main!(benches);
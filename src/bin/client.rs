@@ -1,29 +1,54 @@
-use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use bevy::{
+    app::AppExit,
+    asset::LoadState,
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    ecs::{
+        event::ManualEventReader,
+        system::{EntityCommands, SystemParam},
+    },
+    input::mouse::MouseMotion,
     prelude::{shape::Icosphere, *},
+    render::primitives::{Frustum, Sphere},
+    transform::TransformSystem,
+    window::{CursorGrabMode, PrimaryWindow, WindowFocused},
 };
-use bevy_egui::{EguiContexts, EguiPlugin};
-use bevy_flycam::{FlyCam, NoCameraPlayerPlugin, MovementSettings};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
+use bevy_flycam::{FlyCam, KeyBindings, MovementSettings, NoCameraPlayerPlugin};
 
 use bevy_playground::{
-    connection_config, get_server_addr, setup_level, ClientChannel, NetworkedEntities, PlayerCommand, PlayerInput, ServerChannel, ServerMessages, SolanaSlotBlock, PROTOCOL_ID
+    camera_collision_system, configure_solver_system, encode_player_name, get_server_addr,
+    mark_observer, new_renet_client, player_physics, setup_level, sign_wallet_identity,
+    solana_block_physics, BallConfig, CameraCollision, CameraConfig, ChunkAssembler, ClientChannel,
+    EmoteKind, EntityRequest, FloorGrid, LeaderboardEntry, LockstepMessage, LockstepState,
+    MaterialPresets, NetworkedEntities, PlayerCommand, PlayerInput, PlayerSummary,
+    ProjectileConfig, ProjectileKind, ServerChannel, ServerMessages, SolanaSlotBlock, WorldBounds,
+    WorldConfig, GROUND_GROUP, LOCKSTEP_STEP, WALL_GROUP,
+};
+use bevy_rapier3d::prelude::{
+    Collider, CollisionGroups, Group, NoUserData, QueryFilter, RapierConfiguration, RapierContext,
+    RapierPhysicsPlugin, RigidBody, TransformInterpolation,
 };
-use bevy_rapier3d::prelude::{Collider, Restitution, RigidBody};
 use bevy_renet::{
     renet::{
-        transport::{ClientAuthentication, NetcodeClientTransport, NetcodeTransportError},
+        transport::{NetcodeClientTransport, NetcodeTransportError},
         RenetClient,
     },
     transport::NetcodeClientPlugin,
     RenetClientPlugin,
 };
 use renet_visualizer::{RenetClientVisualizer, RenetVisualizerStyle};
-use smooth_bevy_cameras::{
-    controllers::fps::FpsCameraPlugin, LookTransform, LookTransformBundle, LookTransformPlugin,
-    Smoother,
-};
+use smooth_bevy_cameras::{LookTransform, LookTransformPlugin, Smoother};
+use solana_sdk::signature::read_keypair_file;
 
 #[derive(Component)]
 struct ControlledPlayer;
@@ -35,258 +60,4227 @@ struct NetworkMapping(HashMap<Entity, Entity>);
 struct PlayerInfo {
     client_entity: Entity,
     server_entity: Entity,
+    /// Shield shown while the player has spawn protection.
+    shield: Option<Entity>,
 }
 
 #[derive(Debug, Default, Resource)]
 struct ClientLobby {
     players: HashMap<u64, PlayerInfo>,
+    /// Player holding the king of the hill objective, and the color they had
+    /// before being tinted, restored when they lose it.
+    objective_holder: Option<(u64, Color)>,
 }
 
+/// Consecutive server messages that failed to deserialize. A run of failures
+/// usually means client and server disagree on the protocol, so past
+/// `MAX_DESERIALIZE_FAILURES` we disconnect instead of dropping everything.
+#[derive(Debug, Default, Resource)]
+struct DeserializeFailures(u32);
 
-fn new_renet_client() -> (RenetClient, NetcodeClientTransport) {
-    let client = RenetClient::new(connection_config());
-    let server_addr = get_server_addr().parse().unwrap();
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap(); // Bind to any available port
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap();
-    let client_id = current_time.as_millis() as u64;
-    let authentication = ClientAuthentication::Unsecure {
-        client_id,
-        protocol_id: PROTOCOL_ID,
-        server_addr,
-        user_data: None,
-    };
+const MAX_DESERIALIZE_FAILURES: u32 = 10;
+
+/// Turns `ServerChannel::ServerMessages` payloads back into messages,
+/// reassembling chunked ones and counting failures.
+#[derive(SystemParam)]
+struct ServerMessageDecoder<'w> {
+    failures: ResMut<'w, DeserializeFailures>,
+    chunks: ResMut<'w, ChunkAssembler>,
+    pending: ResMut<'w, PendingServerMessages>,
+}
+
+/// Unpacked `ServerMessages::Batch` contents not handled yet.
+#[derive(Debug, Default, Resource)]
+struct PendingServerMessages(VecDeque<ServerMessages>);
+
+impl ServerMessageDecoder<'_> {
+    /// The next message from the server, with batches unpacked and anything
+    /// undecodable skipped.
+    fn next(&mut self, client: &mut RenetClient) -> Option<ServerMessages> {
+        loop {
+            let message = match self.pending.0.pop_front() {
+                Some(message) => message,
+                None => {
+                    let bytes = client.receive_message(ServerChannel::ServerMessages)?;
+                    let Some(message) = self.decode(&bytes) else {
+                        continue;
+                    };
+                    message
+                }
+            };
+            match message {
+                // Ahead of anything still pending, to keep the server's order
+                ServerMessages::Batch { messages } => {
+                    for message in messages.into_iter().rev() {
+                        self.pending.0.push_front(message);
+                    }
+                }
+                message => return Some(message),
+            }
+        }
+    }
+
+    /// The decoded message, or `None` for failures and incomplete chunked messages.
+    fn decode(&mut self, bytes: &[u8]) -> Option<ServerMessages> {
+        let message = self.deserialize(bytes)?;
+        let ServerMessages::Chunk {
+            id,
+            index,
+            count,
+            bytes,
+        } = message
+        else {
+            return Some(message);
+        };
+        let bytes = self.chunks.receive(id, index, count, bytes)?;
+        self.deserialize(&bytes)
+    }
 
-    let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
+    fn deserialize(&mut self, bytes: &[u8]) -> Option<ServerMessages> {
+        match bincode::deserialize(bytes) {
+            Ok(message) => {
+                self.failures.0 = 0;
+                Some(message)
+            }
+            Err(e) => {
+                println!("Failed to deserialize server message: {}", e);
+                self.failures.0 += 1;
+                None
+            }
+        }
+    }
 
-    (client, transport)
+    fn reset(&mut self) {
+        self.failures.0 = 0;
+        self.chunks.clear();
+        self.pending.0.clear();
+    }
 }
 
-fn main() {
-    let mut app = App::new();
-    app.add_plugins(DefaultPlugins);
-    app.add_plugin(RenetClientPlugin);
-    app.add_plugin(NetcodeClientPlugin);
-    app.add_plugin(LookTransformPlugin);
-    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
-    app.add_plugin(LogDiagnosticsPlugin::default());
-    app.add_plugin(EguiPlugin);
-    app.add_plugin(NoCameraPlayerPlugin);
+const PLAYER_COLOR: Color = Color::rgb(0.8, 0.7, 0.6);
+const INVULNERABLE_PLAYER_COLOR: Color = Color::rgb(1.0, 0.85, 0.2);
+const OBJECTIVE_HOLDER_COLOR: Color = Color::rgb(0.3, 1.0, 0.4);
+const SHIELD_COLOR: Color = Color::rgba(0.3, 0.6, 1.0, 0.3);
+const HEAL_PULSE_COLOR: Color = Color::rgba(0.2, 1.0, 0.3, 0.5);
+const HEAL_PULSE_SECS: f32 = 0.6;
+const CAST_FLASH_COLOR: Color = Color::rgba(1.0, 0.6, 0.1, 0.8);
+const CAST_FLASH_SECS: f32 = 0.15;
+/// Peak flash scale, relative to the projectile mesh.
+const CAST_FLASH_SCALE: f32 = 6.0;
+/// Your own casts flash bigger so they read clearly from the first person view.
+const LOCAL_CAST_FLASH_SCALE: f32 = 10.0;
 
-    app.add_event::<PlayerCommand>();
-    app.insert_resource(PlayerInput::default());
+const MAX_CHAT_LINES: usize = 50;
 
-    app.insert_resource(ClientLobby::default());
-    let (client, transport) = new_renet_client();
-    app.insert_resource(client);
-    app.insert_resource(transport);
+#[derive(Debug, Default, Resource)]
+struct LeaderboardView(Vec<LeaderboardEntry>);
 
-    app.insert_resource(NetworkMapping::default());
+/// Latest `/players` reply, shown until closed.
+#[derive(Debug, Default, Resource)]
+struct PlayerListView(Option<Vec<PlayerSummary>>);
 
-    app.insert_resource(MovementSettings {
-        sensitivity: 0.00015,
-        speed: 12.0,
-    });
+/// Standings and winner ids from the last `MatchResults`, until the results
+/// screen is left.
+#[derive(Debug, Default, Resource)]
+struct MatchResultsView(Option<(Vec<PlayerSummary>, Vec<u64>)>);
 
-    app.add_systems(
-        (
-            client_sync_players,
-            client_send_input,
-            client_send_player_commands,
-        ).distributive_run_if(bevy_renet::transport::client_connected),
-    );
+const ANNOUNCEMENT_SECS: f32 = 4.0;
+/// Last part of an announcement's lifetime spent fading out.
+const ANNOUNCEMENT_FADE_SECS: f32 = 1.0;
 
-    app.insert_resource(RenetClientVisualizer::<200>::new(
-        RenetVisualizerStyle::default(),
-    ));
-    app.add_system(update_visulizer_system);
+/// Latest server announcement and how long it has been shown.
+#[derive(Debug, Default, Resource)]
+struct AnnouncementBanner(Option<(String, Timer)>);
 
-    app.add_startup_system(setup_level);
-    app.add_startup_system(setup_camera_fps);
-    app.add_system(panic_on_error_system);
+const EMOTE_SECS: f32 = 2.0;
+const PING_SECS: f32 = 5.0;
+/// How far a ping can reach along the camera's view.
+const PING_DISTANCE: f32 = 100.0;
 
-    app.run();
+enum MarkerAnchor {
+    Entity(Entity),
+    Position(Vec3),
 }
 
-// If any error is found we just panic
-fn panic_on_error_system(mut renet_error: EventReader<NetcodeTransportError>) {
-    for e in renet_error.iter() {
-        panic!("{}", e);
+/// A short-lived label drawn over a player or a point in the world.
+struct WorldMarker {
+    anchor: MarkerAnchor,
+    label: String,
+    timer: Timer,
+}
+
+/// Emotes and pings currently on screen.
+#[derive(Default, Resource)]
+struct WorldMarkers(Vec<WorldMarker>);
+
+/// Views fed by server messages and drawn with egui.
+#[derive(SystemParam)]
+struct UiViews<'w> {
+    chat: ResMut<'w, ChatLog>,
+    leaderboard: ResMut<'w, LeaderboardView>,
+    player_list: ResMut<'w, PlayerListView>,
+    markers: ResMut<'w, WorldMarkers>,
+    match_results: ResMut<'w, MatchResultsView>,
+    announcement: ResMut<'w, AnnouncementBanner>,
+}
+
+/// How far behind the newest snapshot entities are rendered, in seconds.
+/// Gives the buffer a second sample to interpolate toward.
+const INTERPOLATION_DELAY: f64 = 0.1;
+
+/// `INTERPOLATION_DELAY`, or two snapshot intervals when the server sends
+/// snapshots less often than that, as it does to observers.
+#[derive(Debug, Resource)]
+struct InterpolationDelay(f64);
+
+impl Default for InterpolationDelay {
+    fn default() -> Self {
+        InterpolationDelay(INTERPOLATION_DELAY)
     }
 }
 
-fn update_visulizer_system(
-    mut egui_contexts: EguiContexts,
-    mut visualizer: ResMut<RenetClientVisualizer<200>>,
-    client: Res<RenetClient>,
-    mut show_visualizer: Local<bool>,
-    keyboard_input: Res<Input<KeyCode>>,
-) {
-    visualizer.add_network_info(client.network_info());
-    if keyboard_input.just_pressed(KeyCode::F1) {
-        *show_visualizer = !*show_visualizer;
+impl InterpolationDelay {
+    fn for_snapshot_interval(interval: Option<f32>) -> Self {
+        let interval = interval.map_or(0.0, |interval| interval as f64);
+        InterpolationDelay(INTERPOLATION_DELAY.max(2.0 * interval))
     }
-    if *show_visualizer {
-        visualizer.show_window(egui_contexts.ctx_mut());
+}
+const INTERPOLATION_BUFFER_LEN: usize = 16;
+
+/// Recent `(server_time, translation)` samples of a networked entity.
+#[derive(Debug, Default, Component)]
+struct InterpolationBuffer {
+    samples: VecDeque<(f64, Vec3)>,
+}
+
+impl InterpolationBuffer {
+    fn push(&mut self, sample: (f64, Vec3)) {
+        if self
+            .samples
+            .back()
+            .is_some_and(|(time, _)| *time >= sample.0)
+        {
+            return;
+        }
+        self.samples.push_back(sample);
+        if self.samples.len() > INTERPOLATION_BUFFER_LEN {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Position at `time`, clamped to the oldest and newest samples.
+    fn sample(&self, time: f64) -> Option<Vec3> {
+        let (first_time, first) = *self.samples.front()?;
+        if time <= first_time {
+            return Some(first);
+        }
+
+        for ((from_time, from), (to_time, to)) in
+            self.samples.iter().zip(self.samples.iter().skip(1))
+        {
+            if time <= *to_time {
+                let t = (time - from_time) / (to_time - from_time);
+                return Some(from.lerp(*to, t as f32));
+            }
+        }
+
+        self.samples.back().map(|(_, translation)| *translation)
+    }
+
+    fn newest(&self) -> Option<Vec3> {
+        self.samples.back().map(|(_, translation)| *translation)
+    }
+
+    fn shift(&mut self, shift: Vec3) {
+        for (_, translation) in self.samples.iter_mut() {
+            *translation -= shift;
+        }
+    }
+
+    /// Newest sample carried on under `gravity` until `time`, extrapolating at
+    /// most `MAX_EXTRAPOLATION` seconds. The velocity comes from the two newest
+    /// samples, so it picks up bounces, or is `initial_velocity` until there
+    /// are two.
+    fn extrapolate(&self, time: f64, initial_velocity: Vec3, gravity: Vec3) -> Option<Vec3> {
+        let (newest_time, newest) = *self.samples.back()?;
+        let velocity = match self.samples.iter().rev().nth(1) {
+            Some((previous_time, previous)) if newest_time > *previous_time => {
+                // The average velocity between two samples is the one halfway between them
+                let dt = (newest_time - previous_time) as f32;
+                (newest - *previous) / dt + gravity * dt / 2.
+            }
+            _ => initial_velocity,
+        };
+        let elapsed = (time - newest_time).clamp(0.0, MAX_EXTRAPOLATION) as f32;
+        Some(newest + velocity * elapsed + gravity * elapsed * elapsed / 2.)
     }
 }
 
-fn client_send_input(
-    mut client: ResMut<RenetClient>,
-    mut player_input: ResMut<PlayerInput>,
-    camera_query: Query<&Transform, With<FlyCam>>,
-) {
-    if let Ok(camera_transform) = camera_query.get_single() {
-        player_input.position = camera_transform.translation.into();
+/// Longest a projectile is moved past its newest snapshot before it waits
+/// for the next one.
+const MAX_EXTRAPOLATION: f64 = 0.25;
+
+/// Velocity the server gave a projectile at spawn. Projectiles fly
+/// ballistically, so they are extrapolated at the current server time
+/// instead of interpolated behind it.
+#[derive(Debug, Component)]
+struct ProjectileVelocity(Vec3);
+
+/// Networked entity hidden by `culling_system`. Its buffer keeps filling, but
+/// it isn't interpolated until it's back in view.
+#[derive(Debug, Component)]
+struct Culled;
+
+/// Slack around an entity's position for the frustum test, so an entity
+/// doesn't vanish while its edge is still on screen.
+const CULL_MARGIN: f32 = 2.0;
+
+/// Tracks the server clock from snapshot timestamps.
+#[derive(Debug, Default, Resource)]
+struct ServerClock {
+    latest_tick: Option<u64>,
+    /// Server time minus local time, smoothed over snapshots.
+    offset: Option<f64>,
+}
+
+impl ServerClock {
+    fn observe(&mut self, tick: u64, server_time: f64, local_time: f64) {
+        self.latest_tick = Some(tick);
+        let offset = server_time - local_time;
+        self.offset = Some(match self.offset {
+            Some(previous) => previous + (offset - previous) * 0.1,
+            None => offset,
+        });
     }
 
-    let input_message = bincode::serialize(&*player_input).unwrap();
-    client.send_message(ClientChannel::Input, input_message);
+    fn server_time(&self, local_time: f64) -> Option<f64> {
+        self.offset.map(|offset| local_time + offset)
+    }
 }
 
-fn client_send_player_commands(
-    mut player_commands: EventReader<PlayerCommand>,
-    mut client: ResMut<RenetClient>,
-) {
-    for command in player_commands.iter() {
-        let command_message = bincode::serialize(command).unwrap();
-        client.send_message(ClientChannel::Command, command_message);
+/// Shrinks an entity away before despawning it. The entity's interpolation
+/// buffer is dropped when this is added, so it shrinks where it was last drawn
+/// instead of chasing a snapshot target that will never update again.
+#[derive(Component)]
+struct Despawning(Timer);
+
+impl Default for Despawning {
+    fn default() -> Self {
+        Self(Timer::from_seconds(0.3, TimerMode::Once))
     }
 }
 
-fn client_sync_players(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut client: ResMut<RenetClient>,
-    transport: Res<NetcodeClientTransport>,
-    mut lobby: ResMut<ClientLobby>,
-    mut network_mapping: ResMut<NetworkMapping>,
-) {
-    let client_id = transport.client_id();
-    while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
-        let server_message = bincode::deserialize(&message).unwrap();
-        match server_message {
-            ServerMessages::PlayerCreate {
-                id,
-                translation,
-                entity,
-            } => {
-                println!("Player {} connected.", id);
+const DECAL_SECS: f32 = 4.0;
+const DECAL_COLOR: Color = Color::rgba(0.1, 0.08, 0.05, 0.8);
 
-                let mut client_entity = commands.spawn(PbrBundle {
-                    mesh: meshes.add(Mesh::from(shape::Capsule::default())),
-                    material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                    transform: Transform::from_xyz(translation[0], translation[1], translation[2]),
-                    ..Default::default()
-                });
+/// Scorch mark left by a projectile impact, fading out over its timer.
+#[derive(Component)]
+struct Decal(Timer);
 
-                if client_id == id {
-                    client_entity.insert(ControlledPlayer);
-                }
+/// Green sphere growing and fading around a healed player.
+#[derive(Component)]
+struct HealPulse(Timer);
 
-                let player_info = PlayerInfo {
-                    server_entity: entity,
-                    client_entity: client_entity.id(),
-                };
-                lobby.players.insert(id, player_info);
-                network_mapping.0.insert(entity, client_entity.id());
-            }
-            ServerMessages::PlayerRemove { id } => {
-                println!("Player {} disconnected.", id);
-                if let Some(PlayerInfo {
-                    server_entity,
-                    client_entity,
-                }) = lobby.players.remove(&id)
-                {
-                    commands.entity(client_entity).despawn();
-                    network_mapping.0.remove(&server_entity);
-                }
-            }
-            ServerMessages::SpawnProjectile {
-                entity,
-                translation,
-            } => {
-                let projectile_entity = commands.spawn(PbrBundle {
-                    mesh: meshes.add(
-                        Mesh::try_from(Icosphere {
-                            radius: 0.1,
-                            subdivisions: 5,
-                        })
-                        .unwrap(),
-                    ),
-                    material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
-                    transform: Transform::from_translation(translation.into()),
-                    ..Default::default()
-                });
-                network_mapping.0.insert(entity, projectile_entity.id());
-            }
-            ServerMessages::DespawnProjectile { entity } => {
-                if let Some(entity) = network_mapping.0.remove(&entity) {
-                    commands.entity(entity).despawn();
-                }
-            }
-            ServerMessages::SpawnSolanaBlock {
-                entity,
-                transform,
-                slot,
-            } => {
-                println!(
-                    "Solana Slot {} spawned. Transform: {}, {}, {}",
-                    slot, transform.0, transform.1, transform.2
-                );
+/// Muzzle flash at a caster, swelling to `peak_scale` and fading out.
+#[derive(Component)]
+struct CastFlash {
+    timer: Timer,
+    peak_scale: f32,
+}
 
-                // Spawn location
-                let spawn_location = Transform::from_xyz(transform.0, transform.1, transform.2);
+/// Latest state of a capture point, from `ServerMessages::CapturePointUpdate`.
+#[derive(Debug, Clone, Copy)]
+struct CapturePointState {
+    /// World position; the zone entity is placed at it once and then moves
+    /// with the floating origin like any other root.
+    center: Vec3,
+    radius: f32,
+    controlling_team: Option<u8>,
+    progress: f32,
+}
 
-                // Spawn new
-                let solana_block_entity = commands
-                    .spawn(PbrBundle {
-                        mesh: meshes.add(Mesh::from(shape::Box::new(1.0, 1.0, 1.0))),
-                        material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                        transform: spawn_location,
-                        ..Default::default()
-                    })
-                    .insert(RigidBody::Dynamic)
-                    // .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y)
-                    .insert(Collider::cuboid(1.0, 1.0, 1.0))
-                    .insert(Restitution::coefficient(0.7))
-                    .insert(SolanaSlotBlock { id: slot })
-                    .id();
+/// Capture points the server has reported, and the zone and progress ring
+/// entities `capture_point_render_system` draws them with.
+#[derive(Default, Resource)]
+struct CapturePoints {
+    states: HashMap<u32, CapturePointState>,
+    entities: HashMap<u32, CapturePointView>,
+}
 
-                network_mapping.0.insert(entity, solana_block_entity);
-            }
-            ServerMessages::DespawnSolanaBlock { entity } => {
-                if let Some(entity) = network_mapping.0.remove(&entity) {
-                    commands.entity(entity).despawn();
-                }
-            }
+#[derive(Debug, Clone, Copy)]
+struct CapturePointView {
+    /// Root placed at the point's center and scaled to its radius.
+    zone: Entity,
+    disc: Entity,
+    ring: Entity,
+}
+
+const NEUTRAL_TEAM_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+const TEAM_COLORS: [Color; 4] = [
+    Color::rgb(0.9, 0.25, 0.2),
+    Color::rgb(0.2, 0.45, 0.95),
+    Color::rgb(0.3, 0.85, 0.3),
+    Color::rgb(0.95, 0.8, 0.2),
+];
+const CAPTURE_ZONE_ALPHA: f32 = 0.3;
+
+fn team_color(team: Option<u8>) -> Color {
+    team.map_or(NEUTRAL_TEAM_COLOR, |team| {
+        TEAM_COLORS[team as usize % TEAM_COLORS.len()]
+    })
+}
+
+/// Mesh shared by every decal.
+#[derive(Default, Resource)]
+struct Decals {
+    mesh: Handle<Mesh>,
+}
+
+/// Decides which effect gets dropped first when the budget is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum EffectPriority {
+    /// Ambient clutter such as impact decals.
+    Low,
+    /// Feedback about something happening to a player.
+    High,
+}
+
+/// Caps how many transient effect entities exist at once. Effect spawners
+/// `reserve` a slot before spawning and `track` what they spawn.
+#[derive(Default, Resource)]
+struct EffectBudget {
+    max: usize,
+    /// Live effects, oldest first.
+    effects: VecDeque<(Entity, EffectPriority)>,
+}
+
+impl EffectBudget {
+    fn from_settings(settings: &ClientSettings) -> Self {
+        Self {
+            max: settings.effect_budget,
+            effects: VecDeque::new(),
         }
     }
 
-    while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
-        let networked_entities: NetworkedEntities = bincode::deserialize(&message).unwrap();
+    /// Makes room for an effect of `priority`, despawning the oldest effect of
+    /// the lowest priority present if the budget is full. Returns false, and
+    /// frees nothing, when every live effect outranks the new one.
+    fn reserve(&mut self, commands: &mut Commands, priority: EffectPriority) -> bool {
+        // Effects attached to a player are despawned along with it without
+        // finishing, so their slots are released here instead
+        self.effects
+            .retain(|(effect, _)| commands.get_entity(*effect).is_some());
+        if self.effects.len() < self.max {
+            return true;
+        }
+        let victim = self
+            .effects
+            .iter()
+            .enumerate()
+            .min_by_key(|(index, (_, effect_priority))| (*effect_priority, *index))
+            .filter(|(_, (_, victim_priority))| *victim_priority <= priority)
+            .map(|(index, _)| index);
+        let Some((entity, _)) = victim.and_then(|index| self.effects.remove(index)) else {
+            return false;
+        };
+        commands.entity(entity).despawn_recursive();
+        true
+    }
 
-        for i in 0..networked_entities.entities.len() {
-            if let Some(entity) = network_mapping.0.get(&networked_entities.entities[i]) {
-                let translation = networked_entities.translations[i].into();
-                let transform = Transform {
-                    translation,
-                    ..Default::default()
-                };
-                commands.entity(*entity).insert(transform);
+    fn track(&mut self, entity: Entity, priority: EffectPriority) {
+        self.effects.push_back((entity, priority));
+    }
+
+    /// Call when an effect finishes on its own.
+    fn forget(&mut self, entity: Entity) {
+        self.effects.retain(|(effect, _)| *effect != entity);
+    }
+}
+
+/// Seconds of snapshots kept for the killcam.
+const KILLCAM_SECS: f64 = 5.0;
+/// Where the killcam sits relative to the player it follows.
+const KILLCAM_OFFSET: Vec3 = Vec3::new(0.0, 4.0, 8.0);
+
+struct ActiveReplay {
+    snapshots: Vec<NetworkedEntities>,
+    elapsed: f64,
+    /// Server entity the camera follows.
+    focus: Option<Entity>,
+    /// Camera transform to restore when the replay ends.
+    camera_return: Transform,
+}
+
+/// Rolling buffer of recent snapshots and the replay playing from it, if any.
+#[derive(Default, Resource)]
+struct Killcam {
+    history: VecDeque<NetworkedEntities>,
+    active: Option<ActiveReplay>,
+}
+
+impl Killcam {
+    fn record(&mut self, snapshot: NetworkedEntities) {
+        let cutoff = snapshot.server_time - KILLCAM_SECS;
+        self.history.push_back(snapshot);
+        while self
+            .history
+            .front()
+            .is_some_and(|oldest| oldest.server_time < cutoff)
+        {
+            self.history.pop_front();
+        }
+    }
+}
+
+fn not_replaying(killcam: Res<Killcam>) -> bool {
+    killcam.active.is_none()
+}
+
+fn input_focused(focus: Res<WindowFocus>, settings: Res<ClientSettings>) -> bool {
+    focus.0 || settings.pause_on_focus_loss == PauseOnFocusLoss::Off
+}
+
+/// Marks a stand-in for a networked entity whose spawn message hasn't arrived.
+#[derive(Component)]
+struct Placeholder;
+
+/// How long a despawned server entity is ignored if it shows up in a late snapshot.
+const DESPAWN_MEMORY_SECS: f32 = 1.0;
+
+/// Stand-ins for networked entities seen in snapshots before their spawn
+/// message. They are kept out of `NetworkMapping` so the real spawn can upgrade
+/// them in place.
+#[derive(Default, Resource)]
+struct Placeholders {
+    entities: HashMap<Entity, Entity>,
+    /// Server entities despawned recently, with the time they were despawned.
+    recently_despawned: HashMap<Entity, f32>,
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+impl Placeholders {
+    /// Returns the placeholder for `server_entity` to upgrade, or a new entity.
+    fn spawn_or_upgrade<'w, 's, 'a>(
+        &mut self,
+        commands: &'a mut Commands<'w, 's>,
+        server_entity: Entity,
+    ) -> EntityCommands<'w, 's, 'a> {
+        match self.entities.remove(&server_entity) {
+            Some(placeholder) => {
+                let mut entity_commands = commands.entity(placeholder);
+                entity_commands.remove::<Placeholder>();
+                entity_commands
             }
+            None => commands.spawn_empty(),
         }
     }
+
+    fn despawned(&mut self, commands: &mut Commands, server_entity: Entity, now: f32) {
+        if let Some(placeholder) = self.entities.remove(&server_entity) {
+            commands.entity(placeholder).despawn();
+        }
+        self.recently_despawned.insert(server_entity, now);
+    }
 }
 
-fn setup_camera_fps(mut commands: Commands) {
-    commands.spawn((
-        Camera3dBundle {
-            transform: Transform::from_xyz(0.0, 2.0, 0.5),
-            ..default()
-        },
-        FlyCam,
-    ));
+/// How the client moves Solana blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockSimMode {
+    /// Blocks are kinematic and only follow server snapshots.
+    ServerAuthoritative,
+    /// Blocks are simulated locally and pulled back toward snapshots when they drift.
+    ClientPredicted,
+}
+
+/// Locally simulated block under `BlockSimMode::ClientPredicted`.
+#[derive(Component)]
+struct PredictedBlock;
+
+/// Drift from the server position that a predicted block is eased back from.
+const PREDICTION_TOLERANCE: f32 = 0.1;
+/// Drift past which a predicted block is snapped straight to the server position.
+const PREDICTION_SNAP_DISTANCE: f32 = 1.0;
+
+/// Clear color, also used for fog so the horizon blends into it.
+const SKY_COLOR: Color = Color::rgb(0.55, 0.7, 0.85);
+/// Fraction of the render distance where fog starts.
+const FOG_START: f32 = 0.6;
+
+/// Client-only rendering options.
+#[derive(Debug, Resource)]
+struct ClientSettings {
+    /// Icosphere subdivisions for projectiles. An icosphere has
+    /// `20 * (subdivisions + 1)^2` triangles: 180 at 2, 720 at 5.
+    projectile_subdivisions: usize,
+    block_sim_mode: BlockSimMode,
+    /// Camera far plane. Fog thickens from `FOG_START` of this until
+    /// everything past it is fully fogged.
+    render_distance: f32,
+    /// Inputs sent to the server per second.
+    input_send_rate: f32,
+    /// CSV file to append per-second network stats to. Read from
+    /// `NETWORK_LOG_PATH`; logging is off when unset.
+    network_log_path: Option<PathBuf>,
+    /// Fraction of the angle to the nearest enemy that gamepad casts are bent
+    /// by, capped at `MAX_AIM_ASSIST_STRENGTH`. 0 disables aim-assist.
+    aim_assist_strength: f32,
+    /// Half-angle in degrees around the aim within which enemies attract it.
+    aim_assist_cone: f32,
+    /// Most transient effect entities (decals, pulses) alive at once.
+    effect_budget: usize,
+    materials: MaterialPresets,
+    /// How far the rendered camera lags behind the flycam each frame, from 0
+    /// (snappy, no smoothing) up to just under 1 (cinematic).
+    camera_smoothing: f32,
+    /// Mouse look movement shorter than this many counts in a frame is
+    /// dropped, and longer movement shortened by it, so sensor jitter doesn't
+    /// drift the camera. 0 disables the deadzone.
+    look_deadzone: f32,
+    /// Blends each frame's look movement with the previous frame's, from 0
+    /// (raw, no added latency) up to `MAX_LOOK_SMOOTHING`. Off by default so
+    /// mouse aim isn't delayed; gamepad players can raise it in the menu.
+    look_smoothing: f32,
+    /// GLTF file, relative to `assets/`, drawn for players instead of the
+    /// capsule. Read from `PLAYER_MODEL`.
+    player_model: Option<String>,
+    /// Places the model relative to the capsule collider. The default suits
+    /// a model about two units tall with its origin at its feet, facing +Z.
+    player_model_transform: Transform,
+    /// Camera distance from the origin that triggers a floating origin
+    /// rebase, read from `FLOATING_ORIGIN_THRESHOLD`. `None` disables rebasing.
+    floating_origin_threshold: Option<f32>,
+    pause_on_focus_loss: PauseOnFocusLoss,
+    /// Networked entities further than this from the camera, or outside its
+    /// view, are hidden and not interpolated. `None` disables culling.
+    cull_distance: Option<f32>,
+    /// Times a connection lost mid-game is retried before returning to the
+    /// main menu. 0 disables reconnecting.
+    reconnect_attempts: u32,
+    /// Renders client-simulated bodies between physics steps instead of at
+    /// the last one, so they don't visibly step when physics runs slower
+    /// than the frame rate. Separate from network interpolation.
+    physics_interpolation: bool,
+    /// Watches without a player, set with `OBSERVER=1`.
+    observer: bool,
+    /// Keeps an observer's free camera inside this box so it can't wander
+    /// under the floor or far from the play area. `None` lets it roam.
+    /// Players' cameras follow their player and are never clamped.
+    camera_bounds: Option<CameraBounds>,
+    radar: RadarConfig,
+    /// Draws the path a basic attack would take, falling under
+    /// `ProjectileConfig::gravity_scale`, up to where it would hit.
+    trajectory_preview: bool,
+}
+
+/// HUD radar around the crosshair showing other players' bearing and
+/// distance relative to where the camera faces.
+#[derive(Debug, Clone, Copy)]
+struct RadarConfig {
+    enabled: bool,
+    /// Players further than this don't show up.
+    range: f32,
+}
+
+/// Corners of a box in world coordinates.
+#[derive(Debug, Clone, Copy)]
+struct CameraBounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Default for CameraBounds {
+    /// The default level with room to look at it from outside the walls, from
+    /// just above the floor to well over where Solana blocks spawn.
+    fn default() -> Self {
+        Self {
+            min: Vec3::new(-60.0, 1.0, -60.0),
+            max: Vec3::new(60.0, 60.0, 60.0),
+        }
+    }
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            projectile_subdivisions: 2,
+            block_sim_mode: block_sim_mode_from_env(),
+            render_distance: 150.0,
+            input_send_rate: 60.0,
+            network_log_path: std::env::var_os("NETWORK_LOG_PATH").map(PathBuf::from),
+            aim_assist_strength: 0.3,
+            aim_assist_cone: 8.0,
+            effect_budget: 48,
+            materials: MaterialPresets::default(),
+            camera_smoothing: 0.0,
+            look_deadzone: 0.5,
+            look_smoothing: 0.0,
+            player_model: std::env::var("PLAYER_MODEL").ok(),
+            // The capsule is centered on the player; Bevy's forward is -Z
+            player_model_transform: Transform::from_xyz(0.0, -1.0, 0.0)
+                .with_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+            floating_origin_threshold: std::env::var("FLOATING_ORIGIN_THRESHOLD")
+                .ok()
+                .and_then(|threshold| threshold.parse::<f32>().ok())
+                .filter(|threshold| threshold.is_finite() && *threshold > 0.0),
+            pause_on_focus_loss: PauseOnFocusLoss::Input,
+            cull_distance: Some(150.0),
+            reconnect_attempts: 3,
+            physics_interpolation: true,
+            observer: observer_from_env(),
+            camera_bounds: Some(CameraBounds::default()),
+            radar: RadarConfig {
+                enabled: false,
+                range: 50.0,
+            },
+            trajectory_preview: false,
+        }
+    }
+}
+
+/// What the client stops doing while its window is unfocused. Network
+/// messages are always processed so the connection doesn't time out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseOnFocusLoss {
+    Off,
+    /// Ignore held keys and clicks, and release the cursor so the flycam stops.
+    Input,
+    /// Also stop rendering the world to save the GPU.
+    InputAndRender,
+}
+
+/// Whether the primary window has focus, kept by `window_focus_system`.
+#[derive(Debug, Resource)]
+struct WindowFocus(bool);
+
+impl Default for WindowFocus {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Open CSV writer for `ClientSettings::network_log_path`.
+#[derive(Resource)]
+struct NetworkLog {
+    writer: Option<BufWriter<File>>,
+    timer: Timer,
+}
+
+impl Default for NetworkLog {
+    fn default() -> Self {
+        Self {
+            writer: None,
+            timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+        }
+    }
+}
+
+/// Paces `client_send_input` at `ClientSettings::input_send_rate`.
+#[derive(Resource)]
+struct InputSendTimer(Timer);
+
+impl InputSendTimer {
+    fn from_settings(settings: &ClientSettings) -> Self {
+        Self(Timer::from_seconds(
+            1.0 / settings.input_send_rate,
+            TimerMode::Repeating,
+        ))
+    }
+}
+
+/// Shifts the world back under the camera when it strays far from the
+/// origin, where f32 precision starts to jitter physics and rendering. The
+/// server and every network message keep absolute positions; local
+/// transforms are those minus `offset`.
+#[derive(Debug, Resource)]
+struct FloatingOrigin {
+    threshold: Option<f32>,
+    offset: Vec3,
+}
+
+impl FloatingOrigin {
+    fn from_settings(settings: &ClientSettings) -> Self {
+        Self {
+            threshold: settings.floating_origin_threshold,
+            offset: Vec3::ZERO,
+        }
+    }
+
+    fn to_local(&self, world: Vec3) -> Vec3 {
+        world - self.offset
+    }
+
+    fn to_world(&self, local: Vec3) -> Vec3 {
+        local + self.offset
+    }
+}
+
+/// Radius of the shared projectile mesh. Spawned projectiles are scaled to the
+/// radius the server sends.
+const PROJECTILE_MESH_RADIUS: f32 = 0.1;
+
+/// Sphere mesh shared by projectiles, stress test balls and heal pulses,
+/// scaled to size, and the balls' material.
+#[derive(Default, Resource)]
+struct ProjectileAssets {
+    mesh: Handle<Mesh>,
+    ball_material: Handle<StandardMaterial>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct ProjectileVisual {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// How each projectile kind is drawn, filled in at startup. Adding a kind
+/// only needs an entry here; kinds without one are drawn with `fallback`.
+#[derive(Debug, Default, Resource)]
+struct ProjectileVisuals {
+    kinds: HashMap<ProjectileKind, ProjectileVisual>,
+    fallback: ProjectileVisual,
+}
+
+impl ProjectileVisuals {
+    fn get(&self, kind: ProjectileKind) -> &ProjectileVisual {
+        self.kinds.get(&kind).unwrap_or(&self.fallback)
+    }
+}
+
+/// Scene drawn for players, or `None` to draw the capsule.
+#[derive(Default, Resource)]
+struct PlayerModel(Option<Handle<Scene>>);
+
+/// A player drawn with `PlayerModel`. It carries a material like capsule
+/// players do, which isn't drawn but holds the color tints set, and
+/// `tint_player_models_system` copies onto the model.
+#[derive(Component)]
+struct PlayerModelRoot;
+
+/// A model mesh's own copy of its material, and the color it was loaded with.
+#[derive(Component)]
+struct ModelMaterial {
+    original: Color,
+}
+
+/// Assets `client_sync_players` spawns entities with.
+#[derive(SystemParam)]
+struct SpawnAssets<'w> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    projectiles: Res<'w, ProjectileAssets>,
+    projectile_visuals: Res<'w, ProjectileVisuals>,
+    player_model: Res<'w, PlayerModel>,
+    decals: Res<'w, Decals>,
+}
+
+/// This client's connection and the camera its position is sent from.
+#[derive(SystemParam)]
+struct LocalPlayer<'w, 's> {
+    transport: Res<'w, NetcodeClientTransport>,
+    camera: Query<'w, 's, &'static mut Transform, With<FlyCam>>,
+    origin: Res<'w, FloatingOrigin>,
+    knock_up: ResMut<'w, KnockUpArc>,
+}
+
+/// Lifts the camera along the arc of a knock-up the server reported for this
+/// client, ahead of the server bringing the player back down.
+#[derive(Debug, Default, Resource)]
+struct KnockUpArc {
+    velocity: f32,
+    /// Height the camera has been lifted so far.
+    offset: f32,
+}
+
+/// A player entity was created from `PlayerCreate`.
+pub struct PlayerJoinedEvent {
+    pub id: u64,
+    pub entity: Entity,
+    /// Whether this is the player this client controls.
+    pub local: bool,
+}
+
+/// Sent from `PlayerRemove`, before the player's entity is despawned.
+pub struct PlayerLeftEvent {
+    pub id: u64,
+}
+
+/// Sent from `PlayerKilled`.
+pub struct PlayerKilledEvent {
+    pub id: u64,
+    pub killer: Option<u64>,
+}
+
+pub struct SolanaBlockSpawnedEvent {
+    pub entity: Entity,
+    pub slot: u64,
+}
+
+/// Lifecycle events `client_sync_players` emits as it applies server
+/// messages, so other systems can react without living in it.
+#[derive(SystemParam)]
+struct LifecycleEvents<'w> {
+    player_joined: EventWriter<'w, PlayerJoinedEvent>,
+    player_left: EventWriter<'w, PlayerLeftEvent>,
+    player_killed: EventWriter<'w, PlayerKilledEvent>,
+    block_spawned: EventWriter<'w, SolanaBlockSpawnedEvent>,
+}
+
+/// Simulation settings the server can override on connect.
+#[derive(SystemParam)]
+struct PhysicsSettings<'w> {
+    world: ResMut<'w, WorldConfig>,
+    rapier: ResMut<'w, RapierConfiguration>,
+    lockstep: ResMut<'w, ClientLockstep>,
+    projectile: ResMut<'w, ProjectileConfig>,
+    interpolation_delay: ResMut<'w, InterpolationDelay>,
+}
+
+/// This client's part in a lockstep session, when the server runs one.
+#[derive(Debug, Default, Resource)]
+struct ClientLockstep {
+    /// Tick the next input is sent for. `None` when not in lockstep.
+    next_input_tick: Option<u64>,
+    /// Ticks the server confirmed, waiting for `lockstep_apply_system`.
+    confirmed: VecDeque<(u64, Vec<(u64, PlayerInput)>)>,
+    state: LockstepState,
+    /// Our own id in `state`.
+    client_id: u64,
+}
+
+impl ClientLockstep {
+    /// Starts over from the tick and positions the server told us to join at.
+    fn join(&mut self, tick: Option<u64>, positions: &[(u64, [f32; 3])], client_id: u64) {
+        *self = ClientLockstep {
+            next_input_tick: tick,
+            state: tick
+                .map(|tick| LockstepState::resume(tick, positions))
+                .unwrap_or_default(),
+            client_id,
+            ..default()
+        };
+    }
+}
+
+impl PhysicsSettings<'_> {
+    /// Adopts the server's physics timestep, warning when it differs from ours.
+    fn match_server_timestep(&mut self, physics_timestep: f32) {
+        if (self.world.physics_timestep - physics_timestep).abs() <= f32::EPSILON {
+            return;
+        }
+        println!(
+            "Warning: server physics timestep {} differs from the client's {}, using the server's",
+            physics_timestep, self.world.physics_timestep
+        );
+        self.world.physics_timestep = physics_timestep;
+        self.rapier.timestep_mode = self.world.rapier_configuration().timestep_mode;
+    }
+}
+
+/// Whether the handles gameplay spawns rely on have finished loading.
+#[derive(States, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+enum AssetState {
+    #[default]
+    Loading,
+    Ready,
+}
+
+/// Handles registered by the startup asset systems. Gameplay spawns wait in
+/// `AssetState::Loading` until every one of them is available.
+#[derive(Default, Resource)]
+struct RequiredAssets {
+    meshes: Vec<Handle<Mesh>>,
+    materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl RequiredAssets {
+    fn mesh(&mut self, handle: Handle<Mesh>) -> Handle<Mesh> {
+        self.meshes.push(handle.clone());
+        handle
+    }
+
+    fn material(&mut self, handle: Handle<StandardMaterial>) -> Handle<StandardMaterial> {
+        self.materials.push(handle.clone());
+        handle
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+struct ChatLog {
+    lines: Vec<String>,
+    input: String,
+}
+
+impl ChatLog {
+    fn push(&mut self, line: String) {
+        self.lines.push(line);
+        if self.lines.len() > MAX_CHAT_LINES {
+            self.lines.remove(0);
+        }
+    }
+}
+
+#[derive(States, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+enum AppState {
+    #[default]
+    MainMenu,
+    Connecting,
+    InGame,
+    Paused,
+    /// Final standings of a finished match.
+    Results,
+}
+
+/// Where the current session connected, so a dropped connection can be
+/// resumed under the same client id.
+#[derive(Debug, Resource)]
+struct Session {
+    server_addr: SocketAddr,
+    client_id: u64,
+}
+
+/// Automatic reconnection after the connection drops mid-game. The world
+/// stays up while reconnecting; server entities that outlive the drop keep
+/// their ids, so only what changed meanwhile is spawned or despawned.
+#[derive(Debug, Default, Resource)]
+struct Reconnect {
+    active: bool,
+    attempts_left: u32,
+    /// Set on reconnecting until `client_sync_snapshots` has reconciled the
+    /// world against the next snapshot.
+    resync_pending: bool,
+}
+
+/// Server address typed into the main menu and the last connection error.
+#[derive(Debug, Resource)]
+struct MainMenuForm {
+    address: String,
+    error: Option<String>,
+}
+
+impl Default for MainMenuForm {
+    fn default() -> Self {
+        Self {
+            address: get_server_addr(),
+            error: None,
+        }
+    }
+}
+
+/// Resolves `host:port`, accepting hostnames as well as IP addresses.
+fn parse_server_addr(address: &str) -> Result<SocketAddr, String> {
+    address
+        .trim()
+        .to_socket_addrs()
+        .map_err(|e| format!("Invalid server address '{}': {}", address.trim(), e))?
+        .next()
+        .ok_or_else(|| format!("'{}' did not resolve to an address", address.trim()))
+}
+
+fn observer_from_env() -> bool {
+    std::env::var("OBSERVER").is_ok_and(|observer| observer == "1")
+}
+
+fn block_sim_mode_from_env() -> BlockSimMode {
+    match std::env::var("BLOCK_SIM_MODE").as_deref() {
+        Ok("predicted") => BlockSimMode::ClientPredicted,
+        _ => BlockSimMode::ServerAuthoritative,
+    }
+}
+
+/// Connects as `PLAYER_NAME`, signed with the `SOLANA_KEYPAIR` wallet when set.
+/// With `OBSERVER=1` the client only watches and gets no player.
+fn new_player_client(
+    server_addr: SocketAddr,
+    client_id: u64,
+) -> Result<(RenetClient, NetcodeClientTransport), Box<dyn Error>> {
+    let name = std::env::var("PLAYER_NAME").unwrap_or_else(|_| format!("player-{}", client_id));
+    let mut user_data = encode_player_name(&name);
+    if let Ok(keypair_path) = std::env::var("SOLANA_KEYPAIR") {
+        let keypair = read_keypair_file(&keypair_path)
+            .map_err(|e| format!("Failed to read wallet keypair '{}': {}", keypair_path, e))?;
+        let signed_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        sign_wallet_identity(&mut user_data, &keypair, client_id, signed_at);
+    }
+    if observer_from_env() {
+        mark_observer(&mut user_data);
+    }
+    new_renet_client(server_addr, client_id, user_data)
+}
+
+fn main() {
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins);
+    app.add_plugin(RenetClientPlugin);
+    app.add_plugin(NetcodeClientPlugin);
+    app.add_plugin(LookTransformPlugin);
+    app.add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
+
+    let world_config = WorldConfig::default();
+    app.insert_resource(world_config.rapier_configuration());
+    app.insert_resource(world_config);
+    app.insert_resource(ProjectileConfig::default());
+    app.insert_resource(WorldBounds::default());
+    app.insert_resource(FloorGrid::default());
+    app.insert_resource(CameraConfig::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(EguiPlugin);
+    app.add_plugin(NoCameraPlayerPlugin);
+    // Escape opens the pause menu, which grabs and releases the cursor itself.
+    app.insert_resource(KeyBindings {
+        toggle_grab_cursor: KeyCode::LAlt,
+        ..default()
+    });
+
+    app.add_state::<AppState>();
+    app.add_state::<AssetState>();
+    app.insert_resource(RequiredAssets::default());
+
+    app.add_event::<PlayerCommand>();
+    app.add_event::<PlayerJoinedEvent>();
+    app.add_event::<PlayerLeftEvent>();
+    app.add_event::<PlayerKilledEvent>();
+    app.add_event::<SolanaBlockSpawnedEvent>();
+    app.insert_resource(PlayerInput::default());
+
+    app.insert_resource(ClientLobby::default());
+    app.insert_resource(MainMenuForm::default());
+    app.insert_resource(Reconnect::default());
+    app.init_resource::<KnockUpArc>();
+    app.init_resource::<ClientLockstep>();
+
+    app.insert_resource(NetworkMapping::default());
+    app.insert_resource(DeserializeFailures::default());
+    app.insert_resource(ChunkAssembler::default());
+    app.insert_resource(PendingServerMessages::default());
+    app.insert_resource(ChatLog::default());
+    app.insert_resource(LeaderboardView::default());
+    app.insert_resource(PlayerListView::default());
+    app.insert_resource(WorldMarkers::default());
+    app.insert_resource(MatchResultsView::default());
+    app.insert_resource(AnnouncementBanner::default());
+    app.insert_resource(Killcam::default());
+    app.insert_resource(Placeholders::default());
+    let settings = ClientSettings::default();
+    app.insert_resource(InputSendTimer::from_settings(&settings));
+    app.insert_resource(EffectBudget::from_settings(&settings));
+    app.insert_resource(FloatingOrigin::from_settings(&settings));
+    app.insert_resource(settings);
+    app.insert_resource(ClearColor(SKY_COLOR));
+    app.insert_resource(ProjectileAssets::default());
+    app.insert_resource(ProjectileVisuals::default());
+    app.insert_resource(PlayerModel::default());
+    app.insert_resource(Decals::default());
+    app.insert_resource(ServerClock::default());
+    app.init_resource::<InterpolationDelay>();
+    app.insert_resource(NetworkLog::default());
+    app.insert_resource(WindowFocus::default());
+    app.insert_resource(CapturePoints::default());
+
+    app.insert_resource(MovementSettings {
+        sensitivity: 0.00015,
+        speed: 12.0,
+    });
+
+    app.add_systems(
+        (
+            client_sync_players,
+            client_sync_snapshots.after(client_sync_players),
+            interpolate_networked_entities.after(client_sync_snapshots),
+            move_new_projectiles_system,
+            disconnect_on_deserialize_failures.after(client_sync_snapshots),
+            client_send_input,
+            lockstep_apply_system.after(client_sync_players),
+            client_send_player_commands,
+        )
+            .distributive_run_if(bevy_renet::transport::client_connected)
+            .distributive_run_if(in_state(AssetState::Ready)),
+    );
+    app.add_systems(
+        (
+            client_attack_input_system,
+            client_emote_input_system,
+            emote_radial_menu_system,
+            trajectory_preview_system,
+        )
+            .distributive_run_if(in_state(AppState::InGame))
+            .distributive_run_if(not_replaying)
+            .distributive_run_if(input_focused),
+    );
+    app.add_system(culling_system.before(interpolate_networked_entities));
+    app.add_system(
+        killcam_trigger_system
+            .after(client_sync_players)
+            .run_if(in_state(AppState::InGame))
+            .run_if(not_replaying),
+    );
+    app.add_system(killcam_playback_system.after(interpolate_networked_entities));
+    app.add_system(floating_origin_system.after(interpolate_networked_entities));
+    app.add_system(world_markers_ui_system);
+    app.add_system(announcement_ui_system);
+    app.add_system(radar_ui_system.run_if(in_state(AppState::InGame)));
+    app.add_system(restore_camera_target.in_base_set(CoreSet::PreUpdate));
+    app.add_system(filter_look_input_system.in_base_set(CoreSet::PreUpdate));
+    app.add_system(
+        camera_bounds_system
+            .in_base_set(CoreSet::PostUpdate)
+            .before(smooth_camera_system),
+    );
+    app.add_system(
+        smooth_camera_system
+            .in_base_set(CoreSet::PostUpdate)
+            .before(TransformSystem::TransformPropagate),
+    );
+    #[cfg(debug_assertions)]
+    app.add_systems((
+        interpolation_debug_system,
+        prediction_divergence_graph_system.run_if(in_state(AppState::InGame)),
+    ));
+
+    app.add_systems(
+        (connecting_system, connecting_ui_system)
+            .distributive_run_if(in_state(AppState::Connecting)),
+    );
+    app.add_system(pause_input_system);
+    app.add_system(window_focus_system);
+    app.add_system(pause_menu_system.run_if(in_state(AppState::Paused)));
+    app.add_system(main_menu_system.run_if(in_state(AppState::MainMenu)));
+    app.add_system(show_match_results_system);
+    app.add_system(match_results_ui_system.run_if(in_state(AppState::Results)));
+    app.add_system(return_to_menu_on_disconnect_system);
+    app.add_system(grab_cursor.in_schedule(OnEnter(AppState::InGame)));
+    // The flycam only moves while the cursor is grabbed, so this also suppresses movement.
+    app.add_system(release_cursor.run_if(not(in_state(AppState::InGame))));
+    app.add_system(teardown_connection.in_schedule(OnEnter(AppState::MainMenu)));
+
+    app.insert_resource(RenetClientVisualizer::<200>::new(
+        RenetVisualizerStyle::default(),
+    ));
+    app.add_system(update_visulizer_system.run_if(resource_exists::<RenetClient>()));
+    app.add_startup_system(open_network_log);
+    app.add_system(network_log_system.run_if(resource_exists::<RenetClient>()));
+    app.add_system(close_network_log_on_exit);
+    app.add_system(chat_ui_system);
+    app.add_system(leaderboard_ui_system);
+    app.add_system(player_list_ui_system);
+
+    app.add_system(setup_level.in_schedule(OnEnter(AssetState::Ready)));
+    app.add_startup_system(configure_solver_system);
+    app.add_startup_system(setup_camera_fps);
+    app.add_startup_system(setup_placeholder_assets);
+    app.add_startup_system(setup_projectile_assets);
+    app.add_startup_system(setup_player_model);
+    app.add_startup_system(setup_decal_assets);
+    app.add_system(check_assets_ready_system.run_if(in_state(AssetState::Loading)));
+    app.insert_resource(CameraCollision::default());
+    app.add_system(camera_collision_system);
+    app.add_system(despawn_animation_system);
+    app.add_system(decal_fade_system);
+    app.add_system(heal_pulse_system);
+    app.add_system(cast_flash_system);
+    app.add_system(knock_up_arc_system);
+    app.add_system(capture_point_render_system);
+    app.add_system(connection_error_system);
+    app.add_system(log_lifecycle_events_system);
+    app.add_system(tint_player_models_system);
+
+    app.run();
+}
+
+/// Shows the color set on a `PlayerModelRoot` on its model. `PLAYER_COLOR`
+/// leaves the model as it was loaded.
+fn tint_player_models_system(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    roots: Query<(Entity, &Handle<StandardMaterial>), With<PlayerModelRoot>>,
+    children: Query<&Children>,
+    mut model_meshes: Query<
+        (&mut Handle<StandardMaterial>, Option<&ModelMaterial>),
+        Without<PlayerModelRoot>,
+    >,
+) {
+    for (root, tint) in roots.iter() {
+        let Some(tint) = materials.get(tint).map(|material| material.base_color) else {
+            continue;
+        };
+        for descendant in children.iter_descendants(root) {
+            let Ok((mut handle, model_material)) = model_meshes.get_mut(descendant) else {
+                continue;
+            };
+            let original = match model_material {
+                Some(model_material) => model_material.original,
+                None => {
+                    // Every instance of a scene shares its materials, so each
+                    // player gets copies it can tint on its own
+                    let Some(material) = materials.get(&handle).cloned() else {
+                        continue;
+                    };
+                    let original = material.base_color;
+                    *handle = materials.add(material);
+                    commands
+                        .entity(descendant)
+                        .insert(ModelMaterial { original });
+                    original
+                }
+            };
+            let color = if tint == PLAYER_COLOR { original } else { tint };
+            if materials
+                .get(&handle)
+                .is_some_and(|material| material.base_color != color)
+            {
+                materials.get_mut(&handle).unwrap().base_color = color;
+            }
+        }
+    }
+}
+
+fn log_lifecycle_events_system(
+    mut player_joined: EventReader<PlayerJoinedEvent>,
+    mut player_left: EventReader<PlayerLeftEvent>,
+    mut block_spawned: EventReader<SolanaBlockSpawnedEvent>,
+) {
+    for event in player_joined.iter() {
+        let suffix = if event.local { " (you)" } else { "" };
+        println!("Player {} connected{}.", event.id, suffix);
+    }
+    for event in player_left.iter() {
+        println!("Player {} disconnected.", event.id);
+    }
+    for event in block_spawned.iter() {
+        println!("Solana Slot {} spawned.", event.slot);
+    }
+}
+
+/// Transport errors drop us back to the main menu, which shows the error.
+fn connection_error_system(
+    mut renet_error: EventReader<NetcodeTransportError>,
+    mut form: ResMut<MainMenuForm>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for e in renet_error.iter() {
+        println!("Connection error: {}", e);
+        form.error = Some(e.to_string());
+        if state.0 != AppState::Results {
+            next_state.set(AppState::MainMenu);
+        }
+    }
+}
+
+fn connecting_system(
+    transport: Option<Res<NetcodeClientTransport>>,
+    mut reconnect: ResMut<Reconnect>,
+    mut clock: ResMut<ServerClock>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if transport.is_some_and(|transport| transport.is_connected()) {
+        if reconnect.active {
+            println!("Reconnected, resyncing the world.");
+            reconnect.active = false;
+            reconnect.resync_pending = true;
+            // The server may have restarted and reset its ticks
+            *clock = ServerClock::default();
+        }
+        next_state.set(AppState::InGame);
+    }
+}
+
+/// Escape toggles between playing and the pause menu.
+fn pause_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    match state.0 {
+        AppState::InGame => next_state.set(AppState::Paused),
+        AppState::Paused => next_state.set(AppState::InGame),
+        _ => {}
+    }
+}
+
+fn pause_menu_system(
+    mut egui_contexts: EguiContexts,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut movement_settings: ResMut<MovementSettings>,
+    mut settings: ResMut<ClientSettings>,
+    mut show_settings: Local<bool>,
+) {
+    egui::Window::new("Paused")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            if ui.button("Resume").clicked() {
+                next_state.set(AppState::InGame);
+            }
+            if ui.button("Settings").clicked() {
+                *show_settings = !*show_settings;
+            }
+            if *show_settings {
+                ui.add(
+                    egui::Slider::new(&mut movement_settings.sensitivity, 0.00005..=0.0005)
+                        .text("Mouse sensitivity"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut movement_settings.speed, 1.0..=30.0).text("Move speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut settings.camera_smoothing, 0.0..=MAX_CAMERA_SMOOTHING)
+                        .text("Camera smoothing"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut settings.look_deadzone, 0.0..=5.0).text("Look deadzone"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut settings.look_smoothing, 0.0..=MAX_LOOK_SMOOTHING)
+                        .text("Look smoothing"),
+                );
+                ui.checkbox(&mut settings.radar.enabled, "Radar");
+                ui.add(
+                    egui::Slider::new(&mut settings.radar.range, 10.0..=150.0).text("Radar range"),
+                );
+                ui.checkbox(&mut settings.trajectory_preview, "Trajectory preview");
+            }
+            if ui.button("Disconnect").clicked() {
+                next_state.set(AppState::MainMenu);
+            }
+        });
+}
+
+fn main_menu_system(
+    mut commands: Commands,
+    mut egui_contexts: EguiContexts,
+    mut form: ResMut<MainMenuForm>,
+    mut next_state: ResMut<NextState<AppState>>,
+    asset_state: Res<State<AssetState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let assets_ready = asset_state.0 == AssetState::Ready;
+    egui::Window::new("Main Menu")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Server");
+                ui.text_edit_singleline(&mut form.address);
+            });
+            if let Some(error) = &form.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            if !assets_ready {
+                ui.label("Loading assets...");
+            }
+            if ui
+                .add_enabled(assets_ready, egui::Button::new("Connect"))
+                .clicked()
+            {
+                // Random rather than time based so clients started together don't share an id
+                let client_id = fastrand::u64(..);
+                let connection = parse_server_addr(&form.address).and_then(|server_addr| {
+                    new_player_client(server_addr, client_id)
+                        .map(|connection| (server_addr, connection))
+                        .map_err(|e| e.to_string())
+                });
+                match connection {
+                    Ok((server_addr, (client, transport))) => {
+                        commands.insert_resource(client);
+                        commands.insert_resource(transport);
+                        commands.insert_resource(Session {
+                            server_addr,
+                            client_id,
+                        });
+                        form.error = None;
+                        next_state.set(AppState::Connecting);
+                    }
+                    Err(error) => form.error = Some(error),
+                }
+            }
+            if ui.button("Quit").clicked() {
+                exit.send(AppExit);
+            }
+        });
+}
+
+fn connecting_ui_system(
+    mut egui_contexts: EguiContexts,
+    form: Res<MainMenuForm>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    egui::Window::new("Connecting")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.label(format!("Connecting to {}...", form.address.trim()));
+            if ui.button("Cancel").clicked() {
+                next_state.set(AppState::MainMenu);
+            }
+        });
+}
+
+/// Switches to the results screen when a match ends mid-game.
+fn show_match_results_system(
+    match_results: Res<MatchResultsView>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if match_results.0.is_some() && matches!(state.0, AppState::InGame | AppState::Paused) {
+        next_state.set(AppState::Results);
+    }
+}
+
+fn match_results_ui_system(
+    mut egui_contexts: EguiContexts,
+    mut match_results: ResMut<MatchResultsView>,
+    client: Option<Res<RenetClient>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let connected = client.is_some_and(|client| !client.is_disconnected());
+    egui::CentralPanel::default().show(egui_contexts.ctx_mut(), |ui| {
+        ui.vertical_centered(|ui| {
+            ui.heading("Match over");
+            if let Some((standings, winners)) = &match_results.0 {
+                let names: Vec<&str> = standings
+                    .iter()
+                    .filter(|player| winners.contains(&player.id))
+                    .map(|player| player.name.as_str())
+                    .collect();
+                if !names.is_empty() {
+                    ui.label(format!("Winner: {}", names.join(", ")));
+                }
+            }
+            egui::Grid::new("match_results")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (rank, player) in match_results
+                        .0
+                        .iter()
+                        .flat_map(|(standings, _)| standings)
+                        .enumerate()
+                    {
+                        ui.label(format!("{}.", rank + 1));
+                        ui.label(&player.name);
+                        ui.label(player.score.to_string());
+                        ui.label(if player.is_bot { "bot" } else { "" });
+                        ui.end_row();
+                    }
+                });
+
+            if connected && ui.button("Keep playing").clicked() {
+                match_results.0 = None;
+                next_state.set(AppState::InGame);
+            }
+            if ui.button("Return to menu").clicked() {
+                match_results.0 = None;
+                next_state.set(AppState::MainMenu);
+            }
+        });
+    });
+}
+
+/// Falls back to the main menu when the server drops or refuses us.
+#[allow(clippy::too_many_arguments)]
+fn return_to_menu_on_disconnect_system(
+    mut commands: Commands,
+    client: Option<Res<RenetClient>>,
+    state: Res<State<AppState>>,
+    session: Option<Res<Session>>,
+    settings: Res<ClientSettings>,
+    mut reconnect: ResMut<Reconnect>,
+    mut form: ResMut<MainMenuForm>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    // Results stay up after a disconnect, they no longer need the server
+    if matches!(state.0, AppState::MainMenu | AppState::Results) {
+        return;
+    }
+    let Some(client) = client else {
+        next_state.set(AppState::MainMenu);
+        return;
+    };
+    if client.is_disconnected() {
+        // Connections lost mid-game are retried with the same client id,
+        // keeping the world on screen until the server answers
+        if matches!(state.0, AppState::InGame | AppState::Paused) && !reconnect.active {
+            reconnect.active = true;
+            reconnect.attempts_left = settings.reconnect_attempts;
+        }
+        if reconnect.active && reconnect.attempts_left > 0 {
+            if let Some(session) = session {
+                reconnect.attempts_left -= 1;
+                match new_player_client(session.server_addr, session.client_id) {
+                    Ok((client, transport)) => {
+                        println!(
+                            "Connection lost, reconnecting ({} attempts left).",
+                            reconnect.attempts_left
+                        );
+                        commands.insert_resource(client);
+                        commands.insert_resource(transport);
+                        next_state.set(AppState::Connecting);
+                        return;
+                    }
+                    Err(e) => println!("Failed to reconnect: {}", e),
+                }
+            }
+        }
+
+        if let Some(reason) = client.disconnect_reason() {
+            form.error = Some(format!("Disconnected: {}", reason));
+        }
+        next_state.set(AppState::MainMenu);
+    }
+}
+
+/// Disconnects from the server and clears everything it spawned.
+#[allow(clippy::too_many_arguments)]
+fn teardown_connection(
+    mut commands: Commands,
+    client: Option<ResMut<RenetClient>>,
+    transport: Option<ResMut<NetcodeClientTransport>>,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut placeholders: ResMut<Placeholders>,
+    mut clock: ResMut<ServerClock>,
+    mut decoder: ServerMessageDecoder,
+    mut markers: ResMut<WorldMarkers>,
+    mut match_results: ResMut<MatchResultsView>,
+    mut killcam: ResMut<Killcam>,
+    mut capture_points: ResMut<CapturePoints>,
+    mut reconnect: ResMut<Reconnect>,
+) {
+    if let Some(mut client) = client {
+        client.disconnect();
+        commands.remove_resource::<RenetClient>();
+    }
+    if let Some(mut transport) = transport {
+        transport.disconnect();
+        commands.remove_resource::<NetcodeClientTransport>();
+    }
+
+    for (_, entity) in network_mapping.0.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (_, entity) in placeholders.entities.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+    placeholders.recently_despawned.clear();
+    lobby.players.clear();
+    lobby.objective_holder = None;
+    *clock = ServerClock::default();
+    decoder.reset();
+    markers.0.clear();
+    match_results.0 = None;
+    *killcam = Killcam::default();
+    capture_points.states.clear();
+    *reconnect = Reconnect::default();
+    commands.remove_resource::<Session>();
+    commands.insert_resource(KnockUpArc::default());
+    commands.insert_resource(ClientLockstep::default());
+}
+
+fn grab_cursor(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        window.cursor.grab_mode = CursorGrabMode::Confined;
+        window.cursor.visible = false;
+    }
+}
+
+/// Applies `ClientSettings::pause_on_focus_loss` when the primary window gains
+/// or loses focus. Only input and rendering pause; networking keeps running.
+#[allow(clippy::too_many_arguments)]
+fn window_focus_system(
+    mut focus_events: EventReader<WindowFocused>,
+    mut focus: ResMut<WindowFocus>,
+    settings: Res<ClientSettings>,
+    state: Res<State<AppState>>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut mouse_input: ResMut<Input<MouseButton>>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut cameras: Query<&mut Camera>,
+) {
+    let Some(focused) = focus_events
+        .iter()
+        .filter(|event| windows.contains(event.window))
+        .last()
+        .map(|event| event.focused)
+    else {
+        return;
+    };
+    focus.0 = focused;
+    if settings.pause_on_focus_loss == PauseOnFocusLoss::Off {
+        return;
+    }
+
+    if let Ok(mut window) = windows.get_single_mut() {
+        if !focused {
+            // Keys held when focus left never see their release
+            keyboard_input.reset_all();
+            mouse_input.reset_all();
+            window.cursor.grab_mode = CursorGrabMode::None;
+            window.cursor.visible = true;
+        } else if state.0 == AppState::InGame {
+            window.cursor.grab_mode = CursorGrabMode::Confined;
+            window.cursor.visible = false;
+        }
+    }
+    if settings.pause_on_focus_loss == PauseOnFocusLoss::InputAndRender {
+        for mut camera in cameras.iter_mut() {
+            camera.is_active = focused;
+        }
+    }
+}
+
+fn release_cursor(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        if window.cursor.grab_mode == CursorGrabMode::None {
+            return;
+        }
+        window.cursor.grab_mode = CursorGrabMode::None;
+        window.cursor.visible = true;
+    }
+}
+
+fn update_visulizer_system(
+    mut egui_contexts: EguiContexts,
+    mut visualizer: ResMut<RenetClientVisualizer<200>>,
+    client: Res<RenetClient>,
+    mut show_visualizer: Local<bool>,
+    keyboard_input: Res<Input<KeyCode>>,
+) {
+    visualizer.add_network_info(client.network_info());
+    if keyboard_input.just_pressed(KeyCode::F1) {
+        *show_visualizer = !*show_visualizer;
+    }
+    if *show_visualizer {
+        visualizer.show_window(egui_contexts.ctx_mut());
+    }
+}
+
+fn open_network_log(settings: Res<ClientSettings>, mut log: ResMut<NetworkLog>) {
+    let Some(path) = &settings.network_log_path else {
+        return;
+    };
+    let file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Failed to open network log {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let is_empty = file.metadata().map_or(true, |metadata| metadata.len() == 0);
+    let mut writer = BufWriter::new(file);
+    if is_empty {
+        let _ = writeln!(
+            writer,
+            "unix_time,rtt_ms,packet_loss,sent_kbps,received_kbps"
+        );
+    }
+    println!("Logging network stats to {}", path.display());
+    log.writer = Some(writer);
+}
+
+/// Appends a row of `RenetClient::network_info` to the network log each second.
+fn network_log_system(time: Res<Time>, client: Res<RenetClient>, mut log: ResMut<NetworkLog>) {
+    if !log.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Some(writer) = log.writer.as_mut() else {
+        return;
+    };
+    let info = client.network_info();
+    let unix_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if let Err(e) = writeln!(
+        writer,
+        "{},{:.1},{:.4},{:.2},{:.2}",
+        unix_time,
+        info.rtt,
+        info.packet_loss,
+        info.bytes_sent_per_second * 8. / 1000.,
+        info.bytes_received_per_second * 8. / 1000.
+    ) {
+        println!("Failed to write network log, disabling it: {}", e);
+        log.writer = None;
+    }
+}
+
+fn close_network_log_on_exit(mut exit: EventReader<AppExit>, mut log: ResMut<NetworkLog>) {
+    if exit.iter().next().is_none() {
+        return;
+    }
+    if let Some(mut writer) = log.writer.take() {
+        if let Err(e) = writer.flush() {
+            println!("Failed to flush network log: {}", e);
+        }
+    }
+}
+
+/// Draws the latest announcement large and centered near the top of the
+/// screen, fading it out before it disappears.
+fn announcement_ui_system(
+    mut egui_contexts: EguiContexts,
+    mut banner: ResMut<AnnouncementBanner>,
+    time: Res<Time>,
+) {
+    let Some((text, timer)) = &mut banner.0 else {
+        return;
+    };
+    if timer.tick(time.delta()).finished() {
+        banner.0 = None;
+        return;
+    }
+
+    let alpha = (timer.remaining_secs() / ANNOUNCEMENT_FADE_SECS).min(1.0);
+    let color = egui::Color32::from_rgba_unmultiplied(255, 215, 0, (alpha * 255.0) as u8);
+    egui::Area::new("announcement")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 80.0])
+        .interactable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.label(
+                egui::RichText::new(text.as_str())
+                    .size(32.0)
+                    .strong()
+                    .color(color),
+            );
+        });
+}
+
+/// Radius in pixels of the radar drawn around the crosshair.
+const RADAR_RADIUS: f32 = 90.0;
+
+/// Draws `ClientSettings::radar`: a ring around the screen center with a
+/// blip for every other player in range, placed by where they are relative
+/// to the camera's heading. Straight ahead is up.
+fn radar_ui_system(
+    mut egui_contexts: EguiContexts,
+    settings: Res<ClientSettings>,
+    lobby: Res<ClientLobby>,
+    camera_query: Query<&Transform, With<FlyCam>>,
+    players: Query<&GlobalTransform, Without<ControlledPlayer>>,
+) {
+    let radar = settings.radar;
+    if !radar.enabled || radar.range <= 0.0 {
+        return;
+    }
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let forward = camera_transform.forward();
+    let Some(forward) = Vec3::new(forward.x, 0.0, forward.z).try_normalize() else {
+        return;
+    };
+    let right = Vec3::new(-forward.z, 0.0, forward.x);
+
+    let ctx = egui_contexts.ctx_mut();
+    let center = ctx.screen_rect().center();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("radar"),
+    ));
+    let ring = egui::Stroke::new(1.0, egui::Color32::from_white_alpha(60));
+    painter.circle_stroke(center, RADAR_RADIUS, ring);
+    painter.circle_stroke(center, RADAR_RADIUS / 2.0, ring);
+
+    for player_info in lobby.players.values() {
+        let Ok(transform) = players.get(player_info.client_entity) else {
+            continue;
+        };
+        let offset = transform.translation() - camera_transform.translation;
+        let distance = Vec2::new(offset.x, offset.z).length();
+        if distance > radar.range {
+            continue;
+        }
+        let blip = center
+            + egui::vec2(offset.dot(right), -offset.dot(forward)) / radar.range * RADAR_RADIUS;
+        painter.circle_filled(blip, 4.0, egui::Color32::RED);
+        painter.text(
+            blip + egui::vec2(6.0, 0.0),
+            egui::Align2::LEFT_CENTER,
+            format!("{:.0}m", distance),
+            egui::FontId::proportional(11.0),
+            egui::Color32::from_white_alpha(180),
+        );
+    }
+}
+
+fn chat_ui_system(
+    mut egui_contexts: EguiContexts,
+    mut chat: ResMut<ChatLog>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    egui::Window::new("Chat")
+        .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &chat.lines {
+                        ui.label(line);
+                    }
+                });
+
+            let response = ui.text_edit_singleline(&mut chat.input);
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let message = std::mem::take(&mut chat.input);
+                if !message.trim().is_empty() {
+                    player_commands.send(PlayerCommand::Chat { message });
+                }
+            }
+        });
+}
+
+fn leaderboard_ui_system(
+    mut egui_contexts: EguiContexts,
+    leaderboard: Res<LeaderboardView>,
+    mut show_leaderboard: Local<bool>,
+    keyboard_input: Res<Input<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F2) {
+        *show_leaderboard = !*show_leaderboard;
+    }
+    if !*show_leaderboard {
+        return;
+    }
+
+    egui::Window::new("Leaderboard")
+        .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            egui::Grid::new("leaderboard").striped(true).show(ui, |ui| {
+                for (rank, entry) in leaderboard.0.iter().enumerate() {
+                    ui.label(format!("{}.", rank + 1));
+                    ui.label(&entry.name);
+                    ui.label(entry.score.to_string());
+                    ui.end_row();
+                }
+            });
+        });
+}
+
+fn player_list_ui_system(mut egui_contexts: EguiContexts, mut player_list: ResMut<PlayerListView>) {
+    let Some(players) = &player_list.0 else {
+        return;
+    };
+
+    let mut open = true;
+    egui::Window::new("Players")
+        .open(&mut open)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("players").striped(true).show(ui, |ui| {
+                        for player in players {
+                            ui.label(player.id.to_string());
+                            ui.label(&player.name);
+                            ui.label(player.score.to_string());
+                            ui.label(if player.is_bot { "bot" } else { "" });
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+
+    if !open {
+        player_list.0 = None;
+    }
+}
+
+/// Keeps `PlayerInput` up to date every frame but only sends the latest one at
+/// the configured rate. The flycam moves locally, so movement stays responsive
+/// however slowly inputs go out.
+fn client_send_input(
+    time: Res<Time>,
+    mut send_timer: ResMut<InputSendTimer>,
+    mut client: ResMut<RenetClient>,
+    mut player_input: ResMut<PlayerInput>,
+    mut lockstep: ResMut<ClientLockstep>,
+    origin: Res<FloatingOrigin>,
+    camera_query: Query<&Transform, With<FlyCam>>,
+) {
+    if let Ok(camera_transform) = camera_query.get_single() {
+        player_input.position = origin.to_world(camera_transform.translation).into();
+    }
+
+    if !send_timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    if let Some(tick) = lockstep.next_input_tick {
+        // Only held directions move lockstep players, so walk ours toward the camera
+        if let Some(position) = lockstep.state.positions.get(&lockstep.client_id) {
+            let camera = Vec3::from(player_input.position);
+            player_input.steer(*position, camera, LOCKSTEP_STEP);
+        }
+        let message = LockstepMessage::Input {
+            tick,
+            input: *player_input,
+        };
+        client.send_message(
+            ClientChannel::Lockstep,
+            bincode::serialize(&message).unwrap(),
+        );
+        lockstep.next_input_tick = Some(tick + 1);
+        return;
+    }
+    let input_message = bincode::serialize(&*player_input).unwrap();
+    client.send_message(ClientChannel::Input, input_message);
+}
+
+/// Applies confirmed lockstep ticks in order, reports the state hash after
+/// each so the server can spot desyncs, and places players where the
+/// simulation puts them.
+fn lockstep_apply_system(
+    mut client: ResMut<RenetClient>,
+    mut lockstep: ResMut<ClientLockstep>,
+    lobby: Res<ClientLobby>,
+    origin: Res<FloatingOrigin>,
+    mut transforms: Query<&mut Transform, Without<FlyCam>>,
+) {
+    if lockstep.confirmed.is_empty() {
+        return;
+    }
+    while let Some((tick, inputs)) = lockstep.confirmed.pop_front() {
+        lockstep.state.step(tick, &inputs);
+        let message = LockstepMessage::StateHash {
+            tick,
+            hash: lockstep.state.hash(),
+        };
+        client.send_message(
+            ClientChannel::Lockstep,
+            bincode::serialize(&message).unwrap(),
+        );
+    }
+
+    for (id, position) in &lockstep.state.positions {
+        let Some(player_info) = lobby.players.get(id) else {
+            continue;
+        };
+        if let Ok(mut transform) = transforms.get_mut(player_info.client_entity) {
+            transform.translation = origin.to_local(*position);
+        }
+    }
+}
+
+/// Distance in front of the camera used as the attack target.
+const CAST_DISTANCE: f32 = 10.0;
+
+/// Aim-assist only ever nudges; it can't pull the aim all the way onto a target.
+const MAX_AIM_ASSIST_STRENGTH: f32 = 0.5;
+
+/// Bends `aim` toward the enemy closest to it in angle, if one is inside the
+/// cone. The pull weakens toward the edge of the cone.
+fn aim_assist(
+    origin: Vec3,
+    aim: Vec3,
+    enemies: impl Iterator<Item = Vec3>,
+    settings: &ClientSettings,
+) -> Vec3 {
+    let strength = settings
+        .aim_assist_strength
+        .clamp(0.0, MAX_AIM_ASSIST_STRENGTH);
+    let cone = settings.aim_assist_cone.to_radians();
+    if strength <= 0.0 || cone <= 0.0 {
+        return aim;
+    }
+
+    let target = enemies
+        .filter_map(|position| (position - origin).try_normalize())
+        .map(|direction| (direction, aim.angle_between(direction)))
+        .filter(|(_, angle)| *angle < cone)
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    let Some((direction, angle)) = target else {
+        return aim;
+    };
+
+    let pull = strength * (1.0 - angle / cone);
+    aim.lerp(direction, pull).normalize()
+}
+
+/// Left click casts a basic attack toward where the camera looks, right click a spread
+/// and Q a seeker. E heals the ally nearest that point. The gamepad right trigger casts a basic
+/// attack with aim-assist.
+#[allow(clippy::too_many_arguments)]
+fn client_attack_input_system(
+    mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut egui_contexts: EguiContexts,
+    camera_query: Query<&Transform, With<FlyCam>>,
+    lobby: Res<ClientLobby>,
+    players: Query<&Transform, Without<ControlledPlayer>>,
+    settings: Res<ClientSettings>,
+    time: Res<Time>,
+    origin: Res<FloatingOrigin>,
+    mut charge_started: Local<Option<f32>>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    if egui_contexts.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+
+    // Basic attacks charge while the button is held and fire on release
+    let now = time.elapsed_seconds();
+    if mouse_input.just_pressed(MouseButton::Left) {
+        *charge_started = Some(now);
+    }
+
+    let cast_at =
+        origin.to_world(camera_transform.translation + camera_transform.forward() * CAST_DISTANCE);
+    if mouse_input.just_released(MouseButton::Left) {
+        if let Some(started) = charge_started.take() {
+            player_commands.send(PlayerCommand::BasicAttack {
+                cast_at,
+                charge: now - started,
+            });
+        }
+    } else if mouse_input.just_pressed(MouseButton::Right) {
+        player_commands.send(PlayerCommand::SpreadAttack { cast_at });
+    } else if keyboard_input.just_pressed(KeyCode::Q) {
+        player_commands.send(PlayerCommand::SeekingAttack { cast_at });
+    } else if keyboard_input.just_pressed(KeyCode::R) {
+        player_commands.send(PlayerCommand::PiercingAttack { cast_at });
+    } else if keyboard_input.just_pressed(KeyCode::E) {
+        player_commands.send(PlayerCommand::Heal {
+            target_position: cast_at,
+        });
+    } else if gamepads.iter().any(|gamepad| {
+        gamepad_buttons.just_pressed(GamepadButton::new(
+            gamepad,
+            GamepadButtonType::RightTrigger2,
+        ))
+    }) {
+        let enemies = lobby
+            .players
+            .values()
+            .filter_map(|info| players.get(info.client_entity).ok())
+            .map(|transform| transform.translation);
+        let aim = aim_assist(
+            camera_transform.translation,
+            camera_transform.forward(),
+            enemies,
+            &settings,
+        );
+        let cast_at = origin.to_world(camera_transform.translation + aim * CAST_DISTANCE);
+        player_commands.send(PlayerCommand::BasicAttack {
+            cast_at,
+            charge: 0.0,
+        });
+    }
+}
+
+/// Draws `ClientSettings::trajectory_preview`: where a basic attack cast now
+/// would fly with the server's `ProjectileConfig`, stepped under gravity at
+/// the physics rate from where the server spawns it, and a marker where it
+/// would first hit the ground or a wall.
+#[allow(clippy::too_many_arguments)]
+fn trajectory_preview_system(
+    mut egui_contexts: EguiContexts,
+    settings: Res<ClientSettings>,
+    config: Res<ProjectileConfig>,
+    world_config: Res<WorldConfig>,
+    rapier_context: Res<RapierContext>,
+    rapier_config: Res<RapierConfiguration>,
+    origin: Res<FloatingOrigin>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+) {
+    if !settings.trajectory_preview {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    // Casts fire level from the caster, so only the heading matters
+    let forward = camera_transform.forward();
+    let Some(direction) = Vec3::new(forward.x, 0.0, forward.z).try_normalize() else {
+        return;
+    };
+    // Matches where `cast_fireballs` spawns the projectile
+    let mut spawn_at = origin.to_world(camera_transform.translation()) + direction * 0.7;
+    spawn_at.y = 1.0;
+
+    let points: Vec<Vec3> = config
+        .trajectory(
+            spawn_at,
+            direction,
+            rapier_config.gravity,
+            world_config.physics_timestep,
+        )
+        .into_iter()
+        .map(|point| origin.to_local(point))
+        .collect();
+
+    // Cut the path at the first ground or wall it crosses
+    let filter =
+        QueryFilter::new().groups(CollisionGroups::new(Group::ALL, GROUND_GROUP | WALL_GROUP));
+    let mut path = vec![points[0]];
+    let mut impact = None;
+    for segment in points.windows(2) {
+        let offset = segment[1] - segment[0];
+        let Some(heading) = offset.try_normalize() else {
+            continue;
+        };
+        if let Some((_, toi)) =
+            rapier_context.cast_ray(segment[0], heading, offset.length(), true, filter)
+        {
+            let hit = segment[0] + heading * toi;
+            path.push(hit);
+            impact = Some(hit);
+            break;
+        }
+        path.push(segment[1]);
+    }
+
+    // Viewport coordinates start at the bottom left, egui's at the top left
+    let to_screen = |position: Vec3| {
+        camera
+            .world_to_viewport(camera_transform, position)
+            .map(|viewport| egui::pos2(viewport.x, window.height() - viewport.y))
+    };
+    let painter = egui_contexts.ctx_mut().layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("trajectory_preview"),
+    ));
+    let stroke = egui::Stroke::new(
+        2.0,
+        egui::Color32::from_rgba_unmultiplied(255, 120, 40, 160),
+    );
+    for segment in path.windows(2) {
+        if let (Some(from), Some(to)) = (to_screen(segment[0]), to_screen(segment[1])) {
+            painter.line_segment([from, to], stroke);
+        }
+    }
+    if let Some(impact) = impact.and_then(to_screen) {
+        painter.circle_stroke(impact, 6.0, stroke);
+    }
+}
+
+/// Keys 1-3 play emotes and middle click pings where the camera looks.
+fn client_emote_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut egui_contexts: EguiContexts,
+    rapier_context: Res<RapierContext>,
+    origin: Res<FloatingOrigin>,
+    camera_query: Query<&Transform, With<FlyCam>>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    if !egui_contexts.ctx_mut().wants_keyboard_input() {
+        for (key, kind) in [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3]
+            .into_iter()
+            .zip(EmoteKind::ALL)
+        {
+            if keyboard_input.just_pressed(key) {
+                player_commands.send(PlayerCommand::Emote { kind });
+            }
+        }
+    }
+
+    if !mouse_input.just_pressed(MouseButton::Middle) {
+        return;
+    }
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let filter =
+        QueryFilter::new().groups(CollisionGroups::new(Group::ALL, GROUND_GROUP | WALL_GROUP));
+    if let Some((_, toi)) = rapier_context.cast_ray(
+        camera_transform.translation,
+        camera_transform.forward(),
+        PING_DISTANCE,
+        true,
+        filter,
+    ) {
+        let position = camera_transform.translation + camera_transform.forward() * toi;
+        player_commands.send(PlayerCommand::Ping {
+            position: origin.to_world(position),
+        });
+    }
+}
+
+/// Holding T frees the cursor and shows the emotes in a ring around the screen centre.
+fn emote_radial_menu_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut egui_contexts: EguiContexts,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    let ctx = egui_contexts.ctx_mut();
+    if ctx.wants_keyboard_input() {
+        return;
+    }
+
+    if let Ok(mut window) = windows.get_single_mut() {
+        if keyboard_input.just_pressed(KeyCode::T) {
+            window.cursor.grab_mode = CursorGrabMode::None;
+            window.cursor.visible = true;
+        } else if keyboard_input.just_released(KeyCode::T) {
+            window.cursor.grab_mode = CursorGrabMode::Confined;
+            window.cursor.visible = false;
+        }
+    }
+    if !keyboard_input.pressed(KeyCode::T) {
+        return;
+    }
+
+    let center = ctx.screen_rect().center();
+    for (i, kind) in EmoteKind::ALL.into_iter().enumerate() {
+        let angle = -std::f32::consts::FRAC_PI_2
+            + i as f32 * std::f32::consts::TAU / EmoteKind::ALL.len() as f32;
+        let position = center + egui::vec2(angle.cos(), angle.sin()) * 80.0;
+        egui::Area::new(format!("emote-{}", kind.label()))
+            .fixed_pos(position)
+            .pivot(egui::Align2::CENTER_CENTER)
+            .show(ctx, |ui| {
+                if ui.button(kind.label()).clicked() {
+                    player_commands.send(PlayerCommand::Emote { kind });
+                }
+            });
+    }
+}
+
+fn world_markers_ui_system(
+    time: Res<Time>,
+    mut egui_contexts: EguiContexts,
+    mut markers: ResMut<WorldMarkers>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    transforms: Query<&GlobalTransform>,
+) {
+    markers
+        .0
+        .retain_mut(|marker| !marker.timer.tick(time.delta()).finished());
+
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let ctx = egui_contexts.ctx_mut();
+    for (i, marker) in markers.0.iter().enumerate() {
+        let world_position = match marker.anchor {
+            MarkerAnchor::Entity(entity) => match transforms.get(entity) {
+                Ok(transform) => transform.translation() + Vec3::Y * 1.5,
+                Err(_) => continue,
+            },
+            MarkerAnchor::Position(position) => position,
+        };
+        let Some(viewport_position) = camera.world_to_viewport(camera_transform, world_position)
+        else {
+            continue;
+        };
+
+        // Viewport coordinates start at the bottom left, egui's at the top left
+        let position = egui::pos2(viewport_position.x, window.height() - viewport_position.y);
+        egui::Area::new(format!("marker-{}", i))
+            .fixed_pos(position)
+            .pivot(egui::Align2::CENTER_BOTTOM)
+            .interactable(false)
+            .show(ctx, |ui| {
+                ui.label(egui::RichText::new(&marker.label).strong());
+            });
+    }
+}
+
+/// F4 toggles an overlay marking each networked entity's newest snapshot
+/// position (red) and where it is drawn (green), joined by a line, to show
+/// how far interpolation lags or extrapolation overshoots.
+#[cfg(debug_assertions)]
+fn interpolation_debug_system(
+    mut egui_contexts: EguiContexts,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut show_overlay: Local<bool>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    entities: Query<(&Transform, &InterpolationBuffer)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        *show_overlay = !*show_overlay;
+    }
+    if !*show_overlay {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    // Viewport coordinates start at the bottom left, egui's at the top left
+    let to_screen = |position: Vec3| {
+        camera
+            .world_to_viewport(camera_transform, position)
+            .map(|viewport| egui::pos2(viewport.x, window.height() - viewport.y))
+    };
+
+    let painter = egui_contexts.ctx_mut().layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("interpolation_debug"),
+    ));
+    for (transform, buffer) in entities.iter() {
+        let Some((_, received)) = buffer.samples.back() else {
+            continue;
+        };
+        let (Some(received), Some(rendered)) =
+            (to_screen(*received), to_screen(transform.translation))
+        else {
+            continue;
+        };
+        painter.line_segment([received, rendered], (1.0, egui::Color32::YELLOW));
+        painter.circle_filled(received, 3.0, egui::Color32::RED);
+        painter.circle_filled(rendered, 3.0, egui::Color32::GREEN);
+    }
+}
+
+/// Seconds of history shown by `prediction_divergence_graph_system`.
+#[cfg(debug_assertions)]
+const PREDICTION_DIVERGENCE_SECS: f64 = 10.0;
+
+/// State of `prediction_divergence_graph_system`.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct PredictionDivergenceGraph {
+    visible: bool,
+    /// Where the controlled player predicted itself in world space, keyed by
+    /// estimated server time, oldest first.
+    predicted: VecDeque<(f64, Vec3)>,
+    /// Local time in seconds and divergence in meters, oldest first.
+    divergence: VecDeque<[f64; 2]>,
+}
+
+/// F5 toggles a graph of how far the server's position for the controlled
+/// player diverges from where this client predicted it, sampled every frame.
+/// The newest snapshot position is compared with the prediction made a round
+/// trip before it, when the input it reflects was sent. The client never
+/// reconciles its own player with the server, so this is how far apart the
+/// two are, not a correction that was applied.
+#[cfg(debug_assertions)]
+#[allow(clippy::too_many_arguments)]
+fn prediction_divergence_graph_system(
+    mut egui_contexts: EguiContexts,
+    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    clock: Res<ServerClock>,
+    client: Res<RenetClient>,
+    origin: Res<FloatingOrigin>,
+    mut graph: Local<PredictionDivergenceGraph>,
+    camera_query: Query<&Transform, With<FlyCam>>,
+    player_query: Query<&InterpolationBuffer, With<ControlledPlayer>>,
+) {
+    let graph = &mut *graph;
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        graph.visible = !graph.visible;
+        graph.predicted.clear();
+        graph.divergence.clear();
+    }
+    if !graph.visible {
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    let Some(server_time) = clock.server_time(now) else {
+        return;
+    };
+
+    if let Ok(camera_transform) = camera_query.get_single() {
+        graph
+            .predicted
+            .push_back((server_time, origin.to_world(camera_transform.translation)));
+    }
+    let rtt = client.network_info().rtt / 1000.0;
+    while graph
+        .predicted
+        .front()
+        .is_some_and(|(predicted_at, _)| server_time - predicted_at > rtt + 1.0)
+    {
+        graph.predicted.pop_front();
+    }
+
+    let newest = player_query
+        .get_single()
+        .ok()
+        .and_then(|buffer| buffer.samples.back().copied());
+    if let Some((snapshot_time, server_position)) = newest {
+        let sent_at = snapshot_time - rtt;
+        let predicted = graph
+            .predicted
+            .iter()
+            .min_by(|(a, _), (b, _)| (a - sent_at).abs().total_cmp(&(b - sent_at).abs()));
+        if let Some((_, predicted)) = predicted {
+            let divergence = origin.to_world(server_position).distance(*predicted);
+            graph.divergence.push_back([now, divergence as f64]);
+        }
+    }
+    while graph
+        .divergence
+        .front()
+        .is_some_and(|[sampled_at, _]| now - sampled_at > PREDICTION_DIVERGENCE_SECS)
+    {
+        graph.divergence.pop_front();
+    }
+
+    egui::Window::new("Prediction divergence").show(egui_contexts.ctx_mut(), |ui| {
+        let latest = graph
+            .divergence
+            .back()
+            .map_or(0.0, |[_, divergence]| *divergence);
+        ui.label(format!(
+            "Latest: {:.2}m, round trip {:.0}ms",
+            latest,
+            rtt * 1000.0
+        ));
+        let points: egui::plot::PlotPoints = graph
+            .divergence
+            .iter()
+            .map(|[sampled_at, divergence]| [sampled_at - now, *divergence])
+            .collect();
+        egui::plot::Plot::new("prediction_divergence")
+            .height(150.0)
+            .include_x(-PREDICTION_DIVERGENCE_SECS)
+            .include_y(0.0)
+            .show(ui, |plot_ui| plot_ui.line(egui::plot::Line::new(points)));
+    });
+}
+
+fn client_send_player_commands(
+    mut player_commands: EventReader<PlayerCommand>,
+    mut client: ResMut<RenetClient>,
+) {
+    for command in player_commands.iter() {
+        let command_message = bincode::serialize(command).unwrap();
+        client.send_message(ClientChannel::Command, command_message);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn client_sync_players(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    mut local_player: LocalPlayer,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut decoder: ServerMessageDecoder,
+    mut ui_views: UiViews,
+    mut events: LifecycleEvents,
+    mut placeholders: ResMut<Placeholders>,
+    mut spawn_assets: SpawnAssets,
+    mut physics: PhysicsSettings,
+    mut effect_budget: ResMut<EffectBudget>,
+    mut capture_points: ResMut<CapturePoints>,
+    settings: Res<ClientSettings>,
+    time: Res<Time>,
+    material_handles: Query<&Handle<StandardMaterial>>,
+) {
+    let client_id = local_player.transport.client_id();
+    let now = time.elapsed_seconds();
+    while let Some(server_message) = decoder.next(&mut client) {
+        match server_message {
+            ServerMessages::PlayerCreate {
+                id,
+                translation,
+                entity,
+            } => {
+                if network_mapping.0.contains_key(&entity) {
+                    continue;
+                }
+                let mut client_entity = placeholders.spawn_or_upgrade(&mut commands, entity);
+                let transform =
+                    Transform::from_translation(local_player.origin.to_local(translation.into()));
+                match &spawn_assets.player_model.0 {
+                    Some(scene) => {
+                        // A placeholder may already carry a mesh
+                        client_entity
+                            .remove::<Handle<Mesh>>()
+                            .insert(SpatialBundle::from_transform(transform))
+                            .insert((
+                                PlayerModelRoot,
+                                spawn_assets
+                                    .materials
+                                    .add(settings.materials.player.material(PLAYER_COLOR)),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(SceneBundle {
+                                    scene: scene.clone(),
+                                    transform: settings.player_model_transform,
+                                    ..default()
+                                });
+                            });
+                    }
+                    None => {
+                        client_entity.insert(PbrBundle {
+                            mesh: spawn_assets
+                                .meshes
+                                .add(Mesh::from(shape::Capsule::default())),
+                            material: spawn_assets
+                                .materials
+                                .add(settings.materials.player.material(PLAYER_COLOR)),
+                            transform,
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                if client_id == id {
+                    client_entity.insert(ControlledPlayer);
+                } else {
+                    // Moved by network updates, but solid to local physics.
+                    client_entity
+                        .insert(RigidBody::KinematicPositionBased)
+                        .insert(player_physics());
+                }
+
+                let client_entity = client_entity.id();
+                let player_info = PlayerInfo {
+                    server_entity: entity,
+                    client_entity,
+                    shield: None,
+                };
+                lobby.players.insert(id, player_info);
+                network_mapping.0.insert(entity, client_entity);
+                events.player_joined.send(PlayerJoinedEvent {
+                    id,
+                    entity: client_entity,
+                    local: client_id == id,
+                });
+            }
+            ServerMessages::PlayerRemove { id } => {
+                events.player_left.send(PlayerLeftEvent { id });
+                if let Some(PlayerInfo {
+                    server_entity,
+                    client_entity,
+                    ..
+                }) = lobby.players.remove(&id)
+                {
+                    commands.entity(client_entity).despawn_recursive();
+                    network_mapping.0.remove(&server_entity);
+                    placeholders.despawned(&mut commands, server_entity, now);
+                }
+            }
+            ServerMessages::PlayerKilled { id, killer } => {
+                events.player_killed.send(PlayerKilledEvent { id, killer })
+            }
+            ServerMessages::SpawnProjectile {
+                entity,
+                translation,
+                velocity,
+                radius,
+                kind,
+            } => {
+                if network_mapping.0.contains_key(&entity) {
+                    continue;
+                }
+
+                let visual = spawn_assets.projectile_visuals.get(kind);
+                let mut projectile_entity = placeholders.spawn_or_upgrade(&mut commands, entity);
+                projectile_entity.insert(PbrBundle {
+                    mesh: visual.mesh.clone(),
+                    material: visual.material.clone(),
+                    transform: Transform::from_translation(
+                        local_player.origin.to_local(translation.into()),
+                    )
+                    .with_scale(Vec3::splat(radius / PROJECTILE_MESH_RADIUS)),
+                    ..Default::default()
+                });
+                projectile_entity.insert(ProjectileVelocity(velocity.into()));
+                network_mapping.0.insert(entity, projectile_entity.id());
+            }
+            ServerMessages::DespawnProjectile { entity } => {
+                if let Some(client_entity) = network_mapping.0.remove(&entity) {
+                    commands.entity(client_entity).despawn();
+                }
+                placeholders.despawned(&mut commands, entity, now);
+            }
+            ServerMessages::SpawnSolanaBlock {
+                entity,
+                transform,
+                slot,
+                shape,
+            } => {
+                // A resent spawn can race the original one
+                if network_mapping.0.contains_key(&entity) {
+                    continue;
+                }
+
+                // Spawn location
+                let spawn_location = Transform::from_translation(
+                    local_player
+                        .origin
+                        .to_local(Vec3::new(transform.0, transform.1, transform.2)),
+                );
+
+                // Spawn new
+                let solana_block_entity = placeholders
+                    .spawn_or_upgrade(&mut commands, entity)
+                    .insert(PbrBundle {
+                        mesh: spawn_assets.meshes.add(shape.mesh()),
+                        material: spawn_assets
+                            .materials
+                            .add(settings.materials.block.material(Color::rgb(0.8, 0.7, 0.6))),
+                        transform: spawn_location,
+                        ..Default::default()
+                    })
+                    .insert(solana_block_physics(shape))
+                    .insert(SolanaSlotBlock {
+                        id: slot,
+                        shape,
+                        lifetime: None,
+                    })
+                    .id();
+                match settings.block_sim_mode {
+                    BlockSimMode::ServerAuthoritative => {
+                        commands
+                            .entity(solana_block_entity)
+                            .insert(RigidBody::KinematicPositionBased);
+                    }
+                    BlockSimMode::ClientPredicted => {
+                        commands
+                            .entity(solana_block_entity)
+                            .insert((RigidBody::Dynamic, PredictedBlock));
+                        if settings.physics_interpolation {
+                            commands
+                                .entity(solana_block_entity)
+                                .insert(TransformInterpolation::default());
+                        }
+                    }
+                }
+
+                network_mapping.0.insert(entity, solana_block_entity);
+                events.block_spawned.send(SolanaBlockSpawnedEvent {
+                    entity: solana_block_entity,
+                    slot,
+                });
+            }
+            ServerMessages::SpawnBall {
+                entity,
+                translation,
+            } => {
+                if network_mapping.0.contains_key(&entity) {
+                    continue;
+                }
+
+                let mut ball_entity = placeholders.spawn_or_upgrade(&mut commands, entity);
+                ball_entity.insert(PbrBundle {
+                    mesh: spawn_assets.projectiles.mesh.clone(),
+                    material: spawn_assets.projectiles.ball_material.clone(),
+                    transform: Transform::from_translation(
+                        local_player.origin.to_local(translation.into()),
+                    )
+                    .with_scale(Vec3::splat(
+                        BallConfig::default().radius / PROJECTILE_MESH_RADIUS,
+                    )),
+                    ..Default::default()
+                });
+                network_mapping.0.insert(entity, ball_entity.id());
+            }
+            ServerMessages::DespawnSolanaBlock { entity } => {
+                if let Some(client_entity) = network_mapping.0.remove(&entity) {
+                    commands
+                        .entity(client_entity)
+                        .remove::<(RigidBody, Collider, InterpolationBuffer)>()
+                        .insert(Despawning::default());
+                }
+                placeholders.despawned(&mut commands, entity, now);
+            }
+            ServerMessages::ChatMessage { sender, message } => match sender {
+                Some(id) => ui_views.chat.push(format!("{}: {}", id, message)),
+                None => ui_views.chat.push(format!("[server] {}", message)),
+            },
+            ServerMessages::PlayerTeleport { id, translation } => {
+                // Our position comes from the camera, so moving it makes the teleport stick
+                if id == client_id {
+                    let translation = local_player.origin.to_local(translation.into());
+                    if let Ok(mut camera_transform) = local_player.camera.get_single_mut() {
+                        camera_transform.translation = translation;
+                    }
+                }
+            }
+            ServerMessages::WorldSettings {
+                physics_timestep,
+                snapshot_interval,
+                lockstep_tick,
+                lockstep_positions,
+                projectile,
+            } => {
+                physics.match_server_timestep(physics_timestep);
+                *physics.interpolation_delay =
+                    InterpolationDelay::for_snapshot_interval(snapshot_interval);
+                *physics.projectile = projectile;
+                physics
+                    .lockstep
+                    .join(lockstep_tick, &lockstep_positions, client_id);
+            }
+            ServerMessages::LockstepTick { tick, inputs } => {
+                physics.lockstep.confirmed.push_back((tick, inputs))
+            }
+            ServerMessages::LockstepDesync { tick } => {
+                println!("Lockstep desync at tick {}", tick);
+                ui_views
+                    .chat
+                    .push(format!("[server] Lockstep desync at tick {}", tick));
+            }
+            ServerMessages::Announcement { text } => {
+                ui_views.chat.push(format!("[server] {}", text));
+                ui_views.announcement.0 = Some((
+                    text,
+                    Timer::from_seconds(ANNOUNCEMENT_SECS, TimerMode::Once),
+                ));
+            }
+            ServerMessages::PlayerInvulnerable { id, invulnerable } => {
+                let Some(player_info) = lobby.players.get(&id) else {
+                    continue;
+                };
+                if let Ok(handle) = material_handles.get(player_info.client_entity) {
+                    if let Some(material) = spawn_assets.materials.get_mut(handle) {
+                        material.base_color = if invulnerable {
+                            INVULNERABLE_PLAYER_COLOR
+                        } else {
+                            PLAYER_COLOR
+                        };
+                    }
+                }
+            }
+            ServerMessages::ObjectiveHolder { id } => {
+                if lobby.objective_holder.map(|(holder, _)| holder) == id {
+                    continue;
+                }
+                let player_material = |lobby: &ClientLobby, id: u64| {
+                    let player_info = lobby.players.get(&id)?;
+                    material_handles
+                        .get(player_info.client_entity)
+                        .ok()
+                        .cloned()
+                };
+                if let Some((previous, color)) = lobby.objective_holder.take() {
+                    let handle = player_material(&lobby, previous);
+                    if let Some(material) =
+                        handle.and_then(|handle| spawn_assets.materials.get_mut(&handle))
+                    {
+                        material.base_color = color;
+                    }
+                }
+                let handle = id.and_then(|id| player_material(&lobby, id));
+                if let Some(material) =
+                    handle.and_then(|handle| spawn_assets.materials.get_mut(&handle))
+                {
+                    lobby.objective_holder = id.map(|id| (id, material.base_color));
+                    material.base_color = OBJECTIVE_HOLDER_COLOR;
+                }
+            }
+            ServerMessages::PlayerSpawnProtection { id, protected } => {
+                let Some(player_info) = lobby.players.get_mut(&id) else {
+                    continue;
+                };
+                match (protected, player_info.shield) {
+                    (true, None) => {
+                        let shield = commands
+                            .spawn(PbrBundle {
+                                mesh: spawn_assets.meshes.add(
+                                    Mesh::try_from(Icosphere {
+                                        radius: 1.2,
+                                        subdivisions: 3,
+                                    })
+                                    .unwrap(),
+                                ),
+                                material: spawn_assets.materials.add(StandardMaterial {
+                                    base_color: SHIELD_COLOR,
+                                    alpha_mode: AlphaMode::Blend,
+                                    ..default()
+                                }),
+                                ..default()
+                            })
+                            .id();
+                        commands.entity(player_info.client_entity).add_child(shield);
+                        player_info.shield = Some(shield);
+                    }
+                    (false, Some(shield)) => {
+                        commands.entity(shield).despawn_recursive();
+                        player_info.shield = None;
+                    }
+                    _ => {}
+                }
+            }
+            ServerMessages::LiveSolanaBlocks { entities } => {
+                let missing: Vec<Entity> = entities
+                    .into_iter()
+                    .filter(|entity| !network_mapping.0.contains_key(entity))
+                    .collect();
+                if !missing.is_empty() {
+                    println!("Requesting {} missed Solana blocks", missing.len());
+                    let request = bincode::serialize(&EntityRequest { entities: missing }).unwrap();
+                    client.send_message(ClientChannel::RequestEntity, request);
+                }
+            }
+            ServerMessages::Leaderboard { entries } => {
+                ui_views.leaderboard.0 = entries;
+            }
+            ServerMessages::PlayerList { players } => {
+                ui_views.player_list.0 = Some(players);
+            }
+            ServerMessages::ImpactDecal { position, normal } => {
+                if !effect_budget.reserve(&mut commands, EffectPriority::Low) {
+                    continue;
+                }
+                let normal = Vec3::from(normal);
+                let decal = commands
+                    .spawn(PbrBundle {
+                        mesh: spawn_assets.decals.mesh.clone(),
+                        // Each decal fades on its own, so they can't share a material
+                        material: spawn_assets.materials.add(StandardMaterial {
+                            base_color: DECAL_COLOR,
+                            alpha_mode: AlphaMode::Blend,
+                            unlit: true,
+                            ..default()
+                        }),
+                        // Lifted slightly off the surface to avoid z-fighting
+                        transform: Transform::from_translation(
+                            local_player.origin.to_local(position.into()) + normal * 0.01,
+                        )
+                        .with_rotation(Quat::from_rotation_arc(Vec3::Z, normal)),
+                        ..default()
+                    })
+                    .insert(Decal(Timer::from_seconds(DECAL_SECS, TimerMode::Once)))
+                    .id();
+                effect_budget.track(decal, EffectPriority::Low);
+            }
+            ServerMessages::MatchResults { standings, winners } => {
+                ui_views.match_results.0 = Some((standings, winners));
+            }
+            ServerMessages::EmotePlayed { id, kind } => {
+                let Some(player_info) = lobby.players.get(&id) else {
+                    continue;
+                };
+                ui_views.markers.0.push(WorldMarker {
+                    anchor: MarkerAnchor::Entity(player_info.client_entity),
+                    label: kind.label().to_string(),
+                    timer: Timer::from_seconds(EMOTE_SECS, TimerMode::Once),
+                });
+            }
+            ServerMessages::HealEffect { target_id } => {
+                let Some(player_info) = lobby.players.get(&target_id) else {
+                    continue;
+                };
+                if !effect_budget.reserve(&mut commands, EffectPriority::High) {
+                    continue;
+                }
+                let pulse = commands
+                    .spawn(PbrBundle {
+                        mesh: spawn_assets.projectiles.mesh.clone(),
+                        material: spawn_assets.materials.add(StandardMaterial {
+                            base_color: HEAL_PULSE_COLOR,
+                            alpha_mode: AlphaMode::Blend,
+                            unlit: true,
+                            ..default()
+                        }),
+                        ..default()
+                    })
+                    .insert(HealPulse(Timer::from_seconds(
+                        HEAL_PULSE_SECS,
+                        TimerMode::Once,
+                    )))
+                    .id();
+                commands.entity(player_info.client_entity).add_child(pulse);
+                effect_budget.track(pulse, EffectPriority::High);
+            }
+            ServerMessages::CapturePointUpdate {
+                id,
+                center,
+                radius,
+                controlling_team,
+                progress,
+            } => {
+                capture_points.states.insert(
+                    id,
+                    CapturePointState {
+                        center: center.into(),
+                        radius,
+                        controlling_team,
+                        progress,
+                    },
+                );
+            }
+            ServerMessages::CastPerformed {
+                caster_id,
+                position,
+            } => {
+                let local = caster_id == client_id;
+                let priority = if local {
+                    EffectPriority::High
+                } else {
+                    EffectPriority::Low
+                };
+                if !effect_budget.reserve(&mut commands, priority) {
+                    continue;
+                }
+                let flash = commands
+                    .spawn(PbrBundle {
+                        mesh: spawn_assets.projectiles.mesh.clone(),
+                        material: spawn_assets.materials.add(StandardMaterial {
+                            base_color: CAST_FLASH_COLOR,
+                            alpha_mode: AlphaMode::Blend,
+                            unlit: true,
+                            ..default()
+                        }),
+                        transform: Transform::from_translation(
+                            local_player.origin.to_local(position.into()),
+                        ),
+                        ..default()
+                    })
+                    .insert(CastFlash {
+                        timer: Timer::from_seconds(CAST_FLASH_SECS, TimerMode::Once),
+                        peak_scale: if local {
+                            LOCAL_CAST_FLASH_SCALE
+                        } else {
+                            CAST_FLASH_SCALE
+                        },
+                    })
+                    .id();
+                effect_budget.track(flash, priority);
+            }
+            ServerMessages::PlayerKnockedUp { id, velocity } => {
+                if id == client_id {
+                    local_player.knock_up.velocity = velocity;
+                }
+            }
+            ServerMessages::PingPlaced { id, position } => {
+                ui_views.markers.0.push(WorldMarker {
+                    anchor: MarkerAnchor::Position(local_player.origin.to_local(position.into())),
+                    label: format!("v ping ({})", id),
+                    timer: Timer::from_seconds(PING_SECS, TimerMode::Once),
+                });
+            }
+            // The decoder reassembles chunks, so only a chunk nested in a chunk gets here
+            ServerMessages::Chunk { .. } => {}
+            // Unpacked by the decoder
+            ServerMessages::Batch { .. } => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn client_sync_snapshots(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut lobby: ResMut<ClientLobby>,
+    mut reconnect: ResMut<Reconnect>,
+    mut deserialize_failures: ResMut<DeserializeFailures>,
+    mut placeholders: ResMut<Placeholders>,
+    mut clock: ResMut<ServerClock>,
+    mut killcam: ResMut<Killcam>,
+    lockstep: Res<ClientLockstep>,
+    origin: Res<FloatingOrigin>,
+    time: Res<Time>,
+    mut buffers: Query<&mut InterpolationBuffer>,
+) {
+    let now = time.elapsed_seconds();
+    while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
+        let networked_entities: NetworkedEntities = match bincode::deserialize(&message) {
+            Ok(networked_entities) => {
+                deserialize_failures.0 = 0;
+                networked_entities
+            }
+            Err(e) => {
+                println!("Failed to deserialize networked entities: {}", e);
+                deserialize_failures.0 += 1;
+                continue;
+            }
+        };
+
+        if clock
+            .latest_tick
+            .is_some_and(|tick| networked_entities.tick <= tick)
+        {
+            continue;
+        }
+        clock.observe(
+            networked_entities.tick,
+            networked_entities.server_time,
+            time.elapsed_seconds_f64(),
+        );
+
+        let mut unmapped = Vec::new();
+        for i in 0..networked_entities.entities.len() {
+            let server_entity = networked_entities.entities[i];
+            let translation = origin.to_local(networked_entities.translations[i].into());
+            let sample = (networked_entities.server_time, translation);
+
+            let client_entity = if let Some(entity) = network_mapping.0.get(&server_entity) {
+                *entity
+            } else if let Some(entity) = placeholders.entities.get(&server_entity) {
+                *entity
+            } else if !placeholders.recently_despawned.contains_key(&server_entity) {
+                let placeholder = commands
+                    .spawn(PbrBundle {
+                        mesh: placeholders.mesh.clone(),
+                        material: placeholders.material.clone(),
+                        transform: Transform::from_translation(translation),
+                        ..Default::default()
+                    })
+                    .insert(Placeholder)
+                    .id();
+                placeholders.entities.insert(server_entity, placeholder);
+                unmapped.push(server_entity);
+                placeholder
+            } else {
+                continue;
+            };
+
+            match buffers.get_mut(client_entity) {
+                Ok(mut buffer) => buffer.push(sample),
+                Err(_) => {
+                    let mut buffer = InterpolationBuffer::default();
+                    buffer.push(sample);
+                    commands.entity(client_entity).insert(buffer);
+                }
+            }
+        }
+
+        // Complete snapshots list every networked entity, so placeholders
+        // missing from one belong to entities the server no longer has
+        let seen: HashSet<Entity> = networked_entities.entities.iter().copied().collect();
+        if networked_entities.complete {
+            placeholders.entities.retain(|server_entity, placeholder| {
+                let keep = seen.contains(server_entity);
+                if !keep {
+                    commands.entity(*placeholder).despawn();
+                }
+                keep
+            });
+        }
+
+        // Despawns sent while we were disconnected never arrived, so after a
+        // reconnect anything the server no longer has only shows by its absence
+        if reconnect.resync_pending && networked_entities.complete {
+            reconnect.resync_pending = false;
+            // Lockstep players are never in snapshots
+            let lockstep_players: HashSet<Entity> = match lockstep.next_input_tick {
+                Some(_) => lobby
+                    .players
+                    .values()
+                    .map(|player| player.server_entity)
+                    .collect(),
+                None => HashSet::new(),
+            };
+            let stale: Vec<Entity> = network_mapping
+                .0
+                .keys()
+                .filter(|server_entity| {
+                    !seen.contains(server_entity) && !lockstep_players.contains(server_entity)
+                })
+                .copied()
+                .collect();
+            for server_entity in &stale {
+                if let Some(client_entity) = network_mapping.0.remove(server_entity) {
+                    commands.entity(client_entity).despawn_recursive();
+                }
+                placeholders.despawned(&mut commands, *server_entity, now);
+            }
+            lobby
+                .players
+                .retain(|_, player| network_mapping.0.contains_key(&player.server_entity));
+            println!(
+                "Resynced after reconnecting, {} stale entities removed.",
+                stale.len()
+            );
+        }
+
+        if !unmapped.is_empty() {
+            let request = bincode::serialize(&EntityRequest { entities: unmapped }).unwrap();
+            client.send_message(ClientChannel::RequestEntity, request);
+        }
+
+        killcam.record(networked_entities);
+    }
+
+    placeholders
+        .recently_despawned
+        .retain(|_, despawned_at| now - *despawned_at < DESPAWN_MEMORY_SECS);
+}
+
+/// Moves networked entities to where their snapshots put them
+/// `InterpolationDelay` ago on the server clock. Predicted blocks keep their
+/// local simulation and are only corrected once they drift.
+#[allow(clippy::type_complexity)]
+fn interpolate_networked_entities(
+    time: Res<Time>,
+    clock: Res<ServerClock>,
+    interpolation_delay: Res<InterpolationDelay>,
+    killcam: Res<Killcam>,
+    rapier_config: Res<RapierConfiguration>,
+    projectile_config: Res<ProjectileConfig>,
+    mut query: Query<
+        (
+            &mut Transform,
+            &InterpolationBuffer,
+            Option<&PredictedBlock>,
+            Option<&ProjectileVelocity>,
+        ),
+        (Without<Despawning>, Without<Culled>),
+    >,
+) {
+    if killcam.active.is_some() {
+        return;
+    }
+    let Some(server_time) = clock.server_time(time.elapsed_seconds_f64()) else {
+        return;
+    };
+    let render_time = server_time - interpolation_delay.0;
+    let gravity = rapier_config.gravity * projectile_config.gravity_scale;
+
+    for (mut transform, buffer, predicted, projectile_velocity) in query.iter_mut() {
+        if let Some(ProjectileVelocity(velocity)) = projectile_velocity {
+            if let Some(translation) = buffer.extrapolate(server_time, *velocity, gravity) {
+                transform.translation = translation;
+            }
+            continue;
+        }
+
+        let Some(translation) = buffer.sample(render_time) else {
+            continue;
+        };
+        if predicted.is_none() {
+            transform.translation = translation;
+            continue;
+        }
+
+        let drift = transform.translation.distance(translation);
+        if drift > PREDICTION_SNAP_DISTANCE {
+            transform.translation = translation;
+        } else if drift > PREDICTION_TOLERANCE {
+            transform.translation = transform.translation.lerp(translation, 0.1);
+        }
+    }
+}
+
+/// Hides networked entities past `ClientSettings::cull_distance` or outside
+/// the camera frustum, so they skip both drawing and interpolation.
+fn culling_system(
+    mut commands: Commands,
+    settings: Res<ClientSettings>,
+    camera_query: Query<(&GlobalTransform, &Frustum), With<FlyCam>>,
+    mut query: Query<
+        (
+            Entity,
+            &InterpolationBuffer,
+            &mut Visibility,
+            Option<&Culled>,
+        ),
+        Without<ControlledPlayer>,
+    >,
+) {
+    let Ok((camera_transform, frustum)) = camera_query.get_single() else {
+        return;
+    };
+    let camera_position = camera_transform.translation();
+
+    for (entity, buffer, mut visibility, culled) in query.iter_mut() {
+        // Culled entities aren't interpolated, so test where the server last put them
+        let Some(position) = buffer.newest() else {
+            continue;
+        };
+        let visible = settings.cull_distance.is_none_or(|cull_distance| {
+            let sphere = Sphere {
+                center: position.into(),
+                radius: CULL_MARGIN,
+            };
+            position.distance(camera_position) <= cull_distance
+                && frustum.intersects_sphere(&sphere, false)
+        });
+
+        match (visible, culled.is_some()) {
+            (false, false) => {
+                *visibility = Visibility::Hidden;
+                commands.entity(entity).insert(Culled);
+            }
+            (true, true) => {
+                *visibility = Visibility::Inherited;
+                commands.entity(entity).remove::<Culled>();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Moves projectiles that haven't appeared in a snapshot yet, so they don't
+/// sit still until the first one arrives.
+fn move_new_projectiles_system(
+    time: Res<Time>,
+    rapier_config: Res<RapierConfiguration>,
+    projectile_config: Res<ProjectileConfig>,
+    mut query: Query<(&mut Transform, &mut ProjectileVelocity), Without<InterpolationBuffer>>,
+) {
+    let gravity = rapier_config.gravity * projectile_config.gravity_scale;
+    for (mut transform, mut velocity) in query.iter_mut() {
+        velocity.0 += gravity * time.delta_seconds();
+        transform.translation += velocity.0 * time.delta_seconds();
+    }
+}
+
+/// When another player kills ours, replays the last `KILLCAM_SECS` seconds
+/// following the killer.
+fn killcam_trigger_system(
+    mut player_killed: EventReader<PlayerKilledEvent>,
+    mut killcam: ResMut<Killcam>,
+    transport: Res<NetcodeClientTransport>,
+    lobby: Res<ClientLobby>,
+    camera_query: Query<&Transform, With<FlyCam>>,
+) {
+    let client_id = transport.client_id();
+    let killer = player_killed
+        .iter()
+        .filter(|event| event.id == client_id)
+        .find_map(|event| event.killer.and_then(|killer| lobby.players.get(&killer)));
+    let Some(killer) = killer else {
+        return;
+    };
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    if killcam.history.is_empty() {
+        return;
+    }
+
+    let focus = Some(killer.server_entity);
+    killcam.active = Some(ActiveReplay {
+        snapshots: killcam.history.iter().cloned().collect(),
+        elapsed: 0.0,
+        focus,
+        camera_return: *camera_transform,
+    });
+}
+
+/// Plays the stored snapshots back through the networked entities' transforms,
+/// then restores the camera and hands back to live interpolation.
+fn killcam_playback_system(
+    time: Res<Time>,
+    mut killcam: ResMut<Killcam>,
+    network_mapping: Res<NetworkMapping>,
+    origin: Res<FloatingOrigin>,
+    mut transforms: Query<&mut Transform, Without<FlyCam>>,
+    mut camera_query: Query<&mut Transform, With<FlyCam>>,
+) {
+    let Some(replay) = &mut killcam.active else {
+        return;
+    };
+    replay.elapsed += time.delta_seconds_f64();
+
+    let start = replay
+        .snapshots
+        .first()
+        .map_or(0.0, |snapshot| snapshot.server_time);
+    let replay_time = start + replay.elapsed;
+    let bracket = replay
+        .snapshots
+        .iter()
+        .zip(replay.snapshots.iter().skip(1))
+        .find(|(_, to)| replay_time <= to.server_time);
+    let Some((from, to)) = bracket else {
+        if let Ok(mut camera_transform) = camera_query.get_single_mut() {
+            *camera_transform = replay.camera_return;
+        }
+        killcam.active = None;
+        return;
+    };
+
+    let t = ((replay_time - from.server_time) / (to.server_time - from.server_time)) as f32;
+    let mut focus_translation = None;
+    for (i, server_entity) in from.entities.iter().enumerate() {
+        let from_translation = Vec3::from(from.translations[i]);
+        let translation = match to
+            .entities
+            .iter()
+            .position(|entity| entity == server_entity)
+        {
+            Some(j) => from_translation.lerp(to.translations[j].into(), t),
+            None => from_translation,
+        };
+        let translation = origin.to_local(translation);
+        if replay.focus == Some(*server_entity) {
+            focus_translation = Some(translation);
+        }
+        let Some(client_entity) = network_mapping.0.get(server_entity) else {
+            continue;
+        };
+        if let Ok(mut transform) = transforms.get_mut(*client_entity) {
+            transform.translation = translation;
+        }
+    }
+
+    if let (Some(focus), Ok(mut camera_transform)) =
+        (focus_translation, camera_query.get_single_mut())
+    {
+        *camera_transform =
+            Transform::from_translation(focus + KILLCAM_OFFSET).looking_at(focus, Vec3::Y);
+    }
+}
+
+/// Moves every top-level entity, buffered snapshot and marker back by the
+/// camera's horizontal offset once it passes `FloatingOrigin::threshold`.
+/// Children move with their parents. Skipped during killcam replays, which
+/// restore the camera afterwards.
+fn floating_origin_system(
+    mut origin: ResMut<FloatingOrigin>,
+    killcam: Res<Killcam>,
+    cameras: Query<Entity, With<FlyCam>>,
+    mut transforms: Query<&mut Transform, Without<Parent>>,
+    mut buffers: Query<&mut InterpolationBuffer>,
+    mut smoothing: Query<&mut CameraSmoothing>,
+    mut markers: ResMut<WorldMarkers>,
+) {
+    let Some(threshold) = origin.threshold else {
+        return;
+    };
+    if killcam.active.is_some() {
+        return;
+    }
+    let Some(camera_translation) = cameras
+        .get_single()
+        .ok()
+        .and_then(|camera| transforms.get(camera).ok())
+        .map(|transform| transform.translation)
+    else {
+        return;
+    };
+    // Only horizontal, so the ground stays at the same height
+    let shift = Vec3::new(camera_translation.x, 0.0, camera_translation.z);
+    if shift.length() < threshold {
+        return;
+    }
+
+    origin.offset += shift;
+    println!(
+        "Rebasing floating origin by {}, offset now {}",
+        shift, origin.offset
+    );
+    for mut transform in transforms.iter_mut() {
+        transform.translation -= shift;
+    }
+    for mut buffer in buffers.iter_mut() {
+        buffer.shift(shift);
+    }
+    for marker in markers.0.iter_mut() {
+        if let MarkerAnchor::Position(position) = &mut marker.anchor {
+            *position -= shift;
+        }
+    }
+    for mut smoothing in smoothing.iter_mut() {
+        // Clear the smoother's last transform so it doesn't glide across the shift
+        smoothing.smoother.reset();
+    }
+}
+
+fn disconnect_on_deserialize_failures(
+    mut client: ResMut<RenetClient>,
+    mut deserialize_failures: ResMut<DeserializeFailures>,
+) {
+    if deserialize_failures.0 >= MAX_DESERIALIZE_FAILURES {
+        println!(
+            "{} consecutive messages failed to deserialize, disconnecting. \
+             Is the server running the same protocol version?",
+            deserialize_failures.0
+        );
+        deserialize_failures.0 = 0;
+        client.disconnect();
+    }
+}
+
+fn despawn_animation_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Despawning, &mut Transform)>,
+) {
+    for (entity, mut despawning, mut transform) in query.iter_mut() {
+        despawning.0.tick(time.delta());
+        if despawning.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            transform.scale = Vec3::splat(despawning.0.percent_left());
+        }
+    }
+}
+
+fn decal_fade_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effect_budget: ResMut<EffectBudget>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut query: Query<(Entity, &mut Decal, &Handle<StandardMaterial>)>,
+) {
+    for (entity, mut decal, material) in query.iter_mut() {
+        decal.0.tick(time.delta());
+        if decal.0.finished() {
+            commands.entity(entity).despawn();
+            effect_budget.forget(entity);
+        } else if let Some(material) = materials.get_mut(material) {
+            material
+                .base_color
+                .set_a(DECAL_COLOR.a() * decal.0.percent_left());
+        }
+    }
+}
+
+fn heal_pulse_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effect_budget: ResMut<EffectBudget>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut query: Query<(
+        Entity,
+        &mut HealPulse,
+        &mut Transform,
+        &Handle<StandardMaterial>,
+    )>,
+) {
+    for (entity, mut pulse, mut transform, material) in query.iter_mut() {
+        pulse.0.tick(time.delta());
+        if pulse.0.finished() {
+            commands.entity(entity).despawn_recursive();
+            effect_budget.forget(entity);
+            continue;
+        }
+        transform.scale = Vec3::splat(1.0 + 14.0 * pulse.0.percent());
+        if let Some(material) = materials.get_mut(material) {
+            material
+                .base_color
+                .set_a(HEAL_PULSE_COLOR.a() * pulse.0.percent_left());
+        }
+    }
+}
+
+fn cast_flash_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effect_budget: ResMut<EffectBudget>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut query: Query<(
+        Entity,
+        &mut CastFlash,
+        &mut Transform,
+        &Handle<StandardMaterial>,
+    )>,
+) {
+    for (entity, mut flash, mut transform, material) in query.iter_mut() {
+        flash.timer.tick(time.delta());
+        if flash.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            effect_budget.forget(entity);
+            continue;
+        }
+        transform.scale = Vec3::splat(1.0 + (flash.peak_scale - 1.0) * flash.timer.percent());
+        if let Some(material) = materials.get_mut(material) {
+            material
+                .base_color
+                .set_a(CAST_FLASH_COLOR.a() * flash.timer.percent_left());
+        }
+    }
+}
+
+fn knock_up_arc_system(
+    time: Res<Time>,
+    rapier_config: Res<RapierConfiguration>,
+    mut arc: ResMut<KnockUpArc>,
+    mut camera_query: Query<&mut Transform, With<FlyCam>>,
+) {
+    if arc.velocity == 0.0 && arc.offset == 0.0 {
+        return;
+    }
+
+    let delta = time.delta_seconds();
+    arc.velocity += rapier_config.gravity.y * delta;
+    let offset = (arc.offset + arc.velocity * delta).max(0.0);
+    for mut transform in camera_query.iter_mut() {
+        transform.translation.y += offset - arc.offset;
+    }
+    if offset == 0.0 {
+        *arc = KnockUpArc::default();
+    } else {
+        arc.offset = offset;
+    }
+}
+
+/// Draws each capture point as a disc tinted by the team holding it, with a
+/// ring in the controlling team's color that grows out to the edge as its
+/// progress fills.
+fn capture_point_render_system(
+    mut commands: Commands,
+    mut capture_points: ResMut<CapturePoints>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    origin: Res<FloatingOrigin>,
+    mut transforms: Query<&mut Transform>,
+    material_handles: Query<&Handle<StandardMaterial>>,
+) {
+    if !capture_points.is_changed() {
+        return;
+    }
+    let CapturePoints { states, entities } = &mut *capture_points;
+
+    entities.retain(|id, view| {
+        let keep = states.contains_key(id);
+        if !keep {
+            commands.entity(view.zone).despawn_recursive();
+        }
+        keep
+    });
+
+    for (id, state) in states.iter() {
+        let holder = state.controlling_team.filter(|_| state.progress >= 1.0);
+        let zone_color = team_color(holder).with_a(CAPTURE_ZONE_ALPHA);
+        let ring_color = team_color(state.controlling_team);
+        let progress = state.progress.clamp(0.0, 1.0);
+        let ring_scale = Vec3::new(progress, 1.0, progress);
+
+        if let Some(view) = entities.get(id) {
+            if let Ok(mut transform) = transforms.get_mut(view.ring) {
+                transform.scale = ring_scale;
+            }
+            for (entity, color) in [(view.disc, zone_color), (view.ring, ring_color)] {
+                if let Some(material) = material_handles
+                    .get(entity)
+                    .ok()
+                    .and_then(|handle| materials.get_mut(handle))
+                {
+                    material.base_color = color;
+                }
+            }
+            continue;
+        }
+
+        let disc = commands
+            .spawn(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Circle::new(1.0))),
+                material: materials.add(StandardMaterial {
+                    base_color: zone_color,
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_rotation(Quat::from_rotation_x(
+                    -std::f32::consts::FRAC_PI_2,
+                )),
+                ..default()
+            })
+            .id();
+        let ring = commands
+            .spawn(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Torus {
+                    radius: 1.0,
+                    ring_radius: 0.04,
+                    ..default()
+                })),
+                material: materials.add(StandardMaterial {
+                    base_color: ring_color,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_xyz(0.0, 0.05, 0.0).with_scale(ring_scale),
+                ..default()
+            })
+            .id();
+        // Lifted a little so the disc doesn't z-fight the floor
+        let translation = origin.to_local(state.center) + Vec3::Y * 0.02;
+        let zone = commands
+            .spawn(SpatialBundle::from_transform(
+                Transform::from_translation(translation).with_scale(Vec3::new(
+                    state.radius,
+                    1.0,
+                    state.radius,
+                )),
+            ))
+            .push_children(&[disc, ring])
+            .id();
+        entities.insert(*id, CapturePointView { zone, disc, ring });
+    }
+}
+
+fn setup_decal_assets(
+    mut decals: ResMut<Decals>,
+    mut required: ResMut<RequiredAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    decals.mesh = required.mesh(meshes.add(Mesh::from(shape::Quad::new(Vec2::splat(0.6)))));
+}
+
+fn setup_placeholder_assets(
+    mut placeholders: ResMut<Placeholders>,
+    mut required: ResMut<RequiredAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    placeholders.mesh = required.mesh(meshes.add(Mesh::from(shape::Cube { size: 0.5 })));
+    placeholders.material =
+        required.material(materials.add(Color::rgba(0.5, 0.5, 0.5, 0.5).into()));
+}
+
+fn setup_projectile_assets(
+    settings: Res<ClientSettings>,
+    mut projectile_assets: ResMut<ProjectileAssets>,
+    mut projectile_visuals: ResMut<ProjectileVisuals>,
+    mut required: ResMut<RequiredAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    projectile_assets.mesh = required.mesh(
+        meshes.add(
+            Mesh::try_from(Icosphere {
+                radius: PROJECTILE_MESH_RADIUS,
+                subdivisions: settings.projectile_subdivisions,
+            })
+            .unwrap(),
+        ),
+    );
+    let mut visual = |color: Color| ProjectileVisual {
+        mesh: projectile_assets.mesh.clone(),
+        material: required.material(materials.add(settings.materials.projectile.material(color))),
+    };
+    projectile_visuals.fallback = visual(Color::WHITE);
+    projectile_visuals
+        .kinds
+        .insert(ProjectileKind::Fireball, visual(Color::rgb(1.0, 0.0, 0.0)));
+    projectile_visuals
+        .kinds
+        .insert(ProjectileKind::Seeker, visual(Color::rgb(0.8, 0.2, 1.0)));
+    projectile_visuals
+        .kinds
+        .insert(ProjectileKind::Piercing, visual(Color::rgb(1.0, 0.8, 0.1)));
+
+    projectile_assets.ball_material = required.material(
+        materials.add(
+            settings
+                .materials
+                .ball
+                .material(BallConfig::default().color),
+        ),
+    );
+}
+
+/// Leaves `AssetState::Loading` once every required handle resolves. Meshes
+/// built in code resolve immediately; ones loaded from files take a few frames.
+fn check_assets_ready_system(
+    required: Res<RequiredAssets>,
+    meshes: Res<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut player_model: ResMut<PlayerModel>,
+    mut next_state: ResMut<NextState<AssetState>>,
+) {
+    // The player model is optional, so a failed load falls back to the capsule
+    if let Some(scene) = &player_model.0 {
+        match asset_server.get_load_state(scene) {
+            LoadState::Loaded => {}
+            LoadState::Failed => {
+                println!("Failed to load player model, using the capsule instead");
+                player_model.0 = None;
+            }
+            _ => return,
+        }
+    }
+
+    let ready = required.meshes.iter().all(|handle| meshes.contains(handle))
+        && required
+            .materials
+            .iter()
+            .all(|handle| materials.contains(handle));
+    if ready {
+        println!("Assets ready");
+        next_state.set(AssetState::Ready);
+    }
+}
+
+fn setup_player_model(
+    settings: Res<ClientSettings>,
+    asset_server: Res<AssetServer>,
+    mut player_model: ResMut<PlayerModel>,
+) {
+    if let Some(path) = &settings.player_model {
+        println!("Loading player model {}", path);
+        player_model.0 = Some(asset_server.load(format!("{}#Scene0", path)));
+    }
+}
+
+fn setup_camera_fps(
+    mut commands: Commands,
+    camera_config: Res<CameraConfig>,
+    settings: Res<ClientSettings>,
+) {
+    commands.spawn((
+        Camera3dBundle {
+            transform: camera_config.transform(),
+            projection: Projection::Perspective(PerspectiveProjection {
+                far: settings.render_distance,
+                ..default()
+            }),
+            ..default()
+        },
+        FogSettings {
+            color: SKY_COLOR,
+            falloff: FogFalloff::Linear {
+                start: settings.render_distance * FOG_START,
+                end: settings.render_distance,
+            },
+            ..default()
+        },
+        FlyCam,
+        CameraSmoothing {
+            smoother: Smoother::new(settings.camera_smoothing),
+            target: None,
+        },
+    ));
+}
+
+const MAX_CAMERA_SMOOTHING: f32 = 0.95;
+const MAX_LOOK_SMOOTHING: f32 = 0.9;
+/// Smoothed look movement below this is snapped to zero instead of decaying forever.
+const LOOK_REST_THRESHOLD: f32 = 0.01;
+
+/// Runs the frame's mouse motion through `ClientSettings::look_deadzone` and
+/// `look_smoothing` before the flycam reads it, replacing the raw events with
+/// one filtered event.
+fn filter_look_input_system(
+    settings: Res<ClientSettings>,
+    mut motion_events: ResMut<Events<MouseMotion>>,
+    mut reader: Local<ManualEventReader<MouseMotion>>,
+    mut smoothed: Local<Vec2>,
+) {
+    let deadzone = settings.look_deadzone.max(0.0);
+    let smoothing = settings.look_smoothing.clamp(0.0, MAX_LOOK_SMOOTHING);
+    if deadzone <= 0.0 && smoothing <= 0.0 {
+        return;
+    }
+
+    let raw: Vec2 = reader.iter(&motion_events).map(|event| event.delta).sum();
+    let length = raw.length();
+    let filtered = if length <= deadzone {
+        Vec2::ZERO
+    } else {
+        raw * (length - deadzone) / length
+    };
+    *smoothed = smoothed.lerp(filtered, 1.0 - smoothing);
+    if smoothed.length() < LOOK_REST_THRESHOLD {
+        *smoothed = Vec2::ZERO;
+    }
+
+    motion_events.drain().for_each(drop);
+    if *smoothed != Vec2::ZERO {
+        motion_events.send(MouseMotion { delta: *smoothed });
+    }
+    // Don't read our own filtered event back next frame
+    reader.iter(&motion_events).for_each(drop);
+}
+
+/// The flycam moves the camera's `Transform` during `Update`, and everything
+/// aiming from the camera reads it there. `smooth_camera_system` then swaps in
+/// a smoothed transform for rendering, and `restore_camera_target` puts the
+/// flycam's own transform back before the next `Update`.
+#[derive(Component)]
+struct CameraSmoothing {
+    smoother: Smoother,
+    target: Option<Transform>,
+}
+
+/// Clamps an observer's flycam to `ClientSettings::camera_bounds`. Runs before
+/// `smooth_camera_system` so the smoothed camera eases up to the edge rather
+/// than being cut off at it.
+fn camera_bounds_system(
+    settings: Res<ClientSettings>,
+    origin: Res<FloatingOrigin>,
+    mut cameras: Query<&mut Transform, With<FlyCam>>,
+) {
+    let Some(bounds) = settings.camera_bounds.filter(|_| settings.observer) else {
+        return;
+    };
+    for mut transform in cameras.iter_mut() {
+        let world = origin.to_world(transform.translation);
+        let clamped = world.clamp(bounds.min.min(bounds.max), bounds.min.max(bounds.max));
+        if clamped != world {
+            transform.translation = origin.to_local(clamped);
+        }
+    }
+}
+
+fn restore_camera_target(mut cameras: Query<(&mut Transform, &mut CameraSmoothing)>) {
+    for (mut transform, mut smoothing) in cameras.iter_mut() {
+        if let Some(target) = smoothing.target.take() {
+            *transform = target;
+        }
+    }
+}
+
+fn smooth_camera_system(
+    settings: Res<ClientSettings>,
+    mut cameras: Query<(&mut Transform, &mut CameraSmoothing)>,
+) {
+    let lag_weight = settings.camera_smoothing.clamp(0.0, MAX_CAMERA_SMOOTHING);
+    for (mut transform, mut smoothing) in cameras.iter_mut() {
+        smoothing.smoother.set_lag_weight(lag_weight);
+        let eye = transform.translation;
+        let look = LookTransform::new(eye, eye + transform.forward(), Vec3::Y);
+        // Keep the smoother's state current even when off, so turning it on doesn't jump
+        let smoothed = smoothing.smoother.smooth_transform(&look);
+        if lag_weight <= 0.0 {
+            continue;
+        }
+
+        smoothing.target = Some(*transform);
+        *transform = Transform::from_translation(smoothed.eye).looking_at(smoothed.target, Vec3::Y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projectiles_extrapolate_along_their_arc() {
+        let gravity = Vec3::new(0., -10., 0.);
+        let initial_velocity = Vec3::new(4., 5., 0.);
+        let position = |t: f32| initial_velocity * t + gravity * t * t / 2.;
+
+        let mut buffer = InterpolationBuffer::default();
+        buffer.push((0.0, position(0.)));
+        // One sample: launched with the spawn velocity
+        let predicted = buffer.extrapolate(0.1, initial_velocity, gravity).unwrap();
+        assert!(predicted.distance(position(0.1)) < 1e-4);
+
+        // Later samples: velocity comes from the snapshots, even after a bounce
+        // the spawn velocity knows nothing about
+        buffer.push((0.05, position(0.05)));
+        let predicted = buffer.extrapolate(0.15, Vec3::ZERO, gravity).unwrap();
+        assert!(predicted.distance(position(0.15)) < 1e-4);
+
+        // Never further than `MAX_EXTRAPOLATION` past the newest sample
+        let capped = buffer.extrapolate(10.0, Vec3::ZERO, gravity).unwrap();
+        assert!(capped.distance(position(0.05 + MAX_EXTRAPOLATION as f32)) < 1e-4);
+    }
+
+    /// An app running just `client_sync_players`, fed through `PendingServerMessages`.
+    fn sync_players_app() -> App {
+        let (client, transport) = new_renet_client(
+            "127.0.0.1:5000".parse().unwrap(),
+            1,
+            encode_player_name("tester"),
+        )
+        .unwrap();
+        let settings = ClientSettings::default();
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default());
+        app.add_asset::<Mesh>().add_asset::<StandardMaterial>();
+        app.insert_resource(client).insert_resource(transport);
+        app.insert_resource(FloatingOrigin::from_settings(&settings));
+        app.insert_resource(settings);
+        app.init_resource::<KnockUpArc>()
+            .init_resource::<ClientLobby>()
+            .init_resource::<NetworkMapping>()
+            .init_resource::<DeserializeFailures>()
+            .init_resource::<ChunkAssembler>()
+            .init_resource::<PendingServerMessages>()
+            .init_resource::<ChatLog>()
+            .init_resource::<LeaderboardView>()
+            .init_resource::<PlayerListView>()
+            .init_resource::<WorldMarkers>()
+            .init_resource::<MatchResultsView>()
+            .init_resource::<AnnouncementBanner>()
+            .init_resource::<Placeholders>()
+            .init_resource::<ProjectileAssets>()
+            .init_resource::<ProjectileVisuals>()
+            .init_resource::<PlayerModel>()
+            .init_resource::<Decals>()
+            .init_resource::<WorldConfig>()
+            .init_resource::<RapierConfiguration>()
+            .init_resource::<ClientLockstep>()
+            .init_resource::<ProjectileConfig>()
+            .init_resource::<InterpolationDelay>()
+            .init_resource::<EffectBudget>()
+            .init_resource::<CapturePoints>();
+        app.add_event::<PlayerJoinedEvent>()
+            .add_event::<PlayerLeftEvent>()
+            .add_event::<PlayerKilledEvent>()
+            .add_event::<SolanaBlockSpawnedEvent>();
+        app.add_system(client_sync_players);
+        app
+    }
+
+    #[test]
+    fn server_messages_emit_lifecycle_events() {
+        let mut app = sync_players_app();
+
+        let server_entity = Entity::from_raw(7);
+        app.world.resource_mut::<PendingServerMessages>().0.extend([
+            ServerMessages::PlayerCreate {
+                entity: server_entity,
+                id: 2,
+                translation: [0.0; 3],
+            },
+            ServerMessages::SpawnSolanaBlock {
+                entity: Entity::from_raw(8),
+                transform: (0.0, 5.0, 0.0),
+                slot: 42,
+                shape: bevy_playground::BlockShape::Cube,
+            },
+            ServerMessages::PlayerKilled {
+                id: 2,
+                killer: Some(1),
+            },
+            ServerMessages::PlayerRemove { id: 2 },
+        ]);
+        app.update();
+
+        let joined: Vec<_> = app
+            .world
+            .resource_mut::<Events<PlayerJoinedEvent>>()
+            .drain()
+            .collect();
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined[0].id, 2);
+        assert!(!joined[0].local);
+        let blocks: Vec<_> = app
+            .world
+            .resource_mut::<Events<SolanaBlockSpawnedEvent>>()
+            .drain()
+            .collect();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].slot, 42);
+        let killed: Vec<_> = app
+            .world
+            .resource_mut::<Events<PlayerKilledEvent>>()
+            .drain()
+            .collect();
+        assert_eq!((killed[0].id, killed[0].killer), (2, Some(1)));
+        let left: Vec<_> = app
+            .world
+            .resource_mut::<Events<PlayerLeftEvent>>()
+            .drain()
+            .collect();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, 2);
+        assert!(app.world.resource::<ClientLobby>().players.is_empty());
+    }
+
+    #[test]
+    fn effects_gone_with_their_player_free_their_slot() {
+        let mut world = World::new();
+        let mut budget = EffectBudget {
+            max: 1,
+            effects: VecDeque::new(),
+        };
+        let pulse = world.spawn_empty().id();
+        let player = world.spawn_empty().push_children(&[pulse]).id();
+        budget.track(pulse, EffectPriority::High);
+        world.entity_mut(player).despawn_recursive();
+
+        let mut queue = bevy::ecs::system::CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        // Nothing outranks a low priority effect once the pulse is gone
+        assert!(budget.reserve(&mut commands, EffectPriority::Low));
+        assert!(budget.effects.is_empty());
+    }
+
+    #[test]
+    fn model_tints_stay_on_their_own_player() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<StandardMaterial>()
+            .add_system(tint_player_models_system);
+
+        let mut materials = app.world.resource_mut::<Assets<StandardMaterial>>();
+        let shared = materials.add(Color::RED.into());
+        let tints = [
+            materials.add(PLAYER_COLOR.into()),
+            materials.add(PLAYER_COLOR.into()),
+        ];
+        let meshes = tints.clone().map(|tint| {
+            let mesh = app.world.spawn(shared.clone()).id();
+            app.world
+                .spawn((PlayerModelRoot, tint))
+                .push_children(&[mesh]);
+            mesh
+        });
+        let color = |app: &App, mesh: Entity| {
+            let handle = app.world.get::<Handle<StandardMaterial>>(mesh).unwrap();
+            app.world
+                .resource::<Assets<StandardMaterial>>()
+                .get(handle)
+                .unwrap()
+                .base_color
+        };
+
+        app.update();
+        assert_eq!(color(&app, meshes[0]), Color::RED);
+
+        app.world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .get_mut(&tints[0])
+            .unwrap()
+            .base_color = INVULNERABLE_PLAYER_COLOR;
+        app.update();
+        assert_eq!(color(&app, meshes[0]), INVULNERABLE_PLAYER_COLOR);
+        assert_eq!(color(&app, meshes[1]), Color::RED);
+        assert_eq!(
+            app.world
+                .resource::<Assets<StandardMaterial>>()
+                .get(&shared)
+                .unwrap()
+                .base_color,
+            Color::RED
+        );
+
+        app.world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .get_mut(&tints[0])
+            .unwrap()
+            .base_color = PLAYER_COLOR;
+        app.update();
+        assert_eq!(color(&app, meshes[0]), Color::RED);
+    }
+
+    #[test]
+    fn only_observer_cameras_are_kept_in_bounds() {
+        for observer in [false, true] {
+            let settings = ClientSettings {
+                observer,
+                ..default()
+            };
+            let mut app = App::new();
+            app.insert_resource(FloatingOrigin::from_settings(&settings))
+                .insert_resource(settings)
+                .add_system(camera_bounds_system);
+            let camera = app
+                .world
+                .spawn((Transform::from_xyz(100.0, -5.0, 0.0), FlyCam))
+                .id();
+            app.update();
+
+            let expected = if observer {
+                Vec3::new(60.0, 1.0, 0.0)
+            } else {
+                Vec3::new(100.0, -5.0, 0.0)
+            };
+            assert_eq!(
+                app.world.get::<Transform>(camera).unwrap().translation,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn objective_holders_get_their_own_color_back() {
+        let mut app = sync_players_app();
+        let send = |app: &mut App, messages: Vec<ServerMessages>| {
+            app.world
+                .resource_mut::<PendingServerMessages>()
+                .0
+                .extend(messages);
+            app.update();
+        };
+        let color = |app: &mut App, id: u64| {
+            let entity = app.world.resource::<ClientLobby>().players[&id].client_entity;
+            let handle = app.world.get::<Handle<StandardMaterial>>(entity).unwrap();
+            app.world
+                .resource::<Assets<StandardMaterial>>()
+                .get(handle)
+                .unwrap()
+                .base_color
+        };
+
+        send(
+            &mut app,
+            vec![
+                ServerMessages::PlayerCreate {
+                    entity: Entity::from_raw(7),
+                    id: 2,
+                    translation: [0.0; 3],
+                },
+                ServerMessages::PlayerCreate {
+                    entity: Entity::from_raw(8),
+                    id: 3,
+                    translation: [0.0; 3],
+                },
+            ],
+        );
+        send(
+            &mut app,
+            vec![
+                ServerMessages::PlayerInvulnerable {
+                    id: 2,
+                    invulnerable: true,
+                },
+                ServerMessages::ObjectiveHolder { id: Some(2) },
+            ],
+        );
+        assert_eq!(color(&mut app, 2), OBJECTIVE_HOLDER_COLOR);
+
+        send(
+            &mut app,
+            vec![ServerMessages::ObjectiveHolder { id: Some(3) }],
+        );
+        assert_eq!(color(&mut app, 2), INVULNERABLE_PLAYER_COLOR);
+        assert_eq!(color(&mut app, 3), OBJECTIVE_HOLDER_COLOR);
+
+        send(&mut app, vec![ServerMessages::ObjectiveHolder { id: None }]);
+        assert_eq!(color(&mut app, 3), PLAYER_COLOR);
+        assert_eq!(app.world.resource::<ClientLobby>().objective_holder, None);
+    }
+
+    #[test]
+    fn despawning_blocks_shrink_where_they_were_last_drawn() {
+        let mut app = sync_players_app();
+        app.init_resource::<Killcam>()
+            .insert_resource(ServerClock {
+                latest_tick: Some(1),
+                offset: Some(500.0),
+            })
+            .add_systems((interpolate_networked_entities, despawn_animation_system));
+
+        let server_entity = Entity::from_raw(8);
+        app.world
+            .resource_mut::<PendingServerMessages>()
+            .0
+            .push_back(ServerMessages::SpawnSolanaBlock {
+                entity: server_entity,
+                transform: (0.0, 5.0, 0.0),
+                slot: 42,
+                shape: bevy_playground::BlockShape::Cube,
+            });
+        app.update();
+        let block = app.world.resource::<NetworkMapping>().0[&server_entity];
+        // Snapshots that would keep dragging the block along if it chased them
+        let mut buffer = InterpolationBuffer::default();
+        buffer.push((0.0, Vec3::new(0.0, 5.0, 0.0)));
+        buffer.push((1000.0, Vec3::new(100.0, 5.0, 0.0)));
+        app.world.entity_mut(block).insert(buffer);
+        app.update();
+        assert!(app.world.get::<Transform>(block).unwrap().translation.x > 40.0);
+
+        app.world
+            .resource_mut::<PendingServerMessages>()
+            .0
+            .push_back(ServerMessages::DespawnSolanaBlock {
+                entity: server_entity,
+            });
+        app.update();
+        assert!(app.world.get::<InterpolationBuffer>(block).is_none());
+        let frozen = app.world.get::<Transform>(block).unwrap().translation;
+        for _ in 0..3 {
+            app.update();
+        }
+        let transform = app.world.get::<Transform>(block).unwrap();
+        assert_eq!(transform.translation, frozen);
+        assert!(transform.scale.x < 1.0);
+    }
 }
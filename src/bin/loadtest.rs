@@ -0,0 +1,206 @@
+//! Headless load generator: connects `LOADTEST_CLIENTS` bot clients to the
+//! server and has each wander the arena and cast fireballs like a player would.
+
+use std::{
+    net::ToSocketAddrs,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::Vec3;
+use bevy_playground::{
+    encode_player_name, get_server_addr, new_renet_client, ClientChannel, PlayerCommand,
+    PlayerInput, ServerChannel, WorldBounds,
+};
+use bevy_renet::renet::{transport::NetcodeClientTransport, RenetClient};
+
+const TICK: Duration = Duration::from_millis(16);
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Distance a bot walks per second.
+const BOT_SPEED: f32 = 5.0;
+
+#[derive(Debug)]
+struct LoadTestConfig {
+    clients: usize,
+    /// Inputs each client sends per second.
+    input_rate: f32,
+    /// Chance per second that a client casts a basic attack.
+    attack_chance: f32,
+}
+
+impl LoadTestConfig {
+    fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+        Self {
+            clients: var("LOADTEST_CLIENTS", 16),
+            input_rate: var("LOADTEST_INPUT_RATE", 20.0_f32).max(1.0),
+            attack_chance: var("LOADTEST_ATTACK_CHANCE", 0.5),
+        }
+    }
+}
+
+struct Bot {
+    client: RenetClient,
+    transport: NetcodeClientTransport,
+    position: Vec3,
+    heading: Vec3,
+    since_input: Duration,
+}
+
+/// Totals since the last report.
+#[derive(Debug, Default)]
+struct Stats {
+    messages_sent: u64,
+    bytes_sent: u64,
+    messages_received: u64,
+    bytes_received: u64,
+    transport_errors: u64,
+}
+
+impl Bot {
+    fn send(&mut self, channel: ClientChannel, message: &impl serde::Serialize, stats: &mut Stats) {
+        let message = bincode::serialize(message).unwrap();
+        stats.messages_sent += 1;
+        stats.bytes_sent += message.len() as u64;
+        self.client.send_message(channel, message);
+    }
+
+    /// Walks in a straight line, turning at random and off the walls.
+    fn wander(&mut self, delta: Duration, limit: f32) {
+        if fastrand::f32() < delta.as_secs_f32() {
+            self.heading = random_heading();
+        }
+        self.position += self.heading * BOT_SPEED * delta.as_secs_f32();
+        if self.position.x.abs() > limit || self.position.z.abs() > limit {
+            self.position = self
+                .position
+                .clamp(Vec3::new(-limit, 0.0, -limit), Vec3::new(limit, 0.0, limit));
+            self.heading = -self.heading;
+        }
+    }
+}
+
+fn random_heading() -> Vec3 {
+    let angle = fastrand::f32() * std::f32::consts::TAU;
+    Vec3::new(angle.cos(), 0.0, angle.sin())
+}
+
+fn main() {
+    let config = LoadTestConfig::from_env();
+    let server_addr = get_server_addr()
+        .to_socket_addrs()
+        .expect("Invalid SERVER_ADDR")
+        .next()
+        .expect("SERVER_ADDR did not resolve");
+    println!("Load testing {} with {:?}", server_addr, config);
+
+    let limit = WorldBounds::default().size / 2. - 1.0;
+    let mut bots: Vec<Bot> = (0..config.clients)
+        .map(|i| {
+            let client_id = fastrand::u64(..);
+            let user_data = encode_player_name(&format!("loadtest-{}", i));
+            let (client, transport) = new_renet_client(server_addr, client_id, user_data).unwrap();
+            Bot {
+                client,
+                transport,
+                position: Vec3::new(fastrand::f32() * 2. - 1., 0.0, fastrand::f32() * 2. - 1.)
+                    * limit,
+                heading: random_heading(),
+                since_input: Duration::ZERO,
+            }
+        })
+        .collect();
+
+    let input_interval = Duration::from_secs_f32(1.0 / config.input_rate);
+    let mut stats = Stats::default();
+    let mut last_report = Instant::now();
+    let mut last_tick = Instant::now();
+    loop {
+        let now = Instant::now();
+        let delta = now - last_tick;
+        last_tick = now;
+
+        for bot in bots.iter_mut() {
+            bot.client.update(delta);
+            if bot.transport.update(delta, &mut bot.client).is_err() {
+                stats.transport_errors += 1;
+            }
+
+            if bot.transport.is_connected() {
+                for channel in [
+                    ServerChannel::NetworkedEntities,
+                    ServerChannel::ServerMessages,
+                ] {
+                    while let Some(message) = bot.client.receive_message(channel) {
+                        stats.messages_received += 1;
+                        stats.bytes_received += message.len() as u64;
+                    }
+                }
+
+                bot.wander(delta, limit);
+                bot.since_input += delta;
+                if bot.since_input >= input_interval {
+                    bot.since_input = Duration::ZERO;
+                    let input = PlayerInput {
+                        position: bot.position.into(),
+                        ..Default::default()
+                    };
+                    bot.send(ClientChannel::Input, &input, &mut stats);
+                }
+                if fastrand::f32() < config.attack_chance * delta.as_secs_f32() {
+                    let command = PlayerCommand::BasicAttack {
+                        cast_at: bot.position + random_heading() * 10.0,
+                        charge: fastrand::f32(),
+                    };
+                    bot.send(ClientChannel::Command, &command, &mut stats);
+                }
+            }
+
+            if bot.transport.send_packets(&mut bot.client).is_err() {
+                stats.transport_errors += 1;
+            }
+        }
+
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            report(&bots, &stats, last_report.elapsed());
+            stats = Stats::default();
+            last_report = Instant::now();
+        }
+
+        thread::sleep(TICK.saturating_sub(now.elapsed()));
+    }
+}
+
+fn report(bots: &[Bot], stats: &Stats, elapsed: Duration) {
+    let connected: Vec<&Bot> = bots
+        .iter()
+        .filter(|bot| bot.transport.is_connected())
+        .collect();
+    let secs = elapsed.as_secs_f64();
+    let average_rtt = if connected.is_empty() {
+        0.0
+    } else {
+        connected
+            .iter()
+            .map(|bot| bot.client.network_info().rtt)
+            .sum::<f64>()
+            / connected.len() as f64
+    };
+    println!(
+        "{}/{} connected | sent {:.0} msg/s {:.1} KiB/s | received {:.0} msg/s {:.1} KiB/s | \
+         avg rtt {:.1}ms | transport errors {}",
+        connected.len(),
+        bots.len(),
+        stats.messages_sent as f64 / secs,
+        stats.bytes_sent as f64 / 1024.0 / secs,
+        stats.messages_received as f64 / secs,
+        stats.bytes_received as f64 / 1024.0 / secs,
+        average_rtt,
+        stats.transport_errors,
+    );
+}
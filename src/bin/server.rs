@@ -1,14 +1,32 @@
-use std::{collections::HashMap, f32::consts::PI, net::UdpSocket, time::SystemTime};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    f32::consts::PI,
+    fs::{self, File},
+    io::BufWriter,
+    net::UdpSocket,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
 use bevy::{
-    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    app::AppExit,
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    ecs::system::SystemParam,
     prelude::*,
-    window::ExitCondition,
+    time::Stopwatch,
 };
 use bevy_playground::{
-    camera_zoom_system, connection_config, get_server_addr, setup_level, spawn_fireball,
-    ClientChannel, NetworkedEntities, Player, PlayerCommand, PlayerInput, Projectile,
-    ServerChannel, ServerMessages, SolanaSlotBlock, PROTOCOL_ID,
+    ball_mesh, ball_physics, configure_solver_system, connection_config, decode_player_name,
+    get_server_addr, is_observer, player_physics, send_chunked, solana_block_physics,
+    spawn_fireball_server, spread_directions, verify_wallet_identity, Ball, BallConfig, BlockShape,
+    CameraConfig, ClientChannel, EntityRequest, FloorGrid, Health, Invulnerable, LeaderboardEntry,
+    LockstepMessage, LockstepState, Mana, MaterialPreset, MovementMode, NetworkedEntities, Player,
+    PlayerCommand, PlayerInput, PlayerSummary, Projectile, ProjectileConfig, ProjectileKind,
+    ReplayEntity, ReplayEntityKind, ReplayFrame, ReplayHeader, ReplayPlayer, ReplayWriter,
+    ServerChannel, ServerMessages, SolanaSlotBlock, SpawnProtection, Team, WalletIdentity,
+    WorldBounds, WorldConfig, GROUND_GROUP, PLAYER_MAX_HEALTH, PLAYER_MAX_MANA, PROTOCOL_ID,
+    WALLET_CHALLENGE_WINDOW_SECS, WALL_GROUP,
 };
 use bevy_rapier3d::prelude::*;
 use bevy_renet::{
@@ -20,17 +38,22 @@ use bevy_renet::{
     RenetServerPlugin,
 };
 
+use serde::de::DeserializeOwned;
 use solana_client::rpc_client::RpcClient;
+use solana_sdk::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey};
 
 #[cfg(debug_assertions)]
-use bevy_egui::{EguiContexts, EguiPlugin};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
+#[cfg(debug_assertions)]
+use bevy_playground::camera_zoom_system;
 #[cfg(debug_assertions)]
 use renet_visualizer::RenetServerVisualizer;
 
-pub const SOLANA_LOCALHOST: &'static str = "http://localhost:8899";
-pub const SOLANA_DEVNET: &'static str = "https://api.devnet.solana.com";
-pub const SOLANA_MAINNET: &'static str = "https://api.mainnet-beta.solana.com";
+pub const SOLANA_LOCALHOST: &str = "http://localhost:8899";
+pub const SOLANA_DEVNET: &str = "https://api.devnet.solana.com";
+pub const SOLANA_MAINNET: &str = "https://api.mainnet-beta.solana.com";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SolanaRpcUrl {
     Localhost,
     Devnet,
@@ -39,7 +62,7 @@ pub enum SolanaRpcUrl {
 
 impl SolanaRpcUrl {
     fn default() -> Self {
-        return SolanaRpcUrl::Mainnet;
+        SolanaRpcUrl::Mainnet
     }
 
     fn as_str(&self) -> &'static str {
@@ -49,6 +72,40 @@ impl SolanaRpcUrl {
             SolanaRpcUrl::Mainnet => SOLANA_MAINNET,
         }
     }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "localhost" => Some(SolanaRpcUrl::Localhost),
+            "devnet" => Some(SolanaRpcUrl::Devnet),
+            "mainnet" => Some(SolanaRpcUrl::Mainnet),
+            _ => None,
+        }
+    }
+}
+
+/// Reads `SOLANA_RPC_FALLBACKS`, a comma-separated list of `localhost`,
+/// `devnet` and `mainnet`.
+fn rpc_fallbacks_from_env(primary: SolanaRpcUrl) -> Vec<SolanaRpcUrl> {
+    std::env::var("SOLANA_RPC_FALLBACKS")
+        .map(|names| parse_rpc_fallbacks(&names, primary))
+        .unwrap_or_default()
+}
+
+/// Skips `primary`, repeats and unknown names.
+fn parse_rpc_fallbacks(names: &str, primary: SolanaRpcUrl) -> Vec<SolanaRpcUrl> {
+    let mut fallbacks = Vec::new();
+    for name in names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match SolanaRpcUrl::from_name(name) {
+            Some(rpc) if rpc != primary && !fallbacks.contains(&rpc) => fallbacks.push(rpc),
+            Some(_) => {}
+            None => println!("Unknown Solana RPC {} in SOLANA_RPC_FALLBACKS", name),
+        }
+    }
+    fallbacks
 }
 
 #[derive(Component, Resource)]
@@ -56,529 +113,6428 @@ pub struct Solana {
     pub rpc: SolanaRpcUrl,
     pub client: RpcClient,
     pub faucet_on: bool,
+    /// Newest slot already turned into blocks, used by `SolanaSpawnMode::ConfirmedBlocks`.
+    pub last_processed_slot: Option<u64>,
+    /// Newest slot a block was spawned for, in either mode. Slots at or
+    /// below it are never spawned again.
+    pub last_spawned_slot: Option<u64>,
+    /// Endpoints tried in order when `rpc` keeps failing.
+    pub fallbacks: Vec<SolanaRpcUrl>,
+    pub consecutive_failures: u32,
+    /// Elapsed seconds before which this connection is not polled.
+    pub retry_at: f32,
 }
 
+/// Consecutive failures before the `RpcClient` is rebuilt on the next endpoint.
+const RPC_FAILURES_BEFORE_RECONNECT: u32 = 3;
+const RPC_BACKOFF_BASE_SECS: f32 = 30.0;
+const RPC_BACKOFF_MAX_SECS: f32 = 300.0;
+
 impl Solana {
     fn default() -> Self {
+        Solana::new(SolanaRpcUrl::default(), false)
+    }
+
+    fn new(rpc: SolanaRpcUrl, faucet_on: bool) -> Self {
         Solana {
-            rpc: SolanaRpcUrl::default(),
-            client: RpcClient::new(SolanaRpcUrl::default().as_str()),
-            faucet_on: false,
+            rpc,
+            client: RpcClient::new(rpc.as_str()),
+            faucet_on,
+            last_processed_slot: None,
+            last_spawned_slot: None,
+            fallbacks: rpc_fallbacks_from_env(rpc),
+            consecutive_failures: 0,
+            retry_at: 0.0,
         }
     }
-}
 
-#[derive(Debug, Default, Resource)]
-pub struct ServerLobby {
-    pub players: HashMap<u64, Entity>,
-}
+    /// Drops slots that already have a block, such as the same epoch slot
+    /// seen on two ticks, and records the newest one kept.
+    fn take_unspawned_slots(&mut self, mut slots: Vec<u64>) -> Vec<u64> {
+        if let Some(last) = self.last_spawned_slot {
+            slots.retain(|slot| *slot > last);
+        }
+        if let Some(newest) = slots.iter().max() {
+            self.last_spawned_slot = Some(*newest);
+        }
+        slots
+    }
 
-const PLAYER_MOVE_SPEED: f32 = 5.0;
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
 
-#[derive(Debug, Component)]
-struct Bot {
-    auto_cast: Timer,
-}
+    /// Backs off exponentially and moves to the next endpoint every
+    /// `RPC_FAILURES_BEFORE_RECONNECT` failures.
+    fn record_failure(&mut self, now: f32) {
+        self.consecutive_failures += 1;
+        let backoff = RPC_BACKOFF_BASE_SECS * 2f32.powi(self.consecutive_failures as i32 - 1);
+        self.retry_at = now + backoff.min(RPC_BACKOFF_MAX_SECS);
 
-#[derive(Debug, Resource)]
-struct BotId(u64);
+        if self
+            .consecutive_failures
+            .is_multiple_of(RPC_FAILURES_BEFORE_RECONNECT)
+        {
+            self.reconnect();
+        }
+    }
 
-fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
-    let server = RenetServer::new(connection_config());
+    fn reconnect(&mut self) {
+        if !self.fallbacks.is_empty() {
+            let next = self.fallbacks.remove(0);
+            let previous = std::mem::replace(&mut self.rpc, next);
+            self.fallbacks.push(previous);
+        }
+        println!("Reconnecting to Solana RPC {}", self.rpc.as_str());
+        self.client = RpcClient::new(self.rpc.as_str());
+    }
+}
 
-    let public_addr = get_server_addr().parse().unwrap();
-    let socket = UdpSocket::bind(public_addr).unwrap();
-    let server_config = ServerConfig {
-        max_clients: 64,
-        protocol_id: PROTOCOL_ID,
-        public_addr,
-        authentication: ServerAuthentication::Unsecure,
-    };
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap();
+/// How `spawn_solana_blocks` decides which slots become blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolanaSpawnMode {
+    /// One block per timer tick for the current epoch slot.
+    EpochSlot,
+    /// One block per confirmed block produced since the previous tick.
+    ConfirmedBlocks,
+}
 
-    let transport = NetcodeServerTransport::new(current_time, server_config, socket).unwrap();
+#[derive(Debug, Resource)]
+pub struct SolanaSpawnConfig {
+    pub mode: SolanaSpawnMode,
+    /// Time between spawn ticks. Admins can change it with `/spawninterval`.
+    pub interval: Duration,
+    /// Caps how many blocks a single tick spawns when catching up after a pause.
+    /// The newest slots are kept and older ones are skipped.
+    pub max_blocks_per_tick: usize,
+    /// How long a block lives before it is despawned. `None` keeps blocks until shot.
+    pub block_ttl: Option<Duration>,
+    /// Blocks spawn at a random height between these two values.
+    pub block_spawn_height_min: f32,
+    pub block_spawn_height_max: f32,
+    /// Each block takes one of these shapes at random. Read from `BLOCK_SHAPES`;
+    /// empty means cubes only.
+    pub block_shapes: Vec<BlockShape>,
+    /// Health blocks spawn with, read from `BLOCK_HEALTH`. `None` breaks
+    /// blocks on the first hit.
+    pub block_health: Option<f32>,
+}
 
-    (server, transport)
+impl Default for SolanaSpawnConfig {
+    fn default() -> Self {
+        Self {
+            mode: SolanaSpawnMode::EpochSlot,
+            interval: Duration::from_secs(30),
+            max_blocks_per_tick: 20,
+            block_ttl: Some(Duration::from_secs(300)),
+            block_spawn_height_min: 15.0,
+            block_spawn_height_max: 30.0,
+            block_shapes: vec![BlockShape::Cube],
+            block_health: None,
+        }
+    }
 }
 
-pub struct SolanaPlugin;
+impl SolanaSpawnConfig {
+    fn from_env() -> Self {
+        let mut config = SolanaSpawnConfig::default();
+        if let Ok(shapes) = std::env::var("BLOCK_SHAPES") {
+            config.block_shapes = shapes
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .filter_map(|name| match BlockShape::from_name(name) {
+                    Some(shape) => Some(shape),
+                    None => {
+                        println!("Unknown block shape {} in BLOCK_SHAPES", name);
+                        None
+                    }
+                })
+                .collect();
+        }
+        config.block_health = std::env::var("BLOCK_HEALTH")
+            .ok()
+            .and_then(|health| health.parse::<f32>().ok())
+            .filter(|health| *health > 0.0);
+        config
+    }
 
-impl Plugin for SolanaPlugin {
-    fn build(&self, app: &mut App) {
-        app.insert_resource(LogConnectionsTimer(Timer::from_seconds(
-            30.0,
-            TimerMode::Repeating,
-        )))
-        .insert_resource(Solana::default())
-        // .add_startup_system(add_devnet_connection)
-        .add_startup_system(add_mainnet_connection)
-        .add_system(spawn_solana_blocks);
+    /// Picks a spawn height within the configured range.
+    pub fn block_spawn_height(&self) -> f32 {
+        let min = self.block_spawn_height_min.min(self.block_spawn_height_max);
+        let max = self.block_spawn_height_min.max(self.block_spawn_height_max);
+        min + fastrand::f32() * (max - min)
+    }
+
+    /// Picks one of the configured shapes.
+    pub fn block_shape(&self) -> BlockShape {
+        if self.block_shapes.is_empty() {
+            return BlockShape::Cube;
+        }
+        self.block_shapes[fastrand::usize(..self.block_shapes.len())]
     }
 }
 
-fn add_mainnet_connection(mut commands: Commands) {
-    commands.spawn(Solana {
-        rpc: SolanaRpcUrl::Mainnet,
-        client: RpcClient::new(SolanaRpcUrl::Mainnet.as_str()),
-        faucet_on: true,
-    });
+/// Faucet airdrop requested when a localhost/devnet connection with
+/// `faucet_on` is added. Never requested on mainnet.
+#[derive(Debug, Resource)]
+pub struct AirdropConfig {
+    /// Read from `AIRDROP_PUBKEY`; no airdrop is requested without one.
+    pub target: Option<Pubkey>,
+    /// Read from `AIRDROP_LAMPORTS`, defaults to 1 SOL.
+    pub lamports: u64,
 }
 
-#[derive(Resource)]
-struct LogConnectionsTimer(Timer);
+impl AirdropConfig {
+    fn from_env() -> Self {
+        let target = std::env::var("AIRDROP_PUBKEY")
+            .ok()
+            .and_then(|pubkey| match pubkey.parse() {
+                Ok(pubkey) => Some(pubkey),
+                Err(e) => {
+                    println!("Invalid AIRDROP_PUBKEY {}: {}", pubkey, e);
+                    None
+                }
+            });
+        let lamports = std::env::var("AIRDROP_LAMPORTS")
+            .ok()
+            .and_then(|lamports| lamports.parse().ok())
+            .unwrap_or(LAMPORTS_PER_SOL);
 
-fn spawn_solana_blocks(
-    time: Res<Time>,
-    mut timer: ResMut<LogConnectionsTimer>,
-    query: Query<&Solana>,
-    solana: ResMut<Solana>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut server: ResMut<RenetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    // Runs after first tick
-    if timer.0.tick(time.delta()).just_finished() {
-        println!("Connected to  {}", solana.rpc.as_str());
+        AirdropConfig { target, lamports }
+    }
+}
 
-        // Run for each connected solana rpc if it is on
-        for solana in &query {
-            if solana.faucet_on {
-                let epoch = solana.client.get_epoch_info().unwrap();
-                println!("Spawning Solana block for slot: {}", epoch.absolute_slot);
-
-                let spawn_location_transform = Transform::from_xyz(0.0, 20.0, 0.0);
-                println!("Spawn location: {:?}", spawn_location_transform.translation);
-
-                // Spawn new
-                let entity: Entity = commands
-                    .spawn(PbrBundle {
-                        mesh: meshes.add(Mesh::from(shape::Box::new(1.0, 1.0, 1.0))),
-                        material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                        transform: spawn_location_transform,
-                        ..Default::default()
-                    })
-                    .insert(RigidBody::Dynamic)
-                    .insert(Collider::cuboid(1.0, 1.0, 1.0))
-                    .insert(Restitution::coefficient(0.7))
-                    .insert(SolanaSlotBlock {
-                        id: epoch.absolute_slot,
-                    })
-                    .id();
+/// Shorter intervals spawn blocks faster than physics comfortably handles.
+const MIN_SOLANA_SPAWN_INTERVAL: Duration = Duration::from_secs(1);
 
-                println!("Created Solana block entity: {:?}", entity);
+const AIRDROP_CONFIRM_ATTEMPTS: u32 = 30;
 
-                let message = ServerMessages::SpawnSolanaBlock {
-                    entity: entity,
-                    transform: (0.0, 20.0, 0.0),
-                    slot: epoch.absolute_slot,
-                };
+#[derive(Debug, Default, Resource)]
+pub struct ServerLobby {
+    pub players: HashMap<u64, Entity>,
+    /// Connected clients watching without a player.
+    pub observers: HashSet<u64>,
+    /// Players of disconnected clients, kept until `ReconnectConfig::grace`
+    /// runs out so a client that comes back resumes the same entity.
+    pub reconnecting: HashMap<u64, Entity>,
+}
 
-                let message = bincode::serialize(&message).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-                println!("Broadcasted Solana block spawn message");
-            }
+impl ServerLobby {
+    /// Frees `client_id` for a new connection. Netcode won't hold two
+    /// connections with one id, so an id still in `players` belongs to either
+    /// a bot, returned as the error since the connection has to be refused,
+    /// or a player whose disconnect was missed. That player is moved to
+    /// `reconnecting` and returned.
+    fn claim_id(
+        &mut self,
+        client_id: u64,
+        is_bot: impl Fn(Entity) -> bool,
+    ) -> Result<Option<Entity>, Entity> {
+        let Some(&existing) = self.players.get(&client_id) else {
+            return Ok(None);
+        };
+        if is_bot(existing) {
+            return Err(existing);
         }
+        self.players.remove(&client_id);
+        self.reconnecting.insert(client_id, existing);
+        Ok(Some(existing))
     }
 }
 
-fn main() {
-    let mut app = App::new();
+/// How long a disconnected player's entity is kept for its client to
+/// reconnect to, read from `RECONNECT_GRACE_SECS` (default 10, 0 removes
+/// players as soon as they disconnect). Resuming keeps the entity id the
+/// reconnecting client and everyone else already have.
+#[derive(Debug, Resource)]
+struct ReconnectConfig {
+    grace: Duration,
+}
 
-    #[cfg(debug_assertions)]
-    {
-        app.add_plugins(DefaultPlugins);
-        app.add_plugin(RapierDebugRenderPlugin::default());
-        app.add_plugin(EguiPlugin);
-    }
-    #[cfg(not(debug_assertions))]
-    {
-        app.add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: None,
-            close_when_requested: false,
-            exit_condition: ExitCondition::DontExit,
-        }));
+impl ReconnectConfig {
+    fn from_env() -> Self {
+        let secs = std::env::var("RECONNECT_GRACE_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<f32>().ok())
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .unwrap_or(10.0);
+        Self {
+            grace: Duration::from_secs_f32(secs),
+        }
     }
+}
 
-    app.add_plugin(RenetServerPlugin);
-    app.add_plugin(NetcodeServerPlugin);
-    app.add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
-    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
-    app.add_plugin(LogDiagnosticsPlugin::default());
-
-    app.add_plugin(SolanaPlugin);
-
-    app.insert_resource(ServerLobby::default());
-    app.insert_resource(BotId(0));
-
-    let (server, transport) = new_renet_server();
-    app.insert_resource(server);
-    app.insert_resource(transport);
+/// On the player of a disconnected client, counting toward `ReconnectConfig::grace`.
+#[derive(Component, Default)]
+struct AwaitingReconnect(Stopwatch);
 
-    #[cfg(debug_assertions)]
-    app.insert_resource(RenetServerVisualizer::<200>::default());
+/// Upper bound on server frames per second, read from `SERVER_FRAME_RATE`
+/// (default 60, 0 for uncapped), held as the shortest time a frame may take.
+/// Without it the headless server spins as fast as it can even when idle.
+#[derive(Debug, Resource)]
+pub struct FrameRateCap(pub Option<Duration>);
 
-    app.add_systems((
-        server_update_system,
-        server_network_sync,
-        move_players_system,
-        update_projectiles_system,
-        #[cfg(debug_assertions)]
-        update_visualizer_system,
-        projectile_collision_system,
-        spawn_bot,
-        bot_autocast,
-    ));
+/// Slowest `SERVER_FRAME_RATE` accepted. Anything lower is almost certainly a
+/// typo, and tiny rates overflow the frame time.
+const MIN_SERVER_FRAME_RATE: f32 = 1.0;
 
-    app.add_system(projectile_on_removal_system.in_base_set(CoreSet::PostUpdate));
-    app.add_system(solana_block_on_removal_system.in_base_set(CoreSet::PostUpdate));
-    app.add_startup_system(setup_level);
-    #[cfg(debug_assertions)] {
-        app.add_system(camera_zoom_system);
-        app.add_system(camera_movement_system);
+impl FrameRateCap {
+    fn from_env() -> Self {
+        let rate = std::env::var("SERVER_FRAME_RATE")
+            .ok()
+            .and_then(|rate| rate.parse::<f32>().ok())
+            .unwrap_or(60.0);
+        Self::from_rate(rate)
     }
 
-    app.run();
+    fn from_rate(rate: f32) -> Self {
+        if rate == 0.0 {
+            return FrameRateCap(None);
+        }
+        let rate = if rate.is_finite() && rate >= MIN_SERVER_FRAME_RATE {
+            rate
+        } else {
+            println!("Invalid SERVER_FRAME_RATE {}, capping at 60", rate);
+            60.0
+        };
+        FrameRateCap(Some(Duration::from_secs_f32(1.0 / rate)))
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn server_update_system(
-    mut server_events: EventReader<ServerEvent>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut lobby: ResMut<ServerLobby>,
-    mut server: ResMut<RenetServer>,
-    players: Query<(Entity, &Player, &Transform)>,
-) {
-    for event in server_events.iter() {
-        match event {
-            ServerEvent::ClientConnected { client_id } => {
-                println!("Player {} connected.", client_id);
+/// Sleeps away whatever is left of the frame budget. Runs last so the
+/// frame's work counts towards the budget.
+fn frame_rate_cap_system(cap: Res<FrameRateCap>, mut frame_start: Local<Option<Instant>>) {
+    if let (Some(frame_time), Some(start)) = (cap.0, *frame_start) {
+        let elapsed = start.elapsed();
+        if elapsed < frame_time {
+            thread::sleep(frame_time - elapsed);
+        }
+    }
+    *frame_start = Some(Instant::now());
+}
 
-                // Initialize other players for this new client
-                for (entity, player, transform) in players.iter() {
-                    let translation: [f32; 3] = transform.translation.into();
-                    let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                        id: player.id,
-                        entity,
-                        translation,
-                    })
-                    .unwrap();
-                    server.send_message(*client_id, ServerChannel::ServerMessages, message);
-                }
+/// Client ids allowed to run admin chat commands, read from the comma
+/// separated `ADMIN_IDS` environment variable.
+#[derive(Debug, Default, Resource)]
+pub struct AdminList(pub HashSet<u64>);
 
-                // Spawn new player
-                let transform = Transform::from_xyz(
-                    (fastrand::f32() - 0.5) * 40.,
-                    0.51,
-                    (fastrand::f32() - 0.5) * 40.,
-                );
-                let player_entity = commands
-                    .spawn(PbrBundle {
-                        mesh: meshes.add(Mesh::from(shape::Capsule::default())),
-                        material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                        transform,
-                        ..Default::default()
-                    })
-                    .insert(RigidBody::Dynamic)
-                    .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y)
-                    .insert(Collider::capsule_y(0.5, 0.5))
-                    .insert(PlayerInput::default())
-                    .insert(Velocity::default())
-                    .insert(Player { id: *client_id })
-                    .id();
+impl AdminList {
+    fn from_env() -> Self {
+        let ids = std::env::var("ADMIN_IDS").unwrap_or_default();
+        AdminList(
+            ids.split(',')
+                .filter_map(|id| id.trim().parse().ok())
+                .collect(),
+        )
+    }
+}
 
-                lobby.players.insert(*client_id, player_entity);
+/// Commands sent over chat as `/<name> <args>`.
+#[derive(Debug)]
+enum ChatCommand {
+    /// Toggles [`Invulnerable`] on a player.
+    God { id: u64 },
+    /// Lists connected players and bots to the requester.
+    Players,
+    /// Sets the Solana block spawn interval.
+    SpawnInterval { interval: Duration },
+    /// Moves a player to a position.
+    Teleport { id: u64, position: Vec3 },
+    /// Sends the requester block telemetry totals and the latest `count` block events.
+    Blocks { count: usize },
+}
 
-                let translation: [f32; 3] = transform.translation.into();
-                let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                    id: *client_id,
-                    entity: player_entity,
-                    translation,
-                })
-                .unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
+impl ChatCommand {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut args = text.split_whitespace();
+        match args.next() {
+            Some("god") => {
+                let id = args
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or("Usage: /god <id>")?;
+                Ok(ChatCommand::God { id })
             }
-            ServerEvent::ClientDisconnected { client_id, reason } => {
-                println!("Player {} disconnected: {}", client_id, reason);
-                if let Some(player_entity) = lobby.players.remove(client_id) {
-                    commands.entity(player_entity).despawn();
+            Some("players") => Ok(ChatCommand::Players),
+            Some("spawninterval") => {
+                // Rejects negative, infinite and out of range values alike
+                let interval = args
+                    .next()
+                    .and_then(|secs| secs.parse::<f32>().ok())
+                    .and_then(|secs| Duration::try_from_secs_f32(secs).ok())
+                    .ok_or("Usage: /spawninterval <seconds>")?;
+                Ok(ChatCommand::SpawnInterval { interval })
+            }
+            Some("tp") => {
+                let usage = "Usage: /tp <id> <x> <y> <z>";
+                let id = args.next().and_then(|id| id.parse().ok()).ok_or(usage)?;
+                let mut position = Vec3::ZERO;
+                for axis in 0..3 {
+                    position[axis] = args
+                        .next()
+                        .and_then(|value| value.parse::<f32>().ok())
+                        .filter(|value| value.is_finite())
+                        .ok_or(usage)?;
                 }
-
-                let message =
-                    bincode::serialize(&ServerMessages::PlayerRemove { id: *client_id }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
+                Ok(ChatCommand::Teleport { id, position })
             }
+            Some("blocks") => {
+                let count = match args.next() {
+                    Some(count) => count.parse().map_err(|_| "Usage: /blocks [count]")?,
+                    None => 5,
+                };
+                Ok(ChatCommand::Blocks { count })
+            }
+            Some(name) => Err(format!("Unknown command /{}", name)),
+            None => Err("Empty command".to_string()),
         }
     }
 
-    for client_id in server.clients_id() {
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
-            let command: PlayerCommand = bincode::deserialize(&message).unwrap();
-            match command {
-                PlayerCommand::BasicAttack { mut cast_at } => {
-                    println!(
-                        "Received basic attack from client {}: {:?}",
-                        client_id, cast_at
-                    );
-
-                    if let Some(player_entity) = lobby.players.get(&client_id) {
-                        if let Ok((_, _, player_transform)) = players.get(*player_entity) {
-                            cast_at[1] = player_transform.translation[1];
-
-                            let direction =
-                                (cast_at - player_transform.translation).normalize_or_zero();
-                            let mut translation = player_transform.translation + (direction * 0.7);
-                            translation[1] = 1.0;
-
-                            let fireball_entity = spawn_fireball(
-                                &mut commands,
-                                &mut meshes,
-                                &mut materials,
-                                translation,
-                                direction,
-                            );
-                            let message = ServerMessages::SpawnProjectile {
-                                entity: fireball_entity,
-                                translation: translation.into(),
-                            };
-                            let message = bincode::serialize(&message).unwrap();
-                            server.broadcast_message(ServerChannel::ServerMessages, message);
-                        }
-                    }
-                }
-            }
-        }
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
-            let input: PlayerInput = bincode::deserialize(&message).unwrap();
-            if let Some(player_entity) = lobby.players.get(&client_id) {
-                commands.entity(*player_entity).insert(input);
-            }
+    fn requires_admin(&self) -> bool {
+        match self {
+            ChatCommand::God { .. }
+            | ChatCommand::SpawnInterval { .. }
+            | ChatCommand::Teleport { .. }
+            | ChatCommand::Blocks { .. } => true,
+            ChatCommand::Players => false,
         }
     }
 }
 
-fn update_projectiles_system(
-    mut commands: Commands,
-    mut projectiles: Query<(Entity, &mut Projectile)>,
-    time: Res<Time>,
-) {
-    for (entity, mut projectile) in projectiles.iter_mut() {
-        projectile.duration.tick(time.delta());
-        if projectile.duration.finished() {
-            commands.entity(entity).despawn();
+const LEADERBOARD_SIZE: usize = 10;
+
+/// Milestones that are announced to every client.
+#[derive(Debug, Resource)]
+pub struct AnnouncerConfig {
+    /// Session scores announced when a player reaches them.
+    pub score_thresholds: Vec<u64>,
+    /// Blocks one player must destroy within `multi_kill_window` to be announced.
+    pub multi_kill_count: usize,
+    pub multi_kill_window: f32,
+    /// Spawned slots crossing a multiple of this are announced. `0` disables it.
+    pub slot_interval: u64,
+}
+
+impl Default for AnnouncerConfig {
+    fn default() -> Self {
+        Self {
+            score_thresholds: vec![10, 25, 50, 100],
+            multi_kill_count: 3,
+            multi_kill_window: 5.0,
+            slot_interval: 1000,
         }
     }
 }
 
-#[allow(clippy::type_complexity)]
-fn server_network_sync(
-    mut server: ResMut<RenetServer>,
-    query: Query<(Entity, &Transform), Or<(With<Player>, With<Projectile>, With<SolanaSlotBlock>)>>,
-) {
-    let mut networked_entities = NetworkedEntities::default();
-    for (entity, transform) in query.iter() {
-        networked_entities.entities.push(entity);
-        networked_entities
-            .translations
-            .push(transform.translation.into());
+/// What the announcer has seen so far.
+#[derive(Debug, Default, Resource)]
+pub struct Announcer {
+    /// Times of each player's recent block kills, keyed by client id.
+    recent_kills: HashMap<u64, Vec<f32>>,
+    last_slot: Option<u64>,
+}
+
+impl Announcer {
+    /// Records a kill at `now` and returns how many landed within the window
+    /// if that makes a multi-kill.
+    fn record_kill(&mut self, config: &AnnouncerConfig, id: u64, now: f32) -> Option<usize> {
+        let kills = self.recent_kills.entry(id).or_default();
+        kills.retain(|time| now - *time <= config.multi_kill_window);
+        kills.push(now);
+        if config.multi_kill_count > 1 && kills.len() >= config.multi_kill_count {
+            let count = kills.len();
+            kills.clear();
+            return Some(count);
+        }
+        None
+    }
+
+    /// Returns the milestone crossed by spawning `slot`, if any.
+    fn record_slot(&mut self, config: &AnnouncerConfig, slot: u64) -> Option<u64> {
+        let previous = self.last_slot.replace(slot);
+        if config.slot_interval == 0 {
+            return None;
+        }
+        let milestone = slot / config.slot_interval * config.slot_interval;
+        match previous {
+            Some(previous) if previous < milestone => Some(milestone),
+            _ => None,
+        }
     }
+}
 
-    let sync_message = bincode::serialize(&networked_entities).unwrap();
-    server.broadcast_message(ServerChannel::NetworkedEntities, sync_message);
+fn announce(outbox: &mut ServerOutbox, text: String) {
+    println!("Announcement: {}", text);
+    outbox.broadcast(ServerMessages::Announcement { text });
 }
 
-fn move_players_system(mut query: Query<(&mut Transform, &PlayerInput), With<Player>>) {
-    for (mut transform, input) in query.iter_mut() {
-        // Update the player's position based on the camera position
-        transform.translation = Vec3::from(input.position);
-    }
+/// Scores keyed by `Player::leaderboard_key` so they survive reconnects and restarts.
+#[derive(Debug, Default, Resource)]
+pub struct Leaderboard {
+    /// Persisted totals, including everything scored this session.
+    pub all_time: HashMap<String, u64>,
+    /// Points scored this session keyed by client id.
+    pub live: HashMap<u64, u64>,
+    /// Changed since the last broadcast.
+    dirty: bool,
+    /// Changed since the last save.
+    unsaved: bool,
 }
 
-fn camera_movement_system(
-    time: Res<Time>,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<&mut Transform, With<Camera>>,
-) {
-    let mut direction = Vec3::ZERO;
-    if keyboard_input.pressed(KeyCode::W) {
-        direction.z -= 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::S) {
-        direction.z += 1.0;
+impl Leaderboard {
+    pub fn add_score(&mut self, id: u64, name: &str, points: u64) {
+        *self.live.entry(id).or_default() += points;
+        *self.all_time.entry(name.to_string()).or_default() += points;
+        self.dirty = true;
+        self.unsaved = true;
     }
-    if keyboard_input.pressed(KeyCode::A) {
-        direction.x -= 1.0;
+
+    pub fn top(&self, count: usize) -> Vec<LeaderboardEntry> {
+        let mut entries: Vec<LeaderboardEntry> = self
+            .all_time
+            .iter()
+            .map(|(name, score)| LeaderboardEntry {
+                name: name.clone(),
+                score: *score,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        entries.truncate(count);
+        entries
     }
-    if keyboard_input.pressed(KeyCode::D) {
-        direction.x += 1.0;
+
+    /// This session's scores for `players` (with whether each is a bot), highest first.
+    pub fn summaries<'a>(
+        &self,
+        players: impl Iterator<Item = (&'a Player, bool)>,
+    ) -> Vec<PlayerSummary> {
+        let mut summaries: Vec<PlayerSummary> = players
+            .map(|(player, is_bot)| PlayerSummary {
+                id: player.id,
+                name: player.name.clone(),
+                score: self.live.get(&player.id).copied().unwrap_or(0),
+                is_bot,
+            })
+            .collect();
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.score));
+        summaries
     }
+}
 
-    if direction != Vec3::ZERO {
-        let speed = 50.0; // Adjust the speed as needed
-        for mut transform in query.iter_mut() {
-            transform.translation += speed * direction.normalize() * time.delta_seconds();
+/// Whether a match is being played or its results are being shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Resource)]
+enum GamePhase {
+    Playing,
+    Ended,
+}
+
+/// What ends a match besides `MatchConfig::duration` running out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WinCondition {
+    /// Only the clock; the match is decided on the standings.
+    TimeLimit,
+    /// The first players to reach this score win.
+    ScoreTarget(u64),
+    /// Players whose health runs out are eliminated until the next match. The
+    /// last player or team left wins.
+    LastStanding,
+}
+
+impl WinCondition {
+    /// Parses `time`, `score:<target>` or `last_standing`.
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "time" => Some(WinCondition::TimeLimit),
+            "last_standing" => Some(WinCondition::LastStanding),
+            name => name
+                .strip_prefix("score:")
+                .and_then(|target| target.parse().ok())
+                .map(WinCondition::ScoreTarget),
         }
     }
 }
 
-pub fn setup_simple_camera(mut commands: Commands) {
-    // camera
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(0., 30.0, 20.5).looking_at(Vec3::ZERO, Vec3::Y),
-        ..Default::default()
-    });
+#[derive(Debug, Resource)]
+struct MatchConfig {
+    duration: Duration,
+    /// How long results are shown before the next match starts.
+    results_duration: Duration,
+    /// Read from `WIN_CONDITION`.
+    win_condition: WinCondition,
 }
 
-fn projectile_collision_system(
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(300),
+            results_duration: Duration::from_secs(15),
+            win_condition: WinCondition::TimeLimit,
+        }
+    }
+}
+
+impl MatchConfig {
+    fn from_env() -> Self {
+        let mut config = MatchConfig::default();
+        if let Ok(name) = std::env::var("WIN_CONDITION") {
+            match WinCondition::from_name(&name) {
+                Some(win_condition) => config.win_condition = win_condition,
+                None => println!("Unknown WIN_CONDITION {}", name),
+            }
+        }
+        config
+    }
+}
+
+/// Counts down the current phase.
+#[derive(Resource)]
+struct MatchTimer(Timer);
+
+/// Out of a `WinCondition::LastStanding` match until the next one starts.
+#[derive(Debug, Component)]
+struct Eliminated;
+
+/// Ids of the players left once at most one player or team remains, or
+/// `None` while it's still contested. Nobody wins before someone has been
+/// eliminated, so a lone player doesn't end the match just by joining.
+fn last_standing(players: impl Iterator<Item = (u64, Option<Team>, bool)>) -> Option<Vec<u64>> {
+    let mut anyone_eliminated = false;
+    let mut sides = HashSet::new();
+    let mut alive = Vec::new();
+    for (id, team, eliminated) in players {
+        if eliminated {
+            anyone_eliminated = true;
+            continue;
+        }
+        // Players without a team are a side of their own
+        sides.insert(team.map_or(Err(id), |team| Ok(team.0)));
+        alive.push(id);
+    }
+    (anyone_eliminated && sides.len() <= 1).then_some(alive)
+}
+
+/// How kills are scored, read from `KILL_SCORE` and `TRADE_KILL_CREDIT`.
+#[derive(Debug, Resource)]
+struct DeathConfig {
+    /// Points the killer scores.
+    kill_score: u64,
+    /// Whether both players score when they kill each other in the same tick.
+    /// Otherwise a trade kill scores for neither.
+    credit_trades: bool,
+}
+
+impl DeathConfig {
+    fn from_env() -> Self {
+        DeathConfig {
+            kill_score: std::env::var("KILL_SCORE")
+                .ok()
+                .and_then(|score| score.parse().ok())
+                .unwrap_or(1),
+            credit_trades: std::env::var("TRADE_KILL_CREDIT")
+                .ok()
+                .and_then(|credit| credit.parse().ok())
+                .unwrap_or(true),
+        }
+    }
+}
+
+/// Sent when a hit takes a player from alive to dead. Deaths are resolved
+/// together by `death_resolution_system` once all of a tick's damage is in.
+pub struct LethalHit {
+    pub victim: Entity,
+    /// Player entity that owned the projectile.
+    pub killer: Option<Entity>,
+}
+
+/// Resolves every `LethalHit` from this tick at once, so when two players'
+/// fireballs kill each other in the same frame both deaths and both kills
+/// are recorded no matter which collision was processed first. Each victim
+/// dies once, crediting the first hit that killed them, and is eliminated in
+/// `WinCondition::LastStanding` matches or respawns with full health
+/// otherwise.
+#[allow(clippy::too_many_arguments)]
+fn death_resolution_system(
     mut commands: Commands,
-    mut collision_events: EventReader<CollisionEvent>,
-    projectile_query: Query<Option<&Projectile>>,
-    solana_entity_query: Query<Option<&SolanaSlotBlock>>,
+    mut lethal_hits: EventReader<LethalHit>,
+    config: Res<DeathConfig>,
+    match_config: Res<MatchConfig>,
+    phase: Res<GamePhase>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut outbox: ResMut<ServerOutbox>,
+    players: Query<(&Player, &Health, Option<&Team>)>,
+    spawn_picker: SpawnPicker,
 ) {
-    for collision_event in collision_events.iter() {
-        if let CollisionEvent::Started(entity1, entity2, _) = collision_event {
-            // let entity1Id = commands.entity(*entity1).id();
-            // let entity2Id = commands.entity(*entity2).id();
+    let mut deaths: Vec<(Entity, Option<Entity>)> = Vec::new();
+    for hit in lethal_hits.iter() {
+        if !deaths.iter().any(|(victim, _)| *victim == hit.victim) {
+            deaths.push((
+                hit.victim,
+                hit.killer.filter(|killer| *killer != hit.victim),
+            ));
+        }
+    }
+    if *phase != GamePhase::Playing {
+        return;
+    }
+
+    for (victim, killer) in &deaths {
+        let Ok((victim_player, victim_health, victim_team)) = players.get(*victim) else {
+            continue;
+        };
+        let killer = killer.and_then(|killer| {
+            players
+                .get(killer)
+                .ok()
+                .map(|(player, _, _)| (killer, player))
+        });
+        if let Some((killer, killer_player)) = killer {
+            let traded = deaths.contains(&(killer, Some(*victim)));
+            if traded && victim.to_bits() < killer.to_bits() {
+                announce(
+                    &mut outbox,
+                    format!(
+                        "{} and {} traded kills!",
+                        victim_player.name, killer_player.name
+                    ),
+                );
+            }
+            if !traded || config.credit_trades {
+                leaderboard.add_score(
+                    killer_player.id,
+                    &killer_player.leaderboard_key(),
+                    config.kill_score,
+                );
+            }
+            println!(
+                "Player {} killed {}",
+                killer_player.name, victim_player.name
+            );
+        }
+        outbox.broadcast(ServerMessages::PlayerKilled {
+            id: victim_player.id,
+            killer: killer.map(|(_, killer_player)| killer_player.id),
+        });
+
+        if match_config.win_condition == WinCondition::LastStanding {
+            commands.entity(*victim).insert(Eliminated);
+            announce(
+                &mut outbox,
+                format!("{} was eliminated!", victim_player.name),
+            );
+            continue;
+        }
+
+        let position = spawn_picker
+            .spawn_transform(victim_team.copied())
+            .translation;
+        commands.entity(*victim).insert((
+            Health::new(victim_health.max),
+            Transform::from_translation(position),
+            Teleported {
+                destination: position,
+                grace: Timer::from_seconds(TELEPORT_GRACE_SECS, TimerMode::Once),
+            },
+            SpawnProtection::default(),
+        ));
+        outbox.broadcast(ServerMessages::PlayerTeleport {
+            id: victim_player.id,
+            translation: position.into(),
+        });
+        outbox.broadcast(ServerMessages::PlayerSpawnProtection {
+            id: victim_player.id,
+            protected: true,
+        });
+    }
+}
 
-            // commands.entity(entity2Id).despawn();
-            println!("Projectile Collision Event Started");
+/// Ends the match when its time is up or its `WinCondition` is met,
+/// broadcasting the standings, and starts the next one with fresh scores once
+/// the results have been shown.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn match_phase_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<MatchConfig>,
+    mut phase: ResMut<GamePhase>,
+    mut timer: ResMut<MatchTimer>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut players: Query<(
+        Entity,
+        &Player,
+        &mut Health,
+        Option<&Team>,
+        Option<&Bot>,
+        Option<&Eliminated>,
+    )>,
+) {
+    let time_up = timer.0.tick(time.delta()).just_finished();
 
-            if let Ok(Some(_)) = projectile_query.get(*entity1) {
-                println!("Projectile Collision Event Started");
-                if let Ok(Some(_)) = solana_entity_query.get(*entity1) {
-                    commands.entity(*entity2).despawn();
+    match *phase {
+        GamePhase::Playing => {
+            let winners = match config.win_condition {
+                WinCondition::TimeLimit => None,
+                WinCondition::ScoreTarget(target) => {
+                    let winners: Vec<u64> = leaderboard
+                        .live
+                        .iter()
+                        .filter(|(_, score)| **score >= target)
+                        .map(|(id, _)| *id)
+                        .collect();
+                    (!winners.is_empty()).then_some(winners)
+                }
+                WinCondition::LastStanding => {
+                    last_standing(players.iter().map(|(_, player, _, team, _, eliminated)| {
+                        (player.id, team.copied(), eliminated.is_some())
+                    }))
                 }
-                // commands.entity(*entity2).despawn();
+            };
+            if winners.is_none() && !time_up {
+                return;
+            }
+
+            let standings = leaderboard.summaries(
+                players
+                    .iter()
+                    .map(|(_, player, _, _, bot, _)| (player, bot.is_some())),
+            );
+            let winners = winners.unwrap_or_default();
+            println!(
+                "Match ended, {} players ranked, winners {:?}",
+                standings.len(),
+                winners
+            );
+            outbox.broadcast(ServerMessages::MatchResults { standings, winners });
+
+            *phase = GamePhase::Ended;
+            timer.0 = Timer::new(config.results_duration, TimerMode::Once);
+        }
+        GamePhase::Ended => {
+            if !time_up {
+                return;
             }
-            if let Ok(Some(_)) = projectile_query.get(*entity2) {
-                println!("Projectile Collision Event Started");
-                if let Ok(Some(_)) = solana_entity_query.get(*entity1) {
-                    commands.entity(*entity1).despawn();
+            leaderboard.live.clear();
+            if config.win_condition == WinCondition::LastStanding {
+                for (entity, _, mut health, _, _, eliminated) in players.iter_mut() {
+                    health.current = health.max;
+                    if eliminated.is_some() {
+                        commands.entity(entity).remove::<Eliminated>();
+                    }
                 }
             }
-        } else if let CollisionEvent::Stopped(e1, e2, _) = collision_event {
-            println!("Collision Event Stopped");
+            outbox.broadcast(ServerMessages::ChatMessage {
+                sender: None,
+                message: "A new match has started".to_string(),
+            });
+
+            *phase = GamePhase::Playing;
+            timer.0 = Timer::new(config.duration, TimerMode::Once);
         }
     }
 }
 
-fn projectile_on_removal_system(
-    mut server: ResMut<RenetServer>,
-    mut removed_projectiles: RemovedComponents<Projectile>,
-) {
-    for entity in &mut removed_projectiles {
-        let message = ServerMessages::DespawnProjectile { entity };
-        let message = bincode::serialize(&message).unwrap();
+fn leaderboard_path() -> String {
+    std::env::var("LEADERBOARD_PATH").unwrap_or_else(|_| "leaderboard.json".to_string())
+}
 
-        server.broadcast_message(ServerChannel::ServerMessages, message);
+/// Loads the all-time totals, starting empty if the file is missing or unreadable.
+pub fn load_leaderboard(path: &str) -> Leaderboard {
+    let all_time = match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            println!("Ignoring malformed leaderboard {}: {}", path, e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    };
+
+    Leaderboard {
+        all_time,
+        ..Default::default()
     }
 }
 
-fn solana_block_on_removal_system(
-    mut server: ResMut<RenetServer>,
-    mut removed_projectiles: RemovedComponents<SolanaSlotBlock>,
-) {
-    for entity in &mut removed_projectiles {
-        let message = ServerMessages::DespawnSolanaBlock { entity };
-        let message = bincode::serialize(&message).unwrap();
+pub fn save_leaderboard(path: &str, leaderboard: &Leaderboard) -> std::io::Result<()> {
+    let contents = serde_json::to_string_pretty(&leaderboard.all_time)?;
+    fs::write(path, contents)
+}
+
+/// Sent when a projectile destroys a Solana block.
+pub struct SolanaBlockDestroyed {
+    pub slot: u64,
+    /// Player entity that owned the projectile.
+    pub destroyed_by: Option<Entity>,
+}
+
+/// What happened to a Solana block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockEventKind {
+    Spawned {
+        position: Vec3,
+    },
+    /// Broken by a projectile cast by the player with this client id, if any.
+    Destroyed {
+        by: Option<u64>,
+    },
+    /// Despawned when its `SolanaSpawnConfig::block_ttl` ran out.
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockEvent {
+    /// Server clock in seconds.
+    pub time: f64,
+    pub slot: u64,
+    pub kind: BlockEventKind,
+    /// Seconds since the block spawned. `None` for spawns and blocks that
+    /// spawned before telemetry started.
+    pub age: Option<f64>,
+}
+
+/// The latest Solana block lifecycle events, kept for the admin console
+/// (`/blocks`) when tuning spawn rates and block health. Holds the last
+/// `capacity` events, read from `BLOCK_TELEMETRY_SIZE`.
+#[derive(Debug, Resource)]
+pub struct BlockTelemetry {
+    capacity: usize,
+    events: VecDeque<BlockEvent>,
+    /// Spawn time of each live block by slot.
+    spawned_at: HashMap<u64, f64>,
+    pub spawned: u64,
+    pub destroyed: u64,
+    pub expired: u64,
+}
+
+impl BlockTelemetry {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+            spawned_at: HashMap::new(),
+            spawned: 0,
+            destroyed: 0,
+            expired: 0,
+        }
+    }
+
+    fn from_env() -> Self {
+        let capacity = std::env::var("BLOCK_TELEMETRY_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(256);
+        Self::new(capacity)
+    }
+
+    pub fn record(&mut self, time: f64, slot: u64, kind: BlockEventKind) {
+        let age = match kind {
+            BlockEventKind::Spawned { .. } => {
+                self.spawned += 1;
+                self.spawned_at.insert(slot, time);
+                None
+            }
+            BlockEventKind::Destroyed { .. } => {
+                self.destroyed += 1;
+                self.spawned_at.remove(&slot).map(|spawned| time - spawned)
+            }
+            BlockEventKind::Expired => {
+                self.expired += 1;
+                self.spawned_at.remove(&slot).map(|spawned| time - spawned)
+            }
+        };
+
+        if self.capacity == 0 {
+            return;
+        }
+        while self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(BlockEvent {
+            time,
+            slot,
+            kind,
+            age,
+        });
+    }
+
+    /// Up to `count` of the most recent events, newest first.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &BlockEvent> {
+        self.events.iter().rev().take(count)
+    }
 
-        server.broadcast_message(ServerChannel::ServerMessages, message);
+    /// Average seconds the blocks in the buffer lived before being destroyed
+    /// or expiring.
+    pub fn average_lifetime(&self) -> Option<f64> {
+        let ages: Vec<f64> = self.events.iter().filter_map(|event| event.age).collect();
+        (!ages.is_empty()).then(|| ages.iter().sum::<f64>() / ages.len() as f64)
+    }
+
+    /// One-line totals, as `/blocks` and the periodic report show them.
+    pub fn summary(&self) -> String {
+        format!(
+            "Blocks: {} spawned, {} destroyed, {} expired, {} live, average lifetime {}",
+            self.spawned,
+            self.destroyed,
+            self.expired,
+            self.spawned_at.len(),
+            self.average_lifetime()
+                .map_or("unknown".to_string(), |secs| format!("{:.1}s", secs)),
+        )
     }
 }
 
-fn spawn_bot(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut lobby: ResMut<ServerLobby>,
-    mut server: ResMut<RenetServer>,
-    mut bot_id: ResMut<BotId>,
-    mut commands: Commands,
+/// Paces `block_telemetry_report_system`, read from
+/// `BLOCK_TELEMETRY_REPORT_SECS` (default 60, 0 to disable).
+#[derive(Resource)]
+struct BlockTelemetryReportTimer(Option<Timer>);
+
+impl BlockTelemetryReportTimer {
+    fn from_env() -> Self {
+        let secs = std::env::var("BLOCK_TELEMETRY_REPORT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<f32>().ok())
+            .unwrap_or(60.0);
+        Self(
+            (secs.is_finite() && secs > 0.0)
+                .then(|| Timer::from_seconds(secs, TimerMode::Repeating)),
+        )
+    }
+}
+
+/// Logs the block telemetry totals to stdout, so they can be scraped
+/// without an admin in game.
+fn block_telemetry_report_system(
+    time: Res<Time>,
+    telemetry: Res<BlockTelemetry>,
+    mut timer: ResMut<BlockTelemetryReportTimer>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        let client_id = bot_id.0;
-        bot_id.0 += 1;
-        // Spawn new player
-        let transform = Transform::from_xyz(
-            (fastrand::f32() - 0.5) * 40.,
-            0.51,
-            (fastrand::f32() - 0.5) * 40.,
-        );
-        let player_entity = commands
-            .spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Capsule::default())),
-                material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                transform,
-                ..Default::default()
-            })
-            .insert(RigidBody::Fixed)
-            .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y)
-            .insert(Collider::capsule_y(0.5, 0.5))
-            .insert(Player { id: client_id })
-            .insert(Bot {
-                auto_cast: Timer::from_seconds(3.0, TimerMode::Repeating),
-            })
-            .id();
+    let Some(timer) = timer.0.as_mut() else {
+        return;
+    };
+    if timer.tick(time.delta()).just_finished() && telemetry.spawned > 0 {
+        println!("{}", telemetry.summary());
+    }
+}
+
+/// Records blocks destroyed by projectiles in [`BlockTelemetry`].
+fn block_telemetry_system(
+    time: Res<Time>,
+    mut block_destroyed: EventReader<SolanaBlockDestroyed>,
+    mut telemetry: ResMut<BlockTelemetry>,
+    players: Query<&Player>,
+) {
+    for event in block_destroyed.iter() {
+        let by = event
+            .destroyed_by
+            .and_then(|entity| players.get(entity).ok())
+            .map(|player| player.id);
+        telemetry.record(
+            time.elapsed_seconds_f64(),
+            event.slot,
+            BlockEventKind::Destroyed { by },
+        );
+    }
+}
 
-        lobby.players.insert(client_id, player_entity);
+#[derive(Resource)]
+struct LeaderboardTimer(Timer);
 
-        let translation: [f32; 3] = transform.translation.into();
-        let message = bincode::serialize(&ServerMessages::PlayerCreate {
-            id: client_id,
-            entity: player_entity,
-            translation,
+/// How often changed all-time scores are written to disk, read from
+/// `LEADERBOARD_SAVE_SECS` (default 30).
+#[derive(Resource)]
+struct LeaderboardSaveTimer(Timer);
+
+impl LeaderboardSaveTimer {
+    fn from_env() -> Self {
+        let secs = std::env::var("LEADERBOARD_SAVE_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<f32>().ok())
+            .filter(|secs| secs.is_finite() && *secs > 0.0)
+            .unwrap_or(30.0);
+        Self(Timer::from_seconds(secs, TimerMode::Repeating))
+    }
+}
+
+/// Circular zones a team takes by standing in them without any enemies.
+#[derive(Debug, Resource)]
+struct CapturePointConfig {
+    /// Center and radius of each zone, read from `CAPTURE_POINTS` as
+    /// `x,y,z,radius` entries separated by `;`. None by default.
+    zones: Vec<(Vec3, f32)>,
+    /// Seconds a lone team needs to take a neutral point, or to neutralize
+    /// an enemy one.
+    capture_time: f32,
+    /// Points each member of a holding team earns every `score_interval`.
+    score_per_interval: u64,
+    score_interval: Duration,
+}
+
+impl Default for CapturePointConfig {
+    fn default() -> Self {
+        Self {
+            zones: Vec::new(),
+            capture_time: 10.0,
+            score_per_interval: 1,
+            score_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl CapturePointConfig {
+    fn from_env() -> Self {
+        let mut config = CapturePointConfig::default();
+        if let Ok(zones) = std::env::var("CAPTURE_POINTS") {
+            config.zones = parse_capture_zones(&zones);
+        }
+        config
+    }
+}
+
+/// Parses `x,y,z,radius;...`, skipping malformed entries.
+fn parse_capture_zones(zones: &str) -> Vec<(Vec3, f32)> {
+    zones
+        .split(';')
+        .filter(|zone| !zone.trim().is_empty())
+        .filter_map(|zone| {
+            let values: Vec<f32> = zone
+                .split(',')
+                .filter_map(|value| value.trim().parse().ok())
+                .collect();
+            match values[..] {
+                [x, y, z, radius] if radius > 0.0 => Some((Vec3::new(x, y, z), radius)),
+                _ => {
+                    println!("Invalid capture point {}, expected x,y,z,radius", zone);
+                    None
+                }
+            }
         })
-        .unwrap();
-        server.broadcast_message(ServerChannel::ServerMessages, message);
+        .collect()
+}
+
+/// A single hill scored by whichever player stands closest to its center,
+/// read from `OBJECTIVE_POSITION` (`x,y,z`) and `OBJECTIVE_RADIUS`. No
+/// objective is spawned without a position.
+#[derive(Debug, Resource)]
+struct ObjectiveConfig {
+    position: Option<Vec3>,
+    radius: f32,
+    /// Points the holder earns every `score_interval`.
+    score_per_interval: u64,
+    score_interval: Duration,
+}
+
+impl Default for ObjectiveConfig {
+    fn default() -> Self {
+        Self {
+            position: None,
+            radius: 5.0,
+            score_per_interval: 1,
+            score_interval: Duration::from_secs(1),
+        }
     }
 }
 
-fn bot_autocast(
-    time: Res<Time>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut server: ResMut<RenetServer>,
-    mut bots: Query<(&Transform, &mut Bot), With<Player>>,
+impl ObjectiveConfig {
+    fn from_env() -> Self {
+        let mut config = ObjectiveConfig::default();
+        if let Ok(position) = std::env::var("OBJECTIVE_POSITION") {
+            let coords: Vec<f32> = position
+                .split(',')
+                .filter_map(|coord| coord.trim().parse().ok())
+                .collect();
+            match coords[..] {
+                [x, y, z] => config.position = Some(Vec3::new(x, y, z)),
+                _ => println!("Invalid OBJECTIVE_POSITION {}, expected x,y,z", position),
+            }
+        }
+        if let Some(radius) = std::env::var("OBJECTIVE_RADIUS")
+            .ok()
+            .and_then(|radius| radius.parse().ok())
+        {
+            config.radius = radius;
+        }
+        config
+    }
+}
+
+/// The contested point spawned from `ObjectiveConfig`, and the player id
+/// currently holding it.
+#[derive(Debug, Component)]
+struct Objective {
+    radius: f32,
+    holder: Option<u64>,
+    score_timer: Timer,
+}
+
+fn spawn_objective(
     mut commands: Commands,
+    mut visuals: DebugVisuals,
+    config: Res<ObjectiveConfig>,
 ) {
-    for (transform, mut bot) in &mut bots {
-        bot.auto_cast.tick(time.delta());
-        if !bot.auto_cast.just_finished() {
-            continue;
+    let Some(position) = config.position else {
+        return;
+    };
+    let entity = commands
+        .spawn(TransformBundle::from_transform(
+            Transform::from_translation(position),
+        ))
+        .insert(Objective {
+            radius: config.radius,
+            holder: None,
+            score_timer: Timer::new(config.score_interval, TimerMode::Repeating),
+        })
+        .id();
+    visuals.insert(
+        &mut commands,
+        entity,
+        Mesh::from(shape::Cylinder {
+            radius: config.radius,
+            height: 0.05,
+            ..default()
+        }),
+        Color::rgb(0.3, 1.0, 0.4),
+    );
+    println!(
+        "Objective spawned at {:?} with radius {}",
+        position, config.radius
+    );
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturePoint {
+    pub id: u32,
+    pub center: Vec3,
+    pub radius: f32,
+    /// Team `progress` belongs to.
+    pub team: Option<u8>,
+    pub progress: f32,
+}
+
+impl CapturePoint {
+    fn contains(&self, position: Vec3) -> bool {
+        Vec2::new(position.x - self.center.x, position.z - self.center.z).length() <= self.radius
+    }
+
+    /// Team holding the point, once its progress is complete.
+    pub fn holder(&self) -> Option<u8> {
+        self.team.filter(|_| self.progress >= 1.0)
+    }
+
+    /// Moves progress by `step` for a lone team in the zone: up for the team
+    /// that owns the progress (or anyone on a neutral point), down for an
+    /// enemy until the point turns neutral. Empty and contested points
+    /// don't change.
+    pub fn advance(&mut self, teams_present: &HashSet<u8>, step: f32) {
+        let mut teams = teams_present.iter();
+        let (Some(&team), None) = (teams.next(), teams.next()) else {
+            return;
+        };
+        if self.team.is_none_or(|owner| owner == team) {
+            self.team = Some(team);
+            self.progress = (self.progress + step).min(1.0);
+        } else {
+            self.progress -= step;
+            if self.progress <= 0.0 {
+                self.progress = 0.0;
+                self.team = None;
+            }
         }
+    }
+}
 
-        for i in 0..8 {
-            let direction = Vec2::from_angle(PI / 4. * i as f32);
-            let direction = Vec3::new(direction.x, 0., direction.y).normalize();
-            let translation: Vec3 = transform.translation + direction;
+#[derive(Debug, Resource)]
+struct CapturePoints(Vec<CapturePoint>);
 
-            let fireball_entity = spawn_fireball(
-                &mut commands,
-                &mut meshes,
-                &mut materials,
-                translation,
-                direction,
-            );
-            let message = ServerMessages::SpawnProjectile {
-                entity: fireball_entity,
-                translation: translation.into(),
-            };
-            let message = bincode::serialize(&message).unwrap();
-            server.broadcast_message(ServerChannel::ServerMessages, message);
+impl CapturePoints {
+    fn from_config(config: &CapturePointConfig) -> Self {
+        Self(
+            config
+                .zones
+                .iter()
+                .enumerate()
+                .map(|(id, (center, radius))| CapturePoint {
+                    id: id as u32,
+                    center: *center,
+                    radius: *radius,
+                    team: None,
+                    progress: 0.0,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Resource)]
+struct CaptureScoreTimer(Timer);
+
+#[derive(Resource)]
+struct CapturePointBroadcastTimer(Timer);
+
+/// Broadcasts queued during the frame, sent together by
+/// `flush_outbox_system` as one `ServerMessages::Batch`. Every broadcast goes
+/// through here, so clients see them in the order they were queued.
+#[derive(Debug, Default, Resource)]
+pub struct ServerOutbox {
+    messages: Vec<ServerMessages>,
+}
+
+impl ServerOutbox {
+    pub fn broadcast(&mut self, message: ServerMessages) {
+        self.messages.push(message);
+    }
+}
+
+/// Runs in `PostUpdate`, after the removal systems, so a despawn lands in the
+/// same batch as, and after, a spawn queued this frame.
+fn flush_outbox_system(mut outbox: ResMut<ServerOutbox>, mut server: ResMut<RenetServer>) {
+    let mut messages = std::mem::take(&mut outbox.messages);
+    let message = match messages.len() {
+        0 => return,
+        1 => messages.pop().unwrap(),
+        _ => ServerMessages::Batch { messages },
+    };
+    send_chunked(&mut server, None, &message);
+}
+
+fn send_chat_reply(server: &mut RenetServer, client_id: u64, message: String) {
+    let message = bincode::serialize(&ServerMessages::ChatMessage {
+        sender: None,
+        message,
+    })
+    .unwrap();
+    server.send_message(client_id, ServerChannel::ServerMessages, message);
+}
+
+const PLAYER_RADIUS: f32 = 0.5;
+/// Distance from a player's center to the bottom of their capsule.
+const PLAYER_HALF_HEIGHT: f32 = 1.0;
+/// Height players stand at. Client positions are flattened onto it.
+const PLAYER_GROUND_Y: f32 = 0.51;
+
+/// Where players are placed when they join or respawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpawnStrategy {
+    Random,
+    /// The random candidate point farthest from its nearest enemy.
+    FarthestFromEnemies,
+    /// A random point inside the player's team base. Players without a team
+    /// or base spawn randomly.
+    TeamBase,
+    /// One of `SpawnConfig::fixed_points`, chosen at random.
+    FixedPoints,
+}
+
+#[derive(Debug, Resource)]
+struct SpawnConfig {
+    strategy: SpawnStrategy,
+    /// Half-width of the square around the origin random points are drawn from.
+    area: f32,
+    /// Random points scored by `FarthestFromEnemies`.
+    candidates: usize,
+    fixed_points: Vec<Vec3>,
+    /// Center and radius of each team's spawn region, indexed by team.
+    team_bases: HashMap<u8, (Vec3, f32)>,
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SpawnStrategy::Random,
+            area: 20.0,
+            candidates: 16,
+            fixed_points: Vec::new(),
+            team_bases: HashMap::new(),
         }
     }
 }
 
-#[cfg(debug_assertions)]
-fn update_visualizer_system(
-    mut egui_contexts: EguiContexts,
-    mut visualizer: ResMut<RenetServerVisualizer<200>>,
-    server: Res<RenetServer>,
-) {
-    visualizer.update(&server);
-    visualizer.show_window(egui_contexts.ctx_mut());
+impl SpawnConfig {
+    /// Reads `SPAWN_STRATEGY` (`random`, `farthest`, `team_base` or `fixed`),
+    /// `SPAWN_POINTS` (`x,y,z;...`) and `TEAM_BASES` (`x,y,z,radius;...`, one
+    /// entry per team in order).
+    fn from_env() -> Self {
+        let mut config = SpawnConfig::default();
+        if let Ok(strategy) = std::env::var("SPAWN_STRATEGY") {
+            match parse_spawn_strategy(&strategy) {
+                Some(strategy) => config.strategy = strategy,
+                None => println!("Unknown spawn strategy {}, spawning randomly", strategy),
+            }
+        }
+        if let Ok(points) = std::env::var("SPAWN_POINTS") {
+            config.fixed_points = parse_spawn_points(&points);
+        }
+        if let Ok(bases) = std::env::var("TEAM_BASES") {
+            config.team_bases = (0..).zip(parse_capture_zones(&bases)).collect();
+        }
+        config
+    }
+
+    fn random_point(&self) -> Vec3 {
+        Vec3::new(
+            (fastrand::f32() * 2. - 1.) * self.area,
+            PLAYER_GROUND_Y,
+            (fastrand::f32() * 2. - 1.) * self.area,
+        )
+    }
+
+    /// Picks a spawn point for a player on `team`, given where its enemies are.
+    /// Strategies that can't apply fall back to a random point.
+    fn pick_point(&self, team: Option<Team>, enemies: &[Vec3]) -> Vec3 {
+        match self.strategy {
+            SpawnStrategy::Random => self.random_point(),
+            SpawnStrategy::FarthestFromEnemies => {
+                let distance_to_nearest = |point: Vec3| {
+                    enemies
+                        .iter()
+                        .map(|enemy| enemy.distance(point))
+                        .fold(f32::INFINITY, f32::min)
+                };
+                (0..self.candidates.max(1))
+                    .map(|_| self.random_point())
+                    .map(|point| (point, distance_to_nearest(point)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map_or_else(|| self.random_point(), |(point, _)| point)
+            }
+            SpawnStrategy::TeamBase => match team.and_then(|team| self.team_bases.get(&team.0)) {
+                Some((center, radius)) => {
+                    let offset = Vec2::from_angle(fastrand::f32() * 2. * PI)
+                        * fastrand::f32().sqrt()
+                        * *radius;
+                    Vec3::new(center.x + offset.x, PLAYER_GROUND_Y, center.z + offset.y)
+                }
+                None => self.random_point(),
+            },
+            SpawnStrategy::FixedPoints => {
+                if self.fixed_points.is_empty() {
+                    return self.random_point();
+                }
+                self.fixed_points[fastrand::usize(..self.fixed_points.len())]
+            }
+        }
+    }
+}
+
+fn parse_spawn_strategy(name: &str) -> Option<SpawnStrategy> {
+    match name.trim() {
+        "random" => Some(SpawnStrategy::Random),
+        "farthest" => Some(SpawnStrategy::FarthestFromEnemies),
+        "team_base" => Some(SpawnStrategy::TeamBase),
+        "fixed" => Some(SpawnStrategy::FixedPoints),
+        _ => None,
+    }
+}
+
+/// Parses `x,y,z;...`, skipping malformed entries.
+fn parse_spawn_points(points: &str) -> Vec<Vec3> {
+    points
+        .split(';')
+        .filter(|point| !point.trim().is_empty())
+        .filter_map(|point| {
+            let values: Vec<f32> = point
+                .split(',')
+                .filter_map(|value| value.trim().parse().ok())
+                .collect();
+            match values[..] {
+                [x, y, z] => Some(Vec3::new(x, y, z)),
+                _ => {
+                    println!("Invalid spawn point {}, expected x,y,z", point);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// How many teams joining players are split between, read from
+/// `TEAM_COUNT`. 0 plays free-for-all, with nobody on a team.
+#[derive(Debug, Resource)]
+struct TeamConfig {
+    count: u8,
+}
+
+impl TeamConfig {
+    fn from_env() -> Self {
+        TeamConfig {
+            count: std::env::var("TEAM_COUNT")
+                .ok()
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// The team with the fewest of `teams` on it, lowest first on ties. `None`
+/// when there are no teams.
+fn balanced_team(count: u8, teams: impl Iterator<Item = Option<Team>>) -> Option<Team> {
+    let mut sizes = vec![0usize; count as usize];
+    for Team(team) in teams.flatten() {
+        if let Some(size) = sizes.get_mut(team as usize) {
+            *size += 1;
+        }
+    }
+    (0..count)
+        .min_by_key(|team| sizes[*team as usize])
+        .map(Team)
+}
+
+/// Picks teams and spawn transforms with `SpawnConfig` against the current players.
+#[derive(SystemParam)]
+struct SpawnPicker<'w, 's> {
+    config: Res<'w, SpawnConfig>,
+    teams: Res<'w, TeamConfig>,
+    players: Query<'w, 's, (&'static Transform, Option<&'static Team>), With<Player>>,
+}
+
+impl SpawnPicker<'_, '_> {
+    /// The team a joining player goes on, keeping teams even.
+    fn pick_team(&self) -> Option<Team> {
+        balanced_team(
+            self.teams.count,
+            self.players.iter().map(|(_, team)| team.copied()),
+        )
+    }
+
+    fn spawn_transform(&self, team: Option<Team>) -> Transform {
+        let enemies: Vec<Vec3> = self
+            .players
+            .iter()
+            .filter(|(_, other_team)| team.is_none() || other_team.copied() != team)
+            .map(|(transform, _)| transform.translation)
+            .collect();
+        Transform::from_translation(self.config.pick_point(team, &enemies))
+    }
+}
+
+#[derive(Debug, Component)]
+struct Bot {
+    auto_cast: Timer,
+}
+
+#[derive(Debug, Resource)]
+struct BotId(u64);
+
+fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
+    let server = RenetServer::new(connection_config());
+
+    let public_addr = get_server_addr().parse().unwrap();
+    let socket = UdpSocket::bind(public_addr).unwrap();
+    let server_config = ServerConfig {
+        max_clients: 64,
+        protocol_id: PROTOCOL_ID,
+        public_addr,
+        authentication: ServerAuthentication::Unsecure,
+    };
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+
+    let transport = NetcodeServerTransport::new(current_time, server_config, socket).unwrap();
+
+    (server, transport)
+}
+
+pub struct SolanaPlugin;
+
+impl Plugin for SolanaPlugin {
+    fn build(&self, app: &mut App) {
+        let spawn_config = SolanaSpawnConfig::from_env();
+        app.insert_resource(LogConnectionsTimer(Timer::new(
+            spawn_config.interval,
+            TimerMode::Repeating,
+        )))
+        .insert_resource(Solana::default())
+        .insert_resource(spawn_config)
+        .insert_resource(BlockTelemetry::from_env())
+        .insert_resource(BlockTelemetryReportTimer::from_env())
+        .insert_resource(AirdropConfig::from_env())
+        // .add_startup_system(add_devnet_connection)
+        .add_startup_system(add_mainnet_connection)
+        .add_system(spawn_solana_blocks)
+        .add_system(block_telemetry_report_system)
+        .add_system(request_airdrop_system);
+    }
+}
+
+fn add_mainnet_connection(mut commands: Commands) {
+    commands.spawn(Solana::new(SolanaRpcUrl::Mainnet, true));
+}
+
+#[derive(Resource)]
+struct LogConnectionsTimer(Timer);
+
+fn request_airdrop_system(config: Res<AirdropConfig>, connections: Query<&Solana, Added<Solana>>) {
+    let Some(target) = config.target else {
+        return;
+    };
+
+    for solana in &connections {
+        if !solana.faucet_on {
+            continue;
+        }
+        if matches!(solana.rpc, SolanaRpcUrl::Mainnet) {
+            println!("Skipping airdrop, faucets are not available on mainnet");
+            continue;
+        }
+
+        let url = solana.rpc.as_str();
+        let lamports = config.lamports;
+        // Confirmation takes seconds, keep it off the game loop
+        thread::spawn(move || request_airdrop(url, target, lamports));
+    }
+}
+
+fn request_airdrop(url: &'static str, target: Pubkey, lamports: u64) {
+    let client = RpcClient::new(url);
+    let signature = match client.request_airdrop(&target, lamports) {
+        Ok(signature) => signature,
+        Err(e) => {
+            println!("Airdrop request to {} failed: {}", url, e);
+            return;
+        }
+    };
+    println!(
+        "Requested airdrop of {} lamports to {}: {}",
+        lamports, target, signature
+    );
+
+    for _ in 0..AIRDROP_CONFIRM_ATTEMPTS {
+        match client.confirm_transaction(&signature) {
+            Ok(true) => {
+                match client.get_balance(&target) {
+                    Ok(balance) => {
+                        println!("Airdrop confirmed, {} has {} lamports", target, balance)
+                    }
+                    Err(e) => println!("Airdrop confirmed, failed to fetch balance: {}", e),
+                }
+                return;
+            }
+            Ok(false) => thread::sleep(Duration::from_millis(500)),
+            Err(e) => {
+                println!("Failed to confirm airdrop {}: {}", signature, e);
+                return;
+            }
+        }
+    }
+
+    println!("Airdrop {} was not confirmed", signature);
+}
+
+/// How often the server tells clients which Solana blocks are alive.
+#[derive(Resource)]
+struct BlockReconcileTimer(Timer);
+
+/// Caps how many messages each client may send per channel per tick.
+/// Messages past the budget are drained and dropped so a flooding client
+/// can't make the server loop do unbounded work. Messages that don't decode
+/// are dropped too, and clients that keep sending them are disconnected.
+#[derive(Debug, Resource)]
+struct RateLimiter {
+    budgets: HashMap<ClientChannel, u32>,
+    received: HashMap<(u64, ClientChannel), u32>,
+    /// Dropped messages per client since the last report.
+    dropped: HashMap<u64, u32>,
+    /// Messages per client that failed to decode, which an honest client never sends.
+    malformed: HashMap<u64, u32>,
+    /// Malformed messages after which a client is disconnected.
+    max_malformed: u32,
+    report_timer: Timer,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            budgets: HashMap::from([
+                (ClientChannel::Input, 8),
+                (ClientChannel::Command, 16),
+                (ClientChannel::RequestEntity, 8),
+            ]),
+            received: HashMap::new(),
+            dropped: HashMap::new(),
+            malformed: HashMap::new(),
+            max_malformed: 5,
+            report_timer: Timer::from_seconds(5.0, TimerMode::Repeating),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Counts a message and returns whether it is within this tick's budget.
+    fn allow(&mut self, client_id: u64, channel: ClientChannel) -> bool {
+        let received = self.received.entry((client_id, channel)).or_insert(0);
+        *received += 1;
+        let budget = self.budgets.get(&channel).copied().unwrap_or(u32::MAX);
+        if *received > budget {
+            *self.dropped.entry(client_id).or_insert(0) += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Decodes a message from `client_id`, counting it as dropped and
+    /// malformed if it doesn't decode.
+    fn decode<T: DeserializeOwned>(&mut self, client_id: u64, message: &[u8]) -> Option<T> {
+        match bincode::deserialize(message) {
+            Ok(decoded) => Some(decoded),
+            Err(_) => {
+                *self.dropped.entry(client_id).or_insert(0) += 1;
+                *self.malformed.entry(client_id).or_insert(0) += 1;
+                None
+            }
+        }
+    }
+
+    /// Whether `client_id` has sent enough malformed messages to be disconnected.
+    fn should_kick(&self, client_id: u64) -> bool {
+        self.malformed.get(&client_id).copied().unwrap_or(0) >= self.max_malformed
+    }
+
+    fn forget(&mut self, client_id: u64) {
+        self.received.retain(|(id, _), _| *id != client_id);
+        self.dropped.remove(&client_id);
+        self.malformed.remove(&client_id);
+    }
+}
+
+/// Decodes a message from `client_id` through `rate_limiter`, disconnecting
+/// the client once it has sent too many that don't decode.
+fn decode_client_message<T: DeserializeOwned>(
+    server: &mut RenetServer,
+    rate_limiter: &mut RateLimiter,
+    client_id: u64,
+    message: &[u8],
+) -> Option<T> {
+    let decoded = rate_limiter.decode(client_id, message);
+    if decoded.is_none() {
+        println!("Client {} sent a malformed message", client_id);
+        if rate_limiter.should_kick(client_id) {
+            println!(
+                "Client {} disconnected for sending malformed messages",
+                client_id
+            );
+            server.disconnect(client_id);
+        }
+    }
+    decoded
+}
+
+/// Wallet challenges accepted within the last `WALLET_CHALLENGE_WINDOW_SECS`,
+/// so the same signed connect data can't be used twice.
+#[derive(Debug, Default, Resource)]
+struct SeenWalletChallenges {
+    seen: HashSet<(Pubkey, u64, u64)>,
+}
+
+impl SeenWalletChallenges {
+    /// Records a verified challenge, returning false if it was already used.
+    /// Challenges too old to verify any more are forgotten.
+    fn first_use(&mut self, wallet: Pubkey, client_id: u64, signed_at: u64, now: u64) -> bool {
+        let oldest = now.saturating_sub(WALLET_CHALLENGE_WINDOW_SECS);
+        self.seen.retain(|(_, _, seen_at)| *seen_at >= oldest);
+        self.seen.insert((wallet, client_id, signed_at))
+    }
+}
+
+/// What `server_update_system` needs to check who is connecting.
+#[derive(SystemParam)]
+struct ConnectAuth<'w> {
+    transport: Res<'w, NetcodeServerTransport>,
+    wallet_challenges: ResMut<'w, SeenWalletChallenges>,
+}
+
+/// Starts a fresh budget every tick and periodically logs clients that went over it.
+fn rate_limiter_system(time: Res<Time>, mut rate_limiter: ResMut<RateLimiter>) {
+    rate_limiter.received.clear();
+
+    rate_limiter.report_timer.tick(time.delta());
+    if rate_limiter.report_timer.just_finished() {
+        for (client_id, dropped) in rate_limiter.dropped.drain() {
+            println!(
+                "Client {} exceeded its message budget, dropped {} messages",
+                client_id, dropped
+            );
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_solana_blocks(
+    time: Res<Time>,
+    mut timer: ResMut<LogConnectionsTimer>,
+    mut query: Query<&mut Solana>,
+    solana: ResMut<Solana>,
+    spawn_config: Res<SolanaSpawnConfig>,
+    announcer_config: Res<AnnouncerConfig>,
+    mut announcer: ResMut<Announcer>,
+    mut commands: Commands,
+    mut visuals: DebugVisuals,
+    mut outbox: ResMut<ServerOutbox>,
+    mut telemetry: ResMut<BlockTelemetry>,
+) {
+    if timer.0.duration() != spawn_config.interval {
+        timer.0.set_duration(spawn_config.interval);
+    }
+
+    // Runs after first tick
+    if timer.0.tick(time.delta()).just_finished() {
+        println!("Connected to  {}", solana.rpc.as_str());
+
+        let now = time.elapsed_seconds();
+        // Run for each connected solana rpc if it is on
+        for mut solana in &mut query {
+            if !solana.faucet_on || now < solana.retry_at {
+                continue;
+            }
+
+            let slots = match spawn_config.mode {
+                SolanaSpawnMode::EpochSlot => match solana.client.get_epoch_info() {
+                    Ok(epoch) => vec![epoch.absolute_slot],
+                    Err(e) => {
+                        println!(
+                            "Failed to fetch epoch info from {}: {}",
+                            solana.rpc.as_str(),
+                            e
+                        );
+                        solana.record_failure(now);
+                        continue;
+                    }
+                },
+                SolanaSpawnMode::ConfirmedBlocks => {
+                    match confirmed_slots_since(&solana, spawn_config.max_blocks_per_tick) {
+                        Ok((slots, current_slot)) => {
+                            solana.last_processed_slot = Some(current_slot);
+                            slots
+                        }
+                        Err(e) => {
+                            println!(
+                                "Failed to fetch confirmed blocks from {}: {}",
+                                solana.rpc.as_str(),
+                                e
+                            );
+                            solana.record_failure(now);
+                            continue;
+                        }
+                    }
+                }
+            };
+            solana.record_success();
+
+            for slot in solana.take_unspawned_slots(slots) {
+                spawn_solana_block(
+                    &mut commands,
+                    &mut visuals,
+                    &mut outbox,
+                    &mut telemetry,
+                    time.elapsed_seconds_f64(),
+                    &spawn_config,
+                    slot,
+                );
+                if let Some(milestone) = announcer.record_slot(&announcer_config, slot) {
+                    announce(&mut outbox, format!("Solana slot {} reached!", milestone));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the confirmed slots produced since `solana.last_processed_slot`
+/// (at most `max_blocks`, newest kept) along with the current slot.
+#[allow(clippy::result_large_err)]
+fn confirmed_slots_since(
+    solana: &Solana,
+    max_blocks: usize,
+) -> Result<(Vec<u64>, u64), solana_client::client_error::ClientError> {
+    let current_slot = solana.client.get_slot()?;
+    let start_slot = solana
+        .last_processed_slot
+        .map_or(current_slot, |slot| slot + 1);
+    if start_slot > current_slot {
+        return Ok((Vec::new(), current_slot));
+    }
+
+    let mut slots = solana.client.get_blocks(start_slot, Some(current_slot))?;
+    if slots.len() > max_blocks {
+        println!(
+            "Catching up on {} confirmed blocks, skipping the oldest {}",
+            slots.len(),
+            slots.len() - max_blocks
+        );
+        slots.drain(..slots.len() - max_blocks);
+    }
+
+    Ok((slots, current_slot))
+}
+
+fn spawn_solana_block(
+    commands: &mut Commands,
+    visuals: &mut DebugVisuals,
+    outbox: &mut ServerOutbox,
+    telemetry: &mut BlockTelemetry,
+    now: f64,
+    config: &SolanaSpawnConfig,
+    slot: u64,
+) {
+    let height = config.block_spawn_height();
+    let shape = config.block_shape();
+    let spawn_location_transform = Transform::from_xyz(0.0, height, 0.0);
+
+    // Spawn new
+    let entity: Entity = commands
+        .spawn(TransformBundle::from_transform(spawn_location_transform))
+        .insert(RigidBody::Dynamic)
+        .insert(solana_block_physics(shape))
+        .insert(ActiveEvents::COLLISION_EVENTS)
+        .insert(SolanaSlotBlock {
+            id: slot,
+            shape,
+            lifetime: config.block_ttl.map(|ttl| Timer::new(ttl, TimerMode::Once)),
+        })
+        .id();
+    if let Some(health) = config.block_health {
+        commands.entity(entity).insert(Health::new(health));
+    }
+
+    visuals.insert(commands, entity, shape.mesh(), Color::rgb(0.8, 0.7, 0.6));
+
+    let message = ServerMessages::SpawnSolanaBlock {
+        entity,
+        transform: (0.0, height, 0.0),
+        slot,
+        shape,
+    };
+
+    outbox.broadcast(message);
+    telemetry.record(
+        now,
+        slot,
+        BlockEventKind::Spawned {
+            position: spawn_location_transform.translation,
+        },
+    );
+}
+
+fn main() {
+    let mut app = App::new();
+
+    #[cfg(debug_assertions)]
+    {
+        app.add_plugins(DefaultPlugins);
+        app.add_plugin(RapierDebugRenderPlugin::default());
+        app.add_plugin(EguiPlugin);
+    }
+    // Headless: no window, renderer or asset storage
+    #[cfg(not(debug_assertions))]
+    {
+        app.add_plugins(MinimalPlugins);
+        app.add_plugin(bevy::log::LogPlugin::default());
+        app.add_plugin(TransformPlugin);
+        app.add_plugin(HierarchyPlugin);
+        app.add_plugin(bevy::diagnostic::DiagnosticsPlugin);
+    }
+
+    app.add_plugin(RenetServerPlugin);
+    app.add_plugin(NetcodeServerPlugin);
+    app.add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
+
+    let world_config = WorldConfig::default();
+    app.insert_resource(world_config.rapier_configuration());
+    app.insert_resource(world_config);
+    app.insert_resource(WorldBounds::default());
+    app.insert_resource(FloorGrid::default());
+    app.insert_resource(CameraConfig::overview());
+    app.add_plugin(FrameTimeDiagnosticsPlugin);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+
+    app.add_plugin(SolanaPlugin);
+
+    app.insert_resource(ServerLobby::default());
+    app.insert_resource(ServerOutbox::default());
+    app.insert_resource(ReconnectConfig::from_env());
+    app.insert_resource(AdminList::from_env());
+    app.insert_resource(FrameRateCap::from_env());
+    app.insert_resource(ProjectileConfig::default());
+    app.insert_resource(load_leaderboard(&leaderboard_path()));
+    app.insert_resource(AnnouncerConfig::default());
+    app.insert_resource(Announcer::default());
+    app.insert_resource(LeaderboardTimer(Timer::from_seconds(
+        10.0,
+        TimerMode::Repeating,
+    )));
+    app.insert_resource(LeaderboardSaveTimer::from_env());
+    let capture_point_config = CapturePointConfig::from_env();
+    app.insert_resource(CapturePoints::from_config(&capture_point_config));
+    app.insert_resource(CaptureScoreTimer(Timer::new(
+        capture_point_config.score_interval,
+        TimerMode::Repeating,
+    )));
+    app.insert_resource(CapturePointBroadcastTimer(Timer::from_seconds(
+        0.25,
+        TimerMode::Repeating,
+    )));
+    app.insert_resource(capture_point_config);
+    app.insert_resource(ObjectiveConfig::from_env());
+    app.add_event::<SolanaBlockDestroyed>();
+    app.add_event::<HealRequest>();
+    app.init_resource::<HealCooldowns>();
+    app.insert_resource(TeamConfig::from_env());
+    app.add_event::<LethalHit>();
+    app.insert_resource(DeathConfig::from_env());
+    app.insert_resource(HealConfig::default());
+    app.insert_resource(BotId(0));
+    app.insert_resource(BotSpawnConfig::default());
+    app.insert_resource(BotConfig::from_env());
+    app.insert_resource(SpawnConfig::from_env());
+    app.insert_resource(NetworkTick::default());
+    app.insert_resource(SnapshotSize::default());
+    app.insert_resource(SnapshotBudget::from_env());
+    app.insert_resource(GroundCheckConfig::from_env());
+    app.insert_resource(LockstepConfig::from_env());
+    app.insert_resource(LockstepRelay::default());
+    app.insert_resource(SnapshotPriorities::default());
+    app.insert_resource(SnapshotHistory::from_env());
+    app.insert_resource(ReplayExportConfig::from_env());
+    app.insert_resource(ReplayExport::default());
+    app.insert_resource(StressTestConfig::from_env());
+    app.insert_resource(StressTestReportTimer(Timer::from_seconds(
+        5.0,
+        TimerMode::Repeating,
+    )));
+    app.insert_resource(RateLimiter::default());
+    app.init_resource::<SeenWalletChallenges>();
+    let match_config = MatchConfig::from_env();
+    app.insert_resource(MatchTimer(Timer::new(
+        match_config.duration,
+        TimerMode::Once,
+    )));
+    app.insert_resource(match_config);
+    app.insert_resource(GamePhase::Playing);
+    app.insert_resource(BlockReconcileTimer(Timer::from_seconds(
+        5.0,
+        TimerMode::Repeating,
+    )));
+
+    let (server, transport) = new_renet_server();
+    app.insert_resource(server);
+    app.insert_resource(transport);
+
+    app.insert_resource(EntityCensus::default());
+    app.insert_resource(CensusTimer(Timer::from_seconds(5.0, TimerMode::Repeating)));
+    app.add_system(census_system);
+    #[cfg(debug_assertions)]
+    {
+        app.insert_resource(RenetServerVisualizer::<200>::default());
+        app.add_system(census_ui_system);
+    }
+
+    app.add_system(
+        rate_limiter_system
+            .before(server_update_system)
+            .before(entity_request_system),
+    );
+    app.add_systems((
+        server_update_system,
+        server_network_sync,
+        record_snapshot.after(server_network_sync),
+        replay_export_system.after(record_snapshot),
+        move_players_system,
+        apply_movement_mode_system,
+        update_projectiles_system,
+        #[cfg(debug_assertions)]
+        update_visualizer_system,
+        entity_request_system,
+        stress_test_report_system,
+        send_world_settings_system,
+        lockstep_relay_system.before(server_update_system),
+    ));
+    app.add_systems((
+        projectile_collision_system,
+        projectile_damage_system,
+        grounded_check_system.before(knock_up_landing_system),
+        knock_up_landing_system,
+        homing_system,
+        heal_system.after(server_update_system),
+        mana_regen_system,
+        impact_decal_system,
+        projectile_bounce_system,
+        spawn_protection_system,
+    ));
+    app.add_systems((
+        score_system,
+        block_telemetry_system,
+        leaderboard_system,
+        death_resolution_system
+            .after(projectile_damage_system)
+            .before(match_phase_system),
+        match_phase_system,
+        reconcile_solana_blocks_system,
+        expire_solana_blocks_system,
+        capture_point_system,
+        capture_point_broadcast_system,
+        objective_system,
+    ));
+    app.add_systems((
+        maintain_bot_count_system,
+        bot_autocast,
+        save_leaderboard_system,
+        reconnect_grace_system,
+    ));
+
+    app.add_system(
+        flush_outbox_system
+            .in_base_set(CoreSet::PostUpdate)
+            .after(projectile_on_removal_system)
+            .after(solana_block_on_removal_system),
+    );
+    app.add_system(projectile_on_removal_system.in_base_set(CoreSet::PostUpdate));
+    app.add_system(solana_block_on_removal_system.in_base_set(CoreSet::PostUpdate));
+    app.add_system(save_leaderboard_on_exit_system.in_base_set(CoreSet::Last));
+    app.add_system(frame_rate_cap_system.in_base_set(CoreSet::Last));
+    #[cfg(debug_assertions)]
+    app.add_startup_system(bevy_playground::setup_level);
+    #[cfg(not(debug_assertions))]
+    app.add_startup_system(bevy_playground::setup_level_server);
+    app.add_startup_system(configure_solver_system);
+    app.add_startup_system(spawn_stress_test_balls);
+    app.add_startup_system(spawn_objective);
+    #[cfg(debug_assertions)] {
+        app.add_startup_system(setup_simple_camera);
+        app.add_system(camera_zoom_system);
+        app.add_system(camera_movement_system);
+        app.add_system(spawn_bot);
+    }
+
+    app.run();
+}
+
+/// Solana block spawning as the admin console sees it.
+#[derive(SystemParam)]
+struct BlockAdmin<'w> {
+    spawn_config: ResMut<'w, SolanaSpawnConfig>,
+    telemetry: Res<'w, BlockTelemetry>,
+}
+
+/// Status markers `server_update_system` reports to joining clients.
+#[derive(SystemParam)]
+struct PlayerStatus<'w, 's> {
+    invulnerable: Query<'w, 's, (), With<Invulnerable>>,
+    protected: Query<'w, 's, (), With<SpawnProtection>>,
+    bots: Query<'w, 's, (), With<Bot>>,
+    eliminated: Query<'w, 's, (), With<Eliminated>>,
+}
+
+#[derive(Debug, Resource)]
+struct HealConfig {
+    amount: f32,
+    /// How far from the target point a friendly player can be and still be healed.
+    radius: f32,
+    mana_cost: f32,
+    cooldown: f32,
+    /// Mana regenerated per second.
+    mana_regen: f32,
+}
+
+impl Default for HealConfig {
+    fn default() -> Self {
+        Self {
+            amount: 25.0,
+            radius: 2.0,
+            mana_cost: 30.0,
+            cooldown: 3.0,
+            mana_regen: 5.0,
+        }
+    }
+}
+
+struct HealRequest {
+    caster: Entity,
+    target_position: Vec3,
+}
+
+/// When each caster can heal again, in seconds since startup. Checked and
+/// updated in place, so a second request in the same tick is already blocked.
+#[derive(Debug, Default, Resource)]
+struct HealCooldowns(HashMap<Entity, f64>);
+
+/// Heals the friendly player nearest the requested point, if the caster has
+/// mana and is off cooldown. Mana is only spent when someone is healed.
+fn heal_system(
+    time: Res<Time>,
+    mut requests: EventReader<HealRequest>,
+    config: Res<HealConfig>,
+    mut cooldowns: ResMut<HealCooldowns>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut casters: Query<(&mut Mana, Option<&Team>)>,
+    mut targets: Query<(Entity, &Player, &Transform, &mut Health, Option<&Team>)>,
+) {
+    let now = time.elapsed_seconds_f64();
+    cooldowns.0.retain(|_, ready_at| *ready_at > now);
+    for request in requests.iter() {
+        if cooldowns.0.contains_key(&request.caster) {
+            continue;
+        }
+        let Ok((mut mana, caster_team)) = casters.get_mut(request.caster) else {
+            continue;
+        };
+
+        let target = targets
+            .iter_mut()
+            .filter(|(entity, _, _, _, team)| {
+                *entity == request.caster || (caster_team.is_some() && caster_team == *team)
+            })
+            .map(|(entity, player, transform, health, _)| {
+                let distance = transform.translation.distance(request.target_position);
+                (entity, player, health, distance)
+            })
+            .filter(|(_, _, _, distance)| *distance <= config.radius)
+            .min_by(|a, b| a.3.total_cmp(&b.3));
+        let Some((_, player, mut health, _)) = target else {
+            continue;
+        };
+        if !mana.spend(config.mana_cost) {
+            continue;
+        }
+
+        health.heal(config.amount);
+        println!(
+            "Player {} healed, health {}/{}",
+            player.id, health.current, health.max
+        );
+        cooldowns
+            .0
+            .insert(request.caster, now + config.cooldown as f64);
+
+        outbox.broadcast(ServerMessages::HealEffect {
+            target_id: player.id,
+        });
+    }
+}
+
+fn mana_regen_system(time: Res<Time>, config: Res<HealConfig>, mut query: Query<&mut Mana>) {
+    for mut mana in query.iter_mut() {
+        mana.regenerate(config.mana_regen * time.delta_seconds());
+    }
+}
+
+/// Runs small matches in deterministic lockstep, read from `NETWORK_MODEL`
+/// (`lockstep` to enable). Clients exchange only inputs through the server
+/// and each simulates a [`LockstepState`], so players are left out of
+/// snapshots. Blocks, projectiles and the rest stay server-authoritative.
+#[derive(Debug, Resource)]
+struct LockstepConfig {
+    enabled: bool,
+    /// Participants past this get a warning; every extra client slows the
+    /// barrier down to its own latency.
+    max_players: usize,
+    /// Ticks of state hashes kept waiting for a slow participant's report,
+    /// and how far ahead of the barrier inputs are buffered.
+    hash_history: u64,
+    /// Seconds the barrier waits on a tick before disconnecting whoever
+    /// hasn't sent their input for it, read from `LOCKSTEP_INPUT_TIMEOUT`.
+    input_timeout: f64,
+}
+
+impl LockstepConfig {
+    fn from_env() -> Self {
+        LockstepConfig {
+            enabled: std::env::var("NETWORK_MODEL").is_ok_and(|model| model == "lockstep"),
+            max_players: 4,
+            hash_history: 600,
+            input_timeout: std::env::var("LOCKSTEP_INPUT_TIMEOUT")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .filter(|secs: &f64| *secs > 0.0)
+                .unwrap_or(2.0),
+        }
+    }
+}
+
+/// Inputs and state hashes collected per tick by `lockstep_relay_system`.
+#[derive(Debug, Default, Resource)]
+struct LockstepRelay {
+    /// Oldest tick not yet confirmed.
+    next_tick: u64,
+    /// When the barrier started waiting on `next_tick`.
+    waiting_since: f64,
+    inputs: BTreeMap<u64, HashMap<u64, PlayerInput>>,
+    hashes: BTreeMap<u64, HashMap<u64, u64>>,
+    /// The server's own simulation, which clients' hashes are checked against.
+    state: LockstepState,
+    /// The server's hash of `state` after each confirmed tick.
+    expected: BTreeMap<u64, u64>,
+}
+
+impl LockstepRelay {
+    /// Participants still missing their input for `next_tick` once the
+    /// barrier has waited on it for longer than `timeout` seconds.
+    fn stalled(&self, participants: &[u64], now: f64, timeout: f64) -> Vec<u64> {
+        if now - self.waiting_since <= timeout {
+            return Vec::new();
+        }
+        let inputs = self.inputs.get(&self.next_tick);
+        participants
+            .iter()
+            .copied()
+            .filter(|id| inputs.is_none_or(|inputs| !inputs.contains_key(id)))
+            .collect()
+    }
+
+    /// Simulates `tick` and records the hash clients should report for it.
+    fn confirm(&mut self, tick: u64, inputs: &[(u64, PlayerInput)], now: f64) {
+        self.state.step(tick, inputs);
+        self.expected.insert(tick, self.state.hash());
+        self.next_tick = tick + 1;
+        self.waiting_since = now;
+    }
+}
+
+/// Confirms each tick once every connected client has sent its input for
+/// it, broadcasting the inputs together and moving the server's own players
+/// to where the simulation puts them, and checks the state hashes clients
+/// report back. Participants that stall the barrier are disconnected.
+#[allow(clippy::too_many_arguments)]
+fn lockstep_relay_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<LockstepConfig>,
+    mut relay: ResMut<LockstepRelay>,
+    mut server: ResMut<RenetServer>,
+    mut outbox: ResMut<ServerOutbox>,
+    lobby: Res<ServerLobby>,
+    mut warned: Local<bool>,
+) {
+    if !config.enabled {
+        return;
+    }
+    // Observers have no player to send inputs for
+    let mut participants = server.clients_id();
+    participants.retain(|client_id| lobby.players.contains_key(client_id));
+    if participants.len() > config.max_players && !*warned {
+        println!(
+            "Lockstep has {} participants, more than the {} it is meant for",
+            participants.len(),
+            config.max_players
+        );
+        *warned = true;
+    }
+
+    for client_id in participants.iter().copied() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Lockstep) {
+            let Ok(message) = bincode::deserialize::<LockstepMessage>(&message) else {
+                println!("Client {} sent an invalid lockstep message", client_id);
+                continue;
+            };
+            match message {
+                // Late inputs for confirmed ticks can't be used any more
+                LockstepMessage::Input { tick, input }
+                    if tick >= relay.next_tick && tick < relay.next_tick + config.hash_history =>
+                {
+                    relay
+                        .inputs
+                        .entry(tick)
+                        .or_default()
+                        .insert(client_id, input);
+                }
+                LockstepMessage::Input { .. } => {}
+                LockstepMessage::StateHash { tick, hash } => {
+                    relay
+                        .hashes
+                        .entry(tick)
+                        .or_default()
+                        .insert(client_id, hash);
+                }
+            }
+        }
+    }
+    let now = time.elapsed_seconds_f64();
+    if participants.is_empty() {
+        relay.waiting_since = now;
+        return;
+    }
+
+    // The barrier: a tick goes out only once nobody is missing from it
+    loop {
+        let tick = relay.next_tick;
+        let ready = relay
+            .inputs
+            .get(&tick)
+            .is_some_and(|inputs| participants.iter().all(|id| inputs.contains_key(id)));
+        if !ready {
+            break;
+        }
+        let mut inputs: Vec<(u64, PlayerInput)> = relay
+            .inputs
+            .remove(&tick)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(id, _)| participants.contains(id))
+            .collect();
+        inputs.sort_by_key(|(id, _)| *id);
+        relay.confirm(tick, &inputs, now);
+        for (id, input) in &inputs {
+            if let (Some(entity), Some(position)) =
+                (lobby.players.get(id), relay.state.positions.get(id))
+            {
+                let input = PlayerInput {
+                    position: position.to_array(),
+                    ..*input
+                };
+                commands.entity(*entity).insert(input);
+            }
+        }
+        outbox.broadcast(ServerMessages::LockstepTick { tick, inputs });
+    }
+    let window = relay.next_tick..relay.next_tick + config.hash_history;
+    relay.inputs.retain(|tick, _| window.contains(tick));
+
+    let stalled = relay.stalled(&participants, now, config.input_timeout);
+    for client_id in stalled.iter().copied() {
+        println!(
+            "Client {} sent no lockstep input for tick {} in {}s, disconnecting",
+            client_id, relay.next_tick, config.input_timeout
+        );
+        server.disconnect(client_id);
+    }
+    // The rest get a fresh wait now that the barrier no longer counts them
+    if !stalled.is_empty() {
+        relay.waiting_since = now;
+    }
+
+    let confirmed: Vec<u64> = relay
+        .hashes
+        .iter()
+        .filter(|(_, hashes)| participants.iter().all(|id| hashes.contains_key(id)))
+        .map(|(tick, _)| *tick)
+        .collect();
+    for tick in confirmed {
+        let hashes = relay.hashes.remove(&tick).unwrap_or_default();
+        let Some(expected) = relay.expected.get(&tick).copied() else {
+            continue;
+        };
+        if hashes.values().any(|hash| *hash != expected) {
+            println!(
+                "Lockstep desync at tick {}: expected {}, got {:?}",
+                tick, expected, hashes
+            );
+            outbox.broadcast(ServerMessages::LockstepDesync { tick });
+        }
+    }
+    let oldest = relay.next_tick.saturating_sub(config.hash_history);
+    relay.hashes.retain(|tick, _| *tick >= oldest);
+    relay.expected.retain(|tick, _| *tick >= oldest);
+}
+
+/// Tells each new client the physics timestep so it can step at the same
+/// rate, how often observers get snapshots, how projectiles fly, and where to
+/// join the lockstep if it's running.
+#[allow(clippy::too_many_arguments)]
+fn send_world_settings_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut server: ResMut<RenetServer>,
+    transport: Res<NetcodeServerTransport>,
+    world_config: Res<WorldConfig>,
+    projectile_config: Res<ProjectileConfig>,
+    budget: Res<SnapshotBudget>,
+    lockstep_config: Res<LockstepConfig>,
+    relay: Res<LockstepRelay>,
+) {
+    for event in server_events.iter() {
+        if let ServerEvent::ClientConnected { client_id } = event {
+            let observer = transport
+                .user_data(*client_id)
+                .is_some_and(|user_data| is_observer(&user_data));
+            let message = bincode::serialize(&ServerMessages::WorldSettings {
+                physics_timestep: world_config.physics_timestep,
+                snapshot_interval: observer.then_some(1.0 / budget.observer_update_rate),
+                lockstep_tick: lockstep_config.enabled.then_some(relay.next_tick),
+                lockstep_positions: relay.state.positions(),
+                projectile: projectile_config.clone(),
+            })
+            .unwrap();
+            server.send_message(*client_id, ServerChannel::ServerMessages, message);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn server_update_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut commands: Commands,
+    mut visuals: DebugVisuals,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut auth: ConnectAuth,
+    mut rate_limiter: ResMut<RateLimiter>,
+    admins: Res<AdminList>,
+    leaderboard: Res<Leaderboard>,
+    projectile_config: Res<ProjectileConfig>,
+    mut blocks: BlockAdmin,
+    players: Query<(Entity, &Player, &Transform)>,
+    status: PlayerStatus,
+    spawn_picker: SpawnPicker,
+    mut heal_requests: EventWriter<HealRequest>,
+) {
+    for event in server_events.iter() {
+        match event {
+            ServerEvent::ClientConnected { client_id } => {
+                match lobby.claim_id(*client_id, |entity| status.bots.contains(entity)) {
+                    Err(_) => {
+                        println!("Player {} rejected: id is taken by a bot.", client_id);
+                        server.disconnect(*client_id);
+                        continue;
+                    }
+                    Ok(Some(existing)) => {
+                        println!(
+                            "Player {} reconnected before its disconnect was seen.",
+                            client_id
+                        );
+                        commands
+                            .entity(existing)
+                            .insert(AwaitingReconnect::default());
+                    }
+                    Ok(None) => {}
+                }
+
+                let user_data = auth.transport.user_data(*client_id);
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let wallet = match user_data
+                    .map(|user_data| verify_wallet_identity(&user_data, *client_id, now))
+                {
+                    Some(WalletIdentity::Verified { wallet, signed_at }) => {
+                        if !auth
+                            .wallet_challenges
+                            .first_use(wallet, *client_id, signed_at, now)
+                        {
+                            println!(
+                                "Player {} rejected: wallet signature was already used.",
+                                client_id
+                            );
+                            server.disconnect(*client_id);
+                            continue;
+                        }
+                        Some(wallet)
+                    }
+                    Some(WalletIdentity::Expired) => {
+                        println!(
+                            "Player {} rejected: wallet signature is too old or from the future.",
+                            client_id
+                        );
+                        server.disconnect(*client_id);
+                        continue;
+                    }
+                    Some(WalletIdentity::Invalid) => {
+                        println!("Player {} rejected: invalid wallet signature.", client_id);
+                        server.disconnect(*client_id);
+                        continue;
+                    }
+                    Some(WalletIdentity::None) | None => None,
+                };
+                let name = user_data
+                    .and_then(|user_data| decode_player_name(&user_data))
+                    .unwrap_or_else(|| format!("player-{}", client_id));
+                let observer = user_data.is_some_and(|user_data| is_observer(&user_data));
+                match wallet {
+                    Some(wallet) => println!(
+                        "Player {} ({}, wallet {}) connected.",
+                        client_id, name, wallet
+                    ),
+                    None if observer => println!("Observer {} ({}) connected.", client_id, name),
+                    None => println!("Player {} ({}) connected.", client_id, name),
+                }
+
+                let message = bincode::serialize(&ServerMessages::Leaderboard {
+                    entries: leaderboard.top(LEADERBOARD_SIZE),
+                })
+                .unwrap();
+                server.send_message(*client_id, ServerChannel::ServerMessages, message);
+
+                // Initialize other players for this new client
+                for (entity, player, transform) in players.iter() {
+                    let translation: [f32; 3] = transform.translation.into();
+                    let message = bincode::serialize(&ServerMessages::PlayerCreate {
+                        id: player.id,
+                        entity,
+                        translation,
+                    })
+                    .unwrap();
+                    server.send_message(*client_id, ServerChannel::ServerMessages, message);
+
+                    if status.invulnerable.contains(entity) {
+                        let message = bincode::serialize(&ServerMessages::PlayerInvulnerable {
+                            id: player.id,
+                            invulnerable: true,
+                        })
+                        .unwrap();
+                        server.send_message(*client_id, ServerChannel::ServerMessages, message);
+                    }
+                    if status.protected.contains(entity) {
+                        let message = bincode::serialize(&ServerMessages::PlayerSpawnProtection {
+                            id: player.id,
+                            protected: true,
+                        })
+                        .unwrap();
+                        server.send_message(*client_id, ServerChannel::ServerMessages, message);
+                    }
+                }
+                if observer {
+                    lobby.observers.insert(*client_id);
+                    continue;
+                }
+
+                // Resume the player left behind by an earlier connection, so
+                // nobody has to respawn it. A different identity gets a new one.
+                if let Some(previous) = lobby.reconnecting.remove(client_id) {
+                    match players.get(previous) {
+                        Ok((_, player, _)) if player.name == name && player.wallet == wallet => {
+                            println!("Player {} resumed its player.", client_id);
+                            commands
+                                .entity(previous)
+                                .remove::<AwaitingReconnect>()
+                                .insert(PlayerInput::default());
+                            lobby.players.insert(*client_id, previous);
+                            continue;
+                        }
+                        _ => {
+                            commands.entity(previous).despawn();
+                            outbox.broadcast(ServerMessages::PlayerRemove { id: *client_id });
+                        }
+                    }
+                }
+
+                // Spawn new player
+                let team = spawn_picker.pick_team();
+                let transform = spawn_picker.spawn_transform(team);
+                let player_entity = spawn_player_server(
+                    &mut commands,
+                    transform,
+                    Player {
+                        id: *client_id,
+                        name,
+                        wallet,
+                    },
+                    RigidBody::Dynamic,
+                );
+                commands
+                    .entity(player_entity)
+                    .insert(PlayerInput::default())
+                    .insert(Velocity::default())
+                    .insert(SpawnProtection::default());
+                if let Some(team) = team {
+                    commands.entity(player_entity).insert(team);
+                }
+                visuals.insert(
+                    &mut commands,
+                    player_entity,
+                    Mesh::from(shape::Capsule::default()),
+                    Color::rgb(0.8, 0.7, 0.6),
+                );
+
+                lobby.players.insert(*client_id, player_entity);
+
+                let translation: [f32; 3] = transform.translation.into();
+                outbox.broadcast(ServerMessages::PlayerCreate {
+                    id: *client_id,
+                    entity: player_entity,
+                    translation,
+                });
+
+                outbox.broadcast(ServerMessages::PlayerSpawnProtection {
+                    id: *client_id,
+                    protected: true,
+                });
+            }
+            ServerEvent::ClientDisconnected { client_id, reason } => {
+                println!("Player {} disconnected: {}", client_id, reason);
+                rate_limiter.forget(*client_id);
+                if lobby.observers.remove(client_id) {
+                    continue;
+                }
+                // A client rejected for sharing a bot's id must not take the bot with it
+                if lobby
+                    .players
+                    .get(client_id)
+                    .is_some_and(|entity| status.bots.contains(*entity))
+                {
+                    continue;
+                }
+                // Kept still until it is resumed or `reconnect_grace_system` removes it
+                if let Some(player_entity) = lobby.players.remove(client_id) {
+                    commands
+                        .entity(player_entity)
+                        .insert((AwaitingReconnect::default(), PlayerInput::default()));
+                    lobby.reconnecting.insert(*client_id, player_entity);
+                }
+            }
+        }
+    }
+
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
+            if !rate_limiter.allow(client_id, ClientChannel::Command) {
+                continue;
+            }
+            let Some(command) = decode_client_message::<PlayerCommand>(
+                &mut server,
+                &mut rate_limiter,
+                client_id,
+                &message,
+            ) else {
+                continue;
+            };
+            // Eliminated players can still talk, but not fight
+            let eliminated = lobby
+                .players
+                .get(&client_id)
+                .is_some_and(|entity| status.eliminated.contains(*entity));
+            let attack = attack_cast(&command, &projectile_config);
+            if eliminated && (attack.is_some() || matches!(command, PlayerCommand::Heal { .. })) {
+                continue;
+            }
+            if let Some((cast_at, config, count)) = attack {
+                println!("Received {:?} from client {}", command, client_id);
+
+                if let Some(player_entity) = lobby.players.get(&client_id) {
+                    if let Ok((_, _, player_transform)) = players.get(*player_entity) {
+                        if let Err(reason) = cast_fireballs(
+                            &mut commands,
+                            &mut visuals,
+                            &mut outbox,
+                            &config,
+                            *player_entity,
+                            client_id,
+                            player_transform,
+                            cast_at,
+                            count,
+                        ) {
+                            println!("Client {}: {}", client_id, reason);
+                            send_chat_reply(&mut server, client_id, reason);
+                        }
+                    }
+                    if status.protected.contains(*player_entity) {
+                        end_spawn_protection(&mut commands, &mut outbox, *player_entity, client_id);
+                    }
+                }
+                continue;
+            }
+            match command {
+                PlayerCommand::Heal { target_position } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        heal_requests.send(HealRequest {
+                            caster: *player_entity,
+                            target_position,
+                        });
+                    }
+                }
+                PlayerCommand::Emote { kind } => {
+                    outbox.broadcast(ServerMessages::EmotePlayed {
+                        id: client_id,
+                        kind,
+                    });
+                }
+                PlayerCommand::Ping { position } => {
+                    outbox.broadcast(ServerMessages::PingPlaced {
+                        id: client_id,
+                        position: position.into(),
+                    });
+                }
+                PlayerCommand::Chat { message } => {
+                    let Some(command) = message.strip_prefix('/') else {
+                        outbox.broadcast(ServerMessages::ChatMessage {
+                            sender: Some(client_id),
+                            message,
+                        });
+                        continue;
+                    };
+
+                    let command = match ChatCommand::parse(command) {
+                        Ok(command) => command,
+                        Err(e) => {
+                            send_chat_reply(&mut server, client_id, e);
+                            continue;
+                        }
+                    };
+                    if command.requires_admin() && !admins.0.contains(&client_id) {
+                        send_chat_reply(&mut server, client_id, "Admin only command".to_string());
+                        continue;
+                    }
+
+                    match command {
+                        ChatCommand::God { id } => {
+                            let Some(player_entity) = lobby.players.get(&id) else {
+                                send_chat_reply(
+                                    &mut server,
+                                    client_id,
+                                    format!("No player with id {}", id),
+                                );
+                                continue;
+                            };
+
+                            let invulnerable = !status.invulnerable.contains(*player_entity);
+                            if invulnerable {
+                                commands.entity(*player_entity).insert(Invulnerable);
+                            } else {
+                                commands.entity(*player_entity).remove::<Invulnerable>();
+                            }
+                            println!(
+                                "Client {} set god mode {} for player {}",
+                                client_id, invulnerable, id
+                            );
+
+                            outbox
+                                .broadcast(ServerMessages::PlayerInvulnerable { id, invulnerable });
+                        }
+                        ChatCommand::Players => {
+                            let summaries =
+                                leaderboard.summaries(players.iter().map(|(entity, player, _)| {
+                                    (player, status.bots.contains(entity))
+                                }));
+
+                            send_chunked(
+                                &mut server,
+                                Some(client_id),
+                                &ServerMessages::PlayerList { players: summaries },
+                            );
+                        }
+                        ChatCommand::SpawnInterval { interval } => {
+                            let interval = interval.max(MIN_SOLANA_SPAWN_INTERVAL);
+                            blocks.spawn_config.interval = interval;
+                            println!(
+                                "Client {} set Solana block spawn interval to {:?}",
+                                client_id, interval
+                            );
+                            send_chat_reply(
+                                &mut server,
+                                client_id,
+                                format!(
+                                    "Solana blocks now spawn every {:.1}s",
+                                    interval.as_secs_f32()
+                                ),
+                            );
+                        }
+                        ChatCommand::Teleport { id, position } => {
+                            let Some(player_entity) = lobby.players.get(&id) else {
+                                send_chat_reply(
+                                    &mut server,
+                                    client_id,
+                                    format!("No player with id {}", id),
+                                );
+                                continue;
+                            };
+
+                            commands.entity(*player_entity).insert((
+                                Transform::from_translation(position),
+                                Teleported {
+                                    destination: position,
+                                    grace: Timer::from_seconds(
+                                        TELEPORT_GRACE_SECS,
+                                        TimerMode::Once,
+                                    ),
+                                },
+                            ));
+                            println!(
+                                "Client {} teleported player {} to {}",
+                                client_id, id, position
+                            );
+
+                            outbox.broadcast(ServerMessages::PlayerTeleport {
+                                id,
+                                translation: position.into(),
+                            });
+                        }
+                        ChatCommand::Blocks { count } => {
+                            let telemetry = &blocks.telemetry;
+                            let mut lines = vec![telemetry.summary()];
+                            for event in telemetry.recent(count) {
+                                let age = event
+                                    .age
+                                    .map_or(String::new(), |secs| format!(" after {:.1}s", secs));
+                                lines.push(match event.kind {
+                                    BlockEventKind::Spawned { position } => {
+                                        format!(
+                                            "{:.1}s: slot {} spawned at {}",
+                                            event.time, event.slot, position
+                                        )
+                                    }
+                                    BlockEventKind::Destroyed { by: Some(id) } => {
+                                        format!(
+                                            "{:.1}s: slot {} destroyed by {}{}",
+                                            event.time, event.slot, id, age
+                                        )
+                                    }
+                                    BlockEventKind::Destroyed { by: None } => {
+                                        format!(
+                                            "{:.1}s: slot {} destroyed{}",
+                                            event.time, event.slot, age
+                                        )
+                                    }
+                                    BlockEventKind::Expired => {
+                                        format!(
+                                            "{:.1}s: slot {} expired{}",
+                                            event.time, event.slot, age
+                                        )
+                                    }
+                                });
+                            }
+                            send_chat_reply(&mut server, client_id, lines.join("\n"));
+                        }
+                    }
+                }
+                // Attacks were cast above
+                _ => {}
+            }
+        }
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
+            if !rate_limiter.allow(client_id, ClientChannel::Input) {
+                continue;
+            }
+            let Some(input) = decode_client_message::<PlayerInput>(
+                &mut server,
+                &mut rate_limiter,
+                client_id,
+                &message,
+            ) else {
+                continue;
+            };
+            if let Some(player_entity) = lobby.players.get(&client_id) {
+                commands.entity(*player_entity).insert(input);
+            }
+        }
+    }
+}
+
+/// Physics and gameplay components of a player. Clients render players
+/// themselves, so nothing visual is added here.
+fn spawn_player_server(
+    commands: &mut Commands,
+    transform: Transform,
+    player: Player,
+    body: RigidBody,
+) -> Entity {
+    commands
+        .spawn(TransformBundle::from_transform(transform))
+        .insert(body)
+        .insert(player_physics())
+        .insert(Health::new(PLAYER_MAX_HEALTH))
+        .insert(Mana::new(PLAYER_MAX_MANA))
+        .insert(player)
+        .id()
+}
+
+/// Mesh and material storage for the debug server window. Headless release
+/// servers have neither, so their entities keep only physics and gameplay
+/// components.
+#[derive(SystemParam)]
+struct DebugVisuals<'w> {
+    meshes: Option<ResMut<'w, Assets<Mesh>>>,
+    materials: Option<ResMut<'w, Assets<StandardMaterial>>>,
+}
+
+impl DebugVisuals<'_> {
+    /// Gives `entity` a mesh so the debug server window can draw it.
+    fn insert(&mut self, commands: &mut Commands, entity: Entity, mesh: Mesh, color: Color) {
+        let (Some(meshes), Some(materials)) = (&mut self.meshes, &mut self.materials) else {
+            return;
+        };
+        commands.entity(entity).insert((
+            meshes.add(mesh),
+            materials.add(MaterialPreset::MATTE.material(color)),
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+fn fireball_mesh(radius: f32) -> Mesh {
+    Mesh::try_from(shape::Icosphere {
+        radius,
+        subdivisions: 2,
+    })
+    .unwrap()
+}
+
+/// Where an attack command is aimed, and the projectile and how many of them
+/// it casts. `None` for commands that aren't attacks.
+fn attack_cast(
+    command: &PlayerCommand,
+    config: &ProjectileConfig,
+) -> Option<(Vec3, ProjectileConfig, usize)> {
+    match *command {
+        PlayerCommand::BasicAttack { cast_at, charge } => {
+            Some((cast_at, config.charged(charge), 1))
+        }
+        PlayerCommand::SpreadAttack { cast_at } => {
+            Some((cast_at, config.clone(), config.spread_count))
+        }
+        PlayerCommand::SeekingAttack { cast_at } => Some((cast_at, config.seeking(), 1)),
+        PlayerCommand::PiercingAttack { cast_at } => Some((cast_at, config.piercing(), 1)),
+        _ => None,
+    }
+}
+
+/// Fires `count` fireballs from the caster toward `cast_at`, fanned across
+/// `ProjectileConfig::spread_angle` when there is more than one.
+#[allow(clippy::too_many_arguments)]
+fn cast_fireballs(
+    commands: &mut Commands,
+    visuals: &mut DebugVisuals,
+    outbox: &mut ServerOutbox,
+    config: &ProjectileConfig,
+    caster: Entity,
+    caster_id: u64,
+    caster_transform: &Transform,
+    cast_at: Vec3,
+    count: usize,
+) -> Result<(), String> {
+    let aim = validate_cast(config, caster_transform, cast_at)?;
+    broadcast_cast_performed(outbox, caster_id, caster_transform.translation);
+
+    for direction in spread_directions(aim, count, config.spread_angle) {
+        let mut translation = caster_transform.translation + (direction * 0.7);
+        translation[1] = 1.0;
+
+        let Some(fireball_entity) =
+            spawn_fireball_server(commands, config, translation, direction, Some(caster))
+        else {
+            return Err("Cast rejected: invalid direction.".to_string());
+        };
+        visuals.insert(
+            commands,
+            fireball_entity,
+            fireball_mesh(config.radius),
+            Color::rgb(1.0, 0.0, 0.0),
+        );
+        let message = ServerMessages::SpawnProjectile {
+            entity: fireball_entity,
+            translation: translation.into(),
+            velocity: (direction * config.speed).into(),
+            radius: config.radius,
+            kind: config.kind,
+        };
+        outbox.broadcast(message);
+    }
+    Ok(())
+}
+
+fn broadcast_cast_performed(outbox: &mut ServerOutbox, caster_id: u64, position: Vec3) {
+    outbox.broadcast(ServerMessages::CastPerformed {
+        caster_id,
+        position: position.into(),
+    });
+}
+
+/// Checks a client-supplied cast target and returns the flattened aim
+/// direction, or the reason the cast is rejected.
+fn validate_cast(
+    config: &ProjectileConfig,
+    caster_transform: &Transform,
+    mut cast_at: Vec3,
+) -> Result<Vec3, String> {
+    if !cast_at.is_finite() {
+        return Err("Cast rejected: invalid target.".to_string());
+    }
+    cast_at[1] = caster_transform.translation[1];
+    let offset = cast_at - caster_transform.translation;
+    if offset.length() > config.max_cast_range {
+        return Err(format!(
+            "Cast rejected: target is further than {} from you.",
+            config.max_cast_range
+        ));
+    }
+    offset
+        .try_normalize()
+        .ok_or_else(|| "Cast rejected: target is on top of you.".to_string())
+}
+
+fn update_projectiles_system(
+    mut commands: Commands,
+    mut projectiles: Query<(Entity, &mut Projectile)>,
+    time: Res<Time>,
+) {
+    for (entity, mut projectile) in projectiles.iter_mut() {
+        projectile.duration.tick(time.delta());
+        if projectile.duration.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Tick of the last snapshot sent by `server_network_sync`.
+#[derive(Debug, Default, Resource)]
+struct NetworkTick(u64);
+
+/// What and where every networked entity was at one snapshot tick.
+#[derive(Debug)]
+pub struct WorldSnapshot {
+    pub tick: u64,
+    pub server_time: f64,
+    pub entities: HashMap<Entity, (ReplayEntityKind, Transform)>,
+}
+
+/// The last `depth` world snapshots, oldest first, for replay export, lag
+/// compensation and rollback experiments. `depth` is read from
+/// `SNAPSHOT_HISTORY_DEPTH`.
+#[derive(Debug, Resource)]
+pub struct SnapshotHistory {
+    depth: usize,
+    snapshots: VecDeque<WorldSnapshot>,
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        // About two seconds of the 60Hz sync
+        Self::new(120)
+    }
+}
+
+impl SnapshotHistory {
+    fn from_env() -> Self {
+        std::env::var("SNAPSHOT_HISTORY_DEPTH")
+            .ok()
+            .and_then(|depth| depth.parse().ok())
+            .filter(|depth| *depth > 0)
+            .map_or_else(Self::default, Self::new)
+    }
+
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            snapshots: VecDeque::with_capacity(depth),
+        }
+    }
+
+    fn push(&mut self, snapshot: WorldSnapshot) {
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > self.depth {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// The snapshot taken at `tick`, if it is still in the history.
+    pub fn snapshot_at(&self, tick: u64) -> Option<&WorldSnapshot> {
+        let oldest = self.snapshots.front()?.tick;
+        let index = tick.checked_sub(oldest)? as usize;
+        self.snapshots
+            .get(index)
+            .filter(|snapshot| snapshot.tick == tick)
+    }
+}
+
+/// Stores the world as of the snapshot `server_network_sync` just sent.
+#[allow(clippy::type_complexity)]
+fn record_snapshot(
+    time: Res<Time>,
+    tick: Res<NetworkTick>,
+    mut history: ResMut<SnapshotHistory>,
+    query: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&Player>,
+            Option<&Projectile>,
+            Option<&SolanaSlotBlock>,
+        ),
+        Or<(
+            With<Player>,
+            With<Projectile>,
+            With<SolanaSlotBlock>,
+            With<Ball>,
+        )>,
+    >,
+) {
+    history.push(WorldSnapshot {
+        tick: tick.0,
+        server_time: time.elapsed_seconds_f64(),
+        entities: query
+            .iter()
+            .map(|(entity, transform, player, projectile, block)| {
+                let kind = match (player, projectile, block) {
+                    (Some(player), _, _) => ReplayEntityKind::Player { id: player.id },
+                    (_, Some(projectile), _) => ReplayEntityKind::Projectile(projectile.kind),
+                    (_, _, Some(block)) => ReplayEntityKind::Block { slot: block.id },
+                    _ => ReplayEntityKind::Ball,
+                };
+                (entity, (kind, *transform))
+            })
+            .collect(),
+    });
+}
+
+/// Name recorded in exported replays for the one built-in arena.
+const REPLAY_MAP: &str = "arena";
+
+/// Directory `replay_export_system` writes each match's replay to, read
+/// from `REPLAY_EXPORT_DIR`. `None` turns exporting off.
+#[derive(Debug, Resource)]
+struct ReplayExportConfig {
+    dir: Option<PathBuf>,
+}
+
+impl ReplayExportConfig {
+    fn from_env() -> Self {
+        ReplayExportConfig {
+            dir: std::env::var("REPLAY_EXPORT_DIR").ok().map(PathBuf::from),
+        }
+    }
+}
+
+/// The replay being exported for the current match.
+#[derive(Default, Resource)]
+struct ReplayExport {
+    writer: Option<ReplayWriter<BufWriter<File>>>,
+    path: PathBuf,
+    /// Server clock in seconds when the replay started.
+    started: f64,
+    /// Set after a write error so the rest of the match isn't retried.
+    failed: bool,
+}
+
+/// Exports each match as a portable replay: starts a file when the match
+/// starts, adds a frame with every snapshot and finishes the file when the
+/// match ends.
+fn replay_export_system(
+    time: Res<Time>,
+    config: Res<ReplayExportConfig>,
+    phase: Res<GamePhase>,
+    mut export: ResMut<ReplayExport>,
+    tick: Res<NetworkTick>,
+    history: Res<SnapshotHistory>,
+    players: Query<&Player>,
+) {
+    let Some(dir) = &config.dir else {
+        return;
+    };
+    if *phase != GamePhase::Playing {
+        export.failed = false;
+        if let Some(writer) = export.writer.take() {
+            match writer.finalize() {
+                Ok(_) => println!("Exported replay to {}", export.path.display()),
+                Err(error) => println!(
+                    "Failed to finish replay {}: {}",
+                    export.path.display(),
+                    error
+                ),
+            }
+        }
+        return;
+    }
+    if export.failed {
+        return;
+    }
+
+    let now = time.elapsed_seconds_f64();
+    if export.writer.is_none() {
+        let start_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let path = dir.join(format!("match-{}.replay", start_time));
+        let header = ReplayHeader {
+            map: REPLAY_MAP.to_string(),
+            players: players
+                .iter()
+                .map(|player| ReplayPlayer {
+                    id: player.id,
+                    name: player.name.clone(),
+                })
+                .collect(),
+            start_time,
+        };
+        let started = File::create(&path).and_then(|file| {
+            let mut writer = ReplayWriter::new(BufWriter::new(file));
+            writer.write_header(&header)?;
+            Ok(writer)
+        });
+        match started {
+            Ok(writer) => {
+                export.writer = Some(writer);
+                export.path = path;
+                export.started = now;
+            }
+            Err(error) => {
+                println!("Failed to start replay {}: {}", path.display(), error);
+                export.failed = true;
+                return;
+            }
+        }
+    }
+
+    let Some(snapshot) = history.snapshot_at(tick.0) else {
+        return;
+    };
+    let mut entities: Vec<ReplayEntity> = snapshot
+        .entities
+        .iter()
+        .map(|(entity, (kind, transform))| ReplayEntity {
+            id: entity.to_bits(),
+            kind: *kind,
+            translation: transform.translation.into(),
+            rotation: transform.rotation.into(),
+        })
+        .collect();
+    entities.sort_by_key(|entity| entity.id);
+    let frame = ReplayFrame {
+        time: now - export.started,
+        entities,
+    };
+    let written = export
+        .writer
+        .as_mut()
+        .map(|writer| writer.write_frame(&frame));
+    if let Some(Err(error)) = written {
+        println!(
+            "Failed to write replay {}: {}",
+            export.path.display(),
+            error
+        );
+        export.writer = None;
+        export.failed = true;
+    }
+}
+
+/// Serialized size in bytes of the last snapshot, or the largest one sent
+/// to a client when they're trimmed per client.
+#[derive(Debug, Default, Resource)]
+struct SnapshotSize(usize);
+
+/// Fills the arena with synced bouncing balls to load physics and networking.
+/// `ball_count` is read from `STRESS_TEST_BALLS`; 0 turns the stress test off.
+#[derive(Debug, Resource)]
+struct StressTestConfig {
+    ball_count: usize,
+}
+
+impl StressTestConfig {
+    fn from_env() -> Self {
+        let ball_count = std::env::var("STRESS_TEST_BALLS")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        StressTestConfig { ball_count }
+    }
+}
+
+fn spawn_stress_test_balls(
+    mut commands: Commands,
+    mut visuals: DebugVisuals,
+    config: Res<StressTestConfig>,
+    bounds: Res<WorldBounds>,
+) {
+    if config.ball_count == 0 {
+        return;
+    }
+    println!("Stress test: spawning {} balls", config.ball_count);
+
+    let ball_config = BallConfig::default();
+    let spread = bounds.size / 2. - 1.;
+    for _ in 0..config.ball_count {
+        let translation = Vec3::new(
+            (fastrand::f32() * 2. - 1.) * spread,
+            2. + fastrand::f32() * 18.,
+            (fastrand::f32() * 2. - 1.) * spread,
+        );
+        let ball = commands
+            .spawn(TransformBundle::from_transform(
+                Transform::from_translation(translation),
+            ))
+            .insert(ball_physics(&ball_config))
+            .insert(Velocity::linear(
+                Vec3::new(fastrand::f32() - 0.5, 0., fastrand::f32() - 0.5) * 10.,
+            ))
+            .id();
+        visuals.insert(
+            &mut commands,
+            ball,
+            ball_mesh(&ball_config),
+            ball_config.color,
+        );
+    }
+}
+
+#[derive(Resource)]
+struct StressTestReportTimer(Timer);
+
+/// Logs snapshot size and frame time while the stress test runs.
+fn stress_test_report_system(
+    time: Res<Time>,
+    config: Res<StressTestConfig>,
+    snapshot_size: Res<SnapshotSize>,
+    diagnostics: Res<Diagnostics>,
+    mut timer: ResMut<StressTestReportTimer>,
+) {
+    if config.ball_count == 0 || !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let frame_time = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|frame_time| frame_time.average())
+        .unwrap_or_default();
+    println!(
+        "Stress test: {} balls, snapshot {} bytes, frame time {:.2}ms",
+        config.ball_count, snapshot_size.0, frame_time
+    );
+}
+
+/// How often `server_network_sync` sends an entity to clients once snapshots
+/// go over `SnapshotBudget::max_entities`. Entities without one are `High` for
+/// players, `Normal` for projectiles and `Low` for everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub enum NetworkPriority {
+    Low,
+    Normal,
+    /// Sent every snapshot regardless of the budget.
+    High,
+}
+
+impl NetworkPriority {
+    /// Priority accumulated per tick at no distance from the client's player.
+    fn weight(self) -> f32 {
+        match self {
+            NetworkPriority::Low => 1.0,
+            NetworkPriority::Normal => 4.0,
+            NetworkPriority::High => f32::INFINITY,
+        }
+    }
+}
+
+/// Caps how many entities each client's snapshot lists, read from
+/// `SNAPSHOT_ENTITY_BUDGET` (unset for no cap), `SNAPSHOT_PRIORITY_FALLOFF`
+/// and `FULL_SNAPSHOT_INTERVAL`, and how often observers get one, read from
+/// `OBSERVER_UPDATE_RATE`.
+#[derive(Debug, Resource)]
+pub struct SnapshotBudget {
+    pub max_entities: Option<usize>,
+    /// Distance from a client's player at which an entity's priority halves.
+    pub falloff_distance: f32,
+    /// Ticks between complete snapshots while over budget, which clients
+    /// need to notice entities they missed the despawn of.
+    pub full_snapshot_interval: u64,
+    /// Snapshots per second sent to observers, which don't need smooth motion.
+    pub observer_update_rate: f32,
+}
+
+impl SnapshotBudget {
+    fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+        }
+        SnapshotBudget {
+            max_entities: var("SNAPSHOT_ENTITY_BUDGET"),
+            falloff_distance: var("SNAPSHOT_PRIORITY_FALLOFF")
+                .unwrap_or(20.0_f32)
+                .max(f32::EPSILON),
+            full_snapshot_interval: var("FULL_SNAPSHOT_INTERVAL").unwrap_or(60_u64).max(1),
+            observer_update_rate: var("OBSERVER_UPDATE_RATE")
+                .unwrap_or(5.0_f32)
+                .max(f32::EPSILON),
+        }
+    }
+}
+
+/// Priority each client has built up for each entity since it was last sent.
+#[derive(Debug, Default, Resource)]
+struct SnapshotPriorities(HashMap<u64, HashMap<Entity, f32>>);
+
+/// Picks which of `candidates`, weighted by priority, go in a snapshot of at
+/// most `budget` entities. Infinite weights always go, even past the budget.
+/// The rest wait in `accumulated` until their priority outbids the others, so
+/// low priority entities are throttled but never dropped for good. Returns
+/// indices into `candidates`.
+fn select_by_priority(
+    accumulated: &mut HashMap<Entity, f32>,
+    candidates: &[(Entity, f32)],
+    budget: usize,
+) -> Vec<usize> {
+    let mut priorities: Vec<f32> = candidates
+        .iter()
+        .map(|(entity, weight)| accumulated.get(entity).copied().unwrap_or(0.0) + weight)
+        .collect();
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|a, b| priorities[*b].total_cmp(&priorities[*a]));
+
+    let always = priorities
+        .iter()
+        .filter(|priority| priority.is_infinite())
+        .count();
+    order.truncate(budget.max(always));
+    for &index in &order {
+        priorities[index] = 0.0;
+    }
+
+    accumulated.clear();
+    accumulated.extend(
+        candidates
+            .iter()
+            .zip(priorities)
+            .filter(|(_, priority)| *priority > 0.0)
+            .map(|((entity, _), priority)| (*entity, priority)),
+    );
+    order.sort_unstable();
+    order
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn server_network_sync(
+    time: Res<Time>,
+    mut tick: ResMut<NetworkTick>,
+    mut snapshot_size: ResMut<SnapshotSize>,
+    mut server: ResMut<RenetServer>,
+    lobby: Res<ServerLobby>,
+    budget: Res<SnapshotBudget>,
+    mut priorities: ResMut<SnapshotPriorities>,
+    lockstep: Res<LockstepConfig>,
+    mut last_observer_sync: Local<f64>,
+    query: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&NetworkPriority>,
+            Option<&Player>,
+            Option<&Projectile>,
+        ),
+        Or<(
+            With<Player>,
+            With<Projectile>,
+            With<SolanaSlotBlock>,
+            With<Ball>,
+        )>,
+    >,
+) {
+    tick.0 += 1;
+    let mut networked_entities = NetworkedEntities {
+        tick: tick.0,
+        server_time: time.elapsed_seconds_f64(),
+        complete: true,
+        ..Default::default()
+    };
+    let mut base_priorities = Vec::new();
+    let mut player_positions = HashMap::new();
+    for (entity, transform, priority, player, projectile) in query.iter() {
+        // Lockstep clients simulate players themselves
+        if lockstep.enabled && player.is_some() {
+            continue;
+        }
+        networked_entities.entities.push(entity);
+        networked_entities
+            .translations
+            .push(transform.translation.into());
+
+        let priority = priority.copied().unwrap_or(match (player, projectile) {
+            (Some(_), _) => NetworkPriority::High,
+            (_, Some(_)) => NetworkPriority::Normal,
+            _ => NetworkPriority::Low,
+        });
+        base_priorities.push(priority);
+        if let Some(player) = player {
+            player_positions.insert(player.id, transform.translation);
+        }
+    }
+
+    // Observers only get a snapshot every `1 / observer_update_rate` seconds
+    let clients = server.clients_id();
+    let observers = &lobby.observers;
+    let observers_due = networked_entities.server_time - *last_observer_sync
+        >= 1.0 / budget.observer_update_rate as f64;
+    if observers_due {
+        *last_observer_sync = networked_entities.server_time;
+    }
+    let skip = |client_id: &u64| !observers_due && observers.contains(client_id);
+
+    let max_entities = match budget.max_entities {
+        Some(max_entities) if networked_entities.entities.len() > max_entities => max_entities,
+        _ => {
+            priorities.0.clear();
+            let sync_message = bincode::serialize(&networked_entities).unwrap();
+            snapshot_size.0 = sync_message.len();
+            if observers.is_empty() || observers_due {
+                server.broadcast_message(ServerChannel::NetworkedEntities, sync_message);
+            } else {
+                for client_id in clients.iter().filter(|client_id| !skip(client_id)) {
+                    server.send_message(
+                        *client_id,
+                        ServerChannel::NetworkedEntities,
+                        sync_message.clone(),
+                    );
+                }
+            }
+            return;
+        }
+    };
+
+    // Over budget: everyone still gets a complete snapshot now and then
+    let full = tick.0.is_multiple_of(budget.full_snapshot_interval);
+    let full_message = full.then(|| bincode::serialize(&networked_entities).unwrap());
+    priorities
+        .0
+        .retain(|client_id, _| clients.contains(client_id));
+    snapshot_size.0 = 0;
+    for client_id in clients.iter().copied().filter(|client_id| !skip(client_id)) {
+        let accumulated = priorities.0.entry(client_id).or_default();
+        let sync_message = match &full_message {
+            Some(message) => {
+                accumulated.clear();
+                message.clone()
+            }
+            None => {
+                let origin = player_positions.get(&client_id).copied();
+                let candidates: Vec<(Entity, f32)> = networked_entities
+                    .entities
+                    .iter()
+                    .zip(&networked_entities.translations)
+                    .zip(&base_priorities)
+                    .map(|((entity, translation), priority)| {
+                        let distance =
+                            origin.map_or(0.0, |origin| origin.distance(Vec3::from(*translation)));
+                        let falloff =
+                            budget.falloff_distance / (budget.falloff_distance + distance);
+                        (*entity, priority.weight() * falloff)
+                    })
+                    .collect();
+                let selected = select_by_priority(accumulated, &candidates, max_entities);
+                bincode::serialize(&NetworkedEntities {
+                    tick: networked_entities.tick,
+                    server_time: networked_entities.server_time,
+                    complete: false,
+                    entities: selected
+                        .iter()
+                        .map(|&i| networked_entities.entities[i])
+                        .collect(),
+                    translations: selected
+                        .iter()
+                        .map(|&i| networked_entities.translations[i])
+                        .collect(),
+                })
+                .unwrap()
+            }
+        };
+        snapshot_size.0 = snapshot_size.0.max(sync_message.len());
+        server.send_message(client_id, ServerChannel::NetworkedEntities, sync_message);
+    }
+}
+
+/// Gives newly spawned players the locked axes and gravity of `WorldConfig::movement_mode`.
+fn apply_movement_mode_system(
+    mut commands: Commands,
+    world_config: Res<WorldConfig>,
+    players: Query<Entity, Added<Player>>,
+) {
+    let mode = world_config.movement_mode;
+    for entity in players.iter() {
+        commands
+            .entity(entity)
+            .insert((mode.locked_axes(), mode.gravity_scale()));
+    }
+}
+
+/// How long a teleported player's position ignores their input, covering
+/// inputs already in flight when the teleport was sent.
+const TELEPORT_GRACE_SECS: f32 = 0.5;
+
+/// Set by `/tp`. Holds the player at `destination` until `grace` runs out.
+#[derive(Debug, Component)]
+struct Teleported {
+    destination: Vec3,
+    grace: Timer,
+}
+
+/// Gives up on a knock-up that hasn't landed after this many seconds.
+const KNOCK_UP_MAX_SECS: f32 = 3.0;
+
+/// A hit launched this player. Their Y axis is unlocked and left to gravity
+/// until `knock_up_landing_system` sees them come down.
+#[derive(Debug, Component)]
+struct KnockedUp {
+    airborne: Timer,
+    /// Set once they pass the top of the arc.
+    falling: bool,
+}
+
+#[allow(clippy::type_complexity)]
+fn move_players_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    bounds: Res<WorldBounds>,
+    world_config: Res<WorldConfig>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &PlayerInput,
+            Option<&mut Teleported>,
+            Option<&KnockedUp>,
+        ),
+        With<Player>,
+    >,
+) {
+    for (entity, mut transform, input, teleported, knocked_up) in query.iter_mut() {
+        // Update the player's position based on the camera position. Only
+        // flying players take the client's Y; otherwise it's the ground height
+        // or whatever physics left it at.
+        let mut input_position = Vec3::from(input.position);
+        // Inputs sent before the client saw the teleport would undo it
+        if let Some(mut teleported) = teleported {
+            if teleported.grace.tick(time.delta()).finished() {
+                commands.entity(entity).remove::<Teleported>();
+            } else {
+                input_position = teleported.destination;
+            }
+        }
+        transform.translation.x = input_position.x;
+        transform.translation.z = input_position.z;
+        transform.translation.y = match world_config.movement_mode {
+            _ if knocked_up.is_some() => transform.translation.y,
+            MovementMode::Grounded => PLAYER_GROUND_Y,
+            MovementMode::Jumping => transform.translation.y,
+            MovementMode::Flying => input_position.y.max(PLAYER_GROUND_Y),
+        };
+
+        // Positions come straight from the client, so the walls alone can't stop them
+        if bounds.enabled {
+            let limit = bounds.size / 2. - PLAYER_RADIUS;
+            transform.translation.x = transform.translation.x.clamp(-limit, limit);
+            transform.translation.z = transform.translation.z.clamp(-limit, limit);
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+fn camera_movement_system(
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut query: Query<&mut Transform, With<Camera>>,
+) {
+    let mut direction = Vec3::ZERO;
+    if keyboard_input.pressed(KeyCode::W) {
+        direction.z -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::S) {
+        direction.z += 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::A) {
+        direction.x -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::D) {
+        direction.x += 1.0;
+    }
+
+    if direction != Vec3::ZERO {
+        let speed = 50.0; // Adjust the speed as needed
+        for mut transform in query.iter_mut() {
+            transform.translation += speed * direction.normalize() * time.delta_seconds();
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+pub fn setup_simple_camera(mut commands: Commands, camera_config: Res<CameraConfig>) {
+    // camera
+    commands.spawn(Camera3dBundle {
+        transform: camera_config.transform(),
+        ..Default::default()
+    });
+}
+
+/// Rapier only reports a contact when at least one of the two colliders has
+/// `ActiveEvents::COLLISION_EVENTS`. Projectiles and Solana blocks are spawned
+/// with it; players, walls and the ground rely on the other side having it.
+fn projectile_collision_system(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut block_destroyed: EventWriter<SolanaBlockDestroyed>,
+    config: Res<ProjectileConfig>,
+    mut projectiles: Query<(&mut Projectile, &Velocity)>,
+    mut blocks: Query<(&SolanaSlotBlock, Option<&mut Health>)>,
+) {
+    // Two projectiles can hit the same block in one frame; only the first scores
+    let mut destroyed = HashSet::new();
+    for collision_event in collision_events.iter() {
+        let CollisionEvent::Started(entity1, entity2, _) = collision_event else {
+            continue;
+        };
+
+        // Either side of the pair can be the projectile. Players are handled by
+        // `projectile_damage_system` and walls by `projectile_bounce_system`, and
+        // projectiles hitting each other have no effect.
+        for (projectile_entity, target) in [(*entity1, *entity2), (*entity2, *entity1)] {
+            let Ok((mut projectile, velocity)) = projectiles.get_mut(projectile_entity) else {
+                continue;
+            };
+            if destroyed.contains(&target) || projectile.hit.contains(&target) {
+                continue;
+            }
+            let Ok((block, health)) = blocks.get_mut(target) else {
+                continue;
+            };
+
+            // Blocks that survive the hit are knocked along the projectile's path
+            if let Some(mut health) = health {
+                health.damage(projectile.damage);
+                if !health.is_dead() {
+                    commands.entity(target).insert(ExternalImpulse {
+                        impulse: velocity.linvel * config.impact_force,
+                        ..default()
+                    });
+                    if !projectile.pierce(target) {
+                        commands.entity(projectile_entity).despawn();
+                    }
+                    continue;
+                }
+            }
+            // Breaking a block uses up a pierce like any other hit. Other kinds
+            // carry on through the gap it leaves.
+            if projectile.kind == ProjectileKind::Piercing {
+                if !projectile.pierce(target) {
+                    commands.entity(projectile_entity).despawn();
+                }
+            } else {
+                projectile.hit.push(target);
+            }
+
+            destroyed.insert(target);
+            commands.entity(target).despawn();
+            block_destroyed.send(SolanaBlockDestroyed {
+                slot: block.id,
+                destroyed_by: projectile.owner,
+            });
+        }
+    }
+}
+
+fn reconcile_solana_blocks_system(
+    time: Res<Time>,
+    mut timer: ResMut<BlockReconcileTimer>,
+    mut outbox: ResMut<ServerOutbox>,
+    blocks: Query<Entity, With<SolanaSlotBlock>>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let message = ServerMessages::LiveSolanaBlocks {
+        entities: blocks.iter().collect(),
+    };
+    outbox.broadcast(message);
+}
+
+/// Resends spawn data for entities a client reported missing.
+fn entity_request_system(
+    mut server: ResMut<RenetServer>,
+    mut rate_limiter: ResMut<RateLimiter>,
+    blocks: Query<(&Transform, &SolanaSlotBlock)>,
+    players: Query<(&Transform, &Player)>,
+    projectiles: Query<(&Transform, &Velocity, &Projectile)>,
+    balls: Query<&Transform, With<Ball>>,
+    projectile_config: Res<ProjectileConfig>,
+) {
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::RequestEntity) {
+            if !rate_limiter.allow(client_id, ClientChannel::RequestEntity) {
+                continue;
+            }
+            let Some(request) = decode_client_message::<EntityRequest>(
+                &mut server,
+                &mut rate_limiter,
+                client_id,
+                &message,
+            ) else {
+                continue;
+            };
+
+            for entity in request.entities {
+                let message = if let Ok((transform, block)) = blocks.get(entity) {
+                    let translation = transform.translation;
+                    ServerMessages::SpawnSolanaBlock {
+                        entity,
+                        transform: (translation.x, translation.y, translation.z),
+                        slot: block.id,
+                        shape: block.shape,
+                    }
+                } else if let Ok((transform, player)) = players.get(entity) {
+                    ServerMessages::PlayerCreate {
+                        entity,
+                        id: player.id,
+                        translation: transform.translation.into(),
+                    }
+                } else if let Ok((transform, velocity, projectile)) = projectiles.get(entity) {
+                    ServerMessages::SpawnProjectile {
+                        entity,
+                        translation: transform.translation.into(),
+                        velocity: velocity.linvel.into(),
+                        radius: projectile_config.radius,
+                        kind: projectile.kind,
+                    }
+                } else if let Ok(transform) = balls.get(entity) {
+                    ServerMessages::SpawnBall {
+                        entity,
+                        translation: transform.translation.into(),
+                    }
+                } else {
+                    continue;
+                };
+
+                let message = bincode::serialize(&message).unwrap();
+                server.send_message(client_id, ServerChannel::ServerMessages, message);
+            }
+        }
+    }
+}
+
+fn end_spawn_protection(
+    commands: &mut Commands,
+    outbox: &mut ServerOutbox,
+    player_entity: Entity,
+    id: u64,
+) {
+    commands.entity(player_entity).remove::<SpawnProtection>();
+    outbox.broadcast(ServerMessages::PlayerSpawnProtection {
+        id,
+        protected: false,
+    });
+}
+
+fn spawn_protection_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut outbox: ResMut<ServerOutbox>,
+    mut players: Query<(Entity, &Player, &mut SpawnProtection)>,
+) {
+    for (entity, player, mut protection) in players.iter_mut() {
+        if protection.timer.tick(time.delta()).just_finished() {
+            end_spawn_protection(&mut commands, &mut outbox, entity, player.id);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn projectile_damage_system(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut lethal_hits: EventWriter<LethalHit>,
+    projectile_config: Res<ProjectileConfig>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut projectiles: Query<&mut Projectile>,
+    mut players: Query<
+        (&Player, &mut Health, Option<&RigidBody>),
+        (
+            Without<Invulnerable>,
+            Without<SpawnProtection>,
+            Without<Eliminated>,
+        ),
+    >,
+) {
+    // A projectile touching two players in one frame only hits the first,
+    // unless it can pierce the first
+    let mut spent = HashSet::new();
+    for collision_event in collision_events.iter() {
+        let CollisionEvent::Started(entity1, entity2, _) = collision_event else {
+            continue;
+        };
+
+        for (projectile_entity, target) in [(*entity1, *entity2), (*entity2, *entity1)] {
+            if spent.contains(&projectile_entity) {
+                continue;
+            }
+            let Ok(mut projectile) = projectiles.get_mut(projectile_entity) else {
+                continue;
+            };
+            if projectile.hit.contains(&target) {
+                continue;
+            }
+            // Casters can't hit themselves while the projectile is leaving them
+            if projectile.owner == Some(target)
+                && projectile.duration.elapsed_secs() < projectile_config.owner_grace
+            {
+                continue;
+            }
+            if let Ok((player, mut health, body)) = players.get_mut(target) {
+                let was_dead = health.is_dead();
+                health.damage(projectile.damage);
+                println!(
+                    "Player {} hit, health {}/{}",
+                    player.id, health.current, health.max
+                );
+                if !projectile.pierce(target) {
+                    spent.insert(projectile_entity);
+                    commands.entity(projectile_entity).despawn();
+                }
+                if health.is_dead() && !was_dead {
+                    lethal_hits.send(LethalHit {
+                        victim: target,
+                        killer: projectile.owner,
+                    });
+                }
+
+                // Bots are fixed bodies and can't be launched
+                if projectile.knock_up > 0.0 && body == Some(&RigidBody::Dynamic) {
+                    commands.entity(target).insert((
+                        KnockedUp {
+                            airborne: Timer::from_seconds(KNOCK_UP_MAX_SECS, TimerMode::Once),
+                            falling: false,
+                        },
+                        LockedAxes::ROTATION_LOCKED,
+                        GravityScale(1.0),
+                        Velocity::linear(Vec3::Y * projectile.knock_up),
+                    ));
+                    outbox.broadcast(ServerMessages::PlayerKnockedUp {
+                        id: player.id,
+                        velocity: projectile.knock_up,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// How far below a player's feet `grounded_check_system` looks for ground,
+/// read from `GROUND_CHECK_DISTANCE`.
+#[derive(Debug, Resource)]
+pub struct GroundCheckConfig {
+    pub ray_length: f32,
+}
+
+impl GroundCheckConfig {
+    fn from_env() -> Self {
+        let ray_length = std::env::var("GROUND_CHECK_DISTANCE")
+            .ok()
+            .and_then(|distance| distance.parse().ok())
+            .unwrap_or(0.2_f32)
+            .max(0.0);
+        GroundCheckConfig { ray_length }
+    }
+}
+
+/// Whether something is under this player's feet, as of the last
+/// `grounded_check_system`. Systems that need it read this instead of casting
+/// their own rays.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Grounded(pub bool);
+
+/// Casts a ray down from each player to just past their feet and records the
+/// result in their [`Grounded`].
+fn grounded_check_system(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    config: Res<GroundCheckConfig>,
+    mut players: Query<(Entity, &Transform, Option<&mut Grounded>), With<Player>>,
+) {
+    for (entity, transform, grounded) in players.iter_mut() {
+        let filter = QueryFilter::new()
+            .exclude_collider(entity)
+            .exclude_sensors();
+        let hit = rapier_context
+            .cast_ray(
+                transform.translation,
+                Vec3::NEG_Y,
+                PLAYER_HALF_HEIGHT + config.ray_length,
+                true,
+                filter,
+            )
+            .is_some();
+        match grounded {
+            Some(mut grounded) => grounded.0 = hit,
+            None => {
+                commands.entity(entity).insert(Grounded(hit));
+            }
+        }
+    }
+}
+
+/// Re-locks knocked up players' Y axis once they come back down, or after
+/// `KNOCK_UP_MAX_SECS` if something holds them up.
+fn knock_up_landing_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    world_config: Res<WorldConfig>,
+    mut players: Query<(Entity, &mut KnockedUp, &mut Transform, &Velocity, &Grounded)>,
+) {
+    let mode = world_config.movement_mode;
+    for (entity, mut knocked_up, mut transform, velocity, grounded) in players.iter_mut() {
+        let timed_out = knocked_up.airborne.tick(time.delta()).finished();
+        if velocity.linvel.y < 0.0 {
+            knocked_up.falling = true;
+        }
+        // Still grounded on the way up, before the launch lifts them clear
+        let landed = knocked_up.falling && grounded.0;
+        if !(landed || timed_out) {
+            continue;
+        }
+
+        if mode == MovementMode::Grounded {
+            transform.translation.y = PLAYER_GROUND_Y;
+        }
+        commands.entity(entity).remove::<KnockedUp>().insert((
+            mode.locked_axes(),
+            mode.gravity_scale(),
+            Velocity::zero(),
+        ));
+    }
+}
+
+fn score_system(
+    time: Res<Time>,
+    mut block_destroyed: EventReader<SolanaBlockDestroyed>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut outbox: ResMut<ServerOutbox>,
+    announcer_config: Res<AnnouncerConfig>,
+    mut announcer: ResMut<Announcer>,
+    players: Query<&Player>,
+) {
+    for event in block_destroyed.iter() {
+        let Some(Ok(player)) = event.destroyed_by.map(|entity| players.get(entity)) else {
+            continue;
+        };
+        let previous = leaderboard.live.get(&player.id).copied().unwrap_or(0);
+        leaderboard.add_score(player.id, &player.leaderboard_key(), 1);
+        println!(
+            "Player {} destroyed Solana block {}",
+            player.name, event.slot
+        );
+
+        let score = previous + 1;
+        for threshold in &announcer_config.score_thresholds {
+            if previous < *threshold && score >= *threshold {
+                announce(
+                    &mut outbox,
+                    format!("{} reached {} points!", player.name, threshold),
+                );
+            }
+        }
+        if let Some(count) =
+            announcer.record_kill(&announcer_config, player.id, time.elapsed_seconds())
+        {
+            announce(
+                &mut outbox,
+                format!("Multi-kill! {} destroyed {} blocks.", player.name, count),
+            );
+        }
+    }
+}
+
+/// Advances capture points from who stands in them and pays out to the
+/// teams holding them. Players without a team can't capture.
+fn capture_point_system(
+    time: Res<Time>,
+    config: Res<CapturePointConfig>,
+    mut points: ResMut<CapturePoints>,
+    mut score_timer: ResMut<CaptureScoreTimer>,
+    mut leaderboard: ResMut<Leaderboard>,
+    players: Query<(&Player, &Transform, &Team), Without<Eliminated>>,
+) {
+    let step = time.delta_seconds() / config.capture_time.max(f32::EPSILON);
+    for point in points.0.iter_mut() {
+        let teams_present: HashSet<u8> = players
+            .iter()
+            .filter(|(_, transform, _)| point.contains(transform.translation))
+            .map(|(_, _, team)| team.0)
+            .collect();
+        point.advance(&teams_present, step);
+    }
+
+    if score_timer.0.duration() != config.score_interval {
+        score_timer.0.set_duration(config.score_interval);
+    }
+    if !score_timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    for holder in points.0.iter().filter_map(CapturePoint::holder) {
+        for (player, _, _) in players.iter().filter(|(_, _, team)| team.0 == holder) {
+            leaderboard.add_score(
+                player.id,
+                &player.leaderboard_key(),
+                config.score_per_interval,
+            );
+        }
+    }
+}
+
+/// Hands each objective to the player nearest its center within its radius,
+/// tells clients when that changes, and pays the holder out.
+fn objective_system(
+    time: Res<Time>,
+    config: Res<ObjectiveConfig>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut objectives: Query<(&mut Objective, &Transform)>,
+    players: Query<(&Player, &Transform), Without<Eliminated>>,
+) {
+    for (mut objective, objective_transform) in objectives.iter_mut() {
+        let center = objective_transform.translation;
+        let holder = players
+            .iter()
+            .map(|(player, transform)| (player, transform.translation.distance(center)))
+            .filter(|(_, distance)| *distance <= objective.radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(player, _)| player);
+
+        let holder_id = holder.map(|player| player.id);
+        if holder_id != objective.holder {
+            objective.holder = holder_id;
+            objective.score_timer.reset();
+            outbox.broadcast(ServerMessages::ObjectiveHolder { id: holder_id });
+        }
+
+        if !objective.score_timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        if let Some(player) = holder {
+            leaderboard.add_score(
+                player.id,
+                &player.leaderboard_key(),
+                config.score_per_interval,
+            );
+        }
+        // Keeps clients that joined since the last change up to date
+        outbox.broadcast(ServerMessages::ObjectiveHolder { id: holder_id });
+    }
+}
+
+fn capture_point_broadcast_system(
+    time: Res<Time>,
+    mut timer: ResMut<CapturePointBroadcastTimer>,
+    points: Res<CapturePoints>,
+    mut outbox: ResMut<ServerOutbox>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    for point in &points.0 {
+        outbox.broadcast(ServerMessages::CapturePointUpdate {
+            id: point.id,
+            center: point.center.into(),
+            radius: point.radius,
+            controlling_team: point.team,
+            progress: point.progress,
+        });
+    }
+}
+
+/// Broadcasts the leaderboard when scores have changed.
+fn leaderboard_system(
+    time: Res<Time>,
+    mut timer: ResMut<LeaderboardTimer>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut outbox: ResMut<ServerOutbox>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() || !leaderboard.dirty {
+        return;
+    }
+    leaderboard.dirty = false;
+
+    outbox.broadcast(ServerMessages::Leaderboard {
+        entries: leaderboard.top(LEADERBOARD_SIZE),
+    });
+}
+
+/// Writes changed all-time scores to disk, so a crash loses at most one
+/// `LeaderboardSaveTimer` interval.
+fn save_leaderboard_system(
+    time: Res<Time>,
+    mut timer: ResMut<LeaderboardSaveTimer>,
+    mut leaderboard: ResMut<Leaderboard>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() || !leaderboard.unsaved {
+        return;
+    }
+    match save_leaderboard(&leaderboard_path(), &leaderboard) {
+        Ok(()) => leaderboard.unsaved = false,
+        Err(e) => println!("Failed to save leaderboard: {}", e),
+    }
+}
+
+fn save_leaderboard_on_exit_system(
+    mut exit_events: EventReader<AppExit>,
+    leaderboard: Res<Leaderboard>,
+) {
+    if exit_events.iter().next().is_some() {
+        if let Err(e) = save_leaderboard(&leaderboard_path(), &leaderboard) {
+            println!("Failed to save leaderboard: {}", e);
+        }
+    }
+}
+
+/// Removes players whose clients didn't reconnect within `ReconnectConfig::grace`.
+fn reconnect_grace_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<ReconnectConfig>,
+    mut lobby: ResMut<ServerLobby>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut waiting: Query<(Entity, &Player, &mut AwaitingReconnect)>,
+) {
+    for (entity, player, mut awaiting) in waiting.iter_mut() {
+        if awaiting.0.tick(time.delta()).elapsed() < config.grace {
+            continue;
+        }
+        println!(
+            "Player {} did not reconnect in time, removing it.",
+            player.id
+        );
+        lobby.reconnecting.remove(&player.id);
+        commands.entity(entity).despawn();
+        outbox.broadcast(ServerMessages::PlayerRemove { id: player.id });
+    }
+}
+
+fn projectile_on_removal_system(
+    mut outbox: ResMut<ServerOutbox>,
+    mut removed_projectiles: RemovedComponents<Projectile>,
+) {
+    for entity in &mut removed_projectiles {
+        let message = ServerMessages::DespawnProjectile { entity };
+        outbox.broadcast(message);
+    }
+}
+
+/// Despawns blocks past their `block_ttl`; the removal system tells clients.
+fn expire_solana_blocks_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut telemetry: ResMut<BlockTelemetry>,
+    mut blocks: Query<(Entity, &mut SolanaSlotBlock)>,
+) {
+    for (entity, mut block) in blocks.iter_mut() {
+        let slot = block.id;
+        let Some(lifetime) = &mut block.lifetime else {
+            continue;
+        };
+        if lifetime.tick(time.delta()).just_finished() {
+            commands.entity(entity).despawn();
+            telemetry.record(time.elapsed_seconds_f64(), slot, BlockEventKind::Expired);
+        }
+    }
+}
+
+/// World space normal of `surface` where `projectile` touches it, pointing
+/// back toward the projectile. Falls back to up when there's no contact.
+fn surface_normal(
+    rapier_context: &RapierContext,
+    projectile: Entity,
+    projectile_transform: &GlobalTransform,
+    surface: Entity,
+    surface_transform: &GlobalTransform,
+) -> Vec3 {
+    // Manifold normals are local to the first collider and point toward the second
+    rapier_context
+        .contact_pair(projectile, surface)
+        .and_then(|pair| {
+            let normal = pair.manifolds().next()?.normal();
+            Some(if pair.collider1() == surface {
+                surface_transform.compute_transform().rotation * normal
+            } else {
+                -(projectile_transform.compute_transform().rotation * normal)
+            })
+        })
+        .or_else(|| {
+            // Sensors like piercing projectiles have no contact manifold, so
+            // use the direction from the closest point on the surface
+            let position = projectile_transform.translation();
+            let is_surface = |entity: Entity| entity == surface;
+            let (_, projection) = rapier_context.project_point(
+                position,
+                false,
+                QueryFilter::new().predicate(&is_surface),
+            )?;
+            let outward = position - projection.point;
+            Some(if projection.is_inside {
+                -outward
+            } else {
+                outward
+            })
+        })
+        .unwrap_or(Vec3::Y)
+        .normalize_or_zero()
+}
+
+/// Turns seekers toward the nearest enemy in range and within
+/// `ProjectileConfig::homing_cone` of their heading, at up to
+/// `homing_strength` radians per second. A seeker keeps its target until the
+/// target dies or gets out of range, then looks for another.
+#[allow(clippy::type_complexity)]
+fn homing_system(
+    time: Res<Time>,
+    config: Res<ProjectileConfig>,
+    mut projectiles: Query<(&mut Projectile, &Transform, &mut Velocity)>,
+    players: Query<(Entity, &Transform, Option<&Team>), (With<Player>, Without<Eliminated>)>,
+    teams: Query<&Team>,
+) {
+    for (mut projectile, transform, mut velocity) in projectiles.iter_mut() {
+        if projectile.kind != ProjectileKind::Seeker {
+            continue;
+        }
+        let speed = velocity.linvel.length();
+        if speed <= f32::EPSILON {
+            continue;
+        }
+        let heading = velocity.linvel / speed;
+        let position = transform.translation;
+        let in_range =
+            |target: &Transform| target.translation.distance(position) <= config.homing_range;
+
+        let target = projectile
+            .target
+            .and_then(|target| players.get(target).ok())
+            .filter(|(_, target, _)| in_range(target))
+            .or_else(|| {
+                let owner_team = projectile.owner.and_then(|owner| teams.get(owner).ok());
+                players
+                    .iter()
+                    .filter(|(entity, _, team)| {
+                        Some(*entity) != projectile.owner
+                            && (owner_team.is_none() || owner_team != *team)
+                    })
+                    .filter(|(_, target, _)| in_range(target))
+                    .filter(|(_, target, _)| {
+                        heading.angle_between(target.translation - position) <= config.homing_cone
+                    })
+                    .min_by(|(_, a, _), (_, b, _)| {
+                        a.translation
+                            .distance(position)
+                            .total_cmp(&b.translation.distance(position))
+                    })
+            });
+        projectile.target = target.map(|(entity, _, _)| entity);
+        let Some((_, target, _)) = target else {
+            continue;
+        };
+
+        let desired = (target.translation - position).normalize_or_zero();
+        let angle = heading.angle_between(desired);
+        if desired == Vec3::ZERO || angle <= f32::EPSILON {
+            continue;
+        }
+        let turn = (config.homing_strength * time.delta_seconds() / angle).min(1.0);
+        let rotation = Quat::IDENTITY.slerp(Quat::from_rotation_arc(heading, desired), turn);
+        velocity.linvel = rotation * heading * speed;
+    }
+}
+
+/// Reflects projectiles off the ground and walls, keeping their speed, and
+/// despawns them once `Projectile::bounces_remaining` runs out.
+fn projectile_bounce_system(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    rapier_context: Res<RapierContext>,
+    mut projectiles: Query<(&mut Projectile, &mut Velocity, &GlobalTransform)>,
+    surfaces: Query<(&CollisionGroups, &GlobalTransform)>,
+) {
+    let mut despawned = HashSet::new();
+    for collision_event in collision_events.iter() {
+        let CollisionEvent::Started(entity1, entity2, _) = collision_event else {
+            continue;
+        };
+
+        for (projectile, surface) in [(*entity1, *entity2), (*entity2, *entity1)] {
+            if despawned.contains(&projectile) {
+                continue;
+            }
+            let Ok((mut state, mut velocity, projectile_transform)) =
+                projectiles.get_mut(projectile)
+            else {
+                continue;
+            };
+            let Ok((groups, surface_transform)) = surfaces.get(surface) else {
+                continue;
+            };
+            if !groups.memberships.intersects(GROUND_GROUP | WALL_GROUP) {
+                continue;
+            }
+
+            if state.bounces_remaining == 0 {
+                commands.entity(projectile).despawn();
+                despawned.insert(projectile);
+                continue;
+            }
+            state.bounces_remaining -= 1;
+
+            // The contact solver may already have turned the projectile away;
+            // only reflect what is still heading into the surface
+            let normal = surface_normal(
+                &rapier_context,
+                projectile,
+                projectile_transform,
+                surface,
+                surface_transform,
+            );
+            let speed = velocity.linvel.length();
+            let into_surface = velocity.linvel.dot(normal);
+            if into_surface < 0.0 {
+                velocity.linvel -= 2.0 * into_surface * normal;
+            }
+            velocity.linvel = velocity.linvel.normalize_or_zero() * speed;
+        }
+    }
+}
+
+/// Tells clients where projectiles hit the ground or walls so they can draw scorch marks.
+fn impact_decal_system(
+    mut outbox: ResMut<ServerOutbox>,
+    mut collision_events: EventReader<CollisionEvent>,
+    rapier_context: Res<RapierContext>,
+    projectiles: Query<&GlobalTransform, With<Projectile>>,
+    surfaces: Query<(&CollisionGroups, &GlobalTransform)>,
+) {
+    for collision_event in collision_events.iter() {
+        let CollisionEvent::Started(entity1, entity2, _) = collision_event else {
+            continue;
+        };
+
+        for (projectile, surface) in [(*entity1, *entity2), (*entity2, *entity1)] {
+            let Ok(projectile_transform) = projectiles.get(projectile) else {
+                continue;
+            };
+            let Ok((groups, surface_transform)) = surfaces.get(surface) else {
+                continue;
+            };
+            if !groups.memberships.intersects(GROUND_GROUP | WALL_GROUP) {
+                continue;
+            }
+
+            let normal = surface_normal(
+                &rapier_context,
+                projectile,
+                projectile_transform,
+                surface,
+                surface_transform,
+            );
+
+            outbox.broadcast(ServerMessages::ImpactDecal {
+                position: projectile_transform.translation().into(),
+                normal: normal.into(),
+            });
+        }
+    }
+}
+
+fn solana_block_on_removal_system(
+    mut outbox: ResMut<ServerOutbox>,
+    mut removed_projectiles: RemovedComponents<SolanaSlotBlock>,
+) {
+    for entity in &mut removed_projectiles {
+        let message = ServerMessages::DespawnSolanaBlock { entity };
+        outbox.broadcast(message);
+    }
+}
+
+/// Bots the server keeps alive on its own. Bots are not netcode clients, so
+/// they don't take up any of the transport's `max_clients` slots.
+#[derive(Debug, Resource)]
+struct BotSpawnConfig {
+    target_count: usize,
+    auto_spawn: bool,
+}
+
+impl Default for BotSpawnConfig {
+    fn default() -> Self {
+        Self {
+            target_count: 4,
+            auto_spawn: true,
+        }
+    }
+}
+
+/// How bots cast their fireballs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BotCastPattern {
+    /// Eight fireballs in a level ring around the bot.
+    Radial,
+    /// One fireball at the nearest player, leading for height difference and
+    /// gravity. Bots hold fire while no player is around.
+    Aimed,
+}
+
+/// Read from `BOT_CAST_PATTERN`, `radial` (default) or `aimed`.
+#[derive(Debug, Resource)]
+struct BotConfig {
+    pattern: BotCastPattern,
+}
+
+impl BotConfig {
+    fn from_env() -> Self {
+        let pattern = match std::env::var("BOT_CAST_PATTERN").as_deref() {
+            Ok("aimed") => BotCastPattern::Aimed,
+            Ok("radial") | Err(_) => BotCastPattern::Radial,
+            Ok(other) => {
+                println!("Unknown bot cast pattern {}, using radial", other);
+                BotCastPattern::Radial
+            }
+        };
+        Self { pattern }
+    }
+}
+
+/// Unit direction to fire from `origin` at `speed` so a projectile falling
+/// under `gravity` passes through `target`. Lifts the aim by the drop over the
+/// straight-line flight time, which is close enough at fireball ranges.
+fn aim_direction(origin: Vec3, target: Vec3, speed: f32, gravity: Vec3) -> Option<Vec3> {
+    let offset = target - origin;
+    let flight_time = offset.length() / speed;
+    (offset - 0.5 * gravity * flight_time * flight_time).try_normalize()
+}
+
+/// Tops bots back up to `BotSpawnConfig::target_count`, which also respawns destroyed ones.
+#[allow(clippy::too_many_arguments)]
+fn maintain_bot_count_system(
+    config: Res<BotSpawnConfig>,
+    bots: Query<(), With<Bot>>,
+    spawn_picker: SpawnPicker,
+    mut visuals: DebugVisuals,
+    mut lobby: ResMut<ServerLobby>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut bot_id: ResMut<BotId>,
+    mut commands: Commands,
+) {
+    if !config.auto_spawn {
+        return;
+    }
+    for _ in bots.iter().count()..config.target_count {
+        let team = spawn_picker.pick_team();
+        let transform = spawn_picker.spawn_transform(team);
+        spawn_bot_player(
+            &mut commands,
+            &mut visuals,
+            &mut lobby,
+            &mut outbox,
+            &mut bot_id,
+            transform,
+            team,
+        );
+    }
+}
+
+/// Debug shortcut: Space adds a bot on top of the auto-spawned ones.
+#[cfg(debug_assertions)]
+fn spawn_bot(
+    keyboard_input: Res<Input<KeyCode>>,
+    spawn_picker: SpawnPicker,
+    mut visuals: DebugVisuals,
+    mut lobby: ResMut<ServerLobby>,
+    mut outbox: ResMut<ServerOutbox>,
+    mut bot_id: ResMut<BotId>,
+    mut commands: Commands,
+) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        let team = spawn_picker.pick_team();
+        let transform = spawn_picker.spawn_transform(team);
+        spawn_bot_player(
+            &mut commands,
+            &mut visuals,
+            &mut lobby,
+            &mut outbox,
+            &mut bot_id,
+            transform,
+            team,
+        );
+    }
+}
+
+fn spawn_bot_player(
+    commands: &mut Commands,
+    visuals: &mut DebugVisuals,
+    lobby: &mut ServerLobby,
+    outbox: &mut ServerOutbox,
+    bot_id: &mut BotId,
+    transform: Transform,
+    team: Option<Team>,
+) {
+    let client_id = bot_id.0;
+    bot_id.0 += 1;
+    let player_entity = spawn_player_server(
+        commands,
+        transform,
+        Player {
+            id: client_id,
+            name: format!("bot-{}", client_id),
+            wallet: None,
+        },
+        RigidBody::Fixed,
+    );
+    commands.entity(player_entity).insert(Bot {
+        auto_cast: Timer::from_seconds(3.0, TimerMode::Repeating),
+    });
+    if let Some(team) = team {
+        commands.entity(player_entity).insert(team);
+    }
+    visuals.insert(
+        commands,
+        player_entity,
+        Mesh::from(shape::Capsule::default()),
+        Color::rgb(0.8, 0.7, 0.6),
+    );
+
+    lobby.players.insert(client_id, player_entity);
+
+    let translation: [f32; 3] = transform.translation.into();
+    outbox.broadcast(ServerMessages::PlayerCreate {
+        id: client_id,
+        entity: player_entity,
+        translation,
+    });
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn bot_autocast(
+    time: Res<Time>,
+    projectile_config: Res<ProjectileConfig>,
+    bot_config: Res<BotConfig>,
+    rapier_config: Res<RapierConfiguration>,
+    mut visuals: DebugVisuals,
+    mut outbox: ResMut<ServerOutbox>,
+    mut bots: Query<(Entity, &Player, &Transform, &mut Bot), Without<Eliminated>>,
+    players: Query<&Transform, (With<Player>, Without<Bot>, Without<Eliminated>)>,
+    mut commands: Commands,
+) {
+    for (entity, player, transform, mut bot) in &mut bots {
+        bot.auto_cast.tick(time.delta());
+        if !bot.auto_cast.just_finished() {
+            continue;
+        }
+
+        let directions: Vec<Vec3> = match bot_config.pattern {
+            BotCastPattern::Radial => (0..8)
+                .map(|i| {
+                    let direction = Vec2::from_angle(PI / 4. * i as f32);
+                    Vec3::new(direction.x, 0., direction.y).normalize()
+                })
+                .collect(),
+            BotCastPattern::Aimed => players
+                .iter()
+                .map(|player| player.translation)
+                .min_by(|a, b| {
+                    a.distance_squared(transform.translation)
+                        .total_cmp(&b.distance_squared(transform.translation))
+                })
+                .and_then(|target| {
+                    aim_direction(
+                        transform.translation,
+                        target,
+                        projectile_config.speed,
+                        rapier_config.gravity * projectile_config.gravity_scale,
+                    )
+                })
+                .into_iter()
+                .collect(),
+        };
+        if !directions.is_empty() {
+            outbox.broadcast(ServerMessages::CastPerformed {
+                caster_id: player.id,
+                position: transform.translation.into(),
+            });
+        }
+
+        for direction in directions {
+            let translation: Vec3 = transform.translation + direction;
+
+            let Some(fireball_entity) = spawn_fireball_server(
+                &mut commands,
+                &projectile_config,
+                translation,
+                direction,
+                Some(entity),
+            ) else {
+                continue;
+            };
+            visuals.insert(
+                &mut commands,
+                fireball_entity,
+                fireball_mesh(projectile_config.radius),
+                Color::rgb(1.0, 0.0, 0.0),
+            );
+            outbox.broadcast(ServerMessages::SpawnProjectile {
+                entity: fireball_entity,
+                translation: translation.into(),
+                velocity: (direction * projectile_config.speed).into(),
+                radius: projectile_config.radius,
+                kind: projectile_config.kind,
+            });
+        }
+    }
+}
+
+/// Live entity counts by category, refreshed by `census_system`. Kept in
+/// release builds too, where only the log shows it.
+#[derive(Debug, Default, Resource)]
+pub struct EntityCensus {
+    pub players: usize,
+    pub bots: usize,
+    pub projectiles: usize,
+    pub solana_blocks: usize,
+    pub walls: usize,
+}
+
+#[derive(Resource)]
+struct CensusTimer(Timer);
+
+#[allow(clippy::too_many_arguments)]
+fn census_system(
+    time: Res<Time>,
+    mut timer: ResMut<CensusTimer>,
+    mut census: ResMut<EntityCensus>,
+    players: Query<(), (With<Player>, Without<Bot>)>,
+    bots: Query<(), With<Bot>>,
+    projectiles: Query<(), With<Projectile>>,
+    blocks: Query<(), With<SolanaSlotBlock>>,
+    groups: Query<&CollisionGroups>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    *census = EntityCensus {
+        players: players.iter().count(),
+        bots: bots.iter().count(),
+        projectiles: projectiles.iter().count(),
+        solana_blocks: blocks.iter().count(),
+        walls: groups
+            .iter()
+            .filter(|groups| groups.memberships.contains(WALL_GROUP))
+            .count(),
+    };
+    println!("Census: {:?}", *census);
+}
+
+#[cfg(debug_assertions)]
+fn census_ui_system(mut egui_contexts: EguiContexts, census: Res<EntityCensus>) {
+    egui::Window::new("Census").show(egui_contexts.ctx_mut(), |ui| {
+        egui::Grid::new("census").show(ui, |ui| {
+            for (label, count) in [
+                ("Players", census.players),
+                ("Bots", census.bots),
+                ("Projectiles", census.projectiles),
+                ("Solana blocks", census.solana_blocks),
+                ("Walls", census.walls),
+            ] {
+                ui.label(label);
+                ui.label(count.to_string());
+                ui.end_row();
+            }
+        });
+    });
+}
+
+#[cfg(debug_assertions)]
+fn update_visualizer_system(
+    mut egui_contexts: EguiContexts,
+    mut visualizer: ResMut<RenetServerVisualizer<200>>,
+    server: Res<RenetServer>,
+) {
+    visualizer.update(&server);
+    visualizer.show_window(egui_contexts.ctx_mut());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaderboard_round_trips_through_its_file() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.add_score(1, "alice", 3);
+        leaderboard.add_score(2, "bob", 5);
+        assert!(leaderboard.unsaved);
+
+        let path = std::env::temp_dir().join(format!("leaderboard-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_leaderboard(path, &leaderboard).unwrap();
+        let loaded = load_leaderboard(path);
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.all_time, leaderboard.all_time);
+        assert!(loaded.live.is_empty());
+        assert!(!loaded.unsaved);
+    }
+
+    #[test]
+    fn spawn_strategies_parse_from_names_and_points() {
+        assert_eq!(
+            parse_spawn_strategy("farthest"),
+            Some(SpawnStrategy::FarthestFromEnemies)
+        );
+        assert_eq!(
+            parse_spawn_strategy(" team_base"),
+            Some(SpawnStrategy::TeamBase)
+        );
+        assert_eq!(
+            parse_spawn_strategy("fixed"),
+            Some(SpawnStrategy::FixedPoints)
+        );
+        assert_eq!(parse_spawn_strategy("nearest"), None);
+        assert_eq!(
+            parse_spawn_points("1,0.5,2; 3,0.5,4;bad;"),
+            vec![Vec3::new(1.0, 0.5, 2.0), Vec3::new(3.0, 0.5, 4.0)]
+        );
+    }
+
+    #[test]
+    fn spawn_strategies_pick_points_from_their_sources() {
+        let fixed_points = vec![Vec3::new(1.0, 0.5, 1.0), Vec3::new(-1.0, 0.5, -1.0)];
+        let config = SpawnConfig {
+            strategy: SpawnStrategy::FixedPoints,
+            fixed_points: fixed_points.clone(),
+            ..default()
+        };
+        for _ in 0..10 {
+            assert!(fixed_points.contains(&config.pick_point(None, &[])));
+        }
+
+        let bases = (0..)
+            .zip(parse_capture_zones("10,0,10,2;-10,0,-10,2"))
+            .collect();
+        let config = SpawnConfig {
+            strategy: SpawnStrategy::TeamBase,
+            team_bases: bases,
+            ..default()
+        };
+        for _ in 0..10 {
+            let point = config.pick_point(Some(Team(1)), &[]);
+            assert!(Vec2::new(point.x + 10.0, point.z + 10.0).length() <= 2.0);
+            assert_eq!(point.y, PLAYER_GROUND_Y);
+        }
+        // Teams without a base fall back to the whole area
+        let point = config.pick_point(Some(Team(5)), &[]);
+        assert!(point.x.abs() <= config.area && point.z.abs() <= config.area);
+
+        // With an enemy in one corner, the best of many candidates lands well away from it
+        let config = SpawnConfig {
+            strategy: SpawnStrategy::FarthestFromEnemies,
+            candidates: 64,
+            ..default()
+        };
+        let enemy = Vec3::new(config.area, PLAYER_GROUND_Y, config.area);
+        for _ in 0..10 {
+            assert!(config.pick_point(None, &[enemy]).distance(enemy) > config.area);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_caps_messages_per_tick() {
+        let mut limiter = RateLimiter::default();
+        let processed = (0..100)
+            .filter(|_| limiter.allow(1, ClientChannel::Input))
+            .count();
+        assert_eq!(processed, 8);
+        assert_eq!(limiter.dropped[&1], 92);
+        // Other clients have their own budget
+        assert!(limiter.allow(2, ClientChannel::Input));
+    }
+
+    #[test]
+    fn garbage_messages_are_dropped_then_kicked() {
+        let mut limiter = RateLimiter::default();
+        let garbage = [0xFF_u8; 16];
+        for _ in 1..limiter.max_malformed {
+            assert!(limiter.decode::<PlayerCommand>(1, &garbage).is_none());
+            assert!(limiter.decode::<PlayerInput>(1, &garbage[..3]).is_none());
+        }
+        assert!(limiter.should_kick(1));
+        assert!(!limiter.should_kick(2));
+
+        let input = PlayerInput {
+            up: true,
+            ..Default::default()
+        };
+        let decoded: PlayerInput = limiter
+            .decode(2, &bincode::serialize(&input).unwrap())
+            .unwrap();
+        assert!(decoded.up);
+
+        limiter.forget(1);
+        assert!(!limiter.should_kick(1));
+    }
+
+    #[test]
+    fn lockstep_relay_kicks_only_stalled_participants() {
+        let mut relay = LockstepRelay::default();
+        relay
+            .inputs
+            .entry(0)
+            .or_default()
+            .insert(1, PlayerInput::default());
+        assert!(relay.stalled(&[1, 2], 1.0, 2.0).is_empty());
+        assert_eq!(relay.stalled(&[1, 2], 3.0, 2.0), vec![2]);
+
+        relay.confirm(0, &[(1, PlayerInput::default())], 3.0);
+        assert_eq!(relay.next_tick, 1);
+        assert_eq!(relay.expected[&0], relay.state.hash());
+        assert!(relay.stalled(&[1], 4.0, 2.0).is_empty());
+        assert_eq!(relay.stalled(&[1], 6.0, 2.0), vec![1]);
+    }
+
+    #[test]
+    fn wallet_challenges_are_accepted_once() {
+        let mut seen = SeenWalletChallenges::default();
+        let wallet = Pubkey::new_unique();
+        assert!(seen.first_use(wallet, 1, 100, 100));
+        assert!(!seen.first_use(wallet, 1, 100, 110));
+        // A fresh connection signs a new challenge
+        assert!(seen.first_use(wallet, 2, 110, 110));
+
+        // Old challenges are dropped once they can no longer verify
+        seen.first_use(wallet, 3, 200, 200);
+        assert_eq!(seen.seen.len(), 1);
+    }
+
+    #[test]
+    fn joining_players_even_out_teams() {
+        assert_eq!(balanced_team(0, [Some(Team(0))].into_iter()), None);
+        assert_eq!(balanced_team(2, std::iter::empty()), Some(Team(0)));
+        assert_eq!(balanced_team(2, [Some(Team(0))].into_iter()), Some(Team(1)));
+        assert_eq!(
+            balanced_team(
+                3,
+                [Some(Team(0)), Some(Team(2)), None, Some(Team(7))].into_iter()
+            ),
+            Some(Team(1))
+        );
+    }
+
+    #[test]
+    fn heal_only_reaches_allies_once_per_cooldown() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(HealConfig::default())
+            .init_resource::<HealCooldowns>()
+            .init_resource::<ServerOutbox>()
+            .add_event::<HealRequest>()
+            .add_system(heal_system);
+
+        let mut spawn = |id: u64, team: u8, x: f32, health: f32| {
+            let player = test_player(id);
+            app.world
+                .spawn((
+                    player,
+                    Team(team),
+                    Transform::from_xyz(x, 0., 0.),
+                    Health {
+                        current: health,
+                        max: PLAYER_MAX_HEALTH,
+                    },
+                    Mana::new(PLAYER_MAX_MANA),
+                ))
+                .id()
+        };
+        let caster = spawn(1, 0, 0., PLAYER_MAX_HEALTH);
+        let ally = spawn(2, 0, 5., 50.);
+        let enemy = spawn(3, 1, 6., 50.);
+
+        // Aimed right at the enemy, so only the team rule keeps the heal on the
+        // ally. The second request comes while the first's cooldown runs.
+        let mut events = app.world.resource_mut::<Events<HealRequest>>();
+        for target in [6., 6.] {
+            events.send(HealRequest {
+                caster,
+                target_position: Vec3::new(target, 0., 0.),
+            });
+        }
+        app.update();
+
+        let config = HealConfig::default();
+        assert_eq!(
+            app.world.get::<Health>(ally).unwrap().current,
+            50. + config.amount
+        );
+        assert_eq!(app.world.get::<Health>(enemy).unwrap().current, 50.);
+        assert_eq!(
+            app.world.get::<Mana>(caster).unwrap().current,
+            PLAYER_MAX_MANA - config.mana_cost
+        );
+        assert!(app
+            .world
+            .resource::<HealCooldowns>()
+            .0
+            .contains_key(&caster));
+        let effects: Vec<&ServerMessages> = app
+            .world
+            .resource::<ServerOutbox>()
+            .messages
+            .iter()
+            .collect();
+        assert!(matches!(
+            effects[..],
+            [ServerMessages::HealEffect { target_id: 2 }]
+        ));
+
+        // Once the cooldown is over, a nearly full ally is healed up to their max
+        app.world.get_mut::<Health>(ally).unwrap().current = PLAYER_MAX_HEALTH - 5.;
+        app.world.resource_mut::<Time>().update_with_instant(
+            std::time::Instant::now() + Duration::from_secs_f32(config.cooldown + 1.),
+        );
+        app.world.send_event(HealRequest {
+            caster,
+            target_position: Vec3::new(5., 0., 0.),
+        });
+        app.update();
+
+        assert_eq!(
+            app.world.get::<Health>(ally).unwrap().current,
+            PLAYER_MAX_HEALTH
+        );
+        assert_eq!(
+            app.world.get::<Mana>(caster).unwrap().current,
+            PLAYER_MAX_MANA - 2. * config.mana_cost
+        );
+    }
+
+    fn neutral_point() -> CapturePoint {
+        CapturePoint {
+            id: 0,
+            center: Vec3::ZERO,
+            radius: 5.0,
+            team: None,
+            progress: 0.0,
+        }
+    }
+
+    #[test]
+    fn lone_team_captures_then_holds() {
+        let mut point = neutral_point();
+        let red = HashSet::from([0]);
+        point.advance(&red, 0.6);
+        assert_eq!((point.team, point.holder()), (Some(0), None));
+        point.advance(&red, 0.6);
+        assert_eq!(point.progress, 1.0);
+        assert_eq!(point.holder(), Some(0));
+
+        // Nobody there changes nothing
+        point.advance(&HashSet::new(), 0.6);
+        assert_eq!(point.holder(), Some(0));
+    }
+
+    #[test]
+    fn contested_points_freeze_and_enemies_neutralize_first() {
+        let mut point = neutral_point();
+        point.advance(&HashSet::from([0]), 1.0);
+
+        point.advance(&HashSet::from([0, 1]), 0.5);
+        assert_eq!((point.holder(), point.progress), (Some(0), 1.0));
+
+        let blue = HashSet::from([1]);
+        point.advance(&blue, 0.5);
+        assert_eq!((point.team, point.holder()), (Some(0), None));
+        point.advance(&blue, 0.5);
+        assert_eq!((point.team, point.progress), (None, 0.0));
+        point.advance(&blue, 0.5);
+        assert_eq!(point.team, Some(1));
+    }
+
+    #[test]
+    fn capture_zones_parse_from_env_format() {
+        let zones = parse_capture_zones("0,0,0,5; 10, 0, -10, 3;;bad;1,2,3,-1");
+        assert_eq!(
+            zones,
+            vec![(Vec3::ZERO, 5.0), (Vec3::new(10., 0., -10.), 3.0)]
+        );
+    }
+
+    #[test]
+    fn trade_kills_record_both_deaths_and_credits() {
+        let mut app = App::new();
+        app.insert_resource(DeathConfig {
+            kill_score: 1,
+            credit_trades: true,
+        })
+        .insert_resource(MatchConfig::default())
+        .insert_resource(GamePhase::Playing)
+        .init_resource::<Leaderboard>()
+        .init_resource::<ServerOutbox>()
+        .insert_resource(SpawnConfig::default())
+        .insert_resource(TeamConfig { count: 0 })
+        .add_event::<LethalHit>()
+        .add_system(death_resolution_system);
+
+        let mut spawn = |id: u64| {
+            let player = test_player(id);
+            let health = Health {
+                current: 0.0,
+                max: PLAYER_MAX_HEALTH,
+            };
+            app.world.spawn((player, health, Transform::default())).id()
+        };
+        let first = spawn(1);
+        let second = spawn(2);
+
+        // Each fireball killed the other's caster, and a second fireball
+        // landed on an already dead player
+        let mut hits = app.world.resource_mut::<Events<LethalHit>>();
+        hits.send(LethalHit {
+            victim: first,
+            killer: Some(second),
+        });
+        hits.send(LethalHit {
+            victim: second,
+            killer: Some(first),
+        });
+        hits.send(LethalHit {
+            victim: second,
+            killer: Some(first),
+        });
+        app.update();
+
+        let leaderboard = app.world.resource::<Leaderboard>();
+        assert_eq!(leaderboard.live.get(&1), Some(&1));
+        assert_eq!(leaderboard.live.get(&2), Some(&1));
+        for player in [first, second] {
+            assert_eq!(
+                app.world.get::<Health>(player).unwrap().current,
+                PLAYER_MAX_HEALTH
+            );
+            assert!(app.world.get::<SpawnProtection>(player).is_some());
+        }
+        // A kill, teleport and protection notice per death, and one trade announcement
+        let messages = &app.world.resource::<ServerOutbox>().messages;
+        assert_eq!(messages.len(), 7);
+        let kills: Vec<(u64, Option<u64>)> = messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessages::PlayerKilled { id, killer } => Some((*id, *killer)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kills, vec![(1, Some(2)), (2, Some(1))]);
+    }
+
+    #[test]
+    fn block_telemetry_keeps_only_the_latest_events() {
+        let mut telemetry = BlockTelemetry::new(3);
+        for slot in 0..5 {
+            telemetry.record(
+                slot as f64,
+                slot,
+                BlockEventKind::Spawned {
+                    position: Vec3::ZERO,
+                },
+            );
+        }
+        assert_eq!(telemetry.spawned, 5);
+        let recent: Vec<u64> = telemetry.recent(10).map(|event| event.slot).collect();
+        assert_eq!(recent, vec![4, 3, 2]);
+
+        // Counting goes on when nothing is kept
+        let mut disabled = BlockTelemetry::new(0);
+        disabled.record(0.0, 1, BlockEventKind::Expired);
+        assert_eq!((disabled.expired, disabled.recent(1).count()), (1, 0));
+    }
+
+    #[test]
+    fn block_telemetry_averages_lifetimes() {
+        let mut telemetry = BlockTelemetry::new(16);
+        assert_eq!(telemetry.average_lifetime(), None);
+        telemetry.record(
+            0.0,
+            1,
+            BlockEventKind::Spawned {
+                position: Vec3::ZERO,
+            },
+        );
+        telemetry.record(
+            1.0,
+            2,
+            BlockEventKind::Spawned {
+                position: Vec3::ZERO,
+            },
+        );
+        telemetry.record(2.0, 1, BlockEventKind::Destroyed { by: Some(7) });
+        telemetry.record(7.0, 2, BlockEventKind::Expired);
+        // Blocks seen only dying, e.g. from before a restart, have no age
+        telemetry.record(8.0, 3, BlockEventKind::Expired);
+        assert_eq!(telemetry.average_lifetime(), Some(4.0));
+        assert_eq!(
+            (telemetry.spawned, telemetry.destroyed, telemetry.expired),
+            (2, 1, 2)
+        );
+    }
+
+    #[test]
+    fn spawn_interval_rejects_unrepresentable_values() {
+        for secs in ["inf", "NaN", "-1", "1e30", "soon"] {
+            let text = format!("spawninterval {}", secs);
+            assert!(
+                ChatCommand::parse(&text).is_err(),
+                "{} should be rejected",
+                secs
+            );
+        }
+        match ChatCommand::parse("spawninterval 2.5") {
+            Ok(ChatCommand::SpawnInterval { interval }) => {
+                assert_eq!(interval, Duration::from_millis(2500))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rpc_fallbacks_skip_the_primary_and_unknown_names() {
+        assert_eq!(
+            parse_rpc_fallbacks(
+                "devnet, mainnet,bogus,devnet,localhost",
+                SolanaRpcUrl::Mainnet
+            ),
+            vec![SolanaRpcUrl::Devnet, SolanaRpcUrl::Localhost]
+        );
+    }
+
+    #[test]
+    fn repeated_rpc_failures_rotate_to_the_fallback() {
+        let mut solana = Solana::new(SolanaRpcUrl::Mainnet, false);
+        solana.fallbacks = vec![SolanaRpcUrl::Devnet];
+        for _ in 0..RPC_FAILURES_BEFORE_RECONNECT {
+            assert_eq!(solana.rpc, SolanaRpcUrl::Mainnet);
+            solana.record_failure(0.0);
+        }
+        assert_eq!(solana.rpc, SolanaRpcUrl::Devnet);
+        assert_eq!(solana.fallbacks, vec![SolanaRpcUrl::Mainnet]);
+        assert!(solana.retry_at > 0.0);
+    }
+
+    #[test]
+    fn projectiles_bounce_off_walls_until_out_of_bounces() {
+        let mut app = physics_app(Vec3::ZERO);
+        app.add_system(projectile_bounce_system.in_base_set(CoreSet::PostUpdate));
+
+        app.world.spawn((
+            TransformBundle::from(Transform::from_xyz(3., 0., 0.)),
+            Collider::cuboid(0.5, 5., 5.),
+            CollisionGroups::new(WALL_GROUP, Group::ALL),
+        ));
+        let mut fire = |bounces_remaining: u32| {
+            app.world
+                .spawn((
+                    TransformBundle::default(),
+                    bevy_playground::projectile_physics(Vec3::X, 10., 0.1),
+                    Projectile {
+                        bounces_remaining,
+                        ..fireball(0.)
+                    },
+                ))
+                .id()
+        };
+        let bouncer = fire(1);
+        let stopper = fire(0);
+
+        for _ in 0..60 {
+            app.update();
+        }
+
+        let velocity = app.world.get::<Velocity>(bouncer).unwrap().linvel;
+        assert!(
+            velocity.x < -9.,
+            "expected a reflected velocity, got {:?}",
+            velocity
+        );
+        assert_eq!(
+            app.world
+                .get::<Projectile>(bouncer)
+                .unwrap()
+                .bounces_remaining,
+            0
+        );
+        assert!(app.world.get_entity(stopper).is_none());
+    }
+
+    #[test]
+    fn frame_rate_cap_rejects_rates_it_cannot_pace() {
+        assert_eq!(FrameRateCap::from_rate(0.0).0, None);
+        assert_eq!(
+            FrameRateCap::from_rate(50.0).0,
+            Some(Duration::from_millis(20))
+        );
+        for invalid in [1e-30, 0.5, -10.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(
+                FrameRateCap::from_rate(invalid).0,
+                Some(Duration::from_secs_f32(1.0 / 60.0))
+            );
+        }
+    }
+
+    /// CPU time this thread has used, from `/proc/thread-self/stat`, in clock
+    /// ticks (usually 10ms each).
+    #[cfg(target_os = "linux")]
+    fn thread_cpu_ticks() -> u64 {
+        let stat = fs::read_to_string("/proc/thread-self/stat").unwrap();
+        // Fields after the parenthesized command name, starting at the state
+        let fields: Vec<&str> = stat
+            .rsplit_once(')')
+            .unwrap()
+            .1
+            .split_whitespace()
+            .collect();
+        let utime: u64 = fields[11].parse().unwrap();
+        let stime: u64 = fields[12].parse().unwrap();
+        utime + stime
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn idle_capped_frames_sleep_instead_of_spinning() {
+        let mut world = World::new();
+        world.insert_resource(FrameRateCap::from_rate(100.0));
+        let mut system = IntoSystem::into_system(frame_rate_cap_system);
+        system.initialize(&mut world);
+
+        let start = Instant::now();
+        let cpu_start = thread_cpu_ticks();
+        for _ in 0..=50 {
+            system.run((), &mut world);
+        }
+        let wall = start.elapsed();
+        let cpu = thread_cpu_ticks() - cpu_start;
+
+        // Fifty empty frames take half a second, nearly all of it asleep
+        assert!(
+            wall >= Duration::from_millis(500),
+            "ran 50 frames in {:?}",
+            wall
+        );
+        assert!(cpu <= 10, "used {} ticks of CPU over {:?}", cpu, wall);
+    }
+
+    #[test]
+    fn aimed_casts_arc_onto_lower_targets() {
+        let (origin, target, speed) = (Vec3::new(0., 3., 0.), Vec3::new(10., 0., 0.), 20.);
+        let gravity = Vec3::new(0., -9.81, 0.);
+
+        // Without gravity it's a straight line at the target
+        let straight = aim_direction(origin, target, speed, Vec3::ZERO).unwrap();
+        assert!(straight.abs_diff_eq((target - origin).normalize(), 1e-6));
+
+        // With gravity the aim is lifted and the arc still lands near the target
+        let direction = aim_direction(origin, target, speed, gravity).unwrap();
+        assert!(direction.y > straight.y);
+        assert_eq!(direction.z, 0.);
+        let flight_time = (target.x - origin.x) / (direction.x * speed);
+        let landing =
+            origin + direction * speed * flight_time + 0.5 * gravity * flight_time * flight_time;
+        assert!(landing.distance(target) < 0.2, "landed at {:?}", landing);
+
+        assert!(aim_direction(origin, origin, speed, gravity).is_none());
+    }
+
+    #[test]
+    fn piercing_projectiles_spend_a_pierce_on_every_block() {
+        let mut app = App::new();
+        app.insert_resource(ProjectileConfig::default())
+            .add_event::<CollisionEvent>()
+            .add_event::<SolanaBlockDestroyed>()
+            .add_system(projectile_collision_system);
+
+        let projectile = app
+            .world
+            .spawn((
+                Projectile {
+                    kind: ProjectileKind::Piercing,
+                    pierces_remaining: 2,
+                    ..fireball(10.)
+                },
+                Velocity::linear(Vec3::X),
+            ))
+            .id();
+        let mut block = |slot: u64, health: Option<Health>| {
+            let mut block = app.world.spawn(SolanaSlotBlock {
+                id: slot,
+                shape: BlockShape::Cube,
+                lifetime: None,
+            });
+            if let Some(health) = health {
+                block.insert(health);
+            }
+            block.id()
+        };
+        let sturdy = block(1, Some(Health::new(100.)));
+        let brittle = block(2, None);
+        let last = block(3, Some(Health::new(100.)));
+
+        let hit = |app: &mut App, target: Entity| collide(app, projectile, target);
+        // Through a block that survives, then one that breaks: both use a pierce
+        hit(&mut app, sturdy);
+        assert_eq!(app.world.get::<Health>(sturdy).unwrap().current, 90.);
+        // A second contact with the same block doesn't hit it again
+        hit(&mut app, sturdy);
+        assert_eq!(app.world.get::<Health>(sturdy).unwrap().current, 90.);
+        hit(&mut app, brittle);
+        assert!(app.world.get_entity(brittle).is_none());
+        assert_eq!(
+            app.world
+                .get::<Projectile>(projectile)
+                .unwrap()
+                .pierces_remaining,
+            0
+        );
+
+        // Out of pierces, the next block stops it
+        hit(&mut app, last);
+        assert_eq!(app.world.get::<Health>(last).unwrap().current, 90.);
+        assert!(app.world.get_entity(projectile).is_none());
+    }
+
+    #[test]
+    fn observers_get_a_fraction_of_player_snapshot_bandwidth() {
+        let (player, observer) = (1, 2);
+        let mut server = RenetServer::new(connection_config());
+        server.add_connection(player);
+        server.add_connection(observer);
+        let mut lobby = ServerLobby::default();
+        lobby.observers.insert(observer);
+
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<NetworkTick>()
+            .init_resource::<SnapshotSize>()
+            .init_resource::<SnapshotPriorities>()
+            .insert_resource(server)
+            .insert_resource(lobby)
+            .insert_resource(SnapshotBudget {
+                max_entities: None,
+                falloff_distance: 20.0,
+                full_snapshot_interval: 60,
+                observer_update_rate: 5.0,
+            })
+            .insert_resource(LockstepConfig {
+                enabled: false,
+                max_players: 4,
+                hash_history: 600,
+                input_timeout: 2.0,
+            })
+            .add_system(server_network_sync);
+        for slot in 0..20 {
+            app.world.spawn((
+                SolanaSlotBlock {
+                    id: slot,
+                    shape: BlockShape::Cube,
+                    lifetime: None,
+                },
+                Transform::from_xyz(slot as f32, 10., 0.),
+            ));
+        }
+
+        // One second at 60 ticks per second
+        let start = std::time::Instant::now();
+        let mut bytes = HashMap::<u64, usize>::new();
+        for frame in 1..=60 {
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + Duration::from_secs_f64(frame as f64 / 60.0));
+            app.update();
+            let mut server = app.world.resource_mut::<RenetServer>();
+            server.update(Duration::from_secs_f64(1.0 / 60.0));
+            for client_id in [player, observer] {
+                let sent: usize = server
+                    .get_packets_to_send(client_id)
+                    .unwrap()
+                    .iter()
+                    .map(Vec::len)
+                    .sum();
+                *bytes.entry(client_id).or_default() += sent;
+            }
+        }
+
+        assert!(bytes[&observer] > 0);
+        assert!(
+            bytes[&observer] * 5 < bytes[&player],
+            "observer got {} bytes, player {}",
+            bytes[&observer],
+            bytes[&player]
+        );
+    }
+
+    #[test]
+    fn snapshot_history_evicts_the_oldest_ticks() {
+        let mut history = SnapshotHistory::new(3);
+        for tick in 1..=5 {
+            history.push(WorldSnapshot {
+                tick,
+                server_time: tick as f64 / 60.0,
+                entities: HashMap::from([(
+                    Entity::from_raw(1),
+                    (
+                        ReplayEntityKind::Ball,
+                        Transform::from_xyz(tick as f32, 0., 0.),
+                    ),
+                )]),
+            });
+        }
+
+        assert!(history.snapshot_at(1).is_none());
+        assert!(history.snapshot_at(2).is_none());
+        let (_, transform) = history.snapshot_at(4).unwrap().entities[&Entity::from_raw(1)];
+        assert_eq!(transform.translation.x, 4.);
+        assert_eq!(history.snapshot_at(5).unwrap().tick, 5);
+        assert!(history.snapshot_at(6).is_none());
+    }
+
+    #[test]
+    fn disconnected_players_are_removed_once_the_grace_runs_out() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(ReconnectConfig {
+                grace: Duration::from_secs(2),
+            })
+            .init_resource::<ServerLobby>()
+            .init_resource::<ServerOutbox>()
+            .add_system(reconnect_grace_system);
+        let player = app
+            .world
+            .spawn((test_player(1), AwaitingReconnect::default()))
+            .id();
+        app.world
+            .resource_mut::<ServerLobby>()
+            .reconnecting
+            .insert(1, player);
+
+        let start = std::time::Instant::now();
+        app.world.resource_mut::<Time>().update_with_instant(start);
+        app.update();
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs(1));
+        app.update();
+        // Still within the grace, so a reconnect would resume the same entity
+        assert!(app.world.get_entity(player).is_some());
+        assert!(app.world.resource::<ServerOutbox>().messages.is_empty());
+
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs(3));
+        app.update();
+        assert!(app.world.get_entity(player).is_none());
+        assert!(app.world.resource::<ServerLobby>().reconnecting.is_empty());
+        let messages = &app.world.resource::<ServerOutbox>().messages;
+        assert!(matches!(
+            messages[..],
+            [ServerMessages::PlayerRemove { id: 1 }]
+        ));
+    }
+
+    #[test]
+    fn last_standing_waits_for_an_elimination_and_one_side_left() {
+        let red = Some(Team(0));
+        let blue = Some(Team(1));
+        // Nobody out yet, even with a lone player
+        assert_eq!(last_standing([(1, None, false)].into_iter()), None);
+        assert_eq!(
+            last_standing([(1, None, false), (2, None, false)].into_iter()),
+            None
+        );
+        // Free for all: the last player without a team wins
+        assert_eq!(
+            last_standing([(1, None, false), (2, None, true)].into_iter()),
+            Some(vec![1])
+        );
+        assert_eq!(
+            last_standing([(1, None, false), (2, None, false), (3, None, true)].into_iter()),
+            None
+        );
+        // Teams: everyone left on the surviving team wins together
+        assert_eq!(
+            last_standing([(1, red, false), (2, red, false), (3, blue, true)].into_iter()),
+            Some(vec![1, 2])
+        );
+        assert_eq!(
+            last_standing([(1, red, false), (2, blue, false), (3, blue, true)].into_iter()),
+            None
+        );
+        // Everyone out at once leaves no winners
+        assert_eq!(
+            last_standing([(1, None, true), (2, None, true)].into_iter()),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn last_standing_ends_the_match_once_one_player_is_left() {
+        let mut app = App::new();
+        app.insert_resource(DeathConfig {
+            kill_score: 1,
+            credit_trades: true,
+        })
+        .insert_resource(MatchConfig {
+            win_condition: WinCondition::LastStanding,
+            ..Default::default()
+        })
+        .insert_resource(MatchTimer(Timer::new(
+            Duration::from_secs(300),
+            TimerMode::Once,
+        )))
+        .insert_resource(GamePhase::Playing)
+        .init_resource::<Time>()
+        .init_resource::<Leaderboard>()
+        .init_resource::<ServerOutbox>()
+        .insert_resource(SpawnConfig::default())
+        .insert_resource(TeamConfig { count: 0 })
+        .add_event::<LethalHit>()
+        .add_systems((
+            death_resolution_system.before(match_phase_system),
+            match_phase_system,
+        ));
+
+        let mut spawn = |id: u64| {
+            let player = test_player(id);
+            let health = Health {
+                current: 0.0,
+                max: PLAYER_MAX_HEALTH,
+            };
+            app.world.spawn((player, health, Transform::default())).id()
+        };
+        let survivor = spawn(1);
+        let victims = [spawn(2), spawn(3)];
+
+        for victim in victims {
+            app.world.send_event(LethalHit {
+                victim,
+                killer: Some(survivor),
+            });
+        }
+        app.update();
+        for victim in victims {
+            assert!(app.world.get::<Eliminated>(victim).is_some());
+        }
+        // Eliminations land at the end of the tick, so the match ends on the next one
+        app.update();
+
+        assert_eq!(*app.world.resource::<GamePhase>(), GamePhase::Ended);
+        let winners: Vec<&Vec<u64>> = app
+            .world
+            .resource::<ServerOutbox>()
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessages::MatchResults { winners, .. } => Some(winners),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(winners, vec![&vec![1]]);
+    }
+
+    #[test]
+    fn priority_selection_keeps_the_budget_and_carries_over_the_rest() {
+        let a = Entity::from_raw(1);
+        let b = Entity::from_raw(2);
+        let c = Entity::from_raw(3);
+        let mut accumulated = HashMap::new();
+        let candidates = [(a, 1.0), (b, 3.0), (c, 2.0)];
+
+        assert_eq!(
+            select_by_priority(&mut accumulated, &candidates, 1),
+            vec![1]
+        );
+        assert_eq!(accumulated, HashMap::from([(a, 1.0), (c, 2.0)]));
+        // `c` outbids `b` now that it has waited a snapshot
+        assert_eq!(
+            select_by_priority(&mut accumulated, &candidates, 1),
+            vec![2]
+        );
+        assert_eq!(accumulated, HashMap::from([(a, 2.0), (b, 3.0)]));
+        // `a` gets its turn eventually
+        assert_eq!(
+            select_by_priority(&mut accumulated, &candidates, 1),
+            vec![1]
+        );
+        assert_eq!(
+            select_by_priority(&mut accumulated, &candidates, 1),
+            vec![0]
+        );
+
+        // Infinite weights go past the budget and don't accumulate
+        let mut accumulated = HashMap::new();
+        let candidates = [(a, f32::INFINITY), (b, 1.0), (c, f32::INFINITY)];
+        assert_eq!(
+            select_by_priority(&mut accumulated, &candidates, 1),
+            vec![0, 2]
+        );
+        assert_eq!(accumulated, HashMap::from([(b, 1.0)]));
+        // A budget large enough sends everything
+        assert_eq!(
+            select_by_priority(&mut accumulated, &candidates, 3),
+            vec![0, 1, 2]
+        );
+        assert!(accumulated.is_empty());
+    }
+
+    #[test]
+    fn repeated_slots_spawn_one_block() {
+        let mut solana = Solana::default();
+        // Epoch info that hasn't advanced between two ticks
+        assert_eq!(solana.take_unspawned_slots(vec![100]), vec![100]);
+        assert!(solana.take_unspawned_slots(vec![100]).is_empty());
+        assert_eq!(solana.take_unspawned_slots(vec![101]), vec![101]);
+        assert_eq!(solana.last_spawned_slot, Some(101));
+
+        // Confirmed block ranges overlapping the last tick's
+        assert_eq!(
+            solana.take_unspawned_slots(vec![100, 101, 102, 103]),
+            vec![102, 103]
+        );
+        assert!(solana.take_unspawned_slots(vec![102, 103]).is_empty());
+        assert_eq!(solana.last_spawned_slot, Some(103));
+        assert!(solana.take_unspawned_slots(Vec::new()).is_empty());
+        assert_eq!(solana.last_spawned_slot, Some(103));
+    }
+
+    /// Headless app stepping Rapier once per update at 60Hz.
+    fn physics_app(gravity: Vec3) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+            .insert_resource(RapierConfiguration {
+                gravity,
+                timestep_mode: TimestepMode::Fixed {
+                    dt: 1. / 60.,
+                    substeps: 1,
+                },
+                ..Default::default()
+            });
+        app
+    }
+
+    #[test]
+    fn fireballs_report_contacts_with_blocks() {
+        let mut app = physics_app(Vec3::ZERO);
+        app.add_startup_system(|mut commands: Commands| {
+            spawn_fireball_server(
+                &mut commands,
+                &ProjectileConfig::default(),
+                Vec3::ZERO,
+                Vec3::X,
+                None,
+            );
+        });
+        // A block that doesn't ask for events itself, so any reported
+        // contact comes from the fireball's `ActiveEvents`
+        let block = app
+            .world
+            .spawn((
+                TransformBundle::from(Transform::from_xyz(3., 0., 0.)),
+                RigidBody::Fixed,
+                solana_block_physics(BlockShape::Cube),
+            ))
+            .id();
+
+        let mut reader = app.world.resource::<Events<CollisionEvent>>().get_reader();
+        let mut started = false;
+        for _ in 0..60 {
+            app.update();
+            let events = app.world.resource::<Events<CollisionEvent>>();
+            started |= reader.iter(events).any(|event| {
+                matches!(event, CollisionEvent::Started(a, b, _) if *a == block || *b == block)
+            });
+        }
+        assert!(
+            started,
+            "the fireball hit the block without a CollisionEvent::Started"
+        );
+    }
+
+    fn test_player(id: u64) -> Player {
+        Player {
+            id,
+            name: format!("player-{}", id),
+            wallet: None,
+        }
+    }
+
+    /// Reports a contact between `a` and `b` and runs a frame.
+    fn collide(app: &mut App, a: Entity, b: Entity) {
+        app.world
+            .resource_mut::<Events<CollisionEvent>>()
+            .send(CollisionEvent::Started(
+                a,
+                b,
+                bevy_rapier3d::rapier::geometry::CollisionEventFlags::empty(),
+            ));
+        app.update();
+    }
+
+    fn fireball(damage: f32) -> Projectile {
+        Projectile {
+            duration: Timer::from_seconds(10., TimerMode::Once),
+            owner: None,
+            bounces_remaining: 0,
+            damage,
+            knock_up: 0.,
+            kind: ProjectileKind::Fireball,
+            target: None,
+            pierces_remaining: 0,
+            hit: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn spawn_protection_blocks_damage_until_it_expires_or_they_attack() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(ProjectileConfig::default())
+            .init_resource::<ServerOutbox>()
+            .add_event::<CollisionEvent>()
+            .add_event::<LethalHit>()
+            .add_systems((spawn_protection_system, projectile_damage_system));
+        let mut spawn = |id: u64| {
+            let player = test_player(id);
+            let protection = SpawnProtection {
+                timer: Timer::from_seconds(2., TimerMode::Once),
+            };
+            app.world
+                .spawn((player, Health::new(100.), protection))
+                .id()
+        };
+        let waiting = spawn(1);
+        let attacker = spawn(2);
+
+        let hit = |app: &mut App, target: Entity| {
+            let projectile = app.world.spawn(fireball(10.)).id();
+            collide(app, projectile, target);
+        };
+        let health = |app: &App, player: Entity| app.world.get::<Health>(player).unwrap().current;
+
+        let start = std::time::Instant::now();
+        app.world.resource_mut::<Time>().update_with_instant(start);
+        hit(&mut app, waiting);
+        hit(&mut app, attacker);
+        assert_eq!(health(&app, waiting), 100.);
+        assert_eq!(health(&app, attacker), 100.);
+
+        // Attacking gives up the protection straight away
+        app.world
+            .resource_scope(|world, mut outbox: Mut<ServerOutbox>| {
+                let mut queue = bevy::ecs::system::CommandQueue::default();
+                end_spawn_protection(
+                    &mut Commands::new(&mut queue, world),
+                    &mut outbox,
+                    attacker,
+                    2,
+                );
+                queue.apply(world);
+            });
+        hit(&mut app, attacker);
+        hit(&mut app, waiting);
+        assert_eq!(health(&app, attacker), 90.);
+        assert_eq!(health(&app, waiting), 100.);
+
+        // Everyone else keeps it until the timer runs out
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs(3));
+        app.update();
+        assert!(app.world.get::<SpawnProtection>(waiting).is_none());
+        hit(&mut app, waiting);
+        assert_eq!(health(&app, waiting), 90.);
+
+        let ended: Vec<u64> = app
+            .world
+            .resource::<ServerOutbox>()
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessages::PlayerSpawnProtection {
+                    id,
+                    protected: false,
+                } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ended, vec![2, 1]);
+    }
+
+    fn move_players_app(movement_mode: MovementMode) -> App {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(WorldBounds::default())
+            .insert_resource(WorldConfig {
+                movement_mode,
+                ..default()
+            })
+            .add_system(move_players_system);
+        app
+    }
+
+    fn moving_player(app: &mut App, position: [f32; 3]) -> Entity {
+        let player = test_player(1);
+        let input = PlayerInput {
+            position,
+            ..default()
+        };
+        app.world.spawn((player, input, Transform::default())).id()
+    }
+
+    #[test]
+    fn client_heights_are_ignored_unless_flying() {
+        let mut app = move_players_app(MovementMode::Grounded);
+        let player = moving_player(&mut app, [1., 50., 2.]);
+        app.update();
+        assert_eq!(
+            app.world.get::<Transform>(player).unwrap().translation,
+            Vec3::new(1., PLAYER_GROUND_Y, 2.)
+        );
+
+        // Flying players follow the client's height, but never below the ground
+        let mut app = move_players_app(MovementMode::Flying);
+        let player = moving_player(&mut app, [1., 50., 2.]);
+        app.update();
+        assert_eq!(
+            app.world.get::<Transform>(player).unwrap().translation.y,
+            50.
+        );
+        app.world.get_mut::<PlayerInput>(player).unwrap().position = [1., -50., 2.];
+        app.update();
+        assert_eq!(
+            app.world.get::<Transform>(player).unwrap().translation.y,
+            PLAYER_GROUND_Y
+        );
+    }
+
+    #[test]
+    fn teleports_hold_against_stale_input_and_stay_in_bounds() {
+        let mut app = move_players_app(MovementMode::Grounded);
+        let player = moving_player(&mut app, [1., 0., 2.]);
+        // What `/tp` inserts
+        let destination = Vec3::new(15., PLAYER_GROUND_Y, -12.);
+        app.world.entity_mut(player).insert((
+            Transform::from_translation(destination),
+            Teleported {
+                destination,
+                grace: Timer::from_seconds(TELEPORT_GRACE_SECS, TimerMode::Once),
+            },
+        ));
+
+        let start = std::time::Instant::now();
+        app.world.resource_mut::<Time>().update_with_instant(start);
+        app.update();
+        // Input sent before the client saw the teleport doesn't pull them back,
+        // and the bounds clamp leaves an in-bounds destination alone
+        assert_eq!(
+            app.world.get::<Transform>(player).unwrap().translation,
+            destination
+        );
+
+        // Once the client has caught up, its input is trusted again from there
+        app.world.get_mut::<PlayerInput>(player).unwrap().position = [15.5, 0., -12.];
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs(1));
+        app.update();
+        assert!(app.world.get::<Teleported>(player).is_none());
+        assert_eq!(
+            app.world.get::<Transform>(player).unwrap().translation,
+            Vec3::new(15.5, PLAYER_GROUND_Y, -12.)
+        );
+    }
+
+    #[test]
+    fn stacked_blocks_settle() {
+        let mut app = physics_app(Vec3::NEG_Y * 9.81);
+        app.insert_resource(WorldBounds::default())
+            .insert_resource(WorldConfig::default())
+            .add_startup_systems((bevy_playground::setup_level_server, configure_solver_system));
+        // Dropped a little apart onto the floor, whose top is at y = 0.5
+        let blocks: Vec<Entity> = (0..4)
+            .map(|i| {
+                app.world
+                    .spawn((
+                        TransformBundle::from(Transform::from_xyz(0., 1.05 + 1.05 * i as f32, 0.)),
+                        RigidBody::Dynamic,
+                        Velocity::default(),
+                        solana_block_physics(BlockShape::Cube),
+                    ))
+                    .id()
+            })
+            .collect();
+
+        for _ in 0..300 {
+            app.update();
+        }
+
+        for (i, block) in blocks.into_iter().enumerate() {
+            let translation = app.world.get::<Transform>(block).unwrap().translation;
+            let velocity = app.world.get::<Velocity>(block).unwrap();
+            assert!(
+                velocity.linvel.length() < 0.05 && velocity.angvel.length() < 0.05,
+                "block {} still moving: {:?}",
+                i,
+                velocity
+            );
+            // Resting on the one below, neither sunk into it nor slid off it
+            assert!(
+                (translation.y - (1. + i as f32)).abs() < 0.05,
+                "block {} at {}",
+                i,
+                translation
+            );
+            assert!(
+                Vec2::new(translation.x, translation.z).length() < 0.1,
+                "block {} at {}",
+                i,
+                translation
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_client_ids_take_over_players_but_not_bots() {
+        let mut lobby = ServerLobby::default();
+        let (player, bot) = (Entity::from_raw(1), Entity::from_raw(2));
+        lobby.players.insert(5, player);
+        lobby.players.insert(6, bot);
+        let is_bot = |entity| entity == bot;
+
+        assert_eq!(lobby.claim_id(7, is_bot), Ok(None));
+        // A second connection with a player's id parks the old entity rather
+        // than leaking it, so it's resumed or removed like any disconnect
+        assert_eq!(lobby.claim_id(5, is_bot), Ok(Some(player)));
+        assert!(!lobby.players.contains_key(&5));
+        assert_eq!(lobby.reconnecting.get(&5), Some(&player));
+        // A bot's id can't be taken
+        assert_eq!(lobby.claim_id(6, is_bot), Err(bot));
+        assert_eq!(lobby.players.get(&6), Some(&bot));
+        assert!(!lobby.reconnecting.contains_key(&6));
+    }
+
+    #[test]
+    fn casts_out_of_range_or_nowhere_are_rejected() {
+        let config = ProjectileConfig {
+            max_cast_range: 10.,
+            ..default()
+        };
+        let caster = Transform::from_xyz(1., PLAYER_GROUND_Y, 1.);
+
+        // The target's height is ignored, so aim is always level
+        let direction = validate_cast(&config, &caster, Vec3::new(4., 30., 5.)).unwrap();
+        assert!(direction.abs_diff_eq(Vec3::new(0.6, 0., 0.8), 1e-6));
+
+        assert!(validate_cast(&config, &caster, Vec3::new(20., 0., 1.)).is_err());
+        assert!(validate_cast(&config, &caster, Vec3::new(1., 50., 1.)).is_err());
+        assert!(validate_cast(&config, &caster, Vec3::new(f32::NAN, 0., 1.)).is_err());
+        assert!(validate_cast(&config, &caster, Vec3::new(f32::INFINITY, 0., 1.)).is_err());
+    }
+
+    #[test]
+    fn fireball_colliders_scale_the_visual_radius_by_hit_radius() {
+        let mut world = World::new();
+        let mut spawn = |radius: f32, hit_radius: f32| {
+            let config = ProjectileConfig {
+                radius,
+                hit_radius,
+                ..default()
+            };
+            let mut queue = bevy::ecs::system::CommandQueue::default();
+            let fireball = spawn_fireball_server(
+                &mut Commands::new(&mut queue, &world),
+                &config,
+                Vec3::ZERO,
+                Vec3::X,
+                None,
+            );
+            queue.apply(&mut world);
+            world
+                .get::<Collider>(fireball.unwrap())
+                .unwrap()
+                .as_ball()
+                .unwrap()
+                .radius()
+        };
+        assert_eq!(spawn(0.2, 1.0), 0.2);
+        // Generous hit detection grows only the collider; clients still draw `radius`
+        assert_eq!(spawn(0.2, 1.5), 0.3);
+    }
+
+    #[test]
+    fn collisions_apply_to_whichever_side_the_projectile_is_on() {
+        let mut app = App::new();
+        app.insert_resource(ProjectileConfig::default())
+            .init_resource::<ServerOutbox>()
+            .add_event::<CollisionEvent>()
+            .add_event::<SolanaBlockDestroyed>()
+            .add_event::<LethalHit>()
+            .add_systems((projectile_collision_system, projectile_damage_system));
+
+        let shooter = app.world.spawn_empty().id();
+        let fire = |app: &mut App| {
+            let projectile = Projectile {
+                owner: Some(shooter),
+                ..fireball(10.)
+            };
+            app.world
+                .spawn((projectile, Velocity::linear(Vec3::X)))
+                .id()
+        };
+
+        // Projectile and block, block first
+        let projectile = fire(&mut app);
+        let block = app
+            .world
+            .spawn(SolanaSlotBlock {
+                id: 42,
+                shape: BlockShape::Cube,
+                lifetime: None,
+            })
+            .id();
+        collide(&mut app, block, projectile);
+        assert!(app.world.get_entity(block).is_none());
+        assert_eq!(
+            app.world.get::<Projectile>(projectile).unwrap().hit,
+            vec![block]
+        );
+        let destroyed: Vec<_> = app
+            .world
+            .resource_mut::<Events<SolanaBlockDestroyed>>()
+            .drain()
+            .collect();
+        assert_eq!(destroyed.len(), 1);
+        assert_eq!(
+            (destroyed[0].slot, destroyed[0].destroyed_by),
+            (42, Some(shooter))
+        );
+
+        // Player and projectile: the player is hurt, the projectile is spent
+        let projectile = fire(&mut app);
+        let player = app.world.spawn((test_player(1), Health::new(100.))).id();
+        collide(&mut app, player, projectile);
+        assert_eq!(app.world.get::<Health>(player).unwrap().current, 90.);
+        assert!(app.world.get_entity(projectile).is_none());
+        assert!(app
+            .world
+            .resource::<Events<SolanaBlockDestroyed>>()
+            .is_empty());
+
+        // Two projectiles: neither is a target for the other
+        let (first, second) = (fire(&mut app), fire(&mut app));
+        collide(&mut app, first, second);
+        for projectile in [first, second] {
+            assert!(app
+                .world
+                .get::<Projectile>(projectile)
+                .unwrap()
+                .hit
+                .is_empty());
+        }
+        assert!(app
+            .world
+            .resource::<Events<SolanaBlockDestroyed>>()
+            .is_empty());
+        assert!(app.world.resource::<ServerOutbox>().messages.is_empty());
+    }
+
+    #[test]
+    fn invulnerable_players_take_no_damage() {
+        let mut app = App::new();
+        app.insert_resource(ProjectileConfig::default())
+            .init_resource::<ServerOutbox>()
+            .add_event::<CollisionEvent>()
+            .add_event::<LethalHit>()
+            .add_system(projectile_damage_system);
+        let player = test_player(1);
+        let player = app
+            .world
+            .spawn((player, Health::new(100.), Invulnerable))
+            .id();
+        let hit = |app: &mut App| {
+            let projectile = app.world.spawn(fireball(150.)).id();
+            collide(app, projectile, player);
+        };
+
+        hit(&mut app);
+        assert_eq!(app.world.get::<Health>(player).unwrap().current, 100.);
+        assert!(app.world.resource::<Events<LethalHit>>().is_empty());
+
+        // `/god` again takes it away
+        app.world.entity_mut(player).remove::<Invulnerable>();
+        hit(&mut app);
+        assert!(app.world.get::<Health>(player).unwrap().is_dead());
+        assert!(!app.world.resource::<Events<LethalHit>>().is_empty());
+    }
+
+    #[test]
+    fn knock_ups_launch_dynamic_players_upward() {
+        let mut app = App::new();
+        app.insert_resource(ProjectileConfig::default())
+            .init_resource::<ServerOutbox>()
+            .add_event::<CollisionEvent>()
+            .add_event::<LethalHit>()
+            .add_system(projectile_damage_system);
+        let mut spawn = |id: u64, body: RigidBody| {
+            let player = test_player(id);
+            app.world
+                .spawn((
+                    player,
+                    Health::new(100.),
+                    body,
+                    LockedAxes::TRANSLATION_LOCKED_Y,
+                ))
+                .id()
+        };
+        let player = spawn(1, RigidBody::Dynamic);
+        let bot = spawn(2, RigidBody::Fixed);
+        for target in [player, bot] {
+            let projectile = Projectile {
+                knock_up: 6.,
+                ..fireball(10.)
+            };
+            let projectile = app.world.spawn(projectile).id();
+            collide(&mut app, projectile, target);
+        }
+
+        assert!(app.world.get::<Velocity>(player).unwrap().linvel.y > 0.);
+        assert!(app.world.get::<KnockedUp>(player).is_some());
+        assert!(!app
+            .world
+            .get::<LockedAxes>(player)
+            .unwrap()
+            .contains(LockedAxes::TRANSLATION_LOCKED_Y));
+        // Bots are fixed bodies and stay put
+        assert!(app.world.get::<KnockedUp>(bot).is_none());
+        assert!(app.world.get::<Velocity>(bot).is_none());
+
+        let messages = &app.world.resource::<ServerOutbox>().messages;
+        let [ServerMessages::PlayerKnockedUp { id: 1, velocity }] = &messages[..] else {
+            panic!("unexpected {:?}", messages);
+        };
+        assert_eq!(*velocity, 6.);
+    }
+
+    #[test]
+    fn seekers_bend_toward_the_nearest_target_in_their_cone() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(ProjectileConfig::default())
+            .add_system(homing_system);
+        let mut spawn = |id: u64, translation: Vec3| {
+            let player = test_player(id);
+            app.world
+                .spawn((player, Transform::from_translation(translation)))
+                .id()
+        };
+        let target = spawn(1, Vec3::new(10., 0., 3.));
+        // Closer, but behind the seeker
+        spawn(2, Vec3::new(-5., 0., 0.));
+        let seeker = Projectile {
+            kind: ProjectileKind::Seeker,
+            ..fireball(10.)
+        };
+        let seeker = app
+            .world
+            .spawn((
+                seeker,
+                Transform::default(),
+                Velocity::linear(Vec3::X * 10.),
+            ))
+            .id();
+
+        let start = std::time::Instant::now();
+        app.world.resource_mut::<Time>().update_with_instant(start);
+        let mut angle = Vec3::X.angle_between(Vec3::new(10., 0., 3.));
+        // Turning at most `homing_strength` per second, it takes a few ticks to line up
+        for tick in 1..=5 {
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + Duration::from_millis(50 * tick));
+            app.update();
+            let velocity = app.world.get::<Velocity>(seeker).unwrap().linvel;
+            let new_angle = velocity.angle_between(Vec3::new(10., 0., 3.));
+            assert!(
+                new_angle < angle || new_angle < 1e-3,
+                "tick {}: {} no closer than {}",
+                tick,
+                new_angle,
+                angle
+            );
+            assert!(
+                tick > 3 || new_angle > 1e-3,
+                "tick {}: turned faster than it can",
+                tick
+            );
+            assert!((velocity.length() - 10.).abs() < 1e-3);
+            angle = new_angle;
+        }
+        assert!(angle < 1e-3);
+        assert_eq!(
+            app.world.get::<Projectile>(seeker).unwrap().target,
+            Some(target)
+        );
+    }
+
+    #[test]
+    fn grounded_tracks_whether_a_player_stands_on_the_floor() {
+        let mut app = physics_app(Vec3::ZERO);
+        app.insert_resource(WorldBounds::default())
+            .insert_resource(GroundCheckConfig { ray_length: 0.2 })
+            .add_startup_system(bevy_playground::setup_level_server)
+            .add_system(grounded_check_system);
+        let mut spawn = |id: u64, y: f32| {
+            let player = test_player(id);
+            app.world
+                .spawn((
+                    player,
+                    TransformBundle::from(Transform::from_xyz(0., y, 0.)),
+                    RigidBody::KinematicPositionBased,
+                    player_physics(),
+                ))
+                .id()
+        };
+        let standing = spawn(1, PLAYER_GROUND_Y);
+        let airborne = spawn(2, PLAYER_GROUND_Y + 3.);
+
+        for _ in 0..3 {
+            app.update();
+        }
+        assert!(app.world.get::<Grounded>(standing).unwrap().0);
+        assert!(!app.world.get::<Grounded>(airborne).unwrap().0);
+
+        // Landing updates the same component
+        app.world
+            .get_mut::<Transform>(airborne)
+            .unwrap()
+            .translation
+            .y = PLAYER_GROUND_Y;
+        for _ in 0..3 {
+            app.update();
+        }
+        assert!(app.world.get::<Grounded>(airborne).unwrap().0);
+    }
+
+    #[test]
+    fn blocks_that_survive_a_hit_are_knocked_along_its_path() {
+        let mut app = physics_app(Vec3::ZERO);
+        app.insert_resource(ProjectileConfig {
+            impact_force: 0.5,
+            ..default()
+        })
+        .add_event::<SolanaBlockDestroyed>()
+        .add_system(projectile_collision_system);
+        let block = app
+            .world
+            .spawn((
+                TransformBundle::default(),
+                RigidBody::Dynamic,
+                Velocity::default(),
+                solana_block_physics(BlockShape::Cube),
+                SolanaSlotBlock {
+                    id: 1,
+                    shape: BlockShape::Cube,
+                    lifetime: None,
+                },
+                Health::new(100.),
+            ))
+            .id();
+        let projectile = app
+            .world
+            .spawn((fireball(10.), Velocity::linear(Vec3::new(0., 0., -20.))))
+            .id();
+
+        app.update();
+        collide(&mut app, projectile, block);
+        for _ in 0..2 {
+            app.update();
+        }
+
+        assert_eq!(app.world.get::<Health>(block).unwrap().current, 90.);
+        let velocity = app.world.get::<Velocity>(block).unwrap().linvel;
+        assert!(
+            velocity.z < -0.1,
+            "expected the block pushed along -Z, got {:?}",
+            velocity
+        );
+        assert!(
+            velocity.x.abs() < 1e-3 && velocity.y.abs() < 1e-3,
+            "{:?}",
+            velocity
+        );
+    }
 }
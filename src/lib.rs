@@ -1,24 +1,267 @@
-use std::{f32::consts::PI, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    f32::consts::PI,
+    io::{self, Read, Write},
+    net::{SocketAddr, UdpSocket},
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, SystemTime},
+};
 
 use bevy::{
     input::mouse::MouseWheel,
     prelude::{shape::Icosphere, *},
+    render::{
+        mesh::VertexAttributeValues,
+        render_resource::{
+            AddressMode, Extent3d, FilterMode, PrimitiveTopology, SamplerDescriptor,
+            TextureDimension, TextureFormat,
+        },
+        texture::ImageSampler,
+    },
 };
 use bevy_rapier3d::prelude::*;
-use bevy_renet::renet::{transport::NETCODE_KEY_BYTES, ChannelConfig, ConnectionConfig, SendType};
-use serde::{Deserialize, Serialize};
+use bevy_renet::renet::{
+    transport::{
+        ClientAuthentication, NetcodeClientTransport, NETCODE_KEY_BYTES, NETCODE_USER_DATA_BYTES,
+    },
+    ChannelConfig, ConnectionConfig, RenetClient, RenetServer, SendType,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
 
 pub const PRIVATE_KEY: &[u8; NETCODE_KEY_BYTES] = b"an example very very secret key."; // 32-bytes
 pub const PROTOCOL_ID: u64 = 7;
 
+/// Collision group for the ground plane.
+pub const GROUND_GROUP: Group = Group::GROUP_1;
+/// Collision group for static level walls.
+pub const WALL_GROUP: Group = Group::GROUP_2;
+
 #[derive(Debug, Component)]
 pub struct Player {
     pub id: u64,
+    /// Stable identity sent by the client on connect, used for the leaderboard.
+    pub name: String,
+    /// Wallet the client proved it holds when connecting.
+    pub wallet: Option<Pubkey>,
+}
+
+impl Player {
+    /// Verified wallets keep their score whatever name they play under.
+    pub fn leaderboard_key(&self) -> String {
+        match &self.wallet {
+            Some(wallet) => wallet.to_string(),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// `user_data` layout: the name, then an optional wallet pubkey and its
+/// signature over `wallet_challenge`, then whether the client only observes,
+/// then when the challenge was signed.
+const USER_DATA_NAME_BYTES: usize = 128;
+const USER_DATA_PUBKEY_RANGE: std::ops::Range<usize> = 128..160;
+const USER_DATA_SIGNATURE_RANGE: std::ops::Range<usize> = 160..224;
+const USER_DATA_OBSERVER_BYTE: usize = 224;
+const USER_DATA_SIGNED_AT_RANGE: std::ops::Range<usize> = 225..233;
+
+/// How many seconds a wallet challenge's timestamp may be off from the
+/// server's clock, either way.
+pub const WALLET_CHALLENGE_WINDOW_SECS: u64 = 30;
+
+/// What a client signs to prove it holds its wallet, `signed_at` being unix
+/// seconds. The signature alone could be captured and sent again, so the
+/// server only accepts recent timestamps and remembers the challenges it
+/// has seen within the window.
+pub fn wallet_challenge(client_id: u64, signed_at: u64) -> [u8; 24] {
+    let mut challenge = [0u8; 24];
+    challenge[..8].copy_from_slice(&PROTOCOL_ID.to_le_bytes());
+    challenge[8..16].copy_from_slice(&client_id.to_le_bytes());
+    challenge[16..].copy_from_slice(&signed_at.to_le_bytes());
+    challenge
+}
+
+/// Packs a player name into the netcode connect `user_data`, truncated to fit.
+pub fn encode_player_name(name: &str) -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+    let mut len = name.len().min(USER_DATA_NAME_BYTES);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    user_data[..len].copy_from_slice(&name.as_bytes()[..len]);
+    user_data
+}
+
+pub fn decode_player_name(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<String> {
+    let name_bytes = &user_data[..USER_DATA_NAME_BYTES];
+    let len = name_bytes
+        .iter()
+        .position(|b| *b == 0)
+        .unwrap_or(name_bytes.len());
+    let name = std::str::from_utf8(&name_bytes[..len]).ok()?.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Adds the wallet pubkey and its signature over `wallet_challenge` to
+/// `user_data`, signed at unix time `signed_at`.
+pub fn sign_wallet_identity(
+    user_data: &mut [u8; NETCODE_USER_DATA_BYTES],
+    keypair: &Keypair,
+    client_id: u64,
+    signed_at: u64,
+) {
+    let signature = keypair.sign_message(&wallet_challenge(client_id, signed_at));
+    user_data[USER_DATA_PUBKEY_RANGE].copy_from_slice(keypair.pubkey().as_ref());
+    user_data[USER_DATA_SIGNATURE_RANGE].copy_from_slice(signature.as_ref());
+    user_data[USER_DATA_SIGNED_AT_RANGE].copy_from_slice(&signed_at.to_le_bytes());
+}
+
+/// Marks `user_data` as an observer's: the server gives observers no player
+/// and sends them snapshots at a reduced rate.
+pub fn mark_observer(user_data: &mut [u8; NETCODE_USER_DATA_BYTES]) {
+    user_data[USER_DATA_OBSERVER_BYTE] = 1;
+}
+
+pub fn is_observer(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> bool {
+    user_data[USER_DATA_OBSERVER_BYTE] != 0
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WalletIdentity {
+    /// The client didn't sign in with a wallet.
+    None,
+    /// The signature matches. The caller still has to check `signed_at`
+    /// hasn't been seen before to rule out a replay.
+    Verified { wallet: Pubkey, signed_at: u64 },
+    /// The challenge was signed outside `WALLET_CHALLENGE_WINDOW_SECS` of `now`.
+    Expired,
+    /// A wallet was sent but the signature doesn't match it.
+    Invalid,
+}
+
+/// Checks the wallet signature in `user_data` against `client_id` and the
+/// server's unix time `now`.
+pub fn verify_wallet_identity(
+    user_data: &[u8; NETCODE_USER_DATA_BYTES],
+    client_id: u64,
+    now: u64,
+) -> WalletIdentity {
+    let pubkey = &user_data[USER_DATA_PUBKEY_RANGE];
+    if pubkey.iter().all(|b| *b == 0) {
+        return WalletIdentity::None;
+    }
+    let mut signed_at = [0u8; 8];
+    signed_at.copy_from_slice(&user_data[USER_DATA_SIGNED_AT_RANGE]);
+    let signed_at = u64::from_le_bytes(signed_at);
+    if now.abs_diff(signed_at) > WALLET_CHALLENGE_WINDOW_SECS {
+        return WalletIdentity::Expired;
+    }
+    let signature = Signature::new(&user_data[USER_DATA_SIGNATURE_RANGE]);
+    if signature.verify(pubkey, &wallet_challenge(client_id, signed_at)) {
+        WalletIdentity::Verified {
+            wallet: Pubkey::new(pubkey),
+            signed_at,
+        }
+    } else {
+        WalletIdentity::Invalid
+    }
+}
+
+pub const PLAYER_MAX_HEALTH: f32 = 100.0;
+pub const FIREBALL_DAMAGE: f32 = 10.0;
+
+#[derive(Debug, Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn damage(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+pub const PLAYER_MAX_MANA: f32 = 100.0;
+
+/// Spent on support abilities and regenerated over time.
+#[derive(Debug, Component)]
+pub struct Mana {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Mana {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    /// Deducts `cost` if there is enough mana.
+    pub fn spend(&mut self, cost: f32) -> bool {
+        if self.current < cost {
+            return false;
+        }
+        self.current -= cost;
+        true
+    }
+
+    pub fn regenerate(&mut self, amount: f32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+}
+
+/// Players on the same team are friendly. Players without a team are only
+/// friendly to themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct Team(pub u8);
+
+/// Players with this marker are skipped by the damage system entirely.
+#[derive(Debug, Component)]
+pub struct Invulnerable;
+
+pub const SPAWN_PROTECTION_SECS: f32 = 3.0;
+
+/// Brief damage immunity after joining. Ends when the timer runs out or the
+/// player attacks.
+#[derive(Debug, Component)]
+pub struct SpawnProtection {
+    pub timer: Timer,
+}
+
+impl Default for SpawnProtection {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(SPAWN_PROTECTION_SECS, TimerMode::Once),
+        }
+    }
 }
 
 #[derive(Debug, Component)]
 pub struct SolanaSlotBlock {
     pub id: u64,
+    pub shape: BlockShape,
+    /// Server only: despawns the block when it finishes. `None` never expires.
+    pub lifetime: Option<Timer>,
 }
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, Component, Resource)]
@@ -30,123 +273,1266 @@ pub struct PlayerInput {
     pub position: [f32; 3],
 }
 
+impl PlayerInput {
+    /// Horizontal unit direction of the held directions, `up` being -Z.
+    pub fn direction(&self) -> Vec3 {
+        let axis = |positive: bool, negative: bool| positive as i8 as f32 - negative as i8 as f32;
+        Vec3::new(axis(self.right, self.left), 0., axis(self.down, self.up)).normalize_or_zero()
+    }
+
+    /// Holds the directions that lead from `from` toward `to`, ignoring
+    /// offsets along an axis smaller than `dead_zone`.
+    pub fn steer(&mut self, from: Vec3, to: Vec3, dead_zone: f32) {
+        let offset = to - from;
+        self.right = offset.x > dead_zone;
+        self.left = offset.x < -dead_zone;
+        self.down = offset.z > dead_zone;
+        self.up = offset.z < -dead_zone;
+    }
+}
+
 #[derive(Debug, Component)]
 pub struct WoodBlock {
     pub id: u64,
 }
 
+/// What a projectile is. Clients pick its visuals by kind.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProjectileKind {
+    #[default]
+    Fireball,
+    /// A fireball that steers toward enemies, cast by `PlayerCommand::SeekingAttack`.
+    Seeker,
+    /// A fireball that passes through what it hits, cast by `PlayerCommand::PiercingAttack`.
+    Piercing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EmoteKind {
+    Wave,
+    Taunt,
+    Thanks,
+}
+
+impl EmoteKind {
+    pub const ALL: [EmoteKind; 3] = [EmoteKind::Wave, EmoteKind::Taunt, EmoteKind::Thanks];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EmoteKind::Wave => "Wave",
+            EmoteKind::Taunt => "Taunt",
+            EmoteKind::Thanks => "Thanks",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum PlayerCommand {
-    BasicAttack { cast_at: Vec3 },
+    /// `charge` is how long the fire button was held, in seconds.
+    BasicAttack {
+        cast_at: Vec3,
+        charge: f32,
+    },
+    /// Fires `ProjectileConfig::spread_count` fireballs in a cone toward `cast_at`.
+    SpreadAttack {
+        cast_at: Vec3,
+    },
+    /// Fires a `ProjectileKind::Seeker` toward `cast_at`.
+    SeekingAttack {
+        cast_at: Vec3,
+    },
+    /// Fires a `ProjectileKind::Piercing` toward `cast_at`.
+    PiercingAttack {
+        cast_at: Vec3,
+    },
+    /// Chat text. Messages starting with `/` are handled as server commands.
+    Chat {
+        message: String,
+    },
+    Emote {
+        kind: EmoteKind,
+    },
+    /// Marks a point in the world for every player.
+    Ping {
+        position: Vec3,
+    },
+    /// Heals the friendly player closest to `target_position`.
+    Heal {
+        target_position: Vec3,
+    },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ClientChannel {
     Input,
     Command,
+    RequestEntity,
+    /// [`LockstepMessage`]s, sent instead of `Input` when the server runs in lockstep.
+    Lockstep,
 }
 
+/// What a client sends on `ClientChannel::Lockstep`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LockstepMessage {
+    /// This client's input for simulation tick `tick`.
+    Input { tick: u64, input: PlayerInput },
+    /// [`LockstepState::hash`] after this client applied tick `tick`.
+    StateHash { tick: u64, hash: u64 },
+}
+
+/// How far a lockstep player moves each tick while holding a direction.
+pub const LOCKSTEP_STEP: f32 = 0.25;
+
+/// The state every lockstep peer simulates from confirmed ticks alone. It
+/// only holds what inputs decide, and stays out of Rapier, so peers agree
+/// without needing bit-identical physics.
+#[derive(Debug, Default, Clone, Resource)]
+pub struct LockstepState {
+    /// Last tick applied.
+    pub tick: u64,
+    pub positions: BTreeMap<u64, Vec3>,
+}
+
+impl LockstepState {
+    /// Starts from the positions the server had when we joined, `tick`
+    /// being the first tick still to be applied.
+    pub fn resume(tick: u64, positions: &[(u64, [f32; 3])]) -> Self {
+        LockstepState {
+            tick: tick.saturating_sub(1),
+            positions: positions
+                .iter()
+                .map(|(id, position)| (*id, Vec3::from(*position)))
+                .collect(),
+        }
+    }
+
+    /// Applies every participant's input for `tick`, moving each player
+    /// `LOCKSTEP_STEP` along their held directions. Newcomers start where
+    /// their client says they are; after that only the directions count.
+    /// Players missing from `inputs` have left and are dropped.
+    pub fn step(&mut self, tick: u64, inputs: &[(u64, PlayerInput)]) {
+        self.tick = tick;
+        let previous = std::mem::take(&mut self.positions);
+        self.positions = inputs
+            .iter()
+            .map(|(id, input)| {
+                let position = previous
+                    .get(id)
+                    .copied()
+                    .unwrap_or_else(|| Vec3::from(input.position));
+                (*id, position + input.direction() * LOCKSTEP_STEP)
+            })
+            .collect();
+    }
+
+    /// Positions in id order, as sent to clients joining mid-session.
+    pub fn positions(&self) -> Vec<(u64, [f32; 3])> {
+        self.positions
+            .iter()
+            .map(|(id, position)| (*id, position.to_array()))
+            .collect()
+    }
+
+    /// FNV-1a over ids and position bits in id order, compared between
+    /// peers to catch desyncs.
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        write(&self.tick.to_le_bytes());
+        for (id, position) in &self.positions {
+            write(&id.to_le_bytes());
+            for coord in position.to_array() {
+                write(&coord.to_bits().to_le_bytes());
+            }
+        }
+        hash
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ServerChannel {
     ServerMessages,
     NetworkedEntities,
 }
 
-#[derive(Debug, Serialize, Deserialize, Component)]
-pub enum ServerMessages {
-    PlayerCreate {
-        entity: Entity,
-        id: u64,
-        translation: [f32; 3],
-    },
-    PlayerRemove {
-        id: u64,
-    },
-    SpawnProjectile {
-        entity: Entity,
-        translation: [f32; 3],
-    },
-    DespawnProjectile {
-        entity: Entity,
-    },
-    SpawnSolanaBlock {
-        entity: Entity,
-        transform: (f32, f32, f32),
-        slot: u64,
-    },
-    DespawnSolanaBlock {
-        entity: Entity,
-    },
+#[derive(Debug, Serialize, Deserialize, Component)]
+pub enum ServerMessages {
+    PlayerCreate {
+        entity: Entity,
+        id: u64,
+        translation: [f32; 3],
+    },
+    PlayerRemove {
+        id: u64,
+    },
+    /// `id` died. `killer` is who gets the credit, if anyone.
+    PlayerKilled {
+        id: u64,
+        killer: Option<u64>,
+    },
+    SpawnProjectile {
+        entity: Entity,
+        translation: [f32; 3],
+        /// Linear velocity at spawn, so clients can extrapolate between snapshots.
+        velocity: [f32; 3],
+        /// Visual radius, `ProjectileConfig::radius`.
+        radius: f32,
+        kind: ProjectileKind,
+    },
+    DespawnProjectile {
+        entity: Entity,
+    },
+    SpawnSolanaBlock {
+        entity: Entity,
+        transform: (f32, f32, f32),
+        slot: u64,
+        shape: BlockShape,
+    },
+    DespawnSolanaBlock {
+        entity: Entity,
+    },
+    /// Stress test ball, only sent when a client asks about one it doesn't know.
+    SpawnBall {
+        entity: Entity,
+        translation: [f32; 3],
+    },
+    /// `sender` is `None` for messages from the server itself.
+    ChatMessage {
+        sender: Option<u64>,
+        message: String,
+    },
+    /// Sent on connect so the client can step physics at the server's rate.
+    WorldSettings {
+        physics_timestep: f32,
+        /// Seconds between this client's snapshots when it gets fewer than one
+        /// per tick, as observers do.
+        snapshot_interval: Option<f32>,
+        /// First tick to send lockstep input for, when the server runs in
+        /// lockstep. `None` for the usual server-authoritative model.
+        lockstep_tick: Option<u64>,
+        /// Lockstep player positions as of `lockstep_tick`, so a client
+        /// joining mid-session simulates from where everyone already is.
+        lockstep_positions: Vec<(u64, [f32; 3])>,
+        /// How the server flies projectiles, for client-side previews and extrapolation.
+        projectile: ProjectileConfig,
+    },
+    /// Every participant's input for lockstep tick `tick`, sorted by id. Sent
+    /// once all of them have arrived.
+    LockstepTick {
+        tick: u64,
+        inputs: Vec<(u64, PlayerInput)>,
+    },
+    /// Participants reported different state hashes for `tick`.
+    LockstepDesync {
+        tick: u64,
+    },
+    /// Milestone shown to everyone as a banner.
+    Announcement {
+        text: String,
+    },
+    PlayerInvulnerable {
+        id: u64,
+        invulnerable: bool,
+    },
+    /// An admin moved the player or they respawned. Their own client moves
+    /// its camera there.
+    PlayerTeleport {
+        id: u64,
+        translation: [f32; 3],
+    },
+    PlayerSpawnProtection {
+        id: u64,
+        protected: bool,
+    },
+    /// A projectile hit the ground or a wall at `position`, facing along `normal`.
+    ImpactDecal {
+        position: [f32; 3],
+        normal: [f32; 3],
+    },
+    HealEffect {
+        target_id: u64,
+    },
+    /// State of one capture point, sent periodically. `progress` belongs to
+    /// `controlling_team`; the point is held, and scores, once it reaches 1.
+    CapturePointUpdate {
+        id: u32,
+        center: [f32; 3],
+        radius: f32,
+        controlling_team: Option<u8>,
+        progress: f32,
+    },
+    /// Player holding the king of the hill objective, or `None` when nobody
+    /// is in range. Sent on every change and again with each payout.
+    ObjectiveHolder {
+        id: Option<u64>,
+    },
+    /// A player or bot cast successfully from `position`. Sent alongside the
+    /// projectile spawns so clients can flash at the caster.
+    CastPerformed {
+        caster_id: u64,
+        position: [f32; 3],
+    },
+    /// A hit launched player `id` upward at `velocity`. They land again under
+    /// gravity, so clients can play the arc out ahead of snapshots.
+    PlayerKnockedUp {
+        id: u64,
+        velocity: f32,
+    },
+    /// Final standings, sent when a match ends.
+    MatchResults {
+        standings: Vec<PlayerSummary>,
+        /// Ids of the players who met the win condition. Empty when the match
+        /// ran out of time.
+        winners: Vec<u64>,
+    },
+    EmotePlayed {
+        id: u64,
+        kind: EmoteKind,
+    },
+    PingPlaced {
+        id: u64,
+        position: [f32; 3],
+    },
+    /// Periodic list of every live `SolanaSlotBlock` so clients can request
+    /// spawns they missed.
+    LiveSolanaBlocks {
+        entities: Vec<Entity>,
+    },
+    /// All-time top scores, sent on connect and whenever scores change.
+    Leaderboard {
+        entries: Vec<LeaderboardEntry>,
+    },
+    /// Reply to the `/players` chat command, sent to the requester only.
+    PlayerList {
+        players: Vec<PlayerSummary>,
+    },
+    /// Messages the server queued during one frame, sent together. Clients
+    /// handle them in order as if they had arrived one by one.
+    Batch {
+        messages: Vec<ServerMessages>,
+    },
+    /// One piece of a serialized `ServerMessages` too large to send whole.
+    /// See `send_chunked`.
+    Chunk {
+        id: u32,
+        index: u16,
+        count: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSummary {
+    pub id: u64,
+    pub name: String,
+    /// Points scored this session.
+    pub score: u64,
+    pub is_bot: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub score: u64,
+}
+
+/// Sent on `ClientChannel::RequestEntity` to ask the server to resend the
+/// spawn message for entities the client has no mapping for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EntityRequest {
+    pub entities: Vec<Entity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct NetworkedEntities {
+    /// Incremented for every snapshot, so clients can drop out of order ones.
+    pub tick: u64,
+    /// Server clock in seconds when the snapshot was taken.
+    pub server_time: f64,
+    /// Whether this lists every networked entity. Snapshots trimmed to a
+    /// client's priorities leave some out, so only complete ones show what
+    /// the server no longer has.
+    pub complete: bool,
+    pub entities: Vec<Entity>,
+    pub translations: Vec<[f32; 3]>,
+}
+
+/// Magic bytes every exported replay starts with.
+pub const REPLAY_MAGIC: [u8; 4] = *b"BPRP";
+/// Replay format version written by [`ReplayWriter`]. Bump it whenever a
+/// replay record's layout changes; [`ReplayReader`] refuses newer versions.
+pub const REPLAY_FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayPlayer {
+    pub id: u64,
+    pub name: String,
+}
+
+/// First record of an exported replay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub map: String,
+    /// Players in the match when recording started. Frames name later
+    /// joiners by id only.
+    pub players: Vec<ReplayPlayer>,
+    /// Unix time in seconds when recording started.
+    pub start_time: u64,
+}
+
+/// What a replayed entity is, so a viewer knows how to draw it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayEntityKind {
+    Player { id: u64 },
+    Projectile(ProjectileKind),
+    Block { slot: u64 },
+    Ball,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayEntity {
+    /// Unique among the entities alive at the same time.
+    pub id: u64,
+    pub kind: ReplayEntityKind,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+/// Every replayed entity at one moment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    /// Seconds since recording started.
+    pub time: f64,
+    pub entities: Vec<ReplayEntity>,
+}
+
+/// Writes replays in the portable export format:
+///
+/// - `REPLAY_MAGIC`, then `REPLAY_FORMAT_VERSION` as a little endian `u16`
+/// - the [`ReplayHeader`]
+/// - any number of [`ReplayFrame`]s in time order
+/// - an empty record marking the end
+///
+/// Each record is its length in bytes as a little endian `u32` followed by
+/// the record encoded with bincode. A replay missing its end marker, say
+/// because the server crashed, still reads up to its last complete frame.
+pub struct ReplayWriter<W: Write> {
+    inner: W,
+    header_written: bool,
+}
+
+impl<W: Write> ReplayWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            header_written: false,
+        }
+    }
+
+    /// Writes the magic, format version and `header`. Must come first, once.
+    pub fn write_header(&mut self, header: &ReplayHeader) -> io::Result<()> {
+        if self.header_written {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "replay header already written",
+            ));
+        }
+        self.inner.write_all(&REPLAY_MAGIC)?;
+        self.inner.write_all(&REPLAY_FORMAT_VERSION.to_le_bytes())?;
+        self.write_record(header)?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &ReplayFrame) -> io::Result<()> {
+        if !self.header_written {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "replay header not written",
+            ));
+        }
+        self.write_record(frame)
+    }
+
+    /// Writes the end marker and flushes, returning the underlying writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        if !self.header_written {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "replay header not written",
+            ));
+        }
+        self.inner.write_all(&0_u32.to_le_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_record(&mut self, record: &impl Serialize) -> io::Result<()> {
+        let bytes = bincode::serialize(record)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let length = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "replay record too large"))?;
+        self.inner.write_all(&length.to_le_bytes())?;
+        self.inner.write_all(&bytes)
+    }
+}
+
+/// Reads replays written by [`ReplayWriter`], including those from older
+/// format versions.
+pub struct ReplayReader<R: Read> {
+    inner: R,
+    pub version: u16,
+    pub header: ReplayHeader,
+    finished: bool,
+}
+
+impl<R: Read> ReplayReader<R> {
+    /// Checks the magic and format version and reads the header.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        inner.read_exact(&mut magic)?;
+        if magic != REPLAY_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a replay"));
+        }
+        let mut version = [0; 2];
+        inner.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version > REPLAY_FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "replay format version {} is newer than {}",
+                    version, REPLAY_FORMAT_VERSION
+                ),
+            ));
+        }
+        let header = read_replay_record(&mut inner)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "replay has no header"))?;
+        Ok(Self {
+            inner,
+            version,
+            header,
+            finished: false,
+        })
+    }
+
+    /// The next frame, or `None` once the replay ends.
+    pub fn next_frame(&mut self) -> io::Result<Option<ReplayFrame>> {
+        if self.finished {
+            return Ok(None);
+        }
+        let frame = read_replay_record(&mut self.inner)?;
+        self.finished = frame.is_none();
+        Ok(frame)
+    }
+}
+
+/// Reads one length-prefixed replay record. `None` at the end marker, or if
+/// the input stops partway through a record.
+fn read_replay_record<T: DeserializeOwned>(inner: &mut impl Read) -> io::Result<Option<T>> {
+    let mut length = [0; 4];
+    match inner.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    if length == 0 {
+        return Ok(None);
+    }
+    // Read through `take` so a corrupt length can't allocate gigabytes up front
+    let mut bytes = Vec::new();
+    inner.by_ref().take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < length {
+        return Ok(None);
+    }
+    bincode::deserialize(&bytes)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+impl From<ClientChannel> for u8 {
+    fn from(channel_id: ClientChannel) -> Self {
+        match channel_id {
+            ClientChannel::Command => 0,
+            ClientChannel::Input => 1,
+            ClientChannel::RequestEntity => 2,
+            ClientChannel::Lockstep => 3,
+        }
+    }
+}
+
+impl ClientChannel {
+    pub fn channels_config() -> Vec<ChannelConfig> {
+        vec![
+            ChannelConfig {
+                channel_id: Self::Input.into(),
+                max_memory_usage_bytes: 5 * 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::ZERO,
+                },
+            },
+            ChannelConfig {
+                channel_id: Self::Command.into(),
+                max_memory_usage_bytes: 5 * 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::ZERO,
+                },
+            },
+            ChannelConfig {
+                channel_id: Self::RequestEntity.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::ZERO,
+                },
+            },
+            ChannelConfig {
+                channel_id: Self::Lockstep.into(),
+                max_memory_usage_bytes: 5 * 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::ZERO,
+                },
+            },
+        ]
+    }
+}
+
+impl From<ServerChannel> for u8 {
+    fn from(channel_id: ServerChannel) -> Self {
+        match channel_id {
+            ServerChannel::NetworkedEntities => 0,
+            ServerChannel::ServerMessages => 1,
+        }
+    }
+}
+
+impl ServerChannel {
+    pub fn channels_config() -> Vec<ChannelConfig> {
+        vec![
+            ChannelConfig {
+                channel_id: Self::NetworkedEntities.into(),
+                max_memory_usage_bytes: 10 * 1024 * 1024,
+                send_type: SendType::Unreliable,
+            },
+            ChannelConfig {
+                channel_id: Self::ServerMessages.into(),
+                max_memory_usage_bytes: 10 * 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::from_millis(200),
+                },
+            },
+        ]
+    }
+}
+
+pub fn connection_config() -> ConnectionConfig {
+    ConnectionConfig {
+        available_bytes_per_tick: 1024 * 1024,
+        client_channels_config: ClientChannel::channels_config(),
+        server_channels_config: ServerChannel::channels_config(),
+    }
+}
+
+/// Unsecure client connection to `server_addr` from any free local port.
+pub fn new_renet_client(
+    server_addr: SocketAddr,
+    client_id: u64,
+    user_data: [u8; NETCODE_USER_DATA_BYTES],
+) -> Result<(RenetClient, NetcodeClientTransport), Box<dyn Error>> {
+    let client = RenetClient::new(connection_config());
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let authentication = ClientAuthentication::Unsecure {
+        client_id,
+        protocol_id: PROTOCOL_ID,
+        server_addr,
+        user_data: Some(user_data),
+    };
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket)?;
+
+    Ok((client, transport))
+}
+
+/// Serialized messages larger than this are split by `send_chunked`.
+pub const MAX_MESSAGE_BYTES: usize = 16 * 1024;
+
+static NEXT_CHUNKED_MESSAGE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Serializes `message`, splitting it into `ServerMessages::Chunk`s of at most
+/// `max_bytes` of payload each when it doesn't fit in one.
+pub fn serialize_chunked(message: &ServerMessages, max_bytes: usize) -> Vec<Vec<u8>> {
+    let bytes = bincode::serialize(message).unwrap();
+    if bytes.len() <= max_bytes {
+        return vec![bytes];
+    }
+
+    let id = NEXT_CHUNKED_MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+    let chunks: Vec<&[u8]> = bytes.chunks(max_bytes.max(1)).collect();
+    let count = chunks.len() as u16;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            bincode::serialize(&ServerMessages::Chunk {
+                id,
+                index: index as u16,
+                count,
+                bytes: chunk.to_vec(),
+            })
+            .unwrap()
+        })
+        .collect()
+}
+
+/// Sends `message` on `ServerChannel::ServerMessages` to `client_id`, or to
+/// everyone when it is `None`, chunked if it exceeds `MAX_MESSAGE_BYTES`.
+pub fn send_chunked(server: &mut RenetServer, client_id: Option<u64>, message: &ServerMessages) {
+    for bytes in serialize_chunked(message, MAX_MESSAGE_BYTES) {
+        match client_id {
+            Some(client_id) => server.send_message(client_id, ServerChannel::ServerMessages, bytes),
+            None => server.broadcast_message(ServerChannel::ServerMessages, bytes),
+        }
+    }
+}
+
+/// Collects `ServerMessages::Chunk`s until a whole message has arrived.
+#[derive(Debug, Default, Resource)]
+pub struct ChunkAssembler {
+    partial: HashMap<u32, Vec<Option<Vec<u8>>>>,
+}
+
+impl ChunkAssembler {
+    /// Stores a chunk, returning the reassembled message bytes once every
+    /// chunk of `id` is in.
+    pub fn receive(&mut self, id: u32, index: u16, count: u16, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let chunks = self
+            .partial
+            .entry(id)
+            .or_insert_with(|| vec![None; count as usize]);
+        if chunks.len() != count as usize || index >= count {
+            println!(
+                "Dropping malformed chunk {}/{} of message {}",
+                index, count, id
+            );
+            self.partial.remove(&id);
+            return None;
+        }
+        chunks[index as usize] = Some(bytes);
+        if chunks.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let chunks = self.partial.remove(&id)?;
+        Some(chunks.into_iter().flatten().flatten().collect())
+    }
+
+    pub fn clear(&mut self) {
+        self.partial.clear();
+    }
+}
+
+/// Simulation settings shared by the server and client.
+#[derive(Debug, Clone, Resource)]
+pub struct WorldConfig {
+    /// Fixed physics step in seconds. Server and client must use the same
+    /// value: matching timesteps keep the client's local simulation of dynamic
+    /// blocks close to the server's, which reduces prediction error. The server
+    /// sends its value on connect and the client adopts it, warning on mismatch.
+    pub physics_timestep: f32,
+    pub physics_substeps: usize,
+    /// Velocity solver iterations per step. Rapier's default of 4 lets piled
+    /// blocks sink into each other.
+    pub solver_iterations: usize,
+    pub movement_mode: MovementMode,
+}
+
+/// How players move vertically. The server applies it to every player it
+/// spawns; `move_players_system` then handles the Y axis per mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MovementMode {
+    /// Y is locked and players are pinned to the ground height. Client Y is ignored.
+    #[default]
+    Grounded,
+    /// Y is unlocked and gravity applies. Physics owns the player's height,
+    /// so client Y is ignored here too.
+    Jumping,
+    /// Y is unlocked without gravity and follows the client, never below the ground.
+    Flying,
+}
+
+impl MovementMode {
+    pub fn locked_axes(self) -> LockedAxes {
+        match self {
+            MovementMode::Grounded => {
+                LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y
+            }
+            MovementMode::Jumping | MovementMode::Flying => LockedAxes::ROTATION_LOCKED,
+        }
+    }
+
+    pub fn gravity_scale(self) -> GravityScale {
+        match self {
+            MovementMode::Grounded | MovementMode::Jumping => GravityScale(1.0),
+            MovementMode::Flying => GravityScale(0.0),
+        }
+    }
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            physics_timestep: 1.0 / 60.0,
+            physics_substeps: 1,
+            solver_iterations: 8,
+            movement_mode: MovementMode::Grounded,
+        }
+    }
+}
+
+impl WorldConfig {
+    /// Steps `physics_timestep` as often as real time requires instead of
+    /// once per frame, so physics runs at the configured rate whatever the
+    /// frame rate. The server and client both use this, so they step alike.
+    pub fn rapier_configuration(&self) -> RapierConfiguration {
+        RapierConfiguration {
+            timestep_mode: TimestepMode::Interpolated {
+                dt: self.physics_timestep,
+                time_scale: 1.0,
+                substeps: self.physics_substeps,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Applies the solver settings from [`WorldConfig`] to the physics context.
+pub fn configure_solver_system(
+    config: Res<WorldConfig>,
+    mut rapier_context: ResMut<RapierContext>,
+) {
+    let parameters = &mut rapier_context.integration_parameters;
+    parameters.max_velocity_iterations = config.solver_iterations;
+    parameters.max_stabilization_iterations = 2;
+}
+
+// Physics definitions
+//
+// Each networked entity type gets its shape and contact response from here so
+// the server and client can't drift apart. The rigid body type is picked per
+// side: the server's simulation is authoritative for position and velocity,
+// clients only mirror it (kinematic bodies) or predict it (see the client's
+// `BlockSimMode`).
+
+/// Player shape and locked axes. Players stay upright and on the ground plane;
+/// the server swaps the lock for `MovementMode::locked_axes` on its players.
+pub fn player_physics() -> (Collider, LockedAxes) {
+    (
+        Collider::capsule_y(0.5, 0.5),
+        LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y,
+    )
+}
+
+/// Fireball body moving along `direction` at `speed`. Collision events are
+/// enabled here because players, walls and the ground don't report their own.
+pub fn projectile_physics(
+    direction: Vec3,
+    speed: f32,
+    radius: f32,
+) -> (RigidBody, Collider, Velocity, ActiveEvents) {
+    (
+        RigidBody::Dynamic,
+        Collider::ball(radius),
+        Velocity::linear(direction * speed),
+        ActiveEvents::COLLISION_EVENTS,
+    )
+}
+
+/// Shape of a Solana block. Its collider and mesh are both derived from it,
+/// so they always match. Every shape fits in a unit cube.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockShape {
+    #[default]
+    Cube,
+    Sphere,
+    Cylinder,
+    /// A gem-like octahedron.
+    Convex,
+}
+
+const BLOCK_CYLINDER_SEGMENTS: usize = 16;
+
+impl BlockShape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cube" => Some(BlockShape::Cube),
+            "sphere" => Some(BlockShape::Sphere),
+            "cylinder" => Some(BlockShape::Cylinder),
+            "convex" => Some(BlockShape::Convex),
+            _ => None,
+        }
+    }
+
+    pub fn collider(self) -> Collider {
+        match self {
+            BlockShape::Cube => Collider::cuboid(0.5, 0.5, 0.5),
+            BlockShape::Sphere => Collider::ball(0.5),
+            BlockShape::Cylinder => Collider::cylinder(0.5, 0.5),
+            BlockShape::Convex => Collider::convex_hull(&convex_block_points())
+                .expect("convex block points span a volume"),
+        }
+    }
+
+    pub fn mesh(self) -> Mesh {
+        match self {
+            BlockShape::Cube => Mesh::from(shape::Box::new(1.0, 1.0, 1.0)),
+            BlockShape::Sphere => Mesh::try_from(Icosphere {
+                radius: 0.5,
+                subdivisions: 3,
+            })
+            .unwrap(),
+            BlockShape::Cylinder => flat_shaded_mesh(&cylinder_block_triangles()),
+            BlockShape::Convex => flat_shaded_mesh(&convex_block_triangles()),
+        }
+    }
+}
+
+/// Tips of the convex block: a square equator with a point above and below.
+fn convex_block_points() -> [Vec3; 6] {
+    [
+        Vec3::new(0.5, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 0.5),
+        Vec3::new(-0.5, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -0.5),
+        Vec3::new(0.0, 0.5, 0.0),
+        Vec3::new(0.0, -0.5, 0.0),
+    ]
+}
+
+fn convex_block_triangles() -> Vec<[Vec3; 3]> {
+    let points = convex_block_points();
+    let (top, bottom) = (points[4], points[5]);
+    (0..4)
+        .flat_map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % 4]);
+            [[top, a, b], [bottom, a, b]]
+        })
+        .collect()
+}
+
+fn cylinder_block_triangles() -> Vec<[Vec3; 3]> {
+    let rim = |i: usize, y: f32| {
+        let angle = i as f32 * std::f32::consts::TAU / BLOCK_CYLINDER_SEGMENTS as f32;
+        Vec3::new(0.5 * angle.cos(), y, 0.5 * angle.sin())
+    };
+    let (top, bottom) = (Vec3::Y * 0.5, Vec3::Y * -0.5);
+    (0..BLOCK_CYLINDER_SEGMENTS)
+        .flat_map(|i| {
+            let (t0, t1) = (rim(i, 0.5), rim(i + 1, 0.5));
+            let (b0, b1) = (rim(i, -0.5), rim(i + 1, -0.5));
+            [[top, t0, t1], [bottom, b0, b1], [b0, t0, t1], [b0, t1, b1]]
+        })
+        .collect()
+}
+
+/// Flat shaded mesh of a convex shape centered on the origin. Triangles may
+/// be wound either way; each is turned to face away from the origin.
+fn flat_shaded_mesh(triangles: &[[Vec3; 3]]) -> Mesh {
+    let mut positions = Vec::with_capacity(triangles.len() * 3);
+    let mut normals = Vec::with_capacity(triangles.len() * 3);
+    for [a, mut b, mut c] in triangles.iter().copied() {
+        let mut normal = (b - a).cross(c - a).normalize();
+        if normal.dot(a + b + c) < 0.0 {
+            std::mem::swap(&mut b, &mut c);
+            normal = -normal;
+        }
+        positions.extend([a, b, c].map(<[f32; 3]>::from));
+        normals.extend([<[f32; 3]>::from(normal); 3]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; positions.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh
+}
+
+/// Collider and contact response for a Solana block. Low restitution and
+/// some damping let stacks settle instead of bouncing forever.
+pub fn solana_block_physics(shape: BlockShape) -> (Collider, Restitution, Friction, Damping) {
+    (
+        shape.collider(),
+        Restitution::coefficient(0.2),
+        Friction::coefficient(0.8),
+        Damping {
+            linear_damping: 0.3,
+            angular_damping: 1.0,
+        },
+    )
+}
+
+/// Bouncing ball, spawned by the server's stress test and synced like any
+/// other networked entity.
+#[derive(Debug, Component)]
+pub struct Ball;
+
+#[derive(Debug, Clone)]
+pub struct BallConfig {
+    pub radius: f32,
+    pub restitution: f32,
+    pub color: Color,
+}
+
+impl Default for BallConfig {
+    fn default() -> Self {
+        Self {
+            radius: 0.3,
+            restitution: 0.9,
+            color: Color::rgb(0.2, 0.4, 1.0),
+        }
+    }
+}
+
+pub fn ball_physics(config: &BallConfig) -> (Ball, RigidBody, Collider, Restitution) {
+    (
+        Ball,
+        RigidBody::Dynamic,
+        Collider::ball(config.radius),
+        Restitution::coefficient(config.restitution),
+    )
+}
+
+pub fn ball_mesh(config: &BallConfig) -> Mesh {
+    Mesh::try_from(Icosphere {
+        radius: config.radius,
+        subdivisions: 2,
+    })
+    .unwrap()
+}
+
+/// PBR surface parameters applied on top of an entity's colour.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialPreset {
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+    /// Multiplier on the colour used as emitted light. 0 emits nothing.
+    pub emissive: f32,
+}
+
+impl MaterialPreset {
+    pub const MATTE: Self = Self {
+        perceptual_roughness: 0.9,
+        metallic: 0.0,
+        emissive: 0.0,
+    };
+    pub const METALLIC: Self = Self {
+        perceptual_roughness: 0.35,
+        metallic: 0.8,
+        emissive: 0.0,
+    };
+    pub const EMISSIVE: Self = Self {
+        perceptual_roughness: 0.5,
+        metallic: 0.0,
+        emissive: 2.0,
+    };
+
+    /// Builds the material for `color`, blended when it is translucent like
+    /// `StandardMaterial::from(Color)`.
+    pub fn material(&self, color: Color) -> StandardMaterial {
+        StandardMaterial {
+            base_color: color,
+            perceptual_roughness: self.perceptual_roughness,
+            metallic: self.metallic,
+            emissive: color * self.emissive,
+            alpha_mode: if color.a() < 1.0 {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            },
+            ..default()
+        }
+    }
+}
+
+/// Surface preset per entity type.
+#[derive(Debug, Clone)]
+pub struct MaterialPresets {
+    pub player: MaterialPreset,
+    pub block: MaterialPreset,
+    pub projectile: MaterialPreset,
+    pub ball: MaterialPreset,
+}
+
+impl Default for MaterialPresets {
+    fn default() -> Self {
+        Self {
+            player: MaterialPreset::MATTE,
+            block: MaterialPreset::METALLIC,
+            projectile: MaterialPreset::EMISSIVE,
+            ball: MaterialPreset::METALLIC,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct NetworkedEntities {
-    pub entities: Vec<Entity>,
-    pub translations: Vec<[f32; 3]>,
+/// Ring of walls around the play area. Open world modes can disable it.
+#[derive(Debug, Clone, Resource)]
+pub struct WorldBounds {
+    pub enabled: bool,
+    /// Whether the walls get a mesh or are invisible colliders only.
+    pub visible: bool,
+    /// Width of the square play area, centered on the origin.
+    pub size: f32,
+    pub height: f32,
 }
 
-impl From<ClientChannel> for u8 {
-    fn from(channel_id: ClientChannel) -> Self {
-        match channel_id {
-            ClientChannel::Command => 0,
-            ClientChannel::Input => 1,
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            visible: true,
+            size: 40.0,
+            height: 4.0,
         }
     }
 }
 
-impl ClientChannel {
-    pub fn channels_config() -> Vec<ChannelConfig> {
-        vec![
-            ChannelConfig {
-                channel_id: Self::Input.into(),
-                max_memory_usage_bytes: 5 * 1024 * 1024,
-                send_type: SendType::ReliableOrdered {
-                    resend_time: Duration::ZERO,
-                },
-            },
-            ChannelConfig {
-                channel_id: Self::Command.into(),
-                max_memory_usage_bytes: 5 * 1024 * 1024,
-                send_type: SendType::ReliableOrdered {
-                    resend_time: Duration::ZERO,
-                },
-            },
-        ]
+const WORLD_BOUNDS_THICKNESS: f32 = 1.0;
+
+/// Grid lines drawn on the ground so players can judge distance and speed.
+#[derive(Debug, Clone, Resource)]
+pub struct FloorGrid {
+    /// World units between grid lines.
+    pub spacing: f32,
+    pub color: Color,
+    pub line_color: Color,
+}
+
+impl Default for FloorGrid {
+    fn default() -> Self {
+        Self {
+            spacing: 2.0,
+            color: Color::rgb(0.3, 0.5, 0.3),
+            line_color: Color::rgb(0.22, 0.38, 0.22),
+        }
     }
 }
 
-impl From<ServerChannel> for u8 {
-    fn from(channel_id: ServerChannel) -> Self {
-        match channel_id {
-            ServerChannel::NetworkedEntities => 0,
-            ServerChannel::ServerMessages => 1,
+const FLOOR_GRID_CELL_PIXELS: u32 = 64;
+const FLOOR_GRID_LINE_PIXELS: u32 = 2;
+
+/// One grid cell with a line along two edges, repeated across the floor.
+fn floor_grid_texture(grid: &FloorGrid) -> Image {
+    let to_bytes = |color: Color| {
+        color
+            .as_rgba_f32()
+            .map(|channel| (channel.clamp(0., 1.) * 255.).round() as u8)
+    };
+    let fill = to_bytes(grid.color);
+    let line = to_bytes(grid.line_color);
+    let mut data =
+        Vec::with_capacity((FLOOR_GRID_CELL_PIXELS * FLOOR_GRID_CELL_PIXELS * 4) as usize);
+    for y in 0..FLOOR_GRID_CELL_PIXELS {
+        for x in 0..FLOOR_GRID_CELL_PIXELS {
+            let on_line = x < FLOOR_GRID_LINE_PIXELS || y < FLOOR_GRID_LINE_PIXELS;
+            data.extend_from_slice(if on_line { &line } else { &fill });
         }
     }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: FLOOR_GRID_CELL_PIXELS,
+            height: FLOOR_GRID_CELL_PIXELS,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+    image
 }
 
-impl ServerChannel {
-    pub fn channels_config() -> Vec<ChannelConfig> {
-        vec![
-            ChannelConfig {
-                channel_id: Self::NetworkedEntities.into(),
-                max_memory_usage_bytes: 10 * 1024 * 1024,
-                send_type: SendType::Unreliable,
-            },
-            ChannelConfig {
-                channel_id: Self::ServerMessages.into(),
-                max_memory_usage_bytes: 10 * 1024 * 1024,
-                send_type: SendType::ReliableOrdered {
-                    resend_time: Duration::from_millis(200),
-                },
-            },
-        ]
+/// Box mesh whose UVs repeat the grid texture every `spacing` units.
+fn floor_mesh(size: f32, spacing: f32) -> Mesh {
+    let mut mesh = Mesh::from(shape::Box::new(size, 1., size));
+    let tiles = size / spacing.max(0.1);
+    if let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+        for uv in uvs.iter_mut() {
+            uv[0] *= tiles;
+            uv[1] *= tiles;
+        }
     }
+    mesh
 }
 
-pub fn connection_config() -> ConnectionConfig {
-    ConnectionConfig {
-        available_bytes_per_tick: 1024 * 1024,
-        client_channels_config: ClientChannel::channels_config(),
-        server_channels_config: ServerChannel::channels_config(),
+/// Floor and world bounds colliders, without anything to draw. Headless
+/// servers spawn only these; `setup_level` adds meshes on top.
+pub fn spawn_level_colliders(
+    commands: &mut Commands,
+    bounds: &WorldBounds,
+) -> (Entity, Vec<(Entity, Vec3)>) {
+    let floor = commands
+        .spawn(TransformBundle::from_transform(Transform::from_xyz(
+            0.0, 0.0, 0.0,
+        )))
+        .insert(Collider::cuboid(20., 0.5, 20.))
+        .insert(CollisionGroups::new(GROUND_GROUP, Group::ALL))
+        .id();
+
+    let mut walls = Vec::new();
+    if bounds.enabled {
+        let half_size = bounds.size / 2.;
+        let offset = half_size + WORLD_BOUNDS_THICKNESS / 2.;
+        let half_length = half_size + WORLD_BOUNDS_THICKNESS;
+        let sides = [
+            (
+                Vec3::new(offset, 0., 0.),
+                Vec3::new(WORLD_BOUNDS_THICKNESS / 2., 0., half_length),
+            ),
+            (
+                Vec3::new(-offset, 0., 0.),
+                Vec3::new(WORLD_BOUNDS_THICKNESS / 2., 0., half_length),
+            ),
+            (
+                Vec3::new(0., 0., offset),
+                Vec3::new(half_length, 0., WORLD_BOUNDS_THICKNESS / 2.),
+            ),
+            (
+                Vec3::new(0., 0., -offset),
+                Vec3::new(half_length, 0., WORLD_BOUNDS_THICKNESS / 2.),
+            ),
+        ];
+
+        for (mut position, mut half_extents) in sides {
+            position.y = bounds.height / 2.;
+            half_extents.y = bounds.height / 2.;
+
+            let wall = commands
+                .spawn(TransformBundle::from_transform(
+                    Transform::from_translation(position),
+                ))
+                .insert(RigidBody::Fixed)
+                .insert(Collider::cuboid(
+                    half_extents.x,
+                    half_extents.y,
+                    half_extents.z,
+                ))
+                .insert(CollisionGroups::new(WALL_GROUP, Group::ALL))
+                .id();
+            walls.push((wall, half_extents * 2.));
+        }
     }
+    (floor, walls)
 }
 
 /// set up a simple 3D scene
@@ -154,16 +1540,35 @@ pub fn setup_level(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    bounds: Res<WorldBounds>,
+    grid: Res<FloorGrid>,
 ) {
+    let (floor, walls) = spawn_level_colliders(&mut commands, &bounds);
+
     // plane
-    commands
-        .spawn(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Box::new(400., 1., 400.))),
-            material: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            ..Default::default()
-        })
-        .insert(Collider::cuboid(20., 0.5, 20.));
+    commands.entity(floor).insert((
+        meshes.add(floor_mesh(400., grid.spacing)),
+        materials.add(StandardMaterial {
+            base_color_texture: Some(images.add(floor_grid_texture(&grid))),
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        VisibilityBundle::default(),
+    ));
+
+    // bounds
+    if bounds.visible {
+        let wall_material =
+            materials.add(MaterialPreset::MATTE.material(Color::rgba(0.6, 0.6, 0.7, 0.4)));
+        for (wall, size) in walls {
+            commands.entity(wall).insert((
+                meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+                wall_material.clone(),
+                VisibilityBundle::default(),
+            ));
+        }
+    }
 
     // light
     commands.spawn(DirectionalLightBundle {
@@ -180,48 +1585,195 @@ pub fn setup_level(
     });
 }
 
+/// The server sends its config to clients on connect, so they predict
+/// projectiles the way the server flies them.
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+pub struct ProjectileConfig {
+    pub speed: f32,
+    /// Radius of the fireball as drawn. The collider uses this too, scaled by
+    /// `hit_radius`.
+    pub radius: f32,
+    /// Multiplier applied to `radius` for the collider. Values above 1 make
+    /// hits more forgiving than the visual suggests.
+    pub hit_radius: f32,
+    /// Seconds before a projectile despawns on its own.
+    pub lifetime: f32,
+    /// Number of fireballs fired by `PlayerCommand::SpreadAttack`.
+    pub spread_count: usize,
+    /// Total width of the spread cone in radians.
+    pub spread_angle: f32,
+    /// Seconds after casting during which a projectile can't damage its owner.
+    pub owner_grace: f32,
+    /// Times a projectile ricochets off the ground or walls before despawning.
+    pub max_bounces: u32,
+    /// Furthest a cast target may be from its caster before the server rejects it.
+    pub max_cast_range: f32,
+    pub damage: f32,
+    /// Seconds of charge past which a basic attack grows no stronger.
+    pub max_charge: f32,
+    /// Speed, radius and damage multiplier of a fully charged basic attack.
+    pub max_charge_scale: f32,
+    /// Upward speed given to a player this projectile hits. Zero for none.
+    pub knock_up: f32,
+    /// `knock_up` of a fully charged basic attack.
+    pub charged_knock_up: f32,
+    pub kind: ProjectileKind,
+    /// Radians per second a `ProjectileKind::Seeker` turns toward its target.
+    pub homing_strength: f32,
+    /// Furthest a seeker picks or keeps a target from.
+    pub homing_range: f32,
+    /// Largest angle in radians off its heading at which a seeker picks a target.
+    pub homing_cone: f32,
+    /// Impulse given to a block a projectile hits without breaking, per unit
+    /// of the projectile's velocity.
+    pub impact_force: f32,
+    /// Targets a `ProjectileKind::Piercing` passes through before the next one
+    /// stops it.
+    pub pierce_count: u32,
+    /// Multiplier on world gravity for projectiles. Zero flies them straight.
+    pub gravity_scale: f32,
+}
+
+impl Default for ProjectileConfig {
+    fn default() -> Self {
+        Self {
+            speed: 10.0,
+            radius: 0.1,
+            hit_radius: 1.0,
+            lifetime: 1.5,
+            spread_count: 5,
+            spread_angle: PI / 6.,
+            owner_grace: 0.25,
+            max_bounces: 0,
+            max_cast_range: 60.0,
+            damage: FIREBALL_DAMAGE,
+            max_charge: 1.0,
+            max_charge_scale: 2.0,
+            knock_up: 0.0,
+            charged_knock_up: 6.0,
+            kind: ProjectileKind::Fireball,
+            homing_strength: PI / 2.,
+            homing_range: 20.0,
+            homing_cone: PI / 4.,
+            impact_force: 0.2,
+            pierce_count: 2,
+            gravity_scale: 1.0,
+        }
+    }
+}
+
+impl ProjectileConfig {
+    /// Radius of the physics collider, `radius * hit_radius`.
+    pub fn collider_radius(&self) -> f32 {
+        self.radius * self.hit_radius
+    }
+
+    /// This config with speed, radius and damage scaled for `charge` seconds
+    /// of charge, growing linearly up to `max_charge_scale` at `max_charge`.
+    /// A full charge also knocks up by `charged_knock_up`.
+    pub fn charged(&self, charge: f32) -> Self {
+        let fraction = if charge.is_finite() && self.max_charge > 0.0 {
+            (charge / self.max_charge).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let scale = 1.0 + (self.max_charge_scale - 1.0) * fraction;
+        Self {
+            speed: self.speed * scale,
+            radius: self.radius * scale,
+            damage: self.damage * scale,
+            knock_up: if fraction >= 1.0 {
+                self.charged_knock_up
+            } else {
+                self.knock_up
+            },
+            ..self.clone()
+        }
+    }
+
+    /// This config for a `ProjectileKind::Seeker`.
+    pub fn seeking(&self) -> Self {
+        Self {
+            kind: ProjectileKind::Seeker,
+            ..self.clone()
+        }
+    }
+
+    /// Points along the flight of a projectile launched from `origin` toward
+    /// `direction` under `gravity`, every `step` seconds of its lifetime.
+    /// Steps the way the physics does (semi-implicit Euler), so it matches
+    /// where the projectile actually goes until it hits something.
+    pub fn trajectory(&self, origin: Vec3, direction: Vec3, gravity: Vec3, step: f32) -> Vec<Vec3> {
+        if step <= 0.0 {
+            return vec![origin];
+        }
+        let mut position = origin;
+        let mut velocity = direction * self.speed;
+        let steps = (self.lifetime / step).ceil() as usize;
+        let mut points = Vec::with_capacity(steps + 1);
+        points.push(position);
+        for _ in 0..steps {
+            velocity += gravity * self.gravity_scale * step;
+            position += velocity * step;
+            points.push(position);
+        }
+        points
+    }
+
+    /// This config for a `ProjectileKind::Piercing`.
+    pub fn piercing(&self) -> Self {
+        Self {
+            kind: ProjectileKind::Piercing,
+            ..self.clone()
+        }
+    }
+}
+
+/// Splits `direction` into `count` directions fanned evenly across `angle`
+/// radians around the Y axis.
+pub fn spread_directions(direction: Vec3, count: usize, angle: f32) -> Vec<Vec3> {
+    if count <= 1 {
+        return vec![direction];
+    }
+
+    let step = angle / (count - 1) as f32;
+    (0..count)
+        .map(|i| Quat::from_rotation_y(-angle / 2. + step * i as f32) * direction)
+        .collect()
+}
+
 #[derive(Debug, Component)]
 pub struct Projectile {
     pub duration: Timer,
+    /// Player entity that cast the projectile, if any.
+    pub owner: Option<Entity>,
+    /// Ground or wall hits left before the next one despawns it.
+    pub bounces_remaining: u32,
+    /// Health taken from a player it hits.
+    pub damage: f32,
+    /// Upward speed given to a player it hits. Zero for none.
+    pub knock_up: f32,
+    pub kind: ProjectileKind,
+    /// Player a `ProjectileKind::Seeker` is steering toward.
+    pub target: Option<Entity>,
+    /// Targets a `ProjectileKind::Piercing` can still pass through.
+    pub pierces_remaining: u32,
+    /// Targets this projectile has already hit, so passing through one over
+    /// several frames only hits it once.
+    pub hit: Vec<Entity>,
 }
 
-pub fn spawn_fireball(
-    commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    translation: Vec3,
-    mut direction: Vec3,
-) -> Entity {
-    if !direction.is_normalized() {
-        direction = Vec3::X;
-    }
-
-    // let collider_mprops = ColliderMassProperties::Density(2.0);
-    // // Second option: by setting the mass of the collider.
-    // let collider_mprops = ColliderMassProperties::Mass(0.8);
-
-    commands
-        .spawn(PbrBundle {
-            mesh: meshes.add(
-                Mesh::try_from(Icosphere {
-                    radius: 0.1,
-                    subdivisions: 5,
-                })
-                .unwrap(),
-            ),
-            material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
-            transform: Transform::from_translation(translation),
-            ..Default::default()
-        })
-        .insert(RigidBody::Dynamic)
-        // .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y)
-        .insert(Collider::ball(0.1))
-        .insert(Velocity::linear(direction * 10.))
-        .insert(ActiveEvents::COLLISION_EVENTS)
-        .insert(Projectile {
-            duration: Timer::from_seconds(1.5, TimerMode::Once),
-        })
-        .id()
+impl Projectile {
+    /// Records a hit on `target` and returns whether the projectile carries
+    /// on through it, using up one of its pierces.
+    pub fn pierce(&mut self, target: Entity) -> bool {
+        self.hit.push(target);
+        if self.pierces_remaining == 0 {
+            return false;
+        }
+        self.pierces_remaining -= 1;
+        true
+    }
 }
 
 pub fn camera_zoom_system(
@@ -245,7 +1797,101 @@ pub fn camera_zoom_system(
     }
 }
 
-//// flycam
+/// Where a camera is spawned and the point it initially faces.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct CameraConfig {
+    pub start_position: Vec3,
+    pub look_at: Vec3,
+}
+
+impl Default for CameraConfig {
+    /// The client's first-person start, facing down -Z.
+    fn default() -> Self {
+        Self {
+            start_position: Vec3::new(0.0, 2.0, 0.5),
+            look_at: Vec3::new(0.0, 2.0, -0.5),
+        }
+    }
+}
+
+impl CameraConfig {
+    /// High view over the whole arena, used by the server.
+    pub fn overview() -> Self {
+        Self {
+            start_position: Vec3::new(0.0, 30.0, 20.5),
+            look_at: Vec3::ZERO,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.start_position).looking_at(self.look_at, Vec3::Y)
+    }
+}
+
+/// Third-person camera that trails `target` at `offset`.
+#[derive(Debug, Component)]
+pub struct FollowCamera {
+    pub target: Entity,
+    pub offset: Vec3,
+}
+
+/// Keeps a [`FollowCamera`] from clipping through the ground and walls.
+#[derive(Debug, Resource)]
+pub struct CameraCollision {
+    pub enabled: bool,
+    /// The camera is never pulled closer to its target than this.
+    pub min_distance: f32,
+    /// Gap left between the camera and the surface it was pulled in by.
+    pub padding: f32,
+}
+
+impl Default for CameraCollision {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_distance: 1.0,
+            padding: 0.2,
+        }
+    }
+}
+
+/// Places each [`FollowCamera`] at its offset from the target, pulled in to the
+/// first ground/wall hit along the way so the target always stays visible.
+pub fn camera_collision_system(
+    rapier_context: Res<RapierContext>,
+    settings: Res<CameraCollision>,
+    targets: Query<&GlobalTransform>,
+    mut cameras: Query<(&FollowCamera, &mut Transform)>,
+) {
+    for (follow, mut transform) in cameras.iter_mut() {
+        let Ok(target) = targets.get(follow.target) else {
+            continue;
+        };
+        let origin = target.translation();
+        let max_distance = follow.offset.length();
+
+        let mut distance = max_distance;
+        if settings.enabled && max_distance > settings.min_distance {
+            let filter = QueryFilter::new()
+                .exclude_collider(follow.target)
+                .groups(CollisionGroups::new(Group::ALL, GROUND_GROUP | WALL_GROUP));
+            if let Some((_, toi)) = rapier_context.cast_ray(
+                origin,
+                follow.offset / max_distance,
+                max_distance,
+                true,
+                filter,
+            ) {
+                distance = (toi - settings.padding).max(settings.min_distance);
+            }
+        }
+
+        transform.translation = origin + follow.offset.normalize_or_zero() * distance;
+        transform.look_at(origin, Vec3::Y);
+    }
+}
+
+// flycam
 
 use bevy::ecs::event::{Events, ManualEventReader};
 use bevy::input::mouse::MouseMotion;
@@ -487,46 +2133,460 @@ pub fn get_server_addr() -> String {
     option_env!("SERVER_ADDR").unwrap_or("127.0.0.1:7777").to_string()
 }
 
-// Add this for server-side spawning
+/// Spawns a fireball with only physics and gameplay components. Returns
+/// `None` without spawning when `direction` is too short to aim along.
 pub fn spawn_fireball_server(
     commands: &mut Commands,
+    config: &ProjectileConfig,
     translation: Vec3,
     direction: Vec3,
-) -> Entity {
-    commands
-        .spawn((
-            TransformBundle::from_transform(Transform::from_translation(translation)),
-            Projectile {
-                duration: Timer::from_seconds(1.5, TimerMode::Once),
+    owner: Option<Entity>,
+) -> Option<Entity> {
+    let direction = direction.try_normalize()?;
+
+    let mut fireball = commands.spawn((
+        TransformBundle::from_transform(Transform::from_translation(translation)),
+        Projectile {
+            duration: Timer::from_seconds(config.lifetime, TimerMode::Once),
+            owner,
+            bounces_remaining: config.max_bounces,
+            damage: config.damage,
+            knock_up: config.knock_up,
+            kind: config.kind,
+            target: None,
+            pierces_remaining: if config.kind == ProjectileKind::Piercing {
+                config.pierce_count
+            } else {
+                0
+            },
+            hit: Vec::new(),
+        },
+    ));
+    fireball.insert((
+        projectile_physics(direction, config.speed, config.collider_radius()),
+        GravityScale(config.gravity_scale),
+    ));
+    if config.kind == ProjectileKind::Piercing {
+        fireball.insert(Sensor);
+    }
+    Some(fireball.id())
+}
+
+/// `setup_level` for headless servers, which have nothing to draw with.
+pub fn setup_level_server(mut commands: Commands, bounds: Res<WorldBounds>) {
+    spawn_level_colliders(&mut commands, &bounds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(position: [f32; 3], right: bool, up: bool) -> PlayerInput {
+        PlayerInput {
+            right,
+            up,
+            position,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lockstep_moves_players_by_held_directions() {
+        let mut state = LockstepState::default();
+        state.step(
+            0,
+            &[
+                (1, input([1., 0., 1.], true, false)),
+                (2, input([5., 0., 5.], false, false)),
+            ],
+        );
+        assert_eq!(state.positions[&1], Vec3::new(1. + LOCKSTEP_STEP, 0., 1.));
+        assert_eq!(state.positions[&2], Vec3::new(5., 0., 5.));
+
+        // Once simulated, the reported position no longer matters
+        state.step(1, &[(1, input([100., 0., 100.], false, true))]);
+        assert_eq!(
+            state.positions[&1],
+            Vec3::new(1. + LOCKSTEP_STEP, 0., 1. - LOCKSTEP_STEP)
+        );
+        assert!(!state.positions.contains_key(&2));
+    }
+
+    #[test]
+    fn lockstep_peers_agree_on_the_hash() {
+        let ticks: Vec<Vec<(u64, PlayerInput)>> = (0..10)
+            .map(|tick| {
+                vec![
+                    (1, input([0.; 3], tick % 2 == 0, tick % 3 == 0)),
+                    (2, input([3., 0., 3.], false, tick > 4)),
+                ]
+            })
+            .collect();
+        let mut from_start = LockstepState::default();
+        let mut joined_late = LockstepState::default();
+        for (tick, inputs) in ticks.iter().enumerate() {
+            let tick = tick as u64;
+            if tick == 5 {
+                joined_late = LockstepState::resume(tick, &from_start.positions());
+            }
+            from_start.step(tick, inputs);
+            if tick >= 5 {
+                joined_late.step(tick, inputs);
+                assert_eq!(joined_late.hash(), from_start.hash());
+            }
+        }
+
+        let mut diverged = from_start.clone();
+        diverged.positions.insert(2, Vec3::ZERO);
+        assert_ne!(diverged.hash(), from_start.hash());
+    }
+
+    #[test]
+    fn steer_holds_directions_toward_target() {
+        let mut input = PlayerInput::default();
+        input.steer(Vec3::ZERO, Vec3::new(2., 0., -2.), 0.5);
+        assert!(input.right && input.up && !input.left && !input.down);
+        assert_eq!(input.direction(), Vec3::new(1., 0., -1.).normalize());
+
+        input.steer(Vec3::ZERO, Vec3::new(0.1, 0., 0.), 0.5);
+        assert_eq!(input.direction(), Vec3::ZERO);
+    }
+
+    #[test]
+    fn wallet_signatures_only_verify_fresh_and_for_their_client() {
+        let keypair = Keypair::new();
+        let mut user_data = encode_player_name("signer");
+        sign_wallet_identity(&mut user_data, &keypair, 7, 1_000);
+
+        assert_eq!(
+            verify_wallet_identity(&user_data, 7, 1_010),
+            WalletIdentity::Verified {
+                wallet: keypair.pubkey(),
+                signed_at: 1_000
+            }
+        );
+        assert_eq!(
+            verify_wallet_identity(&user_data, 8, 1_010),
+            WalletIdentity::Invalid
+        );
+        assert_eq!(
+            verify_wallet_identity(&user_data, 7, 1_000 + WALLET_CHALLENGE_WINDOW_SECS + 1),
+            WalletIdentity::Expired
+        );
+
+        // Moving the timestamp forward breaks the signature
+        user_data[USER_DATA_SIGNED_AT_RANGE].copy_from_slice(&1_020u64.to_le_bytes());
+        assert_eq!(
+            verify_wallet_identity(&user_data, 7, 1_020),
+            WalletIdentity::Invalid
+        );
+        assert_eq!(
+            verify_wallet_identity(&encode_player_name("guest"), 7, 1_000),
+            WalletIdentity::None
+        );
+    }
+
+    #[test]
+    fn trajectory_follows_a_parabola() {
+        let config = ProjectileConfig::default();
+        let gravity = Vec3::new(0., -9.81, 0.);
+        let step = 1. / 60.;
+        let points = config.trajectory(Vec3::ZERO, Vec3::X, gravity, step);
+        assert_eq!(points.len(), (config.lifetime / step).ceil() as usize + 1);
+
+        for (i, point) in points.iter().enumerate() {
+            let t = i as f32 * step;
+            assert!((point.x - config.speed * t).abs() < 1e-3);
+            // Euler stepping runs ahead of the exact curve by g * t * step / 2
+            let exact = 0.5 * gravity.y * t * t;
+            assert!((point.y - exact).abs() <= 9.81 * t * step / 2. + 1e-3);
+            assert_eq!(point.z, 0.);
+        }
+
+        let straight = ProjectileConfig {
+            gravity_scale: 0.0,
+            ..default()
+        };
+        assert!(straight
+            .trajectory(Vec3::ZERO, Vec3::X, gravity, step)
+            .iter()
+            .all(|point| point.y == 0.));
+        assert_eq!(
+            config.trajectory(Vec3::ONE, Vec3::X, gravity, 0.),
+            vec![Vec3::ONE]
+        );
+    }
+
+    #[test]
+    fn server_fireballs_need_a_direction() {
+        let mut world = World::new();
+        let mut queue = bevy::ecs::system::CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let config = ProjectileConfig::default();
+
+        assert!(
+            spawn_fireball_server(&mut commands, &config, Vec3::ZERO, Vec3::ZERO, None).is_none()
+        );
+        let fireball =
+            spawn_fireball_server(&mut commands, &config, Vec3::ZERO, Vec3::X * 3., None).unwrap();
+        queue.apply(&mut world);
+
+        assert_eq!(world.entities().len(), 1);
+        let velocity = world.get::<Velocity>(fireball).unwrap();
+        assert_eq!(velocity.linvel, Vec3::X * config.speed);
+    }
+
+    #[test]
+    fn snapshots_carry_their_tick_and_server_time() {
+        let snapshot = NetworkedEntities {
+            tick: 42,
+            server_time: 12.5,
+            complete: true,
+            entities: vec![Entity::from_raw(3)],
+            translations: vec![[1., 2., 3.]],
+        };
+        let decoded: NetworkedEntities =
+            bincode::deserialize(&bincode::serialize(&snapshot).unwrap()).unwrap();
+        assert_eq!(decoded.tick, 42);
+        assert_eq!(decoded.server_time, 12.5);
+        assert_eq!(decoded.entities, vec![Entity::from_raw(3)]);
+        assert_eq!(decoded.translations, vec![[1., 2., 3.]]);
+    }
+
+    #[test]
+    fn projectile_spawns_carry_velocity_and_settings_carry_ballistics() {
+        let message = ServerMessages::SpawnProjectile {
+            entity: Entity::from_raw(7),
+            translation: [1., 2., 3.],
+            velocity: [10., 0., -4.],
+            radius: 0.1,
+            kind: ProjectileKind::Seeker,
+        };
+        let decoded = bincode::deserialize(&bincode::serialize(&message).unwrap()).unwrap();
+        let ServerMessages::SpawnProjectile {
+            entity,
+            velocity,
+            kind,
+            ..
+        } = decoded
+        else {
+            panic!("unexpected {:?}", decoded);
+        };
+        assert_eq!(entity, Entity::from_raw(7));
+        assert_eq!(velocity, [10., 0., -4.]);
+        assert_eq!(kind, ProjectileKind::Seeker);
+
+        let message = ServerMessages::WorldSettings {
+            physics_timestep: 1. / 60.,
+            snapshot_interval: None,
+            lockstep_tick: None,
+            lockstep_positions: Vec::new(),
+            projectile: ProjectileConfig {
+                gravity_scale: 0.5,
+                max_bounces: 2,
+                ..default()
             },
-        ))
-        .insert(RigidBody::Dynamic)
-        .insert(Collider::ball(0.1))
-        .insert(Velocity::linear(direction * 10.))
-        .insert(ActiveEvents::COLLISION_EVENTS)
-        .id()
-}
-
-// Add this new function
-pub fn setup_level_server(mut commands: Commands) {
-    // Ground plane
-    commands
-        .spawn(TransformBundle::from_transform(Transform::from_xyz(0.0, -0.5, 0.0)))
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid(50.0, 0.1, 50.0));
-
-    // Walls
-    let wall_positions = [
-        Vec3::new(-50.0, 0.0, 0.0),   // Left
-        Vec3::new(50.0, 0.0, 0.0),    // Right
-        Vec3::new(0.0, 0.0, -50.0),   // Back
-        Vec3::new(0.0, 0.0, 50.0),    // Front
-    ];
-
-    for position in wall_positions {
-        commands
-            .spawn(TransformBundle::from_transform(Transform::from_translation(position)))
-            .insert(RigidBody::Fixed)
-            .insert(Collider::cuboid(1.0, 10.0, 50.0));
-    }
-}
\ No newline at end of file
+        };
+        let decoded = bincode::deserialize(&bincode::serialize(&message).unwrap()).unwrap();
+        let ServerMessages::WorldSettings { projectile, .. } = decoded else {
+            panic!("unexpected {:?}", decoded);
+        };
+        assert_eq!(projectile.gravity_scale, 0.5);
+        assert_eq!(projectile.max_bounces, 2);
+    }
+
+    #[test]
+    fn replays_round_trip_every_frame_and_entity_kind() {
+        let header = ReplayHeader {
+            map: "arena".to_string(),
+            players: vec![ReplayPlayer {
+                id: 1,
+                name: "alice".to_string(),
+            }],
+            start_time: 1_700_000_000,
+        };
+        let entity = |id, kind, x| ReplayEntity {
+            id,
+            kind,
+            translation: [x, 0.5, -x],
+            rotation: [0., 0., 0., 1.],
+        };
+        let frames: Vec<ReplayFrame> = (0..5)
+            .map(|i| ReplayFrame {
+                time: i as f64 / 10.,
+                entities: vec![
+                    entity(1, ReplayEntityKind::Player { id: 1 }, i as f32),
+                    entity(
+                        2,
+                        ReplayEntityKind::Projectile(ProjectileKind::Fireball),
+                        2. * i as f32,
+                    ),
+                    entity(
+                        3,
+                        ReplayEntityKind::Block {
+                            slot: 250_000_000 + i,
+                        },
+                        0.,
+                    ),
+                    entity(4, ReplayEntityKind::Ball, -(i as f32)),
+                ],
+            })
+            .collect();
+
+        let mut writer = ReplayWriter::new(Vec::new());
+        writer.write_header(&header).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        let bytes = writer.finalize().unwrap();
+
+        let mut reader = ReplayReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.version, REPLAY_FORMAT_VERSION);
+        assert_eq!(reader.header, header);
+        let mut read = Vec::new();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            read.push(frame);
+        }
+        assert_eq!(read, frames);
+        assert_eq!(reader.next_frame().unwrap(), None);
+
+        // Without its end marker and with the last frame cut short, the
+        // replay still reads up to its last complete frame
+        let mut reader = ReplayReader::new(&bytes[..bytes.len() - 10]).unwrap();
+        let mut read = Vec::new();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            read.push(frame);
+        }
+        assert_eq!(read, frames[..4]);
+    }
+
+    #[test]
+    fn oversized_messages_reassemble_from_chunks() {
+        let players: Vec<PlayerSummary> = (0..2000)
+            .map(|id| PlayerSummary {
+                id,
+                name: format!("player-{}", id),
+                score: id * 3,
+                is_bot: id % 2 == 0,
+            })
+            .collect();
+        let message = ServerMessages::PlayerList { players };
+        assert!(bincode::serialize(&message).unwrap().len() > MAX_MESSAGE_BYTES);
+
+        let chunks = serialize_chunked(&message, MAX_MESSAGE_BYTES);
+        assert!(chunks.len() > 1);
+        let mut assembler = ChunkAssembler::default();
+        let mut reassembled = None;
+        // Chunks of one message can arrive in any order
+        for bytes in chunks.iter().rev() {
+            let ServerMessages::Chunk {
+                id,
+                index,
+                count,
+                bytes,
+            } = bincode::deserialize(bytes).unwrap()
+            else {
+                panic!("expected only chunks");
+            };
+            assert!(bytes.len() <= MAX_MESSAGE_BYTES);
+            assert!(reassembled.is_none());
+            reassembled = assembler.receive(id, index, count, bytes);
+        }
+        let ServerMessages::PlayerList { players } =
+            bincode::deserialize(&reassembled.unwrap()).unwrap()
+        else {
+            panic!("expected the player list back");
+        };
+        assert_eq!(players.len(), 2000);
+        assert_eq!(players[1999].name, "player-1999");
+        assert_eq!(players[1999].score, 5997);
+
+        // Messages that fit are sent whole
+        let small = serialize_chunked(&ServerMessages::PlayerRemove { id: 1 }, MAX_MESSAGE_BYTES);
+        assert_eq!(small.len(), 1);
+        assert!(matches!(
+            bincode::deserialize(&small[0]).unwrap(),
+            ServerMessages::PlayerRemove { id: 1 }
+        ));
+    }
+
+    #[test]
+    fn charge_scales_fireballs_up_to_the_max() {
+        let config = ProjectileConfig {
+            speed: 10.,
+            radius: 0.1,
+            damage: 20.,
+            knock_up: 0.,
+            max_charge: 1.,
+            max_charge_scale: 2.,
+            charged_knock_up: 6.,
+            ..default()
+        };
+        let stats = |charged: ProjectileConfig| {
+            (
+                charged.speed,
+                charged.radius,
+                charged.damage,
+                charged.knock_up,
+            )
+        };
+
+        // No charge, or nonsense, leaves the fireball as configured
+        assert_eq!(stats(config.charged(0.)), (10., 0.1, 20., 0.));
+        assert_eq!(stats(config.charged(-3.)), (10., 0.1, 20., 0.));
+        assert_eq!(stats(config.charged(f32::NAN)), (10., 0.1, 20., 0.));
+        assert_eq!(stats(config.charged(0.5)), (15., 0.15, 30., 0.));
+        // Holding past the max charge gains nothing more
+        assert_eq!(stats(config.charged(1.)), (20., 0.2, 40., 6.));
+        assert_eq!(stats(config.charged(5.)), (20., 0.2, 40., 6.));
+    }
+
+    #[test]
+    fn block_shapes_build_matching_meshes_and_colliders() {
+        for (name, shape) in [
+            ("cube", BlockShape::Cube),
+            ("Sphere", BlockShape::Sphere),
+            ("cylinder", BlockShape::Cylinder),
+            ("CONVEX", BlockShape::Convex),
+        ] {
+            assert_eq!(BlockShape::from_name(name), Some(shape));
+
+            let mesh = shape.mesh().compute_aabb().unwrap();
+            let collider = shape.collider().raw.compute_local_aabb();
+            let (mins, maxs) = (Vec3::from(collider.mins), Vec3::from(collider.maxs));
+            // Both fill the unit cube along each axis
+            assert!(
+                Vec3::from(mesh.min()).abs_diff_eq(mins, 1e-3),
+                "{:?}: {:?} vs {}",
+                shape,
+                mesh,
+                mins
+            );
+            assert!(
+                Vec3::from(mesh.max()).abs_diff_eq(maxs, 1e-3),
+                "{:?}: {:?} vs {}",
+                shape,
+                mesh,
+                maxs
+            );
+            assert!(
+                maxs.abs_diff_eq(Vec3::splat(0.5), 1e-3),
+                "{:?} reaches {}",
+                shape,
+                maxs
+            );
+
+            // Solid through the middle, but only the cube reaches its corners
+            let solid = |point: Vec3| shape.collider().contains_local_point(point);
+            assert!(solid(Vec3::ZERO));
+            assert!(solid(Vec3::new(0., 0.45, 0.)));
+            assert_eq!(
+                solid(Vec3::splat(0.45)),
+                shape == BlockShape::Cube,
+                "{:?}",
+                shape
+            );
+        }
+        assert_eq!(BlockShape::from_name("pyramid"), None);
+    }
+}